@@ -0,0 +1,340 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::blocklist;
+use crate::collection::Collection;
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+use crate::format::{self, Format};
+use crate::intern::get_str;
+use crate::ranking;
+use crate::verify;
+use crate::wal;
+use crate::{DocShared, Term, TfIdf};
+
+pub fn routes(collection: Collection) -> Router {
+    let docs = Arc::clone(&collection.docs);
+    Router::new()
+        .route("/cache", get(download_cache))
+        .route("/prune", post(prune))
+        .route("/vocabulary", get(vocabulary))
+        .route("/verify", post(verify::verify))
+        .route("/rebuild_indexes", post(rebuild_indexes))
+        .route(
+            "/blocklist",
+            get(blocklist::get_blocklist)
+                .post(blocklist::block)
+                .delete(blocklist::unblock),
+        )
+        .route(
+            "/ranking",
+            get(ranking::get_ranking)
+                .put(ranking::put_ranking)
+                .delete(ranking::reset_ranking),
+        )
+        .with_state(docs)
+        .merge(Router::new().route("/compact", post(compact)).with_state(collection))
+}
+
+/// Checks for `Authorization: Bearer {PAPER_ENGINE_ADMIN_TOKEN}`
+pub fn require_admin(headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(token) = CONFIG.admin_token.as_deref() else {
+        return Err(ApiError::new(
+            ErrorCode::Unauthorized,
+            "Admin routes are disabled; set PAPER_ENGINE_ADMIN_TOKEN to enable them",
+        ));
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if !given.is_some_and(|given| same_secret(given, token)) {
+        return Err(ApiError::new(ErrorCode::Unauthorized, "Missing or wrong admin token"));
+    }
+    Ok(())
+}
+
+/// `given == secret`, taking as long wherever they differ, so timing doesn't
+/// reveal how much of a guess was right. Only the length can show.
+fn same_secret(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+/// Bytes written to it go to a response body, a buffer at a time
+struct ChannelWriter(tokio::sync::mpsc::Sender<Result<Vec<u8>, std::io::Error>>);
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serializes the live index, in the same format as the cache file, for backups
+async fn download_cache(
+    headers: HeaderMap,
+    State(docs): State<DocShared>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&headers)?;
+    // Written straight into the response under the read lock, off the async
+    // workers; the channel only holds a buffer or two, so the encode goes at
+    // the client's pace
+    let (tx, rx) = tokio::sync::mpsc::channel(2);
+    tokio::task::spawn_blocking(move || {
+        let errors = tx.clone();
+        let written = docs
+            .read()
+            .map_err(|e| format!("Could not get `DocShared` read lock: {e}"))
+            .and_then(|docs| {
+                let mut writer = std::io::BufWriter::with_capacity(64 * 1024, ChannelWriter(tx));
+                docs.serialize(&mut writer)
+                    .and_then(|()| Ok(writer.flush()?))
+                    .map_err(|e| format!("Could not serialize cache: {e}"))
+            });
+        if let Err(e) = written {
+            tracing::warn!("{e}");
+            // Cuts the body short, so the client sees a failed download
+            // rather than a truncated cache
+            let _ = errors.blocking_send(Err(std::io::Error::other(e)));
+        }
+    });
+    let body = Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"paper-engine-cache.pec\"",
+            ),
+        ],
+        body,
+    ))
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneReport {
+    terms_pruned: usize,
+    /// Estimated from `TfIdf::approx_bytes`; the pruned strings themselves
+    /// stay in the intern pool until it's collected (see `gc.rs`)
+    bytes_reclaimed: usize,
+}
+
+impl TfIdf {
+    /// Drops every term seen fewer than `min_count` times across the corpus,
+    /// from `global_term_count` and from every document, so the two stay in
+    /// agreement
+    pub fn prune_terms(&mut self, min_count: usize) -> PruneReport {
+        let before = self.approx_bytes();
+        let rare = self
+            .global_term_count
+            .iter()
+            .filter(|(_, count)| **count < min_count)
+            .map(|(term, _)| *term)
+            .collect::<HashSet<Term>>();
+
+        self.remove_terms(&rare);
+
+        PruneReport {
+            terms_pruned: rare.len(),
+            bytes_reclaimed: before.saturating_sub(self.approx_bytes()),
+        }
+    }
+
+    /// Drops `terms` from `global_term_count`, `postings` and every document;
+    /// returns how many documents had any of them
+    pub fn remove_terms(&mut self, terms: &HashSet<Term>) -> usize {
+        self.changed();
+        self.global_term_count.retain(|term, _| !terms.contains(term));
+        self.global_term_count.shrink_to_fit();
+        self.postings.retain(|term, _| !terms.contains(term));
+        let mut affected = 0;
+        for doc in self.documents.values_mut() {
+            if doc.term_frequency.keys().any(|term| terms.contains(term)) {
+                affected += 1;
+            }
+            doc.term_frequency.retain(|term, _| !terms.contains(term));
+            doc.term_frequency.shrink_to_fit();
+            doc.abstract_frequency.retain(|term, _| !terms.contains(term));
+            doc.abstract_frequency.shrink_to_fit();
+            doc.annotation_frequency.retain(|term, _| !terms.contains(term));
+            doc.annotation_frequency.shrink_to_fit();
+            doc.note_frequency.retain(|term, _| !terms.contains(term));
+            doc.note_frequency.shrink_to_fit();
+            doc.surface_forms.retain(|term, _| !terms.contains(term));
+            doc.surface_forms.shrink_to_fit();
+            doc.first_page.retain(|term, _| !terms.contains(term));
+            doc.first_page.shrink_to_fit();
+        }
+        affected
+    }
+}
+
+async fn prune(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<PruneReport>, ApiError> {
+    require_admin(&headers)?;
+    let min_count = crate::parse_param(&params, "min_count")?.ok_or_else(|| {
+        ApiError::missing_param("Missing `min_count` parameter; give the minimum corpus count to keep")
+    })?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    docs.log_change(wal::Entry::Prune { min_count })?;
+    let report = docs.prune_terms(min_count);
+    tracing::info!("Pruned terms seen fewer than {min_count} times: {report:?}");
+    Ok(Json(report))
+}
+
+/// `GET /api/admin/vocabulary?format=tsv&min_count=N`: every term with its
+/// corpus count and the number of documents containing it, most frequent
+/// first
+async fn vocabulary(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&headers)?;
+    format::negotiate(&headers, &params, &[Format::Tsv])?;
+    let min_count = crate::parse_param::<usize>(&params, "min_count")?.unwrap_or(0);
+
+    // Only ids and counts are copied under the lock; the text of each row is
+    // written as it's sent
+    let mut rows = {
+        let docs = docs.read().map_err(|e| {
+            ApiError::internal(format!("Could not get `DocShared` read lock: {e}"))
+        })?;
+        docs.global_term_count
+            .iter()
+            .filter(|(_, count)| **count >= min_count)
+            .map(|(term, count)| (*term, *count, docs.postings(*term).count()))
+            .collect::<Vec<_>>()
+    };
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| get_str(a.0).cmp(get_str(b.0))));
+
+    let heading = std::iter::once("term\tcount\tdoc_frequency\n".to_owned());
+    let lines = rows
+        .into_iter()
+        .map(|(term, count, doc_frequency)| format!("{term}\t{count}\t{doc_frequency}\n"));
+    let body = Body::from_stream(tokio_stream::iter(
+        heading.chain(lines).map(Ok::<_, Infallible>),
+    ));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/tab-separated-values; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"vocabulary.tsv\"",
+            ),
+        ],
+        body,
+    ))
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuildReport {
+    elapsed_ms: u128,
+    /// Terms in the inverted index
+    terms: usize,
+    /// (term, document) pairs in the inverted index
+    postings: usize,
+    /// Entries in the id => title map
+    titles: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompactReport {
+    /// Sizes in bytes; 0 for a file that wasn't there
+    cache_bytes_before: u64,
+    cache_bytes_after: u64,
+    wal_bytes_before: u64,
+    /// Changes made while the cache was being written stay in the log
+    wal_bytes_after: u64,
+    elapsed_ms: u128,
+}
+
+/// `POST /api/admin/compact`: rewrites the cache file from the live index and
+/// truncates the write-ahead log. Every save already writes the index afresh,
+/// with each string once in the pool table and through a temporary file, so
+/// this is a save on demand that reports what it did to the files.
+async fn compact(
+    headers: HeaderMap,
+    State(collection): State<Collection>,
+) -> Result<Json<CompactReport>, ApiError> {
+    require_admin(&headers)?;
+    let size = |path: &str| std::fs::metadata(path).map_or(0, |m| m.len());
+    let (cache_path, wal_path) = (collection.cache_path(), collection.wal_path());
+    let (cache_bytes_before, wal_bytes_before) = (size(&cache_path), size(&wal_path));
+    let started = std::time::Instant::now();
+    tokio::task::spawn_blocking(move || crate::save_cache(&collection))
+        .await
+        .map_err(|e| ApiError::internal(format!("Compaction task failed: {e}")))?
+        .map_err(|e| ApiError::internal(format!("Could not rewrite the cache: {e}")))?;
+    let report = CompactReport {
+        cache_bytes_before,
+        cache_bytes_after: size(&cache_path),
+        wal_bytes_before,
+        wal_bytes_after: size(&wal_path),
+        elapsed_ms: started.elapsed().as_millis(),
+    };
+    tracing::info!("Compacted {cache_path:?}: {report:?}");
+    Ok(Json(report))
+}
+
+/// `POST /api/admin/rebuild_indexes`: rebuilds the inverted index and id map
+/// from the documents in memory, without reading the cache or any file
+async fn rebuild_indexes(
+    headers: HeaderMap,
+    State(docs): State<DocShared>,
+) -> Result<Json<RebuildReport>, ApiError> {
+    require_admin(&headers)?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    let started = std::time::Instant::now();
+    docs.build_derived_indexes();
+    let report = RebuildReport {
+        elapsed_ms: started.elapsed().as_millis(),
+        terms: docs.postings.len(),
+        postings: docs.postings.values().map(|ids| ids.len()).sum(),
+        titles: docs.titles.len(),
+    };
+    tracing::info!("Rebuilt derived indexes: {report:?}");
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_secret_needs_every_byte() {
+        assert!(same_secret("s3cret-token", "s3cret-token"));
+        assert!(!same_secret("s3cret-tokeN", "s3cret-token"));
+        assert!(!same_secret("s3cret", "s3cret-token"));
+        assert!(!same_secret("", "s3cret-token"));
+    }
+}
@@ -0,0 +1,510 @@
+use axum::Json;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+use crate::config::CONFIG;
+use crate::intern::intern;
+use crate::math;
+use crate::wal;
+use crate::{Term, STEMMER};
+
+/// One word of input, as both indexing and querying see it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Token {
+    /// The word lowercased but otherwise as written
+    pub surface: Term,
+    /// What the word is indexed under
+    pub stem: Term,
+}
+
+/// How words are case-normalized before stemming
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseMode {
+    /// `str::to_lowercase`: Unicode-aware, but "Straße" and "STRASSE" differ
+    Lowercase,
+    /// Lowercase, then the full case foldings that lowercasing leaves alone
+    /// (ß → ss, final sigma, long s, Latin ligatures), so those match
+    Casefold,
+}
+
+impl std::str::FromStr for CaseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lowercase" => Ok(Self::Lowercase),
+            "casefold" => Ok(Self::Casefold),
+            _ => Err(format!("expected \"lowercase\" or \"casefold\", got {s:?}")),
+        }
+    }
+}
+
+/// What happens to camelCase and snake_case words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentifierMode {
+    /// Indexed whole, as any other word
+    Keep,
+    /// Indexed as their parts only: `getUserName` is get, user, name
+    Split,
+    /// Indexed whole and as their parts, so both spellings match
+    Both,
+}
+
+impl std::str::FromStr for IdentifierMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "split" => Ok(Self::Split),
+            "both" => Ok(Self::Both),
+            _ => Err(format!("expected \"keep\", \"split\" or \"both\", got {s:?}")),
+        }
+    }
+}
+
+/// Case-normalizes one word according to `PAPER_ENGINE_CASE_MODE`.
+///
+/// Both modes are locale-independent: Turkish dotted `İ` becomes `i` plus a
+/// combining dot and dotless `ı` stays as it is, so neither collides with
+/// plain `i`.
+pub fn fold_case(word: &str) -> String {
+    fold_case_as(word, CONFIG.case_mode)
+}
+
+fn fold_case_as(word: &str, mode: CaseMode) -> String {
+    let lower = word.to_lowercase();
+    if mode == CaseMode::Lowercase {
+        return lower;
+    }
+    let mut folded = String::with_capacity(lower.len());
+    for c in lower.chars() {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            'ſ' => folded.push('s'),
+            'ﬀ' => folded.push_str("ff"),
+            'ﬁ' => folded.push_str("fi"),
+            'ﬂ' => folded.push_str("fl"),
+            'ﬃ' => folded.push_str("ffi"),
+            'ﬄ' => folded.push_str("ffl"),
+            'ﬅ' | 'ﬆ' => folded.push_str("st"),
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+lazy_static::lazy_static! {
+    /// `PAPER_ENGINE_ABBREVIATIONS`, keyed by the case-folded abbreviation
+    static ref ABBREVIATIONS: HashMap<String, String> = CONFIG
+        .abbreviations
+        .iter()
+        .map(|(short, long)| (fold_case(short), long.clone()))
+        .collect();
+    /// `PAPER_ENGINE_STEM_EXCEPTIONS`, words and stems both case-folded
+    static ref STEM_EXCEPTIONS: HashMap<String, String> = CONFIG
+        .stem_exceptions
+        .iter()
+        .map(|(word, stem)| (fold_case(word), fold_case(stem)))
+        .collect();
+}
+
+/// What `word` stands for, if it's a registered abbreviation; case and the
+/// punctuation around it don't matter, so "NLP," is NLP.
+///
+/// Documents are indexed under an abbreviation's expansion as well as the
+/// abbreviation itself, and a query for it also matches the expansion written
+/// out. The map isn't part of the `fingerprint`: documents indexed before an
+/// entry was added only pick it up when resubmitted.
+pub fn expansion(word: &str) -> Option<&'static str> {
+    if ABBREVIATIONS.is_empty() {
+        return None;
+    }
+    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+    ABBREVIATIONS.get(&fold_case(word)).map(|long| long.as_str())
+}
+
+/// Everything that decides which term a word is indexed under. Caches record
+/// it, so loading one built under other settings fails loudly instead of
+/// quietly matching nothing.
+pub fn fingerprint() -> String {
+    fingerprint_with(&STEM_EXCEPTIONS)
+}
+
+/// [`fingerprint`] with `exceptions` in place of the configured ones
+fn fingerprint_with(exceptions: &HashMap<String, String>) -> String {
+    let case_mode = match CONFIG.case_mode {
+        CaseMode::Lowercase => "lowercase",
+        CaseMode::Casefold => "casefold",
+    };
+    let mut fingerprint = format!(
+        "stemmer=english;case_mode={case_mode};cjk_bigrams={}",
+        CONFIG.cjk_bigrams
+    );
+    // Only named when on, so caches from before these settings still match
+    if CONFIG.drop_numeric_tokens {
+        fingerprint.push_str(";drop_numeric=true");
+    }
+    if CONFIG.drop_symbol_tokens {
+        fingerprint.push_str(";drop_symbols=true");
+    }
+    if CONFIG.math {
+        fingerprint.push_str(";math=true");
+    }
+    match CONFIG.identifiers {
+        IdentifierMode::Keep => {}
+        IdentifierMode::Split => fingerprint.push_str(";identifiers=split"),
+        IdentifierMode::Both => fingerprint.push_str(";identifiers=both"),
+    }
+    if !exceptions.is_empty() {
+        // A checksum rather than the list, which could be long
+        let mut exceptions = exceptions
+            .iter()
+            .map(|(word, stem)| format!("{word}:{stem}\n"))
+            .collect::<Vec<_>>();
+        exceptions.sort_unstable();
+        let checksum = wal::checksum(exceptions.concat().as_bytes());
+        fingerprint.push_str(&format!(";stem_exceptions={checksum:08x}"));
+    }
+    fingerprint
+}
+
+/// Why a word was left out of the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dropped {
+    /// Digits and punctuation but no letters, like "12", "3.14" or "[4]";
+    /// `PAPER_ENGINE_DROP_NUMERIC_TOKENS`
+    Numeric,
+    /// Neither letters nor digits, like "—" or "*";
+    /// `PAPER_ENGINE_DROP_SYMBOL_TOKENS`
+    Symbol,
+}
+
+/// Whether the settings leave `word` out. Words with a letter anywhere are
+/// always kept.
+pub fn dropped(word: &str) -> Option<Dropped> {
+    if word.chars().any(char::is_alphabetic) {
+        return None;
+    }
+    if word.chars().any(char::is_numeric) {
+        CONFIG.drop_numeric_tokens.then_some(Dropped::Numeric)
+    } else {
+        CONFIG.drop_symbol_tokens.then_some(Dropped::Symbol)
+    }
+}
+
+/// Han, kana and Hangul: scripts written without spaces between words
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'     // Hangul Jamo
+        | '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+        | '\u{3130}'..='\u{318F}'   // Hangul Compatibility Jamo
+        | '\u{3400}'..='\u{4DBF}'   // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}'   // Hangul Syllables
+        | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+        | '\u{20000}'..='\u{2A6DF}' // CJK Extension B
+    )
+}
+
+/// Overlapping character pairs of a CJK run, or the lone character of a
+/// one-character run. Not stemmed: stem and surface are the same.
+fn cjk_bigrams(run: &[char], tokens: &mut Vec<Token>) {
+    let mut push = |chars: &[char]| {
+        let term = intern(chars.iter().collect::<String>());
+        tokens.push(Token {
+            surface: term,
+            stem: term,
+        });
+    };
+    if run.len() == 1 {
+        push(run);
+    }
+    run.windows(2).for_each(push);
+}
+
+/// `word` cut where it reads as a camelCase or snake_case identifier: at `_`,
+/// before a capital after a lowercase letter ("getUser" is get, User), and
+/// before the last capital of an acronym followed by lowercase ("HTTPServer"
+/// is HTTP, Server). An ordinary word comes back as one part.
+fn identifier_parts(word: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    for piece in word.split('_').filter(|piece| !piece.is_empty()) {
+        let chars = piece.char_indices().collect::<Vec<_>>();
+        let mut start = 0;
+        for i in 1..chars.len() {
+            let (at, c) = chars[i];
+            let prev = chars[i - 1].1;
+            let next_lower = chars.get(i + 1).is_some_and(|(_, next)| next.is_lowercase());
+            if c.is_uppercase() && (prev.is_lowercase() || (prev.is_uppercase() && next_lower)) {
+                parts.push(&piece[start..at]);
+                start = at;
+            }
+        }
+        parts.push(&piece[start..]);
+    }
+    parts
+}
+
+/// What `word` is indexed as under `mode`: itself, its identifier parts or
+/// both. A word with one part is always itself alone.
+fn identifier_words(word: &str, mode: IdentifierMode) -> Vec<&str> {
+    let parts = match mode {
+        IdentifierMode::Keep => vec![],
+        IdentifierMode::Split | IdentifierMode::Both => identifier_parts(word),
+    };
+    if parts.len() < 2 {
+        return vec![word];
+    }
+    match mode {
+        IdentifierMode::Both => std::iter::once(word).chain(parts).collect(),
+        _ => parts,
+    }
+}
+
+/// Tokens of a word with no CJK in it: itself, its identifier parts or both,
+/// per `PAPER_ENGINE_SPLIT_IDENTIFIERS`, leaving out what [`dropped`] drops.
+/// With `expand`, an abbreviation's [`expansion`] follows.
+fn plain_tokens(word: &str, expand: bool, tokens: &mut Vec<Token>) {
+    for word in identifier_words(word, CONFIG.identifiers) {
+        if dropped(word).is_none() {
+            tokens.push(word_token(word));
+        }
+    }
+    if let Some(expansion) = expand.then(|| expansion(word)).flatten() {
+        for word in expansion.split_whitespace() {
+            if dropped(word).is_none() {
+                tokens.push(word_token(word));
+            }
+        }
+    }
+}
+
+fn word_token(word: &str) -> Token {
+    let word = fold_case(word);
+    let stem = match STEM_EXCEPTIONS.get(&word) {
+        Some(stem) => intern(stem),
+        None => intern(STEMMER.stem(&word)),
+    };
+    Token {
+        surface: intern(word),
+        stem,
+    }
+}
+
+/// Tokens of one whitespace-separated word. With `PAPER_ENGINE_CJK_BIGRAMS`,
+/// CJK runs inside it become bigrams and the text around them stays whole
+/// words, so "使用rust编程" is 使用, rust, 编程. Words (or the runs between
+/// CJK text) that [`dropped`] leaves out give no tokens. With
+/// `PAPER_ENGINE_MATH`, math is rewritten first; see `math.rs`.
+pub fn word_tokens(word: &str) -> Vec<Token> {
+    word_tokens_with(word, true)
+}
+
+/// [`word_tokens`] without abbreviation expansions, for the query parser,
+/// which joins those in itself
+pub fn unexpanded_tokens(word: &str) -> Vec<Token> {
+    word_tokens_with(word, false)
+}
+
+fn word_tokens_with(word: &str, expand: bool) -> Vec<Token> {
+    let mut tokens = vec![];
+    match CONFIG.math.then(|| math::words(word)).flatten() {
+        Some(words) => {
+            for word in words {
+                script_tokens(&word, expand, &mut tokens);
+            }
+        }
+        None => script_tokens(word, expand, &mut tokens),
+    }
+    tokens
+}
+
+/// Tokens of a word, with CJK runs split from the rest
+fn script_tokens(word: &str, expand: bool, tokens: &mut Vec<Token>) {
+    if !CONFIG.cjk_bigrams || !word.chars().any(is_cjk) {
+        plain_tokens(word, expand, tokens);
+        return;
+    }
+    let chars = word.chars().collect::<Vec<_>>();
+    let mut start = 0;
+    while start < chars.len() {
+        let cjk = is_cjk(chars[start]);
+        let len = chars[start..]
+            .iter()
+            .take_while(|c| is_cjk(**c) == cjk)
+            .count();
+        let run = &chars[start..start + len];
+        if cjk {
+            cjk_bigrams(run, tokens);
+        } else {
+            plain_tokens(&run.iter().collect::<String>(), expand, tokens);
+        }
+        start += len;
+    }
+}
+
+/// Splits `text` into tokens. Documents and queries must both go through
+/// here, or they will disagree about what a term is.
+pub fn tokenize(text: &str) -> impl Iterator<Item = Token> + '_ {
+    text.split_whitespace().flat_map(word_tokens)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnalyzedWord {
+    /// As split on whitespace, before any normalization
+    word: String,
+    /// What the word became; several for CJK text split into bigrams, none
+    /// when it's dropped
+    tokens: Vec<Token>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dropped: Option<Dropped>,
+    /// What the word stands for, when it's a registered abbreviation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expansion: Option<&'static str>,
+    /// Rewritten as math notation, with `PAPER_ENGINE_MATH`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    math: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Analysis {
+    case_mode: CaseMode,
+    cjk_bigrams: bool,
+    identifiers: IdentifierMode,
+    drop_numeric_tokens: bool,
+    drop_symbol_tokens: bool,
+    math: bool,
+    /// Words left out as numbers
+    dropped_numeric: usize,
+    /// Words left out as symbols
+    dropped_symbol: usize,
+    /// Every stem in order, as the index stores them
+    terms: Vec<Term>,
+    words: Vec<AnalyzedWord>,
+}
+
+/// `POST /api/analyze` with text as the body: how indexing and queries see
+/// it, word by word. A word can turn into several tokens, a stem that looks
+/// nothing like it, or nothing at all when the settings drop it.
+pub async fn analyze(text: String) -> Json<Analysis> {
+    let words = text
+        .split_whitespace()
+        .map(|word| {
+            let math = CONFIG.math && math::is_math(word);
+            AnalyzedWord {
+                word: word.to_owned(),
+                tokens: word_tokens(word),
+                // Math is split up before anything is dropped
+                dropped: dropped(word).filter(|_| !math),
+                expansion: expansion(word),
+                math,
+            }
+        })
+        .collect::<Vec<_>>();
+    let count = |reason| words.iter().filter(|word| word.dropped == Some(reason)).count();
+    Json(Analysis {
+        case_mode: CONFIG.case_mode,
+        cjk_bigrams: CONFIG.cjk_bigrams,
+        identifiers: CONFIG.identifiers,
+        drop_numeric_tokens: CONFIG.drop_numeric_tokens,
+        drop_symbol_tokens: CONFIG.drop_symbol_tokens,
+        math: CONFIG.math,
+        dropped_numeric: count(Dropped::Numeric),
+        dropped_symbol: count(Dropped::Symbol),
+        terms: words
+            .iter()
+            .flat_map(|word| word.tokens.iter().map(|token| token.stem))
+            .collect(),
+        words,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::get_str;
+
+    fn surfaces(word: &str) -> Vec<&'static str> {
+        word_tokens(word).into_iter().map(|token| get_str(token.surface)).collect()
+    }
+
+    #[test]
+    fn chinese_splits_into_bigrams() {
+        assert_eq!(surfaces("自然语言"), ["自然", "然语", "语言"]);
+    }
+
+    #[test]
+    fn one_character_run_is_kept_whole() {
+        assert_eq!(surfaces("书"), ["书"]);
+        assert_eq!(surfaces("a书b"), ["a", "书", "b"]);
+    }
+
+    #[test]
+    fn latin_between_cjk_stays_one_word() {
+        assert_eq!(surfaces("使用rust编程"), ["使用", "rust", "编程"]);
+    }
+
+    #[test]
+    fn identifiers_split_at_case_changes_and_underscores() {
+        assert_eq!(identifier_parts("getUserName"), ["get", "User", "Name"]);
+        assert_eq!(identifier_parts("HTTPServer"), ["HTTP", "Server"]);
+        assert_eq!(identifier_parts("user_name"), ["user", "name"]);
+        assert_eq!(identifier_parts("parseHTTP_response"), ["parse", "HTTP", "response"]);
+        assert_eq!(identifier_parts("plain"), ["plain"]);
+    }
+
+    #[test]
+    fn identifier_modes() {
+        use IdentifierMode::*;
+        assert_eq!(identifier_words("getUserName", Keep), ["getUserName"]);
+        assert_eq!(identifier_words("getUserName", Split), ["get", "User", "Name"]);
+        assert_eq!(identifier_words("user_name", Split), ["user", "name"]);
+        assert_eq!(identifier_words("getUserName", Both), ["getUserName", "get", "User", "Name"]);
+        assert_eq!(identifier_words("user_name", Both), ["user_name", "user", "name"]);
+        // Nothing to split: once, not twice
+        assert_eq!(identifier_words("plain", Both), ["plain"]);
+    }
+
+    #[test]
+    fn stem_exception_skips_the_stemmer() {
+        // The tests' settings keep "operations" whole
+        let [token] = word_tokens("Operations")[..] else {
+            panic!("expected one token");
+        };
+        assert_eq!(get_str(token.stem), "operations");
+        let [token] = word_tokens("operator")[..] else {
+            panic!("expected one token");
+        };
+        assert_eq!(get_str(token.stem), "oper");
+    }
+
+    #[test]
+    fn stem_exceptions_change_the_fingerprint() {
+        let none = fingerprint_with(&HashMap::new());
+        let kept = HashMap::from([("operations".to_owned(), "operations".to_owned())]);
+        let mapped = HashMap::from([("operations".to_owned(), "operation".to_owned())]);
+        assert_ne!(fingerprint_with(&kept), none);
+        assert_ne!(fingerprint_with(&kept), fingerprint_with(&mapped));
+    }
+
+    #[test]
+    fn casefold_folds_sharp_s() {
+        assert_eq!(fold_case_as("Straße", CaseMode::Casefold), "strasse");
+        assert_eq!(fold_case_as("STRASSE", CaseMode::Casefold), "strasse");
+        assert_eq!(fold_case_as("Straße", CaseMode::Lowercase), "straße");
+    }
+
+    #[test]
+    fn turkish_i_never_becomes_plain_i() {
+        for mode in [CaseMode::Lowercase, CaseMode::Casefold] {
+            assert_eq!(fold_case_as("İstanbul", mode), "i\u{307}stanbul");
+            assert_eq!(fold_case_as("ırmak", mode), "ırmak");
+            assert_ne!(fold_case_as("IRMAK", mode), fold_case_as("ırmak", mode));
+        }
+    }
+}
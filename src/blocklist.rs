@@ -0,0 +1,176 @@
+use axum::{
+    extract::{Query as QueryParams, State},
+    http::HeaderMap,
+    Json,
+};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::admin::require_admin;
+use crate::analyzer;
+use crate::error::ApiError;
+use crate::intern::get_str;
+use crate::query::Query;
+use crate::wal;
+use crate::{DocShared, Term, TfIdf};
+
+// Corpus-specific junk that should be neither indexed nor searched: OCR
+// artifacts like "fi", or an institution's name in every page header. Unlike
+// the analyzer's settings it's a property of one index, edited at runtime.
+//
+// Words are blocked by stem, so every spelling goes. Blocking purges the stems
+// from every document at once; later submits drop them before indexing, and
+// searches drop them from the query. The list is saved in the cache (0x1C
+// records) and goes through the write-ahead log like any other change.
+// Unblocking doesn't bring purged terms back until their documents are
+// submitted again.
+
+#[derive(Debug, Serialize)]
+pub struct BlocklistReport {
+    /// Every blocked stem afterwards, alphabetically
+    blocklist: Vec<&'static str>,
+    /// Stems this request blocked or unblocked that weren't already
+    changed: Vec<&'static str>,
+    /// Documents the newly blocked stems were purged from
+    documents_affected: usize,
+}
+
+/// The stems of the comma-separated `terms` parameter
+fn stems(params: &HashMap<String, String>) -> Result<Vec<Term>, ApiError> {
+    let terms = params
+        .get("terms")
+        .ok_or_else(|| ApiError::missing_param("Missing `terms` parameter; give words to block"))?;
+    let mut stems = vec![];
+    for word in terms.split(',').flat_map(str::split_whitespace) {
+        let tokens = analyzer::unexpanded_tokens(word);
+        if tokens.is_empty() {
+            return Err(ApiError::invalid_param(format!(
+                "{word:?} is not a searchable term, so there's nothing to block"
+            )));
+        }
+        stems.extend(tokens.into_iter().map(|token| token.stem));
+    }
+    Ok(stems)
+}
+
+fn sorted(stems: impl IntoIterator<Item = Term>) -> Vec<&'static str> {
+    let mut stems = stems.into_iter().map(get_str).collect::<Vec<_>>();
+    stems.sort_unstable();
+    stems.dedup();
+    stems
+}
+
+impl TfIdf {
+    pub fn is_blocked(&self, stem: Term) -> bool {
+        self.blocklist.contains(&stem)
+    }
+
+    /// Blocks `stems` and purges them from every document; returns the ones
+    /// that weren't blocked yet and how many documents had any of them
+    pub fn block_terms(&mut self, stems: &[Term]) -> (Vec<Term>, usize) {
+        let added = stems
+            .iter()
+            .copied()
+            .filter(|stem| self.blocklist.insert(*stem))
+            .collect::<Vec<_>>();
+        let purged = added.iter().copied().collect::<HashSet<_>>();
+        (added, self.remove_terms(&purged))
+    }
+
+    /// Returns the `stems` that were blocked
+    pub fn unblock_terms(&mut self, stems: &[Term]) -> Vec<Term> {
+        let removed = stems
+            .iter()
+            .copied()
+            .filter(|stem| self.blocklist.remove(stem))
+            .collect::<Vec<_>>();
+        self.changed();
+        removed
+    }
+
+    /// `query` without its blocked terms, which go in `blocked`; `None` when
+    /// nothing is left of it
+    pub fn drop_blocked(&self, query: Query, blocked: &mut Vec<String>) -> Option<Query> {
+        match query {
+            Query::Term { token, .. } if self.is_blocked(token.stem) => {
+                blocked.push(get_str(token.surface).to_owned());
+                None
+            }
+            Query::And { children } => {
+                let children = children
+                    .into_iter()
+                    .filter_map(|child| self.drop_blocked(child, blocked))
+                    .collect::<Vec<_>>();
+                (!children.is_empty()).then_some(Query::And { children })
+            }
+            Query::Or { children } => {
+                let children = children
+                    .into_iter()
+                    .filter_map(|child| self.drop_blocked(child, blocked))
+                    .collect::<Vec<_>>();
+                (!children.is_empty()).then_some(Query::Or { children })
+            }
+            Query::Not { child } => self.drop_blocked(*child, blocked).map(|child| Query::Not {
+                child: Box::new(child),
+            }),
+            leaf => Some(leaf),
+        }
+    }
+
+    fn blocklist_report(&self, changed: Vec<Term>, documents_affected: usize) -> BlocklistReport {
+        BlocklistReport {
+            blocklist: sorted(self.blocklist.iter().copied()),
+            changed: sorted(changed),
+            documents_affected,
+        }
+    }
+}
+
+/// `GET /api/admin/blocklist`
+pub async fn get_blocklist(
+    headers: HeaderMap,
+    State(docs): State<DocShared>,
+) -> Result<Json<BlocklistReport>, ApiError> {
+    require_admin(&headers)?;
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    Ok(Json(docs.blocklist_report(vec![], 0)))
+}
+
+/// `POST /api/admin/blocklist?terms=fi,acme`: blocks the stems of `terms`
+/// and purges them from every document
+pub async fn block(
+    headers: HeaderMap,
+    QueryParams(params): QueryParams<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<BlocklistReport>, ApiError> {
+    require_admin(&headers)?;
+    let stems = stems(&params)?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    docs.log_change(wal::Entry::Block(&stems))?;
+    let (added, documents_affected) = docs.block_terms(&stems);
+    tracing::info!(
+        "Blocked {} terms, purged from {documents_affected} documents",
+        added.len()
+    );
+    Ok(Json(docs.blocklist_report(added, documents_affected)))
+}
+
+/// `DELETE /api/admin/blocklist?terms=fi`: unblocks the stems of `terms`
+pub async fn unblock(
+    headers: HeaderMap,
+    QueryParams(params): QueryParams<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<BlocklistReport>, ApiError> {
+    require_admin(&headers)?;
+    let stems = stems(&params)?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    docs.log_change(wal::Entry::Unblock(&stems))?;
+    let removed = docs.unblock_terms(&stems);
+    Ok(Json(docs.blocklist_report(removed, 0)))
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::sync::{Arc, RwLock};
+
+use crate::config::CONFIG;
+use crate::extract::{self, ExtractOptions};
+use crate::search::{self, SearchRequest};
+use crate::TfIdf;
+
+/// What's left of the command line once `--cache` and the `--key=value`
+/// options are taken out
+struct Args {
+    /// A cache file, or `-` for stdin (reading) and stdout (writing)
+    cache: String,
+    /// `--key=value` options, the same as the HTTP API's query parameters
+    params: HashMap<String, String>,
+    rest: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = Self {
+            cache: crate::CACHE_PATH.to_owned(),
+            params: HashMap::new(),
+            rest: vec![],
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--cache" {
+                parsed.cache = args
+                    .next()
+                    .ok_or("`--cache` needs a path, or `-` for stdin and stdout")?
+                    .clone();
+            } else if let Some((key, value)) =
+                arg.strip_prefix("--").and_then(|option| option.split_once('='))
+            {
+                parsed.params.insert(key.to_owned(), value.to_owned());
+            } else {
+                parsed.rest.push(arg.clone());
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Loads the index from `cache`. A missing file or empty stdin is an empty
+/// index, so a pipeline can start from nothing.
+fn read_cache(cache: &str) -> Result<TfIdf, Box<dyn Error>> {
+    let data = if cache == "-" {
+        let mut data = vec![];
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        match std::fs::read(cache) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        }
+    };
+    let mut tf_idf = match data.is_empty() {
+        true => TfIdf::default(),
+        false => TfIdf::deserialize(&data)?,
+    };
+    tf_idf.check_analyzer(cache)?;
+    tf_idf.build_derived_indexes();
+    tf_idf.check_on_load(cache, CONFIG.load_check)?;
+    Ok(tf_idf)
+}
+
+/// Writes the index to `cache`, through a temporary file like the server's
+/// saves, or straight to stdout for `-`
+fn write_cache(cache: &str, tf_idf: &TfIdf) -> Result<(), Box<dyn Error>> {
+    if cache == "-" {
+        let mut writer = std::io::BufWriter::new(std::io::stdout().lock());
+        tf_idf.serialize(&mut writer)?;
+        writer.flush()?;
+        return Ok(());
+    }
+    let tmp_path = format!("{cache}.tmp");
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+    tf_idf.serialize(&mut writer)?;
+    writer.flush()?;
+    std::fs::rename(&tmp_path, cache)?;
+    Ok(())
+}
+
+/// `paper-engine index [--cache FILE|-] [--key=value...] PATH...`: adds
+/// files to the index without starting the server. Options are the ones
+/// `/submit` takes, e.g. `--dupe=replace`.
+pub fn index(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Args {
+        cache,
+        params,
+        rest: paths,
+    } = Args::parse(args)?;
+    if paths.is_empty() {
+        return Err("`index` needs at least one file to add".into());
+    }
+    let options = ExtractOptions::from_params(&params).map_err(|e| e.to_string())?;
+    let docs = Arc::new(RwLock::new(read_cache(&cache)?));
+    for path in paths {
+        tracing::info!("Submitting document... {}", crate::redact::path(&path));
+        let path = crate::resolve_path(path).map_err(|e| e.to_string())?;
+        let mut extraction =
+            extract::extract(&path, options.clone()).map_err(|e| e.to_string())?;
+        if options.index_title {
+            extraction.add_title_terms(&path);
+        }
+        crate::add_extraction(&docs, path, extraction, &params).map_err(|e| e.to_string())?;
+    }
+    let docs = docs.read().map_err(|e| e.to_string())?;
+    write_cache(&cache, &docs)
+}
+
+/// `paper-engine query [--cache FILE|-] [--key=value...] WORDS...`: prints
+/// the results as `score,title,path` CSV, the same as `format=csv`. Options
+/// are the ones `/search` takes, e.g. `--limit=5`.
+pub fn query(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Args {
+        cache,
+        params,
+        rest: words,
+    } = Args::parse(args)?;
+    let request = SearchRequest::from_params(&params).map_err(|e| e.to_string())?;
+    let tf_idf = read_cache(&cache)?;
+    let outcome = tf_idf
+        .search(&words.join(" "), &request)
+        .map_err(|e| e.to_string())?;
+    print!("{}", search::results_csv(&outcome.results));
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use axum::{extract::State, routing::get, Json, Router};
+use serde_derive::Serialize;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+
+use crate::config::CONFIG;
+use crate::error::{self, ApiError};
+use crate::file_format::FORMAT_VERSION;
+use crate::verify::LoadCheck;
+use crate::wal::{self, Recovery};
+use crate::{DocShared, TfIdf};
+
+/// The collection served at `/api` itself, as before collections existed
+pub const DEFAULT: &str = "default";
+
+/// One named index with its own cache file. Collections share nothing but
+/// the string pool, so document frequencies in one never affect another, but
+/// collecting the pool stops them all.
+#[derive(Debug, Clone)]
+pub struct Collection {
+    pub name: String,
+    pub docs: DocShared,
+}
+
+/// The default collection keeps the original file name, so existing caches
+/// still load
+fn cache_path(name: &str) -> String {
+    if name == DEFAULT {
+        crate::CACHE_PATH.to_owned()
+    } else {
+        format!("paper-engine-cache.{name}.pec")
+    }
+}
+
+impl Collection {
+    pub fn cache_path(&self) -> String {
+        cache_path(&self.name)
+    }
+
+    pub fn wal_path(&self) -> String {
+        format!("{}.wal", self.cache_path())
+    }
+
+    /// Opens the collection's cache, or starts empty if there is none,
+    /// checks it as `check` says, and replays its write-ahead log on top
+    fn load(name: String, check: LoadCheck) -> error::Result<Self> {
+        let (mut tf_idf, version) = match std::fs::File::open(cache_path(&name)) {
+            Ok(mut f) => {
+                // TODO: Buffer this in small chunks to be able to handle larger files
+                //
+                // But honestly, at that point just use a database
+                let mut data = vec![];
+                f.read_to_end(&mut data)?;
+                TfIdf::deserialize_versioned(&data)?
+            }
+            _ => (TfIdf::default(), FORMAT_VERSION),
+        };
+        tf_idf.check_analyzer(&cache_path(&name))?;
+        let started = std::time::Instant::now();
+        tf_idf.build_derived_indexes();
+        tracing::info!(
+            "Built indexes for collection {name:?} ({} documents) in {:?}",
+            tf_idf.documents.len(),
+            started.elapsed()
+        );
+        let repaired = tf_idf.check_on_load(&cache_path(&name), check)?;
+        let wal_path = format!("{}.wal", cache_path(&name));
+        let recovery = if CONFIG.wal {
+            wal::recover(&mut tf_idf, wal_path)?
+        } else {
+            if std::fs::metadata(&wal_path).is_ok_and(|m| m.len() > 0) {
+                tracing::warn!("Ignoring write-ahead log {wal_path:?}; PAPER_ENGINE_WAL is off");
+            }
+            Recovery::default()
+        };
+        let collection = Self {
+            name,
+            docs: Arc::new(RwLock::new(tf_idf)),
+        };
+        if version < FORMAT_VERSION {
+            tracing::info!(
+                "Upgrading cache {:?} from format version {version} to {FORMAT_VERSION}",
+                collection.cache_path()
+            );
+            crate::save_cache(&collection)?;
+        } else if repaired || recovery.needs_save() {
+            crate::save_cache(&collection)?;
+        }
+        Ok(collection)
+    }
+}
+
+/// Names end up in URLs and file names, so they're kept to
+/// `[A-Za-z0-9_-]`
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The default collection followed by each one in `PAPER_ENGINE_COLLECTIONS`,
+/// each checked as `check` says
+pub fn load_all(check: LoadCheck) -> error::Result<Vec<Collection>> {
+    let mut names = vec![DEFAULT.to_owned()];
+    for name in &CONFIG.collections {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+        .into_iter()
+        .map(|name| Collection::load(name, check))
+        .collect()
+}
+
+pub fn routes(collections: Arc<Vec<Collection>>) -> Router {
+    Router::new()
+        .route("/", get(list_collections))
+        .with_state(collections)
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionInfo {
+    name: String,
+    documents: usize,
+}
+
+/// `GET /api/collections`
+pub async fn list_collections(
+    State(collections): State<Arc<Vec<Collection>>>,
+) -> Result<Json<Vec<CollectionInfo>>, ApiError> {
+    collections
+        .iter()
+        .map(|collection| {
+            let docs = collection.docs.read().map_err(|e| {
+                ApiError::internal(format!("Could not get `DocShared` read lock: {e}"))
+            })?;
+            Ok(CollectionInfo {
+                name: collection.name.clone(),
+                documents: docs.documents.len(),
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Json)
+}
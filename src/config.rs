@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::analyzer::{CaseMode, IdentifierMode};
+use crate::collection;
+use crate::query::Operator;
+use crate::redact::Redaction;
+use crate::title::TitleSource;
+use crate::search::{QueryNorm, MAX_HISTOGRAM_BUCKETS};
+use crate::verify::LoadCheck;
+
+#[cfg(not(test))]
+lazy_static::lazy_static! {
+    pub static ref CONFIG: Config = Config::from_env();
+}
+
+#[cfg(test)]
+lazy_static::lazy_static! {
+    pub static ref CONFIG: Config = Config::for_tests();
+}
+
+/// Server settings, read once from `PAPER_ENGINE_*` environment variables
+#[derive(Debug)]
+pub struct Config {
+    /// Number of tokio worker threads; `None` lets tokio use one per core
+    pub worker_threads: Option<usize>,
+    /// Upper bound on the blocking pool that PDF extraction runs on
+    pub max_blocking_threads: Option<usize>,
+    /// Leading pages indexed a second time as the "abstract" field
+    pub abstract_pages: usize,
+    /// How much more an abstract occurrence counts than a body occurrence
+    pub abstract_boost: f64,
+    /// How much more an annotation occurrence counts than a body occurrence
+    pub annotation_boost: f64,
+    /// Searches slower than this are logged at warn level
+    pub slow_query_threshold: Duration,
+    /// Bearer token for `/api/admin`; admin routes are refused while unset
+    pub admin_token: Option<String>,
+    /// Record searches for `/api/stats/queries`; turn off for private deployments
+    pub query_history: bool,
+    /// How many recent searches to remember
+    pub query_history_size: usize,
+    /// Answer repeated searches from `result_cache.rs`
+    pub result_cache: bool,
+    /// Most search outcomes cached per index, the least recently used going
+    /// first
+    pub result_cache_size: usize,
+    /// Outcomes with more results than this aren't cached, so one huge page
+    /// can't push out many small ones; `None` caches any size
+    pub result_cache_max_results: Option<usize>,
+    /// Store submitted paths in canonical form so one file can't be indexed
+    /// twice under different relative or symlinked paths
+    pub canonicalize_paths: bool,
+    /// Default for the `font_weighting` submit parameter
+    pub font_weighting: bool,
+    /// How much a word in a heading counts, relative to body text
+    pub heading_weight: f64,
+    /// Case normalization for indexing and queries alike; changing it needs a
+    /// reindex, or old and new documents won't agree on terms
+    pub case_mode: CaseMode,
+    /// Most results one search returns, whatever `limit` asks for
+    pub max_results: usize,
+    /// Default for the `min_score` search parameter; 0 keeps every match
+    pub min_score: u64,
+    /// Default for the `buckets` search parameter, for `histogram=true`
+    pub histogram_buckets: usize,
+    /// Most terms one `prefix*` in a query expands to
+    pub max_wildcard_terms: usize,
+    /// Fewest documents a stem must be in for `/api/term/suggest` to offer it
+    pub suggest_min_docs: usize,
+    /// A search offers `did_you_mean` only with fewer results than this; 0
+    /// never offers it
+    pub did_you_mean_max_results: usize,
+    /// How many more documents than were found the corrected words must be
+    /// in for `did_you_mean` to be offered
+    pub did_you_mean_min_gain: usize,
+    /// Largest share of documents a stem may be in for `/api/term/suggest`
+    /// to offer it
+    pub suggest_max_doc_share: f64,
+    /// Queries whose every searched word is shorter than this many characters
+    /// are refused as too broad; 1 allows any
+    pub min_query_chars: usize,
+    /// Save the cache this often while running; `None` only saves on shutdown
+    pub autosave_interval: Option<Duration>,
+    /// Append every change to `{cache}.wal`, synced to disk before it's
+    /// made, and replay it on startup; see `wal.rs`
+    pub wal: bool,
+    /// Split Chinese, Japanese and Korean text into character bigrams, since
+    /// it has no spaces between words; changing it needs a reindex
+    pub cjk_bigrams: bool,
+    /// Whether camelCase and snake_case words are also indexed as their
+    /// parts; changing it needs a reindex
+    pub identifiers: IdentifierMode,
+    /// Abbreviations and what they stand for, from the file named by
+    /// `PAPER_ENGINE_ABBREVIATIONS`; see `analyzer::expansion`
+    pub abbreviations: HashMap<String, String>,
+    /// Words the stemmer gets wrong and the stem to use instead, from the
+    /// file named by `PAPER_ENGINE_STEM_EXCEPTIONS`; changing it needs a
+    /// reindex
+    pub stem_exceptions: HashMap<String, String>,
+    /// Leave out words of digits and punctuation, like page numbers and
+    /// footnote markers; changing it needs a reindex
+    pub drop_numeric_tokens: bool,
+    /// Leave out words with no letters or digits at all; changing it needs a
+    /// reindex
+    pub drop_symbol_tokens: bool,
+    /// Rewrite math notation into searchable words (see `math.rs`); changing
+    /// it needs a reindex
+    pub math: bool,
+    /// Age at which `recency=true` halves a document's score
+    pub recency_half_life: Duration,
+    /// Largest bonus `position_weight=true` gives a term, for one on the
+    /// first page: 0.5 multiplies its score by 1.5
+    pub position_bonus: f64,
+    /// Pages after which that bonus has halved
+    pub position_half_life_pages: f64,
+    /// Default for the `index_title` submit parameter
+    pub index_title: bool,
+    /// Where PDF titles are looked for, in order; see `title.rs`
+    pub title_chain: Vec<TitleSource>,
+    /// Largest multipart body `/upload` accepts
+    pub upload_max_bytes: usize,
+    /// Largest body any other route accepts
+    pub max_body_bytes: usize,
+    /// Longest query string any route accepts
+    pub max_query_bytes: usize,
+    /// How long `/upload` waits for the whole body
+    pub upload_timeout: Duration,
+    /// What search terms written side by side mean, unless `op` says otherwise
+    pub default_operator: Operator,
+    /// Default for the `query_norm` search parameter
+    pub query_norm: QueryNorm,
+    /// Defaults for `expand_docs` and `expand_terms`: how many top results
+    /// `expand=true` takes terms from, and how many it adds
+    pub expand_docs: usize,
+    pub expand_terms: usize,
+    /// Boost of the best term `expand=true` adds; the rest get less
+    pub expand_weight: f64,
+    /// Named collections served besides the default one, each with its own
+    /// index and cache file
+    pub collections: Vec<String>,
+    /// Most files a directory ingest extracts at once; each one holds a whole
+    /// document in memory while it's extracted
+    pub ingest_concurrency: usize,
+    /// Threads a verify pass (`/admin/verify`, or checking a cache as it
+    /// loads) splits the documents between
+    pub verify_threads: usize,
+    /// Documents a verify thread takes at a time
+    pub verify_batch: usize,
+    /// Interned strings past which the pool is collected automatically;
+    /// `None` leaves it to `/admin/intern_gc`
+    pub intern_gc_entries: Option<usize>,
+    /// Default for the `store_text` submit parameter
+    pub store_text: bool,
+    /// Most distinct terms kept per document, the most frequent first;
+    /// `None` keeps them all
+    pub max_terms_per_document: Option<usize>,
+    /// Default for the `min_doc_tf` submit parameter
+    pub min_doc_tf: usize,
+    /// Resolution of page thumbnails unless `dpi` says otherwise
+    pub thumbnail_dpi: u32,
+    /// Longest side of a thumbnail in pixels, whatever the resolution
+    pub thumbnail_max_px: u32,
+    /// Size the thumbnail cache is kept under, oldest thumbnails going first
+    pub thumbnail_cache_bytes: u64,
+    /// PDFs kept open between requests for the same file; see `pdf_pool.rs`
+    pub pdf_pool_size: usize,
+    /// Default for the `trim_repeated` submit parameter
+    pub trim_repeated_lines: bool,
+    /// Share of a document's pages a line must be on to be trimmed as a
+    /// running header or footer
+    pub repeated_line_share: f64,
+    /// A PDF averaging fewer non-blank characters per page than this is
+    /// marked `likely_scanned`
+    pub scanned_chars_per_page: usize,
+    /// Share of a PDF's pages that, at least this many without text, mark it
+    /// `likely_scanned`
+    pub scanned_empty_page_share: f64,
+    /// Decimal places fractional scores are written with
+    pub score_decimals: i32,
+    /// How long shutdown waits for requests in flight before cancelling them
+    pub shutdown_grace: Duration,
+    /// What log lines leave out about documents and queries; see `redact.rs`
+    pub log_redaction: Redaction,
+    /// What happens to a cache whose counts disagree with its documents
+    pub load_check: LoadCheck,
+}
+
+impl Config {
+    /// The environment's settings with the analyzer options the tests rely
+    /// on turned on, so they don't depend on how the tests are run
+    #[cfg(test)]
+    fn for_tests() -> Self {
+        Self {
+            case_mode: CaseMode::Lowercase,
+            cjk_bigrams: true,
+            identifiers: IdentifierMode::Keep,
+            math: false,
+            drop_numeric_tokens: true,
+            drop_symbol_tokens: true,
+            abbreviations: HashMap::from([(
+                "NLP".to_owned(),
+                "natural language processing".to_owned(),
+            )]),
+            stem_exceptions: HashMap::from([("operations".to_owned(), "operations".to_owned())]),
+            ..Self::from_env()
+        }
+    }
+
+    fn from_env() -> Self {
+        Self {
+            worker_threads: env("PAPER_ENGINE_WORKER_THREADS").filter(|n| *n > 0),
+            max_blocking_threads: env("PAPER_ENGINE_MAX_BLOCKING_THREADS").filter(|n| *n > 0),
+            abstract_pages: env_or("PAPER_ENGINE_ABSTRACT_PAGES", 1),
+            abstract_boost: env("PAPER_ENGINE_ABSTRACT_BOOST")
+                .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+                .unwrap_or(2.0),
+            annotation_boost: env("PAPER_ENGINE_ANNOTATION_BOOST")
+                .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+                .unwrap_or(3.0),
+            slow_query_threshold: Duration::from_millis(env_or("PAPER_ENGINE_SLOW_QUERY_MS", 100)),
+            admin_token: env("PAPER_ENGINE_ADMIN_TOKEN").filter(|t: &String| !t.is_empty()),
+            query_history: env_or("PAPER_ENGINE_QUERY_HISTORY", true),
+            query_history_size: env_or("PAPER_ENGINE_QUERY_HISTORY_SIZE", 100),
+            result_cache: env_or("PAPER_ENGINE_RESULT_CACHE", true),
+            result_cache_size: env_or("PAPER_ENGINE_RESULT_CACHE_SIZE", 256),
+            result_cache_max_results: env("PAPER_ENGINE_RESULT_CACHE_MAX_RESULTS")
+                .filter(|n| *n > 0),
+            canonicalize_paths: env_or("PAPER_ENGINE_CANONICALIZE_PATHS", true),
+            font_weighting: env_or("PAPER_ENGINE_FONT_WEIGHTING", false),
+            heading_weight: env("PAPER_ENGINE_HEADING_WEIGHT")
+                .filter(|v: &f64| v.is_finite() && *v >= 1.0)
+                .unwrap_or(2.0),
+            case_mode: env_or("PAPER_ENGINE_CASE_MODE", CaseMode::Lowercase),
+            max_results: env("PAPER_ENGINE_MAX_RESULTS").filter(|n| *n > 0).unwrap_or(500),
+            min_score: env_or("PAPER_ENGINE_MIN_SCORE", 0),
+            histogram_buckets: env("PAPER_ENGINE_HISTOGRAM_BUCKETS")
+                .filter(|n| (1..=MAX_HISTOGRAM_BUCKETS).contains(n))
+                .unwrap_or(10),
+            max_wildcard_terms: env("PAPER_ENGINE_MAX_WILDCARD_TERMS")
+                .filter(|n| *n > 0)
+                .unwrap_or(50),
+            suggest_min_docs: env_or("PAPER_ENGINE_SUGGEST_MIN_DOCS", 2),
+            did_you_mean_max_results: env_or("PAPER_ENGINE_DID_YOU_MEAN_MAX_RESULTS", 5),
+            did_you_mean_min_gain: env("PAPER_ENGINE_DID_YOU_MEAN_MIN_GAIN")
+                .filter(|n| *n > 0)
+                .unwrap_or(1),
+            suggest_max_doc_share: env("PAPER_ENGINE_SUGGEST_MAX_DOC_SHARE")
+                .filter(|v: &f64| (0.0..=1.0).contains(v))
+                .unwrap_or(0.5),
+            min_query_chars: env("PAPER_ENGINE_MIN_QUERY_CHARS").filter(|n| *n > 0).unwrap_or(2),
+            autosave_interval: env("PAPER_ENGINE_AUTOSAVE_SECS")
+                .filter(|n| *n > 0)
+                .map(Duration::from_secs),
+            wal: env_or("PAPER_ENGINE_WAL", false),
+            cjk_bigrams: env_or("PAPER_ENGINE_CJK_BIGRAMS", true),
+            identifiers: env_or("PAPER_ENGINE_SPLIT_IDENTIFIERS", IdentifierMode::Keep),
+            abbreviations: abbreviations("PAPER_ENGINE_ABBREVIATIONS"),
+            stem_exceptions: stem_exceptions("PAPER_ENGINE_STEM_EXCEPTIONS"),
+            drop_numeric_tokens: env_or("PAPER_ENGINE_DROP_NUMERIC_TOKENS", false),
+            drop_symbol_tokens: env_or("PAPER_ENGINE_DROP_SYMBOL_TOKENS", false),
+            math: env_or("PAPER_ENGINE_MATH", false),
+            recency_half_life: env("PAPER_ENGINE_RECENCY_HALF_LIFE_DAYS")
+                .filter(|v: &f64| v.is_finite() && *v > 0.0)
+                .map_or(Duration::from_secs(30 * 86400), |days| {
+                    Duration::from_secs_f64(days * 86400.0)
+                }),
+            position_bonus: env("PAPER_ENGINE_POSITION_BONUS")
+                .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+                .unwrap_or(0.5),
+            position_half_life_pages: env("PAPER_ENGINE_POSITION_HALF_LIFE_PAGES")
+                .filter(|v: &f64| v.is_finite() && *v > 0.0)
+                .unwrap_or(10.0),
+            index_title: env_or("PAPER_ENGINE_INDEX_TITLE", true),
+            title_chain: title_chain("PAPER_ENGINE_TITLE_CHAIN"),
+            upload_max_bytes: env_bytes("PAPER_ENGINE_UPLOAD_MAX_MB", 64, 1024 * 1024),
+            max_body_bytes: env_bytes("PAPER_ENGINE_MAX_BODY_KB", 1024, 1024),
+            max_query_bytes: env_or("PAPER_ENGINE_MAX_QUERY_BYTES", 16 * 1024),
+            upload_timeout: Duration::from_secs(env_or("PAPER_ENGINE_UPLOAD_TIMEOUT_SECS", 60)),
+            default_operator: env_or("PAPER_ENGINE_DEFAULT_OPERATOR", Operator::Or),
+            query_norm: env_or("PAPER_ENGINE_QUERY_NORM", QueryNorm::Count),
+            expand_docs: env("PAPER_ENGINE_EXPAND_DOCS").filter(|n| *n > 0).unwrap_or(5),
+            expand_terms: env("PAPER_ENGINE_EXPAND_TERMS").filter(|n| *n > 0).unwrap_or(5),
+            expand_weight: env("PAPER_ENGINE_EXPAND_WEIGHT")
+                .filter(|v: &f64| v.is_finite() && *v > 0.0)
+                .unwrap_or(0.5),
+            collections: collection_names("PAPER_ENGINE_COLLECTIONS"),
+            ingest_concurrency: env("PAPER_ENGINE_INGEST_CONCURRENCY")
+                .filter(|n| *n > 0)
+                .unwrap_or_else(|| {
+                    std::thread::available_parallelism().map_or(1, |n| n.get().min(4))
+                }),
+            verify_threads: env("PAPER_ENGINE_VERIFY_THREADS")
+                .filter(|n| *n > 0)
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+            verify_batch: env("PAPER_ENGINE_VERIFY_BATCH").filter(|n| *n > 0).unwrap_or(1000),
+            intern_gc_entries: env("PAPER_ENGINE_INTERN_GC_ENTRIES").filter(|n| *n > 0),
+            store_text: env_or("PAPER_ENGINE_STORE_TEXT", false),
+            max_terms_per_document: env("PAPER_ENGINE_MAX_TERMS_PER_DOCUMENT")
+                .filter(|n| *n > 0),
+            min_doc_tf: env("PAPER_ENGINE_MIN_DOC_TF").filter(|n| *n > 0).unwrap_or(1),
+            thumbnail_dpi: env("PAPER_ENGINE_THUMBNAIL_DPI")
+                .filter(|n| (1..=crate::thumbnail::MAX_DPI).contains(n))
+                .unwrap_or(72),
+            thumbnail_max_px: env("PAPER_ENGINE_THUMBNAIL_MAX_PX")
+                .filter(|n| *n > 0)
+                .unwrap_or(1024),
+            thumbnail_cache_bytes: env_bytes("PAPER_ENGINE_THUMBNAIL_CACHE_MB", 64, 1024 * 1024),
+            pdf_pool_size: env_or("PAPER_ENGINE_PDF_POOL_SIZE", 8),
+            trim_repeated_lines: env_or("PAPER_ENGINE_TRIM_REPEATED_LINES", false),
+            repeated_line_share: env("PAPER_ENGINE_REPEATED_LINE_SHARE")
+                .filter(|v: &f64| *v > 0.0 && *v <= 1.0)
+                .unwrap_or(0.5),
+            scanned_chars_per_page: env_or("PAPER_ENGINE_SCANNED_CHARS_PER_PAGE", 200),
+            scanned_empty_page_share: env("PAPER_ENGINE_SCANNED_EMPTY_PAGE_SHARE")
+                .filter(|v: &f64| *v > 0.0 && *v <= 1.0)
+                .unwrap_or(0.5),
+            score_decimals: env("PAPER_ENGINE_SCORE_DECIMALS")
+                .filter(|n| (0..=15).contains(n))
+                .unwrap_or(4),
+            shutdown_grace: Duration::from_secs(env_or("PAPER_ENGINE_SHUTDOWN_GRACE_SECS", 30)),
+            log_redaction: env_or("PAPER_ENGINE_LOG_REDACTION", Redaction::None),
+            load_check: env_or("PAPER_ENGINE_LOAD_CHECK", LoadCheck::Repair),
+        }
+    }
+}
+
+/// Reads and parses `name`; a value that doesn't parse is reported and ignored
+fn env<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: Display,
+{
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            tracing::warn!("Ignoring {name}={value:?}: {e}");
+            None
+        }
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: Display,
+{
+    env(name).unwrap_or(default)
+}
+
+/// `name`, counted in `unit`s of bytes, as bytes; a value too large to count
+/// is reported and ignored like one that doesn't parse
+fn env_bytes<T>(name: &str, default: usize, unit: usize) -> T
+where
+    T: TryFrom<usize>,
+{
+    let bytes = env(name).and_then(|n: usize| {
+        let bytes = n.checked_mul(unit).and_then(|bytes| T::try_from(bytes).ok());
+        if bytes.is_none() {
+            tracing::warn!("Ignoring {name}={n}: too large to count in bytes");
+        }
+        bytes
+    });
+    // The defaults are small enough for any `T` it's used with
+    bytes.unwrap_or_else(|| T::try_from(default * unit).ok().unwrap())
+}
+
+/// `ABBREVIATION: expansion` lines from the file named by `name`. Blank
+/// lines and `#` comments are skipped; other lines that don't fit, and a file
+/// that can't be read, are reported and ignored.
+fn abbreviations(name: &str) -> HashMap<String, String> {
+    let Ok(path) = std::env::var(name) else {
+        return HashMap::new();
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::warn!("Ignoring {name}={path:?}: {e}");
+            return HashMap::new();
+        }
+    };
+    let mut map = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':').map(|(short, long)| (short.trim(), long.trim())) {
+            Some((short, long))
+                if !short.is_empty() && !short.contains(char::is_whitespace) && !long.is_empty() =>
+            {
+                map.insert(short.to_owned(), long.to_owned());
+            }
+            _ => tracing::warn!(
+                "Ignoring line {} of {path:?}: expected `ABBREVIATION: expansion`",
+                n + 1
+            ),
+        }
+    }
+    map
+}
+
+/// `word` or `word: stem` lines from the file named by `name`; a word alone
+/// is its own stem. Blank lines and `#` comments are skipped; other lines
+/// that don't fit, and a file that can't be read, are reported and ignored.
+fn stem_exceptions(name: &str) -> HashMap<String, String> {
+    let Ok(path) = std::env::var(name) else {
+        return HashMap::new();
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::warn!("Ignoring {name}={path:?}: {e}");
+            return HashMap::new();
+        }
+    };
+    let mut map = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (word, stem) = line
+            .split_once(':')
+            .map_or((line, line), |(word, stem)| (word.trim(), stem.trim()));
+        let one_word = |s: &str| !s.is_empty() && !s.contains(char::is_whitespace);
+        if !one_word(word) || !one_word(stem) {
+            tracing::warn!("Ignoring line {} of {path:?}: expected `word` or `word: stem`", n + 1);
+            continue;
+        }
+        map.insert(word.to_owned(), stem.to_owned());
+    }
+    map
+}
+
+/// Comma-separated title sources; unknown names are skipped, and the default
+/// chain is kept if none are left
+fn title_chain(name: &str) -> Vec<TitleSource> {
+    let default = vec![
+        TitleSource::Metadata,
+        TitleSource::Heading,
+        TitleSource::LargestText,
+        TitleSource::Filename,
+    ];
+    let Ok(value) = std::env::var(name) else {
+        return default;
+    };
+    let chain = value
+        .split(',')
+        .map(str::trim)
+        .filter(|source| !source.is_empty())
+        .filter_map(|source| match source.parse() {
+            Ok(source) => Some(source),
+            Err(e) => {
+                tracing::warn!("Ignoring {source:?} in {name}: {e}");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    match chain.is_empty() {
+        true => default,
+        false => chain,
+    }
+}
+
+/// A comma-separated list of collection names; invalid names are reported
+/// and ignored
+fn collection_names(name: &str) -> Vec<String> {
+    let Ok(value) = std::env::var(name) else {
+        return vec![];
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .filter(|n| {
+            let valid = collection::valid_name(n);
+            if !valid {
+                tracing::warn!(
+                    "Ignoring collection {n:?} in {name}: use letters, digits, `-` and `_`"
+                );
+            }
+            valid
+        })
+        .map(str::to_owned)
+        .collect()
+}
@@ -0,0 +1,793 @@
+use axum::Json;
+use serde_derive::Serialize;
+
+// `GET /api` describes every route so clients can find their way without
+// reading the router. The table is kept by hand next to `api_routes`: a route
+// added there belongs here too, in the same order.
+//
+// Paths are relative to `/api`, and each collection serves the same routes
+// under `/api/collection/{name}`. Defaults that come from the environment are
+// given as the variable's name.
+
+#[derive(Debug, Serialize)]
+pub struct Param {
+    name: &'static str,
+    /// `string`, `integer`, `number`, `boolean` or `list` (comma-separated)
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<&'static str>,
+    description: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Route {
+    method: &'static str,
+    path: &'static str,
+    params: &'static [Param],
+    description: &'static str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    admin: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiDescription {
+    name: &'static str,
+    version: &'static str,
+    routes: &'static [Route],
+}
+
+const fn param(
+    name: &'static str,
+    kind: &'static str,
+    default: Option<&'static str>,
+    description: &'static str,
+) -> Param {
+    Param {
+        name,
+        kind,
+        default,
+        description,
+    }
+}
+
+const fn route(
+    method: &'static str,
+    path: &'static str,
+    params: &'static [Param],
+    description: &'static str,
+) -> Route {
+    Route {
+        method,
+        path,
+        params,
+        description,
+        admin: false,
+    }
+}
+
+const fn admin(
+    method: &'static str,
+    path: &'static str,
+    params: &'static [Param],
+    description: &'static str,
+) -> Route {
+    Route {
+        admin: true,
+        ..route(method, path, params, description)
+    }
+}
+
+const FORMAT: Param = param(
+    "format",
+    "string",
+    Some("json"),
+    "`json` or `jsonapi`; overrides `Accept`",
+);
+const OFFSET: Param = param("offset", "integer", Some("0"), "Results to skip");
+const FAVORITE: Param = param("favorite", "boolean", None, "Only favorites, or only not");
+const READ: Param = param(
+    "read",
+    "boolean",
+    None,
+    "Only read documents, or only unread",
+);
+const TAGGED: Param = param(
+    "tagged",
+    "boolean",
+    None,
+    "Only tagged documents, or only untagged",
+);
+const PATH: Param = param(
+    "path",
+    "string",
+    None,
+    "Only documents whose path contains this",
+);
+const IDS: Param = param("ids", "list", None, "Only these document ids");
+const TOGGLE: Param = param("value", "boolean", None, "Set to this; omitted, toggle");
+const PASSWORD: Param = param("password", "string", None, "Password of an encrypted PDF");
+const K: Param = param("k", "integer", Some("10"), "Number of documents");
+
+const SUBMIT: &[Param] = &[
+    param("path", "string", None, "File to index; required"),
+    param(
+        "dupe",
+        "string",
+        None,
+        "`replace`, `rename` or `ignore` a title already indexed",
+    ),
+    param(
+        "abstract_pages",
+        "integer",
+        Some("PAPER_ENGINE_ABSTRACT_PAGES"),
+        "Leading pages indexed as the abstract",
+    ),
+    param(
+        "page_start",
+        "integer",
+        None,
+        "First page to index, 1-based",
+    ),
+    param("page_end", "integer", None, "Last page to index, inclusive"),
+    param(
+        "font_weighting",
+        "boolean",
+        Some("PAPER_ENGINE_FONT_WEIGHTING"),
+        "Weight heading words more",
+    ),
+    param(
+        "index_title",
+        "boolean",
+        Some("PAPER_ENGINE_INDEX_TITLE"),
+        "Search the title and file name as body text",
+    ),
+    param(
+        "annotations",
+        "boolean",
+        Some("false"),
+        "Index PDF highlights and notes",
+    ),
+    param(
+        "store_text",
+        "boolean",
+        Some("PAPER_ENGINE_STORE_TEXT"),
+        "Keep the text for `/document/{id}/text`",
+    ),
+    PASSWORD,
+    param(
+        "min_doc_tf",
+        "integer",
+        Some("PAPER_ENGINE_MIN_DOC_TF"),
+        "Drop terms seen fewer times in the document",
+    ),
+    param(
+        "trim_repeated",
+        "boolean",
+        Some("PAPER_ENGINE_TRIM_REPEATED_LINES"),
+        "Leave out running headers and footers",
+    ),
+];
+
+const SEARCH: &[Param] = &[
+    param(
+        "s",
+        "string",
+        None,
+        "The query; required unless `path` is given",
+    ),
+    param(
+        "op",
+        "string",
+        Some("PAPER_ENGINE_DEFAULT_OPERATOR"),
+        "`and` or `or`: how words side by side are joined",
+    ),
+    param(
+        "sort",
+        "string",
+        Some("score"),
+        "`score`, `title` or `token_count`",
+    ),
+    param("order", "string", Some("desc"), "`asc` or `desc`"),
+    FAVORITE,
+    READ,
+    TAGGED,
+    PATH,
+    IDS,
+    param(
+        "strict_ids",
+        "boolean",
+        Some("false"),
+        "Reject `ids` no document has instead of ignoring them",
+    ),
+    param(
+        "collapse",
+        "string",
+        None,
+        "`path` keeps the best result per file",
+    ),
+    param(
+        "dedupe_similarity",
+        "number",
+        None,
+        "Collapse results at least this similar",
+    ),
+    param("fields", "list", None, "Fields to score, as `field:boost`"),
+    param("fields[documents]", "list", None, "Result fields to return"),
+    param("explain", "boolean", Some("false"), "Break each score down"),
+    param(
+        "exact",
+        "boolean",
+        Some("false"),
+        "Match spellings rather than stems",
+    ),
+    param(
+        "combine",
+        "string",
+        Some("sum"),
+        "`sum`, `max` or `mean` of term scores",
+    ),
+    param(
+        "query_norm",
+        "string",
+        Some("PAPER_ENGINE_QUERY_NORM"),
+        "`none`, `count` or `sqrt`: what a `sum` is divided by for the query's length",
+    ),
+    param(
+        "expand",
+        "boolean",
+        Some("false"),
+        "Add terms from the top results and search again",
+    ),
+    param(
+        "expand_docs",
+        "integer",
+        Some("PAPER_ENGINE_EXPAND_DOCS"),
+        "How many top results `expand` takes terms from",
+    ),
+    param(
+        "expand_terms",
+        "integer",
+        Some("PAPER_ENGINE_EXPAND_TERMS"),
+        "How many terms `expand` adds",
+    ),
+    OFFSET,
+    param(
+        "limit",
+        "integer",
+        None,
+        "Results per page, up to PAPER_ENGINE_MAX_RESULTS",
+    ),
+    param(
+        "format",
+        "string",
+        Some("json"),
+        "`json`, `csv`, `jsonapi` or `atom`; overrides `Accept`",
+    ),
+    param(
+        "bare",
+        "boolean",
+        Some("false"),
+        "Return only the results, not the envelope",
+    ),
+    param(
+        "recency",
+        "boolean",
+        Some("false"),
+        "Favor recently indexed documents",
+    ),
+    param(
+        "coverage_first",
+        "boolean",
+        Some("false"),
+        "Rank documents matching more terms first",
+    ),
+    param(
+        "highlight",
+        "boolean",
+        Some("false"),
+        "Add `title_highlighted` to every result",
+    ),
+    param(
+        "normalize",
+        "boolean",
+        Some("false"),
+        "Add `normalized` scores in [0, 1]",
+    ),
+    param(
+        "model",
+        "string",
+        Some("tfidf"),
+        "Ranking model; only `tfidf` so far",
+    ),
+    param(
+        "idf_scope",
+        "string",
+        Some("global"),
+        "`global` or `filtered`",
+    ),
+    param(
+        "field_stats",
+        "boolean",
+        Some("false"),
+        "Count the matches in each field",
+    ),
+    param(
+        "histogram",
+        "boolean",
+        Some("false"),
+        "Count the scores of every match in buckets",
+    ),
+    param(
+        "buckets",
+        "integer",
+        Some("PAPER_ENGINE_HISTOGRAM_BUCKETS"),
+        "Buckets for `histogram`, up to 1000",
+    ),
+    param(
+        "min_score",
+        "integer",
+        Some("PAPER_ENGINE_MIN_SCORE"),
+        "Drop results scoring less",
+    ),
+    param(
+        "position_weight",
+        "boolean",
+        Some("false"),
+        "Favor terms early in the document",
+    ),
+    param(
+        "include_excluded",
+        "boolean",
+        Some("false"),
+        "Include documents excluded from the corpus",
+    ),
+    param(
+        "knownitem",
+        "boolean",
+        Some("false"),
+        "Rank documents whose title has every word first",
+    ),
+];
+
+const LIST_SORT: Param = param(
+    "sort",
+    "string",
+    Some("title"),
+    "`title`, `word_count`, `unique_terms`, `average_word_length` or `reading_ease`",
+);
+
+const LIST_ORDER: Param = param(
+    "order",
+    "string",
+    None,
+    "`asc` or `desc`; `asc` for `title`, `desc` otherwise",
+);
+
+const LIST: &[Param] = &[
+    LIST_SORT,
+    LIST_ORDER,
+    FAVORITE,
+    READ,
+    TAGGED,
+    PATH,
+    IDS,
+    OFFSET,
+    param("limit", "integer", None, "Documents per page"),
+    FORMAT,
+];
+
+const TERMS: &[Param] = &[param("terms", "list", None, "Words; required")];
+
+const ROUTES: &[Route] = &[
+    route("GET", "/", &[], "This description"),
+    route(
+        "POST",
+        "/analyze",
+        &[],
+        "Run the text body through the analyzer",
+    ),
+    route(
+        "GET",
+        "/document/submit",
+        SUBMIT,
+        "Index a PDF, text, Markdown or HTML file",
+    ),
+    route(
+        "GET",
+        "/document/submit_dir",
+        &[
+            param("path", "string", None, "Directory to index; required"),
+            param(
+                "concurrency",
+                "integer",
+                Some("PAPER_ENGINE_INGEST_CONCURRENCY"),
+                "Files indexed at a time",
+            ),
+            param(
+                "resume",
+                "boolean",
+                Some("true"),
+                "Skip files already indexed from the same path and bytes",
+            ),
+        ],
+        "Index every supported file under a directory, streaming progress events; \
+         also takes the `/document/submit` parameters",
+    ),
+    route(
+        "POST",
+        "/document/check_dupes",
+        &[PASSWORD],
+        "Preview which of the files in the JSON body would collide with indexed documents",
+    ),
+    route(
+        "POST",
+        "/document/upload",
+        &[],
+        "Index files from a multipart body; takes `dupe`, `abstract_pages`, `page_start`, \
+         `page_end` and `password`",
+    ),
+    route(
+        "GET",
+        "/document/search",
+        SEARCH,
+        "Rank documents for a query",
+    ),
+    route(
+        "GET",
+        "/document/search.atom",
+        SEARCH,
+        "`/document/search` as an Atom feed",
+    ),
+    route(
+        "POST",
+        "/document/search_batch",
+        SEARCH,
+        "Run the JSON array of queries in the body under one lock",
+    ),
+    route(
+        "GET",
+        "/document/validate_query",
+        &[
+            param("s", "string", None, "The query; required"),
+            param(
+                "op",
+                "string",
+                Some("PAPER_ENGINE_DEFAULT_OPERATOR"),
+                "`and` or `or`",
+            ),
+        ],
+        "Parse a query without searching",
+    ),
+    route(
+        "GET",
+        "/document/spellcheck",
+        &[
+            param("s", "string", None, "The query; required"),
+            param("limit", "integer", Some("5"), "Corrections per word, up to 20"),
+            param(
+                "op",
+                "string",
+                Some("PAPER_ENGINE_DEFAULT_OPERATOR"),
+                "`and` or `or`",
+            ),
+        ],
+        "Whether the index knows each word of a query, with likely corrections",
+    ),
+    route(
+        "GET",
+        "/document/similarity",
+        &[
+            param("a", "integer", None, "Document id; required"),
+            param("b", "integer", None, "Document id; required"),
+            param("k", "integer", Some("10"), "Shared terms to list"),
+        ],
+        "Tf-idf cosine similarity of two documents",
+    ),
+    route("GET", "/document/list", LIST, "List documents, by title or a statistic"),
+    route(
+        "GET",
+        "/document/untagged",
+        &[
+            LIST_SORT,
+            LIST_ORDER,
+            FAVORITE,
+            READ,
+            OFFSET,
+            param("limit", "integer", None, "Documents per page"),
+            FORMAT,
+        ],
+        "List documents without tags",
+    ),
+    admin(
+        "POST",
+        "/document/tag_by_query",
+        &[
+            param("s", "string", None, "The query; required"),
+            param("tag", "string", None, "Tag to add; required"),
+            param("limit", "integer", None, "Tag only this many of the best matches"),
+            param("dry_run", "boolean", Some("false"), "Report what would be tagged"),
+        ],
+        "Tag every document matching a search; also takes the `/document/search` \
+         parameters",
+    ),
+    route(
+        "GET",
+        "/document/random",
+        &[
+            param("count", "integer", Some("1"), "Documents to pick"),
+            param(
+                "seed",
+                "integer",
+                None,
+                "The same seed picks the same documents",
+            ),
+            param("tag", "string", None, "Only documents with this tag"),
+            FAVORITE,
+            READ,
+            TAGGED,
+            FORMAT,
+        ],
+        "Documents picked at random",
+    ),
+    route(
+        "GET",
+        "/document/{id}",
+        &[FORMAT],
+        "Document info, including flags",
+    ),
+    route(
+        "GET",
+        "/document/{id}/text",
+        &[
+            param("page", "integer", None, "Just this page, 1-based"),
+            param("password", "string", None, "Opens an encrypted PDF to extract it"),
+        ],
+        "The document's text as plain text; stored or extracted again",
+    ),
+    route("GET", "/document/{id}/outline", &[], "A PDF's bookmarks"),
+    route(
+        "GET",
+        "/document/{id}/thumbnail",
+        &[
+            param("page", "integer", Some("1"), "Page to render, 1-based"),
+            param(
+                "dpi",
+                "integer",
+                Some("PAPER_ENGINE_THUMBNAIL_DPI"),
+                "Resolution, up to 300",
+            ),
+            PASSWORD,
+        ],
+        "A PDF page as a PNG",
+    ),
+    route(
+        "POST",
+        "/document/{id}/favorite",
+        &[TOGGLE],
+        "Mark or unmark as favorite",
+    ),
+    route(
+        "POST",
+        "/document/{id}/read",
+        &[TOGGLE],
+        "Mark as read or unread",
+    ),
+    route(
+        "POST",
+        "/document/{id}/exclude_from_corpus",
+        &[TOGGLE],
+        "Keep the document out of IDF and search results",
+    ),
+    route(
+        "POST",
+        "/document/{id}/tags",
+        &[
+            param("add", "list", None, "Tags to add"),
+            param("remove", "list", None, "Tags to remove"),
+        ],
+        "Add and remove tags",
+    ),
+    route(
+        "POST",
+        "/document/{id}/notes",
+        &[],
+        "Replace the document's notes with the text body; an empty body clears them",
+    ),
+    route(
+        "GET",
+        "/document/{id}/siblings",
+        &[K],
+        "Documents sharing the most tags",
+    ),
+    route(
+        "GET",
+        "/document/{id}/similar_by_terms",
+        &[K],
+        "Most similar documents by tf-idf cosine",
+    ),
+    route(
+        "GET",
+        "/document/{id}/why",
+        SEARCH,
+        "How the document scores for a query, term by term",
+    ),
+    route(
+        "GET",
+        "/index/info",
+        &[],
+        "Index generation, document count and last save",
+    ),
+    admin(
+        "GET",
+        "/admin/cache",
+        &[],
+        "Download the live index in the cache file format",
+    ),
+    admin(
+        "POST",
+        "/admin/compact",
+        &[],
+        "Rewrite the cache file and truncate the write-ahead log, reporting their sizes",
+    ),
+    admin(
+        "POST",
+        "/admin/intern_gc",
+        &[],
+        "Free interned strings no collection uses, for every collection at once",
+    ),
+    admin(
+        "POST",
+        "/admin/prune",
+        &[param(
+            "min_count",
+            "integer",
+            None,
+            "Fewest corpus occurrences kept; required",
+        )],
+        "Remove rare terms from the whole corpus",
+    ),
+    admin(
+        "GET",
+        "/admin/vocabulary",
+        &[
+            param(
+                "min_count",
+                "integer",
+                Some("0"),
+                "Fewest corpus occurrences listed",
+            ),
+            param("format", "string", Some("tsv"), "`tsv`"),
+        ],
+        "Every term with its corpus count and document frequency",
+    ),
+    admin(
+        "POST",
+        "/admin/verify",
+        &[param(
+            "repair",
+            "boolean",
+            Some("false"),
+            "Rebuild what disagrees",
+        )],
+        "Check the derived indexes and counts against the documents",
+    ),
+    admin(
+        "POST",
+        "/admin/rebuild_indexes",
+        &[],
+        "Rebuild the derived indexes",
+    ),
+    admin("GET", "/admin/ranking", &[], "Current ranking weights"),
+    admin(
+        "PUT",
+        "/admin/ranking",
+        &[],
+        "Replace the ranking weights with the JSON body",
+    ),
+    admin(
+        "DELETE",
+        "/admin/ranking",
+        &[],
+        "Reset the ranking weights to their defaults",
+    ),
+    admin("GET", "/admin/blocklist", &[], "Blocked terms"),
+    admin(
+        "POST",
+        "/admin/blocklist",
+        TERMS,
+        "Block terms and purge them from every document",
+    ),
+    admin("DELETE", "/admin/blocklist", TERMS, "Unblock terms"),
+    route(
+        "GET",
+        "/stats",
+        &[],
+        "Document, vocabulary and cache counts",
+    ),
+    route("GET", "/stats/queries", &[], "Recent and popular searches"),
+    route(
+        "GET",
+        "/tags/diff",
+        &[
+            param("a", "string", None, "Tag; required"),
+            param("b", "string", None, "Tag; required"),
+            param("k", "integer", Some("20"), "Terms to list"),
+        ],
+        "Terms telling the documents of two tags apart",
+    ),
+    route(
+        "GET",
+        "/tags/{tag}/download",
+        &[],
+        "A zip of the files of every document with a tag",
+    ),
+    route(
+        "GET",
+        "/term/suggest",
+        &[
+            param("prefix", "string", None, "Start of the word; required"),
+            param("limit", "integer", Some("10"), "Suggestions to return"),
+            param(
+                "min_docs",
+                "integer",
+                Some("PAPER_ENGINE_SUGGEST_MIN_DOCS"),
+                "Fewest documents a word is in",
+            ),
+            param(
+                "max_share",
+                "number",
+                Some("PAPER_ENGINE_SUGGEST_MAX_DOC_SHARE"),
+                "Largest share of documents a word is in",
+            ),
+        ],
+        "Indexed words starting with a prefix",
+    ),
+    route(
+        "GET",
+        "/term/{term}/documents",
+        &[],
+        "Every document containing a word",
+    ),
+    route(
+        "GET",
+        "/term/{term}/cooccurring",
+        &[
+            param("limit", "integer", Some("20"), "Terms to list, up to 100"),
+            param(
+                "min_docs",
+                "integer",
+                Some("PAPER_ENGINE_SUGGEST_MIN_DOCS"),
+                "Fewest documents a term is in",
+            ),
+            param(
+                "max_share",
+                "number",
+                Some("PAPER_ENGINE_SUGGEST_MAX_DOC_SHARE"),
+                "Largest share of documents a term is in",
+            ),
+        ],
+        "Terms most often found in the same documents as a word",
+    ),
+    route(
+        "GET",
+        "/term/{term}/top_documents",
+        &[OFFSET, param("limit", "integer", Some("20"), "Documents per page")],
+        "Documents ranked by how much of their text is a word",
+    ),
+    route(
+        "GET",
+        "/collections",
+        &[],
+        "Every collection with its document count",
+    ),
+];
+
+/// `GET /api`
+pub async fn describe() -> Json<ApiDescription> {
+    Json(ApiDescription {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        routes: ROUTES,
+    })
+}
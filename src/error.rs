@@ -0,0 +1,177 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_derive::Serialize;
+use std::sync::PoisonError;
+
+use crate::redact::{self, Redaction};
+
+/// Stable, machine-readable error codes.
+///
+/// These are part of the API; clients branch on them, so never rename a
+/// variant (serialized as `SCREAMING_SNAKE_CASE`), only add new ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// A required query parameter was not given
+    MissingParam,
+    /// A query parameter was given but its value is not understood
+    InvalidParam,
+    /// The submitted path does not point to a regular file
+    NotAFile,
+    /// Poppler could not open or parse the document
+    OpenFailed,
+    /// The document is encrypted and needs a password
+    Encrypted,
+    /// The document opened but has no words to index (e.g. a scanned PDF)
+    NoText,
+    /// A document with the same title is already indexed
+    DuplicateTitle,
+    /// The requested document does not exist
+    NotFound,
+    /// Admin route called without the right token (or none is configured)
+    Unauthorized,
+    /// The route can't answer in the `format` or `Accept` type asked for
+    NotAcceptable,
+    /// The request body is over the configured size limit
+    PayloadTooLarge,
+    /// The query string is over the configured length limit
+    UriTooLong,
+    /// The request body didn't arrive in time
+    Timeout,
+    /// The server shut down before the request finished
+    ShuttingDown,
+    /// The server can't do it while other requests are running; try later
+    Busy,
+    /// Something went wrong on the server (e.g. a poisoned lock)
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::MissingParam | Self::InvalidParam | Self::NotAFile => StatusCode::BAD_REQUEST,
+            Self::OpenFailed | Self::Encrypted | Self::NoText => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::DuplicateTitle => StatusCode::CONFLICT,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UriTooLong => StatusCode::URI_TOO_LONG,
+            Self::Timeout => StatusCode::REQUEST_TIMEOUT,
+            Self::ShuttingDown | Self::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Error returned by every handler; serialized as `{ "code": .., "message": .. }`
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    /// Logs the error as it is created so handlers don't have to. Messages
+    /// often quote a path or the request, so with log redaction on only the
+    /// code is logged.
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        let message = message.into();
+        if redact::at_least(Redaction::Paths) {
+            tracing::info!("{code:?}");
+        } else {
+            tracing::info!("{code:?}: {message}");
+        }
+        Self { code, message }
+    }
+
+    pub fn missing_param(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::MissingParam, message)
+    }
+
+    pub fn invalid_param(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidParam, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.code.status(), Json(self)).into_response()
+    }
+}
+
+/// Errors from the engine itself, outside of any request. Handlers turn them
+/// into an [`ApiError`] with `?`.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// Poppler failed to open or read a document
+    Poppler(String),
+    /// Bytes that aren't in the format we expected (cache file, text encoding)
+    Format(String),
+    /// A thread panicked while holding the index lock
+    LockPoisoned(String),
+    BadRequest(String),
+    NotFound(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {e}"),
+            Self::Poppler(msg) => write!(f, "Poppler error: {msg}"),
+            Self::Format(msg) => write!(f, "Format error: {msg}"),
+            Self::LockPoisoned(msg) => write!(f, "Lock poisoned: {msg}"),
+            Self::BadRequest(msg) => write!(f, "Bad request: {msg}"),
+            Self::NotFound(msg) => write!(f, "Not found: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(e: PoisonError<T>) -> Self {
+        Self::LockPoisoned(e.to_string())
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        let code = match &e {
+            Error::Io(_) | Error::Format(_) | Error::LockPoisoned(_) => ErrorCode::Internal,
+            Error::Poppler(_) => ErrorCode::OpenFailed,
+            Error::BadRequest(_) => ErrorCode::InvalidParam,
+            Error::NotFound(_) => ErrorCode::NotFound,
+        };
+        Self::new(code, e.to_string())
+    }
+}
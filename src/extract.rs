@@ -0,0 +1,752 @@
+use poppler::PopplerDocument;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::analyzer::{self, Token};
+use crate::config::CONFIG;
+use crate::error::{self, ApiError, Error, ErrorCode};
+use crate::pdf::{drop_page, drop_pdf};
+use crate::readability::TextStats;
+use crate::title::{self, TitleSource};
+use crate::{bool_param, html, math, parse_param, pdf, Term};
+
+/// Text pulled out of a document, ready to be merged into `TfIdf`
+#[derive(Debug, Default)]
+pub struct Extraction {
+    pub title: Option<String>,
+    /// Where `title` came from, when there is one
+    pub title_source: Option<TitleSource>,
+    pub term_count: HashMap<Term, usize>,
+    /// Counts over the first `abstract_pages` pages only
+    pub abstract_count: HashMap<Term, usize>,
+    /// Stem => surface form => count
+    pub surface_count: HashMap<Term, HashMap<Term, usize>>,
+    /// Occurrences in heading-sized text, already counted in `term_count`
+    /// too; only filled with `font_weighting`
+    pub heading_count: HashMap<Term, usize>,
+    /// Counts over PDF annotations; only filled with `annotations`
+    pub annotation_count: HashMap<Term, usize>,
+    /// Zero-based page each stem first appears on, for paged documents
+    pub first_page: HashMap<Term, u32>,
+    /// 1-based inclusive page range, when only part of the document was indexed
+    pub page_range: Option<(u32, u32)>,
+    /// The indexed text itself, pages separated by form feeds; only kept
+    /// with `store_text`, or for an encrypted PDF
+    pub text: Option<String>,
+    /// The PDF needed a password to open
+    pub encrypted: bool,
+    /// The PDF's bookmarks, if it has any
+    pub outline: Vec<pdf::OutlineEntry>,
+    /// `content_hash` of the file's bytes
+    pub content_hash: Option<u64>,
+    /// Running header and footer lines left out, with `trim_repeated`
+    pub trimmed_lines: usize,
+    /// A PDF whose pages look like images with little or no text; see
+    /// `likely_scanned`
+    pub likely_scanned: bool,
+    /// Counted over the body text, not the title or annotations
+    pub text_stats: TextStats,
+}
+
+impl Extraction {
+    fn add_token(&mut self, Token { surface, stem }: Token) {
+        self.term_count
+            .entry(stem)
+            .and_modify(|v| *v += 1)
+            .or_insert(1);
+        *self
+            .surface_count
+            .entry(stem)
+            .or_insert_with(HashMap::new)
+            .entry(surface)
+            .or_insert(0) += 1;
+    }
+
+    fn add_page(&mut self, page_idx: usize, text: &str, options: &ExtractOptions) {
+        if let Some(stored) = &mut self.text {
+            if !stored.is_empty() {
+                stored.push('\x0C');
+            }
+        }
+        self.add_text_on_page(text, page_idx < options.abstract_pages, Some(page_idx as u32));
+    }
+
+    /// Counts `text` in the body, and in the abstract too if `in_abstract`
+    fn add_text(&mut self, text: &str, in_abstract: bool) {
+        self.add_text_on_page(text, in_abstract, None);
+    }
+
+    /// Pages come in order, so the first page recorded for a stem is the
+    /// earliest
+    fn add_text_on_page(&mut self, text: &str, in_abstract: bool, page: Option<u32>) {
+        if let Some(stored) = &mut self.text {
+            if !stored.is_empty() && !stored.ends_with(char::is_whitespace) {
+                stored.push('\n');
+            }
+            stored.push_str(text);
+        }
+        self.text_stats.add(text);
+        for token in analyzer::tokenize(text) {
+            self.add_token(token);
+            if let Some(page) = page {
+                self.first_page.entry(token.stem).or_insert(page);
+            }
+            if in_abstract {
+                self.abstract_count
+                    .entry(token.stem)
+                    .and_modify(|v| *v += 1)
+                    .or_insert(1);
+            }
+        }
+    }
+
+    /// Counts the title and the file name (split on `_`, `-` and `.`) as body
+    /// text, so words that only appear there still find the document
+    pub fn add_title_terms(&mut self, path: &str) {
+        let file_name = Path::new(path)
+            .file_stem()
+            .map(|name| name.to_string_lossy().replace(['_', '-', '.'], " "))
+            .unwrap_or_default();
+        let text = format!("{} {file_name}", self.title.as_deref().unwrap_or(""));
+        for token in analyzer::tokenize(&text) {
+            self.add_token(token);
+        }
+    }
+
+    /// Counts the formulas of the document's LaTeX source as body text, with
+    /// `PAPER_ENGINE_MATH`; see `math::tex_math`
+    fn add_tex_source(&mut self, source: &str) {
+        for token in analyzer::tokenize(&math::tex_math(source)) {
+            self.add_token(token);
+        }
+    }
+
+    /// Drops stems seen fewer than `min` times from every count; returns how
+    /// many were dropped
+    pub fn drop_rare_terms(&mut self, min: usize) -> usize {
+        let rare = self
+            .term_count
+            .iter()
+            .filter(|(_, n)| **n < min)
+            .map(|(term, _)| *term)
+            .collect::<Vec<_>>();
+        for term in &rare {
+            self.remove_term(term);
+        }
+        rare.len()
+    }
+
+    /// Drops `terms` from every count; returns how many the document had
+    pub fn drop_terms(&mut self, terms: &HashSet<Term>) -> usize {
+        let found = self
+            .term_count
+            .keys()
+            .filter(|term| terms.contains(term))
+            .copied()
+            .collect::<Vec<_>>();
+        for term in &found {
+            self.remove_term(term);
+        }
+        found.len()
+    }
+
+    fn remove_term(&mut self, term: &Term) {
+        self.term_count.remove(term);
+        self.abstract_count.remove(term);
+        self.surface_count.remove(term);
+        self.heading_count.remove(term);
+        self.annotation_count.remove(term);
+        self.first_page.remove(term);
+    }
+
+    /// Keeps only the `max` most frequent stems, dropping the long tail from
+    /// every count; returns whether anything was dropped. Ties go to the
+    /// alphabetically first stem, so the same document always keeps the same
+    /// terms.
+    pub fn cap_terms(&mut self, max: usize) -> bool {
+        if self.term_count.len() <= max {
+            return false;
+        }
+        let mut by_count = self.term_count.iter().map(|(t, n)| (*t, *n)).collect::<Vec<_>>();
+        by_count.sort_unstable_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+        for (term, _) in &by_count[max..] {
+            self.remove_term(term);
+        }
+        true
+    }
+}
+
+impl Extraction {
+    fn add_headings(&mut self, text: &str) {
+        for Token { stem, .. } in analyzer::tokenize(text) {
+            *self.heading_count.entry(stem).or_insert(0) += 1;
+        }
+    }
+
+    /// Counts everything annotated in the annotations field. Marked text is
+    /// already in the body as page text; notes aren't, so they're added there
+    /// too.
+    fn add_annotations(&mut self, annotations: &pdf::Annotations) {
+        for token in analyzer::tokenize(&annotations.notes) {
+            self.add_token(token);
+            *self.annotation_count.entry(token.stem).or_insert(0) += 1;
+        }
+        for Token { stem, .. } in analyzer::tokenize(&annotations.marked) {
+            *self.annotation_count.entry(stem).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Fewest pages a document needs before lines repeating across them are
+/// taken for running headers and footers
+const MIN_PAGES_FOR_REPEATS: usize = 3;
+
+/// Non-blank lines at each end of a page that may be a header or footer
+const EDGE_LINES: usize = 3;
+
+/// A line with its digits masked, so "Page 3" and "Page 4" are the same
+/// footer
+fn line_key(line: &str) -> String {
+    line.split_whitespace()
+        .map(|word| word.replace(|c: char| c.is_ascii_digit(), "#"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Indices of the first and last `EDGE_LINES` non-blank lines
+fn edge_lines(lines: &[&str]) -> HashSet<usize> {
+    let non_blank = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    non_blank
+        .iter()
+        .take(EDGE_LINES)
+        .chain(non_blank.iter().rev().take(EDGE_LINES))
+        .copied()
+        .collect()
+}
+
+/// Drops running headers and footers (a journal's name, page numbers) from
+/// `pages`: lines at the top or bottom of at least
+/// `PAPER_ENGINE_REPEATED_LINE_SHARE` of them, and of at least two. Returns
+/// how many lines were dropped.
+fn trim_running_lines(pages: &mut [String]) -> usize {
+    if pages.len() < MIN_PAGES_FOR_REPEATS {
+        return 0;
+    }
+    let mut counts = HashMap::<String, usize>::new();
+    for page in pages.iter() {
+        let lines = page.lines().collect::<Vec<_>>();
+        let keys = edge_lines(&lines)
+            .into_iter()
+            .map(|i| line_key(lines[i]))
+            .collect::<HashSet<_>>();
+        for key in keys {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+    let min = ((pages.len() as f64 * CONFIG.repeated_line_share).ceil() as usize).max(2);
+    counts.retain(|_, n| *n >= min);
+    if counts.is_empty() {
+        return 0;
+    }
+
+    let mut trimmed = 0;
+    for page in pages.iter_mut() {
+        let lines = page.lines().collect::<Vec<_>>();
+        let edges = edge_lines(&lines);
+        let kept = lines
+            .iter()
+            .enumerate()
+            .filter(|(i, line)| !(edges.contains(i) && counts.contains_key(&line_key(line))))
+            .map(|(_, line)| *line)
+            .collect::<Vec<_>>();
+        if kept.len() < lines.len() {
+            trimmed += lines.len() - kept.len();
+            *page = kept.join("\n");
+        }
+    }
+    trimmed
+}
+
+/// Per-submit knobs for extraction
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    abstract_pages: usize,
+    /// 1-based and inclusive, like page numbers in a viewer
+    page_start: Option<usize>,
+    page_end: Option<usize>,
+    /// Weight words by font size; PDFs only
+    font_weighting: bool,
+    /// Index highlights and notes as the annotations field; PDFs only
+    annotations: bool,
+    /// Also index the title and file name as body text
+    pub index_title: bool,
+    /// Keep the extracted text on the document
+    store_text: bool,
+    /// Leave out lines repeated across most pages; see `trim_running_lines`
+    trim_repeated: bool,
+    /// Opens encrypted PDFs. Only used while extracting, never stored.
+    password: Option<String>,
+}
+
+impl ExtractOptions {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        Ok(Self {
+            abstract_pages: parse_param(params, "abstract_pages")?.unwrap_or(CONFIG.abstract_pages),
+            page_start: parse_param(params, "page_start")?,
+            page_end: parse_param(params, "page_end")?,
+            font_weighting: bool_param(params, "font_weighting")?
+                .unwrap_or(CONFIG.font_weighting),
+            annotations: bool_param(params, "annotations")?.unwrap_or(false),
+            index_title: bool_param(params, "index_title")?.unwrap_or(CONFIG.index_title),
+            store_text: bool_param(params, "store_text")?.unwrap_or(CONFIG.store_text),
+            trim_repeated: bool_param(params, "trim_repeated")?
+                .unwrap_or(CONFIG.trim_repeated_lines),
+            password: params.get("password").cloned(),
+        })
+    }
+
+    /// Options that only keep the text, for re-extracting a document to read
+    /// it; `page` (1-based) limits it to that page
+    pub fn text_only(page: Option<usize>, password: Option<String>) -> Self {
+        Self {
+            abstract_pages: CONFIG.abstract_pages,
+            page_start: page,
+            page_end: page,
+            font_weighting: false,
+            annotations: false,
+            index_title: false,
+            store_text: true,
+            trim_repeated: CONFIG.trim_repeated_lines,
+            password,
+        }
+    }
+
+    /// Zero-based, half-open page indices to index out of `n_pages`, and the
+    /// range to record on the document if it isn't the whole thing
+    fn pages(
+        &self,
+        path: &str,
+        n_pages: usize,
+    ) -> Result<(Range<usize>, Option<(u32, u32)>), ApiError> {
+        let start = self.page_start.unwrap_or(1);
+        let end = self.page_end.unwrap_or(n_pages);
+        if start == 0 || start > end || end > n_pages {
+            return Err(ApiError::invalid_param(format!(
+                "Invalid page range {start}..={end}; {path:?} has pages 1..={n_pages}"
+            )));
+        }
+        let page_range = (self.page_start.is_some() || self.page_end.is_some())
+            .then_some((start as u32, end as u32));
+        Ok((start - 1..end, page_range))
+    }
+}
+
+/// Opens and tokenizes the document at `path`, picking the extractor from
+/// its extension.
+///
+/// This is slow for big documents and blocks, so it must run on
+/// `spawn_blocking` rather than directly in a handler.
+pub fn extract(path: &str, options: ExtractOptions) -> Result<Extraction, ApiError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let mut extraction = match extension.as_deref() {
+        Some("txt" | "md" | "markdown") => extract_text(path, options),
+        Some("html" | "htm") => extract_html(path, options),
+        // Anything else gets a chance as a PDF, like before extensions mattered
+        _ => extract_pdf(path, options),
+    }?;
+    extraction.content_hash = std::fs::read(path).ok().map(|bytes| content_hash(&bytes));
+    if let Some(source) = CONFIG.math.then(|| math::sidecar(path)).flatten() {
+        extraction.add_tex_source(&source);
+    }
+    Ok(extraction)
+}
+
+/// FNV-1a over a file's bytes, to recognize the same file submitted again
+/// under another title or path
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The title `extract` would find in the file at `path`, without indexing
+/// anything; `None` means it would fall back to `title_from_path`
+pub fn peek_title(path: &str, password: Option<&str>) -> Result<Option<String>, ApiError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let read = || {
+        let bytes = std::fs::read(path).map_err(|e| {
+            ApiError::new(ErrorCode::OpenFailed, format!("Could not read file: {path:?}: {e}"))
+        })?;
+        decode_text(&bytes).map_err(|e| {
+            ApiError::new(ErrorCode::OpenFailed, format!("Could not decode {path:?}: {e}"))
+        })
+    };
+    match extension.as_deref() {
+        Some("txt" | "md" | "markdown") => Ok(markdown_title(&read()?)),
+        Some("html" | "htm") => Ok(html::parse(&read()?).title),
+        _ => {
+            let (pdf, _) = pdf::open_pdf(path, password)?;
+            let title = pdf_title(&pdf).map(|(title, _)| title);
+            drop_pdf(pdf);
+            Ok(title)
+        }
+    }
+}
+
+/// The first plausible title along `PAPER_ENGINE_TITLE_CHAIN`, and where it
+/// came from; `None` leaves it to `title_from_path`. The first page is only
+/// read when the metadata won't do.
+fn pdf_title(pdf: &PopplerDocument) -> Option<(String, TitleSource)> {
+    let mut page_titles = None;
+    let mut first_page = || page_titles.get_or_insert_with(|| pdf::first_page_titles(pdf)).clone();
+    title::choose(|source| match source {
+        TitleSource::Metadata => pdf.get_title(),
+        TitleSource::Heading => first_page().0,
+        TitleSource::LargestText => first_page().1,
+        TitleSource::Filename => None,
+    })
+}
+
+/// A readable title for a file without one, from its name:
+/// `papers/my_great-paper.pdf` is "My Great Paper". Words keep the rest of
+/// their case, so acronyms survive. Uploads (`upload:{name}`) use the name.
+pub fn title_from_path(path: &str) -> String {
+    let path = path.strip_prefix("upload:").unwrap_or(path);
+    let stem = Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().replace(['_', '-'], " "))
+        .unwrap_or_default();
+    let words = stem
+        .split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>();
+    match words.is_empty() {
+        true => path.to_owned(),
+        false => words.join(" "),
+    }
+}
+
+/// A first line of `# Title`, as Markdown headings and plain text notes
+/// often start
+fn markdown_title(text: &str) -> Option<String> {
+    text.lines()
+        .next()
+        .and_then(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_owned())
+        .filter(|title| !title.is_empty())
+}
+
+/// Like [`extract`], but for a file that only exists in memory (an upload).
+/// `name` is its file name, used for the extension and in errors.
+pub fn extract_bytes(
+    name: &str,
+    mut data: Vec<u8>,
+    options: ExtractOptions,
+) -> Result<Extraction, ApiError> {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let hash = content_hash(&data);
+    let mut extraction = match extension.as_deref() {
+        Some("txt" | "md" | "markdown") => extract_text_bytes(name, &data, options),
+        Some("html" | "htm") => extract_html_bytes(name, &data, options),
+        _ => {
+            let (pdf, encrypted) =
+                pdf::open_pdf_data(name, &mut data, options.password.as_deref())?;
+            let extraction = extract_pdf_document(pdf, encrypted, name, options);
+            // Poppler reads from `data` until the document is dropped
+            drop(data);
+            extraction
+        }
+    }?;
+    extraction.content_hash = Some(hash);
+    Ok(extraction)
+}
+
+fn extract_pdf(path: &str, options: ExtractOptions) -> Result<Extraction, ApiError> {
+    let (pdf, encrypted) = pdf::open_pdf(path, options.password.as_deref())?;
+    extract_pdf_document(pdf, encrypted, path, options)
+}
+
+/// Extracts and then drops `pdf`; `path` is only used in errors.
+///
+/// The text of an `encrypted` document is always kept: the password isn't,
+/// so the stored text is the only way back to it.
+fn extract_pdf_document(
+    pdf: PopplerDocument,
+    encrypted: bool,
+    path: &str,
+    options: ExtractOptions,
+) -> Result<Extraction, ApiError> {
+    let (pages, page_range) = match options.pages(path, pdf.get_n_pages()) {
+        Ok(pages) => pages,
+        Err(e) => {
+            drop_pdf(pdf);
+            return Err(e);
+        }
+    };
+
+    let (title, title_source) = pdf_title(&pdf).unzip();
+    let mut extraction = Extraction {
+        title,
+        title_source,
+        page_range,
+        text: (options.store_text || encrypted).then(String::new),
+        encrypted,
+        outline: pdf::outline(&pdf),
+        ..Extraction::default()
+    };
+    // Pages are counted as they're read and then dropped, so page text is
+    // never held for the whole document; except with `trim_repeated`, which
+    // reads every page before counting any, so running headers and footers
+    // can be told from the text around them
+    let mut indices = vec![];
+    let mut texts = vec![];
+    let mut headings = vec![];
+    let mut annotations = vec![];
+    // Non-blank characters on each page, text or not, before any trimming
+    let mut page_chars = vec![];
+    for page_idx in pages {
+        let Some(page) = pdf.get_page(page_idx) else {
+            continue;
+        };
+        let text = page.get_text();
+        page_chars.push(text.map_or(0, |text| {
+            text.chars().filter(|c| !c.is_whitespace()).count()
+        }));
+        if let Some(text) = text {
+            let joined = pdf::join_hyphenated(text);
+            let page_headings = options
+                .font_weighting
+                .then(|| pdf::heading_text(&page, text))
+                .flatten();
+            if options.trim_repeated {
+                indices.push(page_idx);
+                texts.push(joined);
+                headings.extend(page_headings);
+            } else {
+                extraction.add_page(page_idx, &joined, &options);
+                if let Some(page_headings) = page_headings {
+                    extraction.add_headings(&page_headings);
+                }
+            }
+        }
+        if options.annotations {
+            let page_annotations = pdf::annotations(&page);
+            if options.trim_repeated {
+                annotations.push(page_annotations);
+            } else {
+                extraction.add_annotations(&page_annotations);
+            }
+        }
+        drop_page(page);
+    }
+    drop_pdf(pdf);
+    extraction.likely_scanned = likely_scanned(&page_chars);
+
+    if options.trim_repeated {
+        extraction.trimmed_lines = trim_running_lines(&mut texts);
+        for (page_idx, text) in indices.into_iter().zip(&texts) {
+            extraction.add_page(page_idx, text, &options);
+        }
+        for text in &headings {
+            extraction.add_headings(text);
+        }
+        for page_annotations in &annotations {
+            extraction.add_annotations(page_annotations);
+        }
+    }
+
+    if extraction.term_count.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NoText,
+            format!("{path:?} has no extractable text; if it is scanned, try OCR first"),
+        ));
+    }
+    Ok(extraction)
+}
+
+/// Whether a PDF whose pages have `page_chars` non-blank characters each
+/// looks scanned: too few characters per page on average, or too many
+/// pages without any, going by `PAPER_ENGINE_SCANNED_CHARS_PER_PAGE` and
+/// `PAPER_ENGINE_SCANNED_EMPTY_PAGE_SHARE`
+fn likely_scanned(page_chars: &[usize]) -> bool {
+    if page_chars.is_empty() {
+        return false;
+    }
+    let pages = page_chars.len();
+    let empty = page_chars.iter().filter(|chars| **chars == 0).count();
+    page_chars.iter().sum::<usize>() < CONFIG.scanned_chars_per_page * pages
+        || empty as f64 >= CONFIG.scanned_empty_page_share * pages as f64
+}
+
+/// Plain text and Markdown. Form feeds separate pages, and a Markdown
+/// `# Heading` on the first line becomes the title.
+fn extract_text(path: &str, options: ExtractOptions) -> Result<Extraction, ApiError> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        ApiError::new(ErrorCode::OpenFailed, format!("Could not read file: {path:?}: {e}"))
+    })?;
+    extract_text_bytes(path, &bytes, options)
+}
+
+fn extract_text_bytes(
+    path: &str,
+    bytes: &[u8],
+    options: ExtractOptions,
+) -> Result<Extraction, ApiError> {
+    let text = decode_text(bytes).map_err(|e| {
+        ApiError::new(ErrorCode::OpenFailed, format!("Could not decode {path:?}: {e}"))
+    })?;
+
+    let all_pages = text.split('\x0C').collect::<Vec<_>>();
+    let (pages, page_range) = options.pages(path, all_pages.len())?;
+    let title = markdown_title(&text);
+    let mut extraction = Extraction {
+        title_source: title.is_some().then_some(TitleSource::Heading),
+        title,
+        page_range,
+        text: options.store_text.then(String::new),
+        ..Extraction::default()
+    };
+    let mut texts = all_pages[pages.clone()]
+        .iter()
+        .map(|page| page.to_string())
+        .collect::<Vec<_>>();
+    if options.trim_repeated {
+        extraction.trimmed_lines = trim_running_lines(&mut texts);
+    }
+    for (page_idx, text) in pages.zip(&texts) {
+        extraction.add_page(page_idx, text, &options);
+    }
+    if extraction.term_count.is_empty() {
+        return Err(ApiError::new(ErrorCode::NoText, format!("{path:?} has no words to index")));
+    }
+    Ok(extraction)
+}
+
+/// Web pages: the `<title>` becomes the title and the visible text the body,
+/// leaving out scripts and styles. The meta description and keywords, if
+/// any, are the abstract; otherwise the whole page is, like a one-page text
+/// file.
+fn extract_html(path: &str, options: ExtractOptions) -> Result<Extraction, ApiError> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        ApiError::new(ErrorCode::OpenFailed, format!("Could not read file: {path:?}: {e}"))
+    })?;
+    extract_html_bytes(path, &bytes, options)
+}
+
+fn extract_html_bytes(
+    path: &str,
+    bytes: &[u8],
+    options: ExtractOptions,
+) -> Result<Extraction, ApiError> {
+    let text = decode_text(bytes).map_err(|e| {
+        ApiError::new(ErrorCode::OpenFailed, format!("Could not decode {path:?}: {e}"))
+    })?;
+    let page = html::parse(&text);
+
+    // An HTML file counts as one page; this only rejects ranges past it
+    let (_, page_range) = options.pages(path, 1)?;
+    let mut extraction = Extraction {
+        title_source: page.title.is_some().then_some(TitleSource::Metadata),
+        title: page.title,
+        page_range,
+        text: options.store_text.then(String::new),
+        ..Extraction::default()
+    };
+    let in_abstract = options.abstract_pages > 0;
+    if page.summary.trim().is_empty() {
+        extraction.add_text(&page.body, in_abstract);
+    } else {
+        extraction.add_text(&page.summary, in_abstract);
+        extraction.add_text(&page.body, false);
+    }
+    if extraction.term_count.is_empty() {
+        return Err(ApiError::new(ErrorCode::NoText, format!("{path:?} has no words to index")));
+    }
+    Ok(extraction)
+}
+
+/// Decodes text files to UTF-8: a BOM (UTF-8 or UTF-16 LE/BE) decides the
+/// encoding if present, otherwise UTF-8 is tried before falling back to Latin-1.
+///
+/// Latin-1 accepts any byte sequence, so BOM-less UTF-16 and binary files
+/// (which contain NUL bytes, unlike real text) are rejected before that
+/// fallback rather than indexed as garbage.
+pub fn decode_text(bytes: &[u8]) -> error::Result<String> {
+    if let Some((encoding, bom_len)) = encoding_rs::Encoding::for_bom(bytes) {
+        let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        if had_errors {
+            return Err(Error::Format(format!(
+                "file has a {} BOM but is not valid {0}",
+                encoding.name()
+            )));
+        }
+        return Ok(text.into_owned());
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Ok(text.to_owned());
+    }
+    if bytes.contains(&0) {
+        return Err(Error::Format(
+            "not UTF-8 and contains NUL bytes; binary, or UTF-16 without a BOM".to_owned(),
+        ));
+    }
+    // encoding_rs (following WHATWG) decodes ISO-8859-1 as its superset windows-1252
+    let (text, _) = encoding_rs::WINDOWS_1252.decode_without_bom_handling(bytes);
+    Ok(text.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_from_path_reads_the_file_name() {
+        assert_eq!(title_from_path("path/to/my_great_paper.pdf"), "My Great Paper");
+        assert_eq!(title_from_path("papers/my_great-paper.pdf"), "My Great Paper");
+        assert_eq!(title_from_path("upload:intro_to_NLP.txt"), "Intro To NLP");
+    }
+
+    #[test]
+    fn decode_text_strips_a_utf8_bom() {
+        assert_eq!(decode_text(b"\xEF\xBB\xBFcaf\xC3\xA9").unwrap(), "café");
+    }
+
+    #[test]
+    fn decode_text_reads_utf16le_with_a_bom() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("héllo".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode_text(&bytes).unwrap(), "héllo");
+    }
+
+    #[test]
+    fn decode_text_refuses_utf16_without_a_bom() {
+        let bytes = "hello".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        assert!(decode_text(&bytes).is_err());
+    }
+
+    #[test]
+    fn decode_text_falls_back_to_latin1() {
+        assert_eq!(decode_text(b"caf\xE9 na\xEFve").unwrap(), "café naïve");
+    }
+}
@@ -0,0 +1,109 @@
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+};
+use std::collections::HashMap;
+
+use crate::error::ApiError;
+use crate::highlight::escape;
+use crate::search::{SearchOutcome, SearchRequest};
+use crate::{stats, DocShared, TfIdf};
+
+/// `secs` since the Unix epoch as an RFC 3339 UTC timestamp, the form Atom
+/// dates take
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Days to a proleptic Gregorian date, counting 400-year eras from
+    // 0000-03-01 so leap days fall at the end of each year
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// `GET /api/document/search.atom`: the `/search` results as an Atom feed, so
+/// a feed reader can subscribe to a query. Each entry is dated when its
+/// document was indexed; with `recency=true`, new matches come first. The
+/// same as `/search` with `format=atom`.
+///
+/// Takes the `/search` parameters; `format`, `bare` and `explain` have no
+/// effect.
+pub async fn search_feed(
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<impl IntoResponse, ApiError> {
+    let query = params
+        .get("s")
+        .ok_or_else(|| ApiError::missing_param("Missing `s` parameter; give search terms"))?;
+    let request = SearchRequest::from_params(&params)?;
+    // Entries link to `/{id}` next to this route, under any collection prefix
+    let base = uri.path().trim_end_matches("search.atom");
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    stats::record_query(query);
+    let outcome = docs.cached_search(query, &request)?;
+    Ok(response(HeaderMap::new(), &docs, query, &outcome, base))
+}
+
+/// `outcome` of searching `query` as an Atom feed, alongside `headers`.
+/// Entries link to `{base}{id}`.
+pub fn response(
+    mut headers: HeaderMap,
+    docs: &TfIdf,
+    query: &str,
+    outcome: &SearchOutcome,
+    base: &str,
+) -> impl IntoResponse {
+    let mut entries = String::new();
+    let mut updated = 0;
+    for result in &outcome.results {
+        let Some(doc) = docs.documents.get(&result.title) else {
+            continue;
+        };
+        // Documents from caches without index times carry no date; the epoch
+        // at least keeps the feed valid
+        let indexed_at = doc.indexed_at.unwrap_or(0);
+        updated = updated.max(indexed_at);
+        entries += &format!(
+            "  <entry>\n    <id>urn:paper-engine:document:{id}</id>\n    \
+             <title>{title}</title>\n    <link href=\"{base}{id}\"/>\n    \
+             <updated>{date}</updated>\n    <summary>{path}</summary>\n  </entry>\n",
+            id = doc.id,
+            title = escape(&result.title),
+            date = rfc3339(indexed_at),
+            path = escape(&result.path),
+        );
+    }
+    let feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n  \
+         <id>urn:paper-engine:search:{id}</id>\n  <title>paper-engine: {title}</title>\n  \
+         <updated>{updated}</updated>\n  <author><name>paper-engine</name></author>\n\
+         {entries}</feed>\n",
+        id = escape(&serde_urlencoded::to_string([("s", query)]).unwrap_or_default()),
+        title = escape(query),
+        updated = rfc3339(updated),
+    );
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        "application/atom+xml; charset=utf-8".parse().unwrap(),
+    );
+    (headers, feed)
+}
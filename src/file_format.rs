@@ -1,17 +1,173 @@
-use crate::{Document, TfIdf};
-use std::collections::HashMap;
-use crate::intern::{intern, get_str};
+use crate::analyzer;
+use crate::error::{Error, Result};
+use crate::ranking::RankingConfig;
+use crate::{Document, Term, TfIdf};
+use std::collections::{BTreeSet, HashMap};
+use crate::intern::{intern, get_str, PoolId};
+use crate::pdf::OutlineEntry;
+use crate::readability::TextStats;
+use crate::title::TitleSource;
+
+/// Start of every cache since format version 2. Version 1 caches have no
+/// header and begin straight with a record, whose mode byte is never `P`.
+const MAGIC: &[u8] = b"PEC";
+
+/// The format `serialize` writes. Each version only added to the one before,
+/// which is why every one of them reads with the same code:
+///
+/// | Version | Added                                                      |
+/// |---------|------------------------------------------------------------|
+/// | 1       | No header; records straight from the start                 |
+/// | 2       | The `PEC` header and version byte                          |
+/// | 3       | 0x16 stored text                                           |
+/// | 4       | 0x17 first page                                            |
+/// | 5       | 0x18 analyzer fingerprint                                  |
+/// | 6       | 0x19 write-ahead log sequence                              |
+/// | 7       | 0x1A document outline entry                                |
+/// | 8       | 0x1B document content hash                                 |
+/// | 9       | 0x1C blocked term                                          |
+/// | 10      | 0x1D document text statistics                              |
+/// | 11      | 0x1E document title source                                 |
+/// | 12      | 0x1F document notes and 0x20 document note term            |
+///
+/// A record type is only ever written by builds that also read it, so an
+/// older build refuses a newer cache by its version rather than by an
+/// unknown mode byte partway through.
+pub const FORMAT_VERSION: u8 = 12;
+
+/// Bits of the 0x06 document flags record
+pub const FAVORITE: u8 = 0b1;
+pub const READ: u8 = 0b10;
+pub const EXCLUDED: u8 = 0b100;
+pub const TRUNCATED: u8 = 0b1000;
+pub const ENCRYPTED: u8 = 0b10000;
+pub const TITLE_DERIVED: u8 = 0b100000;
+pub const LIKELY_SCANNED: u8 = 0b1000000;
+
+impl Document {
+    pub fn flags(&self) -> u8 {
+        let mut flags = 0;
+        for (set, bit) in [
+            (self.favorite, FAVORITE),
+            (self.read, READ),
+            (self.exclude_from_corpus, EXCLUDED),
+            (self.truncated, TRUNCATED),
+            (self.encrypted, ENCRYPTED),
+            (self.title_derived, TITLE_DERIVED),
+            (self.likely_scanned, LIKELY_SCANNED),
+        ] {
+            if set {
+                flags |= bit;
+            }
+        }
+        flags
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.favorite = flags & FAVORITE != 0;
+        self.read = flags & READ != 0;
+        self.exclude_from_corpus = flags & EXCLUDED != 0;
+        self.truncated = flags & TRUNCATED != 0;
+        self.encrypted = flags & ENCRYPTED != 0;
+        self.title_derived = flags & TITLE_DERIVED != 0;
+        self.likely_scanned = flags & LIKELY_SCANNED != 0;
+    }
+}
+
+/// Strings written to the pool table so far, by their id in the file
+#[derive(Default)]
+struct PoolTable<'a> {
+    ids: HashMap<&'a str, u32>,
+}
+
+impl<'a> PoolTable<'a> {
+    /// Id of `s` in the file, writing its 0x0B record the first time it's seen
+    fn id(&mut self, writer: &mut impl std::io::Write, s: &'a str) -> Result<u32> {
+        if let Some(id) = self.ids.get(s) {
+            return Ok(*id);
+        }
+        let id = self.ids.len() as u32;
+        writer.write(&[0x0B])?;
+        writer.write(&(s.len() as u16).to_le_bytes())?;
+        write!(writer, "{}", s)?;
+        self.ids.insert(s, id);
+        Ok(id)
+    }
+}
+
+fn table_entry(table: &[PoolId], b: &[u8]) -> Result<PoolId> {
+    let id = u32::from_le_bytes(b[..4].try_into().unwrap());
+    table
+        .get(id as usize)
+        .copied()
+        .ok_or_else(|| {
+            Error::Format(format!(
+                "Pool id {id} used before its table entry; potentially corrupted cache file"
+            ))
+        })
+}
 
 impl TfIdf {
-    // TODO: Could significantly reduce file size (and ram size if done on a
-    // structural level) by having document title and terms be an index into
-    // the global term. This essential "interns" the terms, and the program
-    // can maintain a global pool of terms.
-    //
-    // Terms are repeated twice and titles are repeated twice
-    pub fn deserialize(b: &[u8]) -> Result<Self, String> {
+    /// Reads the primary data back; call `build_derived_indexes` before
+    /// searching the result
+    pub fn deserialize(b: &[u8]) -> Result<Self> {
+        Self::deserialize_versioned(b).map(|(tf_idf, _)| tf_idf)
+    }
+
+    /// Like `deserialize`, also returning the format version the cache was
+    /// written in, so older caches can be rewritten in the current one
+    pub fn deserialize_versioned(b: &[u8]) -> Result<(Self, u8)> {
+        let Some(rest) = b.strip_prefix(MAGIC) else {
+            return Ok((Self::deserialize_v1(b)?, 1));
+        };
+        match rest.first().copied() {
+            // Every version since 1 only added record types, so one reader
+            // takes them all; see the table above `FORMAT_VERSION`
+            Some(version @ 2..=FORMAT_VERSION) => Ok((Self::deserialize_v1(&rest[1..])?, version)),
+            Some(version) if version > FORMAT_VERSION => Err(Error::Format(format!(
+                "this cache was written by a newer paper-engine (format version {version}, \
+                 this build reads up to {FORMAT_VERSION}); upgrade paper-engine"
+            ))),
+            version => Err(Error::Format(format!(
+                "Unknown cache format version {version:?}; potentially corrupted cache file"
+            ))),
+        }
+    }
+
+    /// Reads records without a header, as `serialize_submission` and
+    /// `serialize_ranking` write them for the write-ahead log
+    pub fn deserialize_records(b: &[u8]) -> Result<Self> {
+        Self::deserialize_v1(b)
+    }
+
+    /// Fails if the cache at `path` was built with other analyzer settings
+    /// than the current ones, since none of its terms would match a query
+    /// any more. An empty index has nothing to mismatch.
+    pub fn check_analyzer(&self, path: &str) -> Result<()> {
+        let current = analyzer::fingerprint();
+        match &self.analyzer {
+            Some(built) if *built != current && !self.documents.is_empty() => {
+                Err(Error::Format(format!(
+                    "cache {path:?} was built with analyzer settings {built:?}, but they are \
+                     now {current:?}, so searches would silently find nothing. Set \
+                     PAPER_ENGINE_CASE_MODE and PAPER_ENGINE_CJK_BIGRAMS back, or move the \
+                     cache aside and submit the documents again"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // TODO: Titles are still repeated in every record that names them; they
+    // could go through the pool table as well
+    fn deserialize_v1(b: &[u8]) -> Result<Self> {
         let mut tf_idf = Self::default();
         let mut document: Option<Document> = None;
+        // Its saved id, if the cache has one
+        let mut document_id: Option<u32> = None;
+        let mut documents = vec![];
+        // File-local pool ids, interned as the table is read
+        let mut table: Vec<PoolId> = vec![];
         let mut i = 0;
         while i < b.len() {
             let mut offset;
@@ -20,6 +176,48 @@ impl TfIdf {
             // 0x02 document title => 02 {title len}x2
             // 0x03 document path  => 03 {path len}x2
             // 0x04 document term  => 04 {term len}x2 {count}x4
+            // 0x05 document token count => 05 {count}x8
+            // 0x06 document flags => 06 {flags}x1 (bit 0 favorite, bit 1 read, bit 2 excluded from corpus, bit 3 truncated, bit 4 encrypted, bit 5 title derived, bit 6 likely scanned)
+            // 0x07 document abstract term => 07 {term len}x2 {freq}x8
+            // 0x08 document page range => 08 {start}x4 {end}x4
+            // 0x09 document surface form => 09 {stem len}x2 {surface len}x2 {count}x8 {stem}{surface}
+            //      One record per (stem, spelling) pair seen in the document; the
+            //      stem also has its own 0x04 record with the frequency
+            // 0x0A document tag => 0A {tag len}x2 {tag}
+            //
+            // Caches written since the pool table use these instead of 0x01,
+            // 0x04, 0x07, 0x09 and 0x0A; every string is stored once and
+            // referred to by its index in the table
+            // 0x0B pool table entry => 0B {len}x2 {string}
+            // 0x0C global term  => 0C {id}x4 {count}x8
+            // 0x0D document term => 0D {id}x4 {freq}x8
+            // 0x0E document abstract term => 0E {id}x4 {freq}x8
+            // 0x0F document surface form => 0F {stem id}x4 {surface id}x4 {count}x8
+            // 0x10 document tag => 10 {id}x4
+            // 0x11 document indexed at => 11 {unix seconds}x8
+            // 0x12 ranking weight => 12 {name id}x4 {value}x8
+            //      Names `RankingConfig` doesn't know are skipped
+            // 0x13 document annotation term => 13 {id}x4 {freq}x8
+            // 0x14 document id => 14 {id}x4
+            // 0x15 next document id => 15 {id}x4
+            // 0x16 document text => 16 {len}x4 {text}
+            // 0x17 document first page => 17 {id}x4 {page}x4
+            // 0x18 analyzer fingerprint => 18 {len}x2 {fingerprint}
+            // 0x19 write-ahead log sequence => 19 {sequence}x8
+            //      The last log entry the cache includes; see `wal.rs`
+            // 0x1A document outline entry => 1A {depth}x1 {page}x4 {title len}x2 {title}
+            //      In depth-first order, each under the last entry one level
+            //      up; page 0 is none
+            // 0x1B document content hash => 1B {hash}x8
+            // 0x1C blocked term => 1C {pool id}x4
+            //      A stem on the blocklist; see `blocklist.rs`
+            // 0x1D document text statistics => 1D {words}x8 {letters}x8 {syllables}x8
+            //      {sentences}x8
+            // 0x1E document title source => 1E {source}x1
+            //      0 metadata, 1 heading, 2 largest text, 3 file name; see `title.rs`
+            // 0x1F document notes => 1F {len}x4 {notes}
+            // 0x20 document note term => 20 {id}x4 {freq}x8
+            //      What the notes added to the term's 0x0D frequency; see `notes.rs`
             match c {
                 0x01 => {
                     let term_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
@@ -34,16 +232,39 @@ impl TfIdf {
                 }
                 0x02 => {
                     if let Some(doc) = document.take() {
-                        tf_idf.documents.insert(doc.title.clone(), doc);
+                        documents.push((doc, document_id.take()));
                     }
                     let title_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
                     offset = 1 + 2;
                     let title = String::from_utf8(b[i + offset..][..title_len as usize].to_vec())
                         .expect("This should be valid utf8");
                     document = Some(Document {
+                        id: 0,
                         path: String::new(),
                         title,
+                        notes: String::new(),
                         term_frequency: HashMap::new(),
+                        abstract_frequency: HashMap::new(),
+                        annotation_frequency: HashMap::new(),
+                        note_frequency: HashMap::new(),
+                        surface_forms: HashMap::new(),
+                        first_page: HashMap::new(),
+                        tags: BTreeSet::new(),
+                        token_count: 0,
+                        page_range: None,
+                        favorite: false,
+                        read: false,
+                        exclude_from_corpus: false,
+                        truncated: false,
+                        encrypted: false,
+                        title_derived: false,
+                        title_source: None,
+                        likely_scanned: false,
+                        indexed_at: None,
+                        text: None,
+                        outline: vec![],
+                        content_hash: None,
+                        text_stats: None,
                     });
                     offset = 1 + 2 + title_len as usize;
                 }
@@ -51,9 +272,9 @@ impl TfIdf {
                     let doc = match document.as_mut() {
                         Some(doc) => doc,
                         None => {
-                            return Err(format!(
+                            return Err(Error::Format(format!(
                                 "Bytes not in correct order; potentially corrupted cache file"
-                            ))
+                            )))
                         }
                     };
                     let path_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
@@ -67,9 +288,9 @@ impl TfIdf {
                     let doc = match document.as_mut() {
                         Some(doc) => doc,
                         None => {
-                            return Err(format!(
+                            return Err(Error::Format(format!(
                                 "Bytes not in correct order; potentially corrupted cache file"
-                            ))
+                            )))
                         }
                     };
                     let term_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
@@ -82,42 +303,730 @@ impl TfIdf {
 
                     offset = 1 + 2 + 8 + term_len as usize;
                 }
+                0x05 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let count = u64::from_le_bytes(b[i + 1..][..8].try_into().unwrap());
+                    doc.token_count = count as usize;
+                    offset = 1 + 8;
+                }
+                0x06 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    doc.set_flags(b[i + 1]);
+                    offset = 1 + 1;
+                }
+                0x07 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let term_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
+                    let freq = f64::from_le_bytes(b[i + 3..][..8].try_into().unwrap());
+                    offset = 1 + 2 + 8;
+                    let term = String::from_utf8(b[i + offset..][..term_len as usize].to_vec())
+                        .expect("This should be valid utf8");
+                    doc.abstract_frequency.insert(intern(term), freq);
+
+                    offset = 1 + 2 + 8 + term_len as usize;
+                }
+                0x08 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let start = u32::from_le_bytes(b[i + 1..][..4].try_into().unwrap());
+                    let end = u32::from_le_bytes(b[i + 5..][..4].try_into().unwrap());
+                    doc.page_range = Some((start, end));
+                    offset = 1 + 4 + 4;
+                }
+                0x09 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let stem_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap()) as usize;
+                    let surface_len = u16::from_le_bytes(b[i + 3..][..2].try_into().unwrap()) as usize;
+                    let count = u64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    offset = 1 + 2 + 2 + 8;
+                    let stem = String::from_utf8(b[i + offset..][..stem_len].to_vec())
+                        .expect("This should be valid utf8");
+                    let surface = String::from_utf8(b[i + offset + stem_len..][..surface_len].to_vec())
+                        .expect("This should be valid utf8");
+                    doc.surface_forms
+                        .entry(intern(stem))
+                        .or_default()
+                        .insert(intern(surface), count as usize);
+
+                    offset = 1 + 2 + 2 + 8 + stem_len + surface_len;
+                }
+                0x0A => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let tag_len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
+                    offset = 1 + 2;
+                    let tag = String::from_utf8(b[i + offset..][..tag_len as usize].to_vec())
+                        .expect("This should be valid utf8");
+                    doc.tags.insert(tag);
+                    offset = 1 + 2 + tag_len as usize;
+                }
+                0x0B => {
+                    let len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap());
+                    offset = 1 + 2;
+                    let string = String::from_utf8(b[i + offset..][..len as usize].to_vec())
+                        .expect("This should be valid utf8");
+                    table.push(intern(string));
+                    offset = 1 + 2 + len as usize;
+                }
+                0x0C => {
+                    let id = table_entry(&table, &b[i + 1..])?;
+                    let count = u64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    tf_idf.global_term_count.insert(id, count as usize);
+                    offset = 1 + 4 + 8;
+                }
+                0x0D => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let id = table_entry(&table, &b[i + 1..])?;
+                    let freq = f64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    doc.term_frequency.insert(id, freq);
+                    offset = 1 + 4 + 8;
+                }
+                0x0E => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let id = table_entry(&table, &b[i + 1..])?;
+                    let freq = f64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    doc.abstract_frequency.insert(id, freq);
+                    offset = 1 + 4 + 8;
+                }
+                0x0F => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let stem = table_entry(&table, &b[i + 1..])?;
+                    let surface = table_entry(&table, &b[i + 5..])?;
+                    let count = u64::from_le_bytes(b[i + 9..][..8].try_into().unwrap());
+                    doc.surface_forms
+                        .entry(stem)
+                        .or_default()
+                        .insert(surface, count as usize);
+                    offset = 1 + 4 + 4 + 8;
+                }
+                0x10 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let tag = table_entry(&table, &b[i + 1..])?;
+                    doc.tags.insert(get_str(tag).to_owned());
+                    offset = 1 + 4;
+                }
+                0x11 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let secs = u64::from_le_bytes(b[i + 1..][..8].try_into().unwrap());
+                    doc.indexed_at = Some(secs);
+                    offset = 1 + 8;
+                }
+                0x1B => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let hash = u64::from_le_bytes(b[i + 1..][..8].try_into().unwrap());
+                    doc.content_hash = Some(hash);
+                    offset = 1 + 8;
+                }
+                0x1D => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let count =
+                        |at: usize| u64::from_le_bytes(b[i + at..][..8].try_into().unwrap());
+                    doc.text_stats = Some(TextStats {
+                        words: count(1),
+                        letters: count(9),
+                        syllables: count(17),
+                        sentences: count(25),
+                    });
+                    offset = 1 + 8 * 4;
+                }
+                0x1E => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    // Sources a newer version added read as unknown
+                    doc.title_source = b.get(i + 1).copied().and_then(TitleSource::from_byte);
+                    offset = 1 + 1;
+                }
+                0x1C => {
+                    tf_idf.blocklist.insert(table_entry(&table, &b[i + 1..])?);
+                    offset = 1 + 4;
+                }
+                0x12 => {
+                    let name = table_entry(&table, &b[i + 1..])?;
+                    let value = f64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    tf_idf.ranking.set_weight(get_str(name), value);
+                    offset = 1 + 4 + 8;
+                }
+                0x13 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let id = table_entry(&table, &b[i + 1..])?;
+                    let freq = f64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    doc.annotation_frequency.insert(id, freq);
+                    offset = 1 + 4 + 8;
+                }
+                0x14 => {
+                    if document.is_none() {
+                        return Err(Error::Format(format!(
+                            "Bytes not in correct order; potentially corrupted cache file"
+                        )));
+                    }
+                    document_id = Some(u32::from_le_bytes(b[i + 1..][..4].try_into().unwrap()));
+                    offset = 1 + 4;
+                }
+                0x16 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let len = u32::from_le_bytes(b[i + 1..][..4].try_into().unwrap()) as usize;
+                    offset = 1 + 4;
+                    let text = String::from_utf8(b[i + offset..][..len].to_vec())
+                        .expect("This should be valid utf8");
+                    doc.text = Some(text);
+                    offset = 1 + 4 + len;
+                }
+                0x1F => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let len = u32::from_le_bytes(b[i + 1..][..4].try_into().unwrap()) as usize;
+                    offset = 1 + 4;
+                    doc.notes = String::from_utf8(b[i + offset..][..len].to_vec())
+                        .expect("This should be valid utf8");
+                    offset = 1 + 4 + len;
+                }
+                0x20 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let id = table_entry(&table, &b[i + 1..])?;
+                    let freq = f64::from_le_bytes(b[i + 5..][..8].try_into().unwrap());
+                    doc.note_frequency.insert(id, freq);
+                    offset = 1 + 4 + 8;
+                }
+                0x17 => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let id = table_entry(&table, &b[i + 1..])?;
+                    let page = u32::from_le_bytes(b[i + 5..][..4].try_into().unwrap());
+                    doc.first_page.insert(id, page);
+                    offset = 1 + 4 + 4;
+                }
+                0x18 => {
+                    let len = u16::from_le_bytes(b[i + 1..][..2].try_into().unwrap()) as usize;
+                    offset = 1 + 2;
+                    let fingerprint = String::from_utf8(b[i + offset..][..len].to_vec())
+                        .expect("This should be valid utf8");
+                    tf_idf.analyzer = Some(fingerprint);
+                    offset = 1 + 2 + len;
+                }
+                0x15 => {
+                    let next_id = u32::from_le_bytes(b[i + 1..][..4].try_into().unwrap());
+                    tf_idf.next_id = tf_idf.next_id.max(next_id);
+                    offset = 1 + 4;
+                }
+                0x1A => {
+                    let doc = match document.as_mut() {
+                        Some(doc) => doc,
+                        None => {
+                            return Err(Error::Format(format!(
+                                "Bytes not in correct order; potentially corrupted cache file"
+                            )))
+                        }
+                    };
+                    let depth = b[i + 1];
+                    let page = u32::from_le_bytes(b[i + 2..][..4].try_into().unwrap());
+                    let len = u16::from_le_bytes(b[i + 6..][..2].try_into().unwrap()) as usize;
+                    offset = 1 + 1 + 4 + 2;
+                    let title = String::from_utf8(b[i + offset..][..len].to_vec())
+                        .expect("This should be valid utf8");
+                    let entry = OutlineEntry {
+                        title,
+                        page: (page > 0).then_some(page),
+                        children: vec![],
+                    };
+                    push_outline(&mut doc.outline, depth, entry);
+                    offset = 1 + 1 + 4 + 2 + len;
+                }
+                0x19 => {
+                    tf_idf.wal_sequence = u64::from_le_bytes(b[i + 1..][..8].try_into().unwrap());
+                    offset = 1 + 8;
+                }
                 _ => {
-                    dbg!(tf_idf);
-                    dbg!(document);
-                    return Err(format!(
+                    return Err(Error::Format(format!(
                         "Unknown mode byte; potentially corrupted cache file: {c} at idx {i}"
-                    ));
+                    )));
                 }
             }
             i += offset;
         }
+        if let Some(doc) = document.take() {
+            documents.push((doc, document_id.take()));
+        }
+        tf_idf.load_documents(documents);
         Ok(tf_idf)
     }
 
-    pub fn serialize(&self, writer: &mut impl std::io::Write) -> Result<(), std::io::Error> {
+    /// A copy of just what `serialize` writes, plus the generation and log a
+    /// save records afterwards. The derived indexes (see `index.rs`) and the
+    /// result cache stay behind, so this is quicker to take under the read
+    /// lock and smaller than a whole clone; don't search it.
+    pub fn save_snapshot(&self) -> Self {
+        Self {
+            global_term_count: self.global_term_count.clone(),
+            documents: self.documents.clone(),
+            next_id: self.next_id,
+            ranking: self.ranking.clone(),
+            blocklist: self.blocklist.clone(),
+            analyzer: self.analyzer.clone(),
+            generation: self.generation,
+            saved_generation: self.saved_generation,
+            saved_at: self.saved_at,
+            wal: self.wal.clone(),
+            wal_sequence: self.wal_sequence,
+            ..Self::default()
+        }
+    }
+
+    /// Writes the index with every term and tag stored once in the pool
+    /// table, rather than into each record that names it; the more
+    /// vocabulary documents share, the more that saves
+    pub fn serialize(&self, writer: &mut impl std::io::Write) -> Result<()> {
+        let mut table = PoolTable::default();
+        writer.write(MAGIC)?;
+        writer.write(&[FORMAT_VERSION])?;
+        writer.write(&[0x15])?;
+        writer.write(&self.next_id.to_le_bytes())?;
+        writer.write(&[0x19])?;
+        writer.write(&self.wal_sequence.to_le_bytes())?;
+        // Whatever it was loaded with, the index is only ever saved by a
+        // build whose settings it matches (see `check_analyzer`)
+        let fingerprint = analyzer::fingerprint();
+        writer.write(&[0x18])?;
+        writer.write(&(fingerprint.len() as u16).to_le_bytes())?;
+        write!(writer, "{}", fingerprint)?;
+        write_ranking(writer, &mut table, &self.ranking)?;
+        for stem in &self.blocklist {
+            let id = table.id(writer, get_str(*stem))?;
+            writer.write(&[0x1C])?;
+            writer.write(&id.to_le_bytes())?;
+        }
         for (term, count) in &self.global_term_count {
-            writer.write(&[0x01])?;
-            writer.write(&(get_str(*term).len() as u16).to_le_bytes())?;
+            let id = table.id(writer, get_str(*term))?;
+            writer.write(&[0x0C])?;
+            writer.write(&id.to_le_bytes())?;
             writer.write(&(*count as u64).to_le_bytes())?;
-            write!(writer, "{}", term)?;
-        }
-        for (_, doc) in &self.documents {
-            writer.write(&[0x02])?;
-            writer.write(&(doc.title.len() as u16).to_le_bytes())?;
-            write!(writer, "{}", doc.title)?;
-            writer.write(&[0x03])?;
-            writer.write(&(doc.path.len() as u16).to_le_bytes())?;
-            write!(writer, "{}", doc.path)?;
-            for (term, freq) in &doc.term_frequency {
-                writer.write(&[0x04])?;
-                writer.write(&(get_str(*term).len() as u16).to_le_bytes())?;
-                writer.write(&(*freq).to_le_bytes())?;
-                write!(writer, "{}", term)?;
-            }
+        }
+        for doc in self.documents.values() {
+            write_document(writer, &mut table, doc)?;
         }
         Ok(())
     }
+
+    /// A submitted document and the counts it adds to `global_term_count`,
+    /// as records of their own for the write-ahead log. The 0x0C records
+    /// hold those counts rather than totals.
+    pub fn serialize_submission(
+        doc: &Document,
+        term_count: &HashMap<Term, usize>,
+        writer: &mut impl std::io::Write,
+    ) -> Result<()> {
+        let mut table = PoolTable::default();
+        for (term, count) in term_count {
+            let id = table.id(writer, get_str(*term))?;
+            writer.write(&[0x0C])?;
+            writer.write(&id.to_le_bytes())?;
+            writer.write(&(*count as u64).to_le_bytes())?;
+        }
+        write_document(writer, &mut table, doc)
+    }
+
+    /// Every ranking weight, as records of their own for the write-ahead log
+    pub fn serialize_ranking(
+        ranking: &RankingConfig,
+        writer: &mut impl std::io::Write,
+    ) -> Result<()> {
+        write_ranking(writer, &mut PoolTable::default(), ranking)
+    }
+}
+
+fn write_ranking<'a>(
+    writer: &mut impl std::io::Write,
+    table: &mut PoolTable<'a>,
+    ranking: &RankingConfig,
+) -> Result<()> {
+    for (name, value) in ranking.weights() {
+        let id = table.id(writer, name)?;
+        writer.write(&[0x12])?;
+        writer.write(&id.to_le_bytes())?;
+        writer.write(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Every record of `doc`, from its 0x02 title on
+fn write_document<'a>(
+    writer: &mut impl std::io::Write,
+    table: &mut PoolTable<'a>,
+    doc: &'a Document,
+) -> Result<()> {
+    writer.write(&[0x02])?;
+    writer.write(&(doc.title.len() as u16).to_le_bytes())?;
+    write!(writer, "{}", doc.title)?;
+    writer.write(&[0x03])?;
+    writer.write(&(doc.path.len() as u16).to_le_bytes())?;
+    write!(writer, "{}", doc.path)?;
+    writer.write(&[0x14])?;
+    writer.write(&doc.id.to_le_bytes())?;
+    writer.write(&[0x05])?;
+    writer.write(&(doc.token_count as u64).to_le_bytes())?;
+    writer.write(&[0x06, doc.flags()])?;
+    if let Some(secs) = doc.indexed_at {
+        writer.write(&[0x11])?;
+        writer.write(&secs.to_le_bytes())?;
+    }
+    if let Some(hash) = doc.content_hash {
+        writer.write(&[0x1B])?;
+        writer.write(&hash.to_le_bytes())?;
+    }
+    if let Some(source) = doc.title_source {
+        writer.write(&[0x1E, source.byte()])?;
+    }
+    if let Some(stats) = doc.text_stats {
+        writer.write(&[0x1D])?;
+        for count in [stats.words, stats.letters, stats.syllables, stats.sentences] {
+            writer.write(&count.to_le_bytes())?;
+        }
+    }
+    if let Some(text) = &doc.text {
+        writer.write(&[0x16])?;
+        writer.write(&(text.len() as u32).to_le_bytes())?;
+        write!(writer, "{}", text)?;
+    }
+    if !doc.notes.is_empty() {
+        writer.write(&[0x1F])?;
+        writer.write(&(doc.notes.len() as u32).to_le_bytes())?;
+        write!(writer, "{}", doc.notes)?;
+    }
+    if let Some((start, end)) = doc.page_range {
+        writer.write(&[0x08])?;
+        writer.write(&start.to_le_bytes())?;
+        writer.write(&end.to_le_bytes())?;
+    }
+    for (term, freq) in &doc.term_frequency {
+        let id = table.id(writer, get_str(*term))?;
+        writer.write(&[0x0D])?;
+        writer.write(&id.to_le_bytes())?;
+        writer.write(&(*freq).to_le_bytes())?;
+    }
+    for (term, freq) in &doc.abstract_frequency {
+        let id = table.id(writer, get_str(*term))?;
+        writer.write(&[0x0E])?;
+        writer.write(&id.to_le_bytes())?;
+        writer.write(&(*freq).to_le_bytes())?;
+    }
+    for (term, freq) in &doc.annotation_frequency {
+        let id = table.id(writer, get_str(*term))?;
+        writer.write(&[0x13])?;
+        writer.write(&id.to_le_bytes())?;
+        writer.write(&(*freq).to_le_bytes())?;
+    }
+    for (term, freq) in &doc.note_frequency {
+        let id = table.id(writer, get_str(*term))?;
+        writer.write(&[0x20])?;
+        writer.write(&id.to_le_bytes())?;
+        writer.write(&(*freq).to_le_bytes())?;
+    }
+    for (term, page) in &doc.first_page {
+        let id = table.id(writer, get_str(*term))?;
+        writer.write(&[0x17])?;
+        writer.write(&id.to_le_bytes())?;
+        writer.write(&page.to_le_bytes())?;
+    }
+    for (stem, forms) in &doc.surface_forms {
+        for (surface, count) in forms {
+            let stem_id = table.id(writer, get_str(*stem))?;
+            let surface_id = table.id(writer, get_str(*surface))?;
+            writer.write(&[0x0F])?;
+            writer.write(&stem_id.to_le_bytes())?;
+            writer.write(&surface_id.to_le_bytes())?;
+            writer.write(&(*count as u64).to_le_bytes())?;
+        }
+    }
+    for tag in &doc.tags {
+        let id = table.id(writer, tag)?;
+        writer.write(&[0x10])?;
+        writer.write(&id.to_le_bytes())?;
+    }
+    write_outline(writer, &doc.outline, 0)
+}
+
+fn write_outline(
+    writer: &mut impl std::io::Write,
+    entries: &[OutlineEntry],
+    depth: u8,
+) -> Result<()> {
+    for entry in entries {
+        writer.write(&[0x1A, depth])?;
+        writer.write(&entry.page.unwrap_or(0).to_le_bytes())?;
+        writer.write(&(entry.title.len() as u16).to_le_bytes())?;
+        write!(writer, "{}", entry.title)?;
+        write_outline(writer, &entry.children, depth.saturating_add(1))?;
+    }
+    Ok(())
+}
+
+/// Adds `entry` `depth` levels down, under the last entry of each level
+/// above it
+fn push_outline(outline: &mut Vec<OutlineEntry>, depth: u8, entry: OutlineEntry) {
+    let mut level = outline;
+    for _ in 0..depth {
+        let Some(last) = level.len().checked_sub(1) else {
+            break;
+        };
+        level = &mut level[last].children;
+    }
+    level.push(entry);
 }
 
 // TODO: Write some tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::index;
+    use crate::DocShared;
+    use std::collections::BTreeMap;
+
+    /// `tf_idf` as a headerless version 1 cache, every string written into
+    /// each record that names it
+    fn write_v1(tf_idf: &TfIdf) -> Vec<u8> {
+        let mut b = vec![];
+        for (term, count) in &tf_idf.global_term_count {
+            let term = get_str(*term);
+            b.push(0x01);
+            b.extend((term.len() as u16).to_le_bytes());
+            b.extend((*count as u64).to_le_bytes());
+            b.extend(term.as_bytes());
+        }
+        for doc in tf_idf.documents.values() {
+            for (kind, s) in [(0x02, &doc.title), (0x03, &doc.path)] {
+                b.push(kind);
+                b.extend((s.len() as u16).to_le_bytes());
+                b.extend(s.as_bytes());
+            }
+            b.push(0x05);
+            b.extend((doc.token_count as u64).to_le_bytes());
+            for (term, freq) in &doc.term_frequency {
+                let term = get_str(*term);
+                b.push(0x04);
+                b.extend((term.len() as u16).to_le_bytes());
+                b.extend(freq.to_le_bytes());
+                b.extend(term.as_bytes());
+            }
+            for (stem, forms) in &doc.surface_forms {
+                for (surface, count) in forms {
+                    let (stem, surface) = (get_str(*stem), get_str(*surface));
+                    b.push(0x09);
+                    b.extend((stem.len() as u16).to_le_bytes());
+                    b.extend((surface.len() as u16).to_le_bytes());
+                    b.extend((*count as u64).to_le_bytes());
+                    b.extend(stem.as_bytes());
+                    b.extend(surface.as_bytes());
+                }
+            }
+        }
+        b
+    }
+
+    /// Twenty documents sharing one vocabulary
+    fn library() -> DocShared {
+        let text = (0..40)
+            .map(|i| format!("representation{i}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let titles = (0..20).map(|i| format!("Paper {i}")).collect::<Vec<_>>();
+        let documents = titles
+            .iter()
+            .map(|title| (title.as_str(), text.as_str()))
+            .collect::<Vec<_>>();
+        index(&documents)
+    }
+
+    /// Global counts by string, so indexes from different pools compare
+    fn global_counts(tf_idf: &TfIdf) -> BTreeMap<String, usize> {
+        tf_idf
+            .global_term_count
+            .iter()
+            .map(|(term, count)| (get_str(*term).to_owned(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn pool_table_writes_shared_terms_once() {
+        let docs = library();
+        let docs = docs.read().unwrap();
+        let mut pooled = vec![];
+        docs.serialize(&mut pooled).unwrap();
+        let inline = write_v1(&docs);
+        // The pooled cache holds more (ids, flags, first pages, statistics)
+        // and is still smaller
+        assert!(
+            pooled.len() < inline.len(),
+            "pooled cache is {} bytes, inline {} bytes",
+            pooled.len(),
+            inline.len()
+        );
+
+        // Terms are interned again from the table as it's read
+        let loaded = TfIdf::deserialize(&pooled).unwrap();
+        assert_eq!(global_counts(&loaded), global_counts(&docs));
+        assert_eq!(loaded.documents.len(), docs.documents.len());
+        for (title, doc) in &docs.documents {
+            let loaded = &loaded.documents[title];
+            assert_eq!(loaded.term_frequency, doc.term_frequency);
+            assert_eq!(loaded.surface_forms, doc.surface_forms);
+        }
+    }
+
+    #[test]
+    fn save_snapshot_writes_the_same_cache() {
+        let docs = library();
+        let docs = docs.read().unwrap();
+        let snapshot = docs.save_snapshot();
+        assert!(snapshot.postings.is_empty() && snapshot.titles.is_empty());
+        let (mut whole, mut saved) = (vec![], vec![]);
+        docs.serialize(&mut whole).unwrap();
+        snapshot.serialize(&mut saved).unwrap();
+        assert_eq!(saved, whole);
+    }
+
+    #[test]
+    fn headerless_v1_cache_upgrades() {
+        let docs = index(&[("Graphs", "shortest paths in sparse graphs")]);
+        let docs = docs.read().unwrap();
+        let (v1, version) = TfIdf::deserialize_versioned(&write_v1(&docs)).unwrap();
+        assert_eq!(version, 1);
+
+        let mut upgraded = vec![];
+        v1.serialize(&mut upgraded).unwrap();
+        assert!(upgraded.starts_with(MAGIC));
+        let (loaded, version) = TfIdf::deserialize_versioned(&upgraded).unwrap();
+        assert_eq!(version, FORMAT_VERSION);
+        assert_eq!(global_counts(&loaded), global_counts(&docs));
+        let doc = &loaded.documents["Graphs"];
+        assert_eq!(doc.term_frequency, docs.documents["Graphs"].term_frequency);
+        assert_eq!(doc.token_count, docs.documents["Graphs"].token_count);
+    }
+}
@@ -0,0 +1,121 @@
+use axum::http::{header, HeaderMap};
+use std::collections::HashMap;
+
+use crate::error::{ApiError, ErrorCode};
+use crate::jsonapi;
+
+// Content negotiation for routes answering in more than one format. An
+// explicit `format` parameter wins; otherwise the `Accept` header picks among
+// the formats the route offers, and without either the route's first one is
+// used. Error bodies are always plain JSON.
+
+/// A response body format, by its `format` alias
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    JsonApi,
+    Csv,
+    Tsv,
+    Atom,
+}
+
+impl Format {
+    const ALL: [Self; 5] = [Self::Json, Self::JsonApi, Self::Csv, Self::Tsv, Self::Atom];
+
+    /// The `format` parameter's value for it
+    pub fn alias(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::JsonApi => "jsonapi",
+            Self::Csv => "csv",
+            Self::Tsv => "tsv",
+            Self::Atom => "atom",
+        }
+    }
+
+    /// What `Accept` names it by
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::JsonApi => jsonapi::MEDIA_TYPE,
+            Self::Csv => "text/csv",
+            Self::Tsv => "text/tab-separated-values",
+            Self::Atom => "application/atom+xml",
+        }
+    }
+
+    /// Preference (0 to 1) for it in an `Accept` header, from the most
+    /// specific media range matching it; 0 when none does
+    fn quality(self, accept: &str) -> f64 {
+        let media_type = self.media_type();
+        let (kind, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+        let mut best: Option<(u8, f64)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';').map(str::trim);
+            let range = parts.next().unwrap_or("").to_ascii_lowercase();
+            let specificity = match range.split_once('/') {
+                _ if range == media_type => 2,
+                Some((range_kind, "*")) if range_kind == kind => 1,
+                Some(("*", "*")) => 0,
+                _ => continue,
+            };
+            let q = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f64>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            if best.map_or(true, |(most, _)| specificity > most) {
+                best = Some((specificity, q));
+            }
+        }
+        best.map_or(0.0, |(_, q)| q)
+    }
+}
+
+/// What routes giving documents answer in
+pub const JSON_OR_JSONAPI: &[Format] = &[Format::Json, Format::JsonApi];
+
+/// 406 naming what the route can answer in
+fn not_acceptable(asked: String, supported: &[Format]) -> ApiError {
+    let offered = supported
+        .iter()
+        .map(|format| format!("{} (`format={}`)", format.media_type(), format.alias()))
+        .collect::<Vec<_>>();
+    ApiError::new(
+        ErrorCode::NotAcceptable,
+        format!("Can't answer with {asked}; this route gives {}", offered.join(", ")),
+    )
+}
+
+/// The format to answer in, out of `supported` (the default first): the
+/// `format` parameter, or else the one `Accept` prefers
+pub fn negotiate(
+    request_headers: &HeaderMap,
+    params: &HashMap<String, String>,
+    supported: &[Format],
+) -> Result<Format, ApiError> {
+    if let Some(alias) = params.get("format") {
+        return Format::ALL
+            .into_iter()
+            .find(|format| format.alias() == alias && supported.contains(format))
+            .ok_or_else(|| not_acceptable(format!("`format={alias}`"), supported));
+    }
+    let accept = match request_headers.get(header::ACCEPT).map(|v| v.to_str()) {
+        None => return Ok(supported[0]),
+        Some(Ok(accept)) if accept.trim().is_empty() => return Ok(supported[0]),
+        Some(Ok(accept)) => accept,
+        Some(Err(_)) => {
+            return Err(ApiError::invalid_param("`Accept` header is not valid ASCII"))
+        }
+    };
+    // Ties go to the route's order, so `*/*` gets the default
+    let mut best = None;
+    for format in supported {
+        let q = format.quality(accept);
+        if q > 0.0 && best.map_or(true, |(_, most)| q > most) {
+            best = Some((*format, q));
+        }
+    }
+    best.map(|(format, _)| format)
+        .ok_or_else(|| not_acceptable(format!("`Accept: {accept}`"), supported))
+}
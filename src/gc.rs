@@ -0,0 +1,160 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::collection::Collection;
+use crate::error::{ApiError, ErrorCode};
+use crate::intern::{self, Remap};
+use crate::{Term, TfIdf};
+
+// Collecting the intern pool: strings only deleted documents, pruned terms or
+// earlier versions of reindexed documents used are freed, and the rest are
+// renumbered. Ids are held all through each index, so a pass stops
+// everything: it waits for the requests in flight (see `intern::Pin`) and any
+// cache save, then holds every collection's write lock, since they all share
+// the pool.
+
+/// How long a pass waits for the requests in flight before giving up;
+/// requests arriving meanwhile wait behind it
+const WAIT: Duration = Duration::from_secs(5);
+
+/// How often `watch` looks at the pool's size
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize)]
+pub struct GcReport {
+    entries_removed: usize,
+    bytes_reclaimed: usize,
+    /// What the pool holds afterwards
+    entries: usize,
+    bytes: usize,
+    elapsed_ms: u64,
+}
+
+fn remap_keys<V>(map: &mut HashMap<Term, V>, remap: &Remap) {
+    *map = std::mem::take(map)
+        .into_iter()
+        .map(|(term, value)| (remap.get(term), value))
+        .collect();
+}
+
+impl TfIdf {
+    /// Adds every id the index holds to `live`, bar the derived indexes,
+    /// which `remap_terms` rebuilds
+    fn live_terms(&self, live: &mut HashSet<Term>) {
+        live.extend(self.global_term_count.keys());
+        live.extend(&self.blocklist);
+        for doc in self.documents.values() {
+            live.extend(doc.term_frequency.keys());
+            live.extend(doc.abstract_frequency.keys());
+            live.extend(doc.annotation_frequency.keys());
+            live.extend(doc.note_frequency.keys());
+            live.extend(doc.first_page.keys());
+            for (stem, forms) in &doc.surface_forms {
+                live.insert(*stem);
+                live.extend(forms.keys());
+            }
+        }
+    }
+
+    /// Moves every id the index holds to where the pool now keeps its
+    /// string. Nothing the index saves or answers with changes, so neither
+    /// does `generation`.
+    fn remap_terms(&mut self, remap: &Remap) {
+        remap_keys(&mut self.global_term_count, remap);
+        self.blocklist = self.blocklist.iter().map(|term| remap.get(*term)).collect();
+        for doc in self.documents.values_mut() {
+            remap_keys(&mut doc.term_frequency, remap);
+            remap_keys(&mut doc.abstract_frequency, remap);
+            remap_keys(&mut doc.annotation_frequency, remap);
+            remap_keys(&mut doc.note_frequency, remap);
+            remap_keys(&mut doc.first_page, remap);
+            for forms in doc.surface_forms.values_mut() {
+                remap_keys(forms, remap);
+            }
+            remap_keys(&mut doc.surface_forms, remap);
+        }
+        // `surfaces` borrows strings that may have just been freed
+        self.build_derived_indexes();
+    }
+}
+
+/// Collects the pool, keeping what any collection uses
+pub async fn collect(collections: Arc<Vec<Collection>>) -> Result<GcReport, ApiError> {
+    let started = Instant::now();
+    let exclusive = tokio::time::timeout(WAIT, intern::exclusive())
+        .await
+        .map_err(|_| {
+            ApiError::new(
+                ErrorCode::Busy,
+                format!("Requests were still running after {WAIT:?}; try again later"),
+            )
+        })?;
+    let collected = tokio::task::spawn_blocking(move || {
+        let _saving = crate::SAVING
+            .lock()
+            .map_err(|e| ApiError::internal(format!("Could not take the save lock: {e}")))?;
+        let mut locked = collections
+            .iter()
+            .map(|collection| collection.docs.write())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+        let mut live = HashSet::new();
+        for docs in &locked {
+            docs.live_terms(&mut live);
+        }
+        let (remap, collected) = intern::collect(&exclusive, &live);
+        for docs in &mut locked {
+            docs.remap_terms(&remap);
+        }
+        Ok::<_, ApiError>(collected)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Intern pool collection failed: {e}")))??;
+
+    Ok(GcReport {
+        entries_removed: collected.entries_removed,
+        bytes_reclaimed: collected.bytes_reclaimed,
+        entries: collected.entries,
+        bytes: collected.bytes,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// `POST /api/admin/intern_gc`
+pub async fn intern_gc(
+    headers: HeaderMap,
+    State(collections): State<Arc<Vec<Collection>>>,
+) -> Result<Json<GcReport>, ApiError> {
+    crate::admin::require_admin(&headers)?;
+    Ok(Json(collect(collections).await?))
+}
+
+/// Collects the pool whenever it holds more than `threshold` strings, and a
+/// tenth more than the last pass left, so a pool of mostly live strings isn't
+/// collected over and over
+pub async fn watch(collections: Arc<Vec<Collection>>, threshold: usize) {
+    let mut interval = tokio::time::interval(CHECK_EVERY);
+    let mut kept = 0;
+    loop {
+        interval.tick().await;
+        let (entries, _) = intern::pool_stats();
+        if entries <= threshold.max(kept + kept / 10) {
+            continue;
+        }
+        match collect(Arc::clone(&collections)).await {
+            Ok(report) => {
+                kept = report.entries;
+                tracing::info!(
+                    entries_removed = report.entries_removed,
+                    bytes_reclaimed = report.bytes_reclaimed,
+                    elapsed_ms = report.elapsed_ms,
+                    "Collected the intern pool"
+                );
+            }
+            Err(e) => tracing::warn!("Intern pool collection failed: {e}"),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::analyzer::{self, Token};
+
+/// Escapes `s` for HTML text
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `text` HTML-escaped, with every whitespace-separated word for which
+/// `matches` accepts one of its tokens wrapped in `<mark>`. Words are
+/// analyzed exactly like indexed text, so "Running," is marked for a query
+/// on "run".
+pub fn highlight(text: &str, matches: impl Fn(Token) -> bool) -> String {
+    let mut highlighted = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let space = rest.len() - rest.trim_start().len();
+        highlighted.push_str(&rest[..space]);
+        rest = &rest[space..];
+
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..len];
+        if analyzer::word_tokens(word).into_iter().any(&matches) {
+            highlighted.push_str("<mark>");
+            highlighted.push_str(&escape(word));
+            highlighted.push_str("</mark>");
+        } else {
+            highlighted.push_str(&escape(word));
+        }
+        rest = &rest[len..];
+    }
+    highlighted
+}
@@ -0,0 +1,177 @@
+/// What an HTML page contributes to the index
+#[derive(Debug, Default)]
+pub struct HtmlText {
+    pub title: Option<String>,
+    /// Visible text, without scripts, styles or markup
+    pub body: String,
+    /// `<meta name="description">` and `<meta name="keywords">` content
+    pub summary: String,
+}
+
+/// Elements whose content is never shown as text
+const HIDDEN: &[&str] = &["script", "style", "noscript", "template", "svg"];
+
+/// Pulls the title, visible text and meta summary out of `html`.
+///
+/// This is a lenient tag stripper rather than a real parser: it never fails,
+/// so unclosed tags, stray `<` and missing end tags just make their way into
+/// the text as best they can. Every tag becomes a space, which keeps words on
+/// either side of `<br>` or `</p>` apart.
+pub fn parse(html: &str) -> HtmlText {
+    let mut text = HtmlText::default();
+    let mut title: Option<String> = None;
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        push_text(&mut text, &mut title, &rest[..open]);
+        rest = &rest[open..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(close) = rest.find('>') else {
+            // A `<` that never closes is just text
+            push_text(&mut text, &mut title, rest);
+            rest = "";
+            break;
+        };
+        let tag = &rest[1..close];
+        rest = &rest[close + 1..];
+
+        let (name, attributes) = tag
+            .split_once(|c: char| c.is_whitespace())
+            .unwrap_or((tag, ""));
+        let name = name.trim_end_matches('/').to_ascii_lowercase();
+        match name.as_str() {
+            "title" if text.title.is_none() => title = Some(String::new()),
+            "/title" => {
+                if let Some(title) = title.take() {
+                    let title = collapse_whitespace(&title);
+                    text.title = (!title.is_empty()).then_some(title);
+                }
+            }
+            "meta" => {
+                let attributes = parse_attributes(attributes);
+                let name = attributes.iter().find(|(k, _)| k == "name").map(|(_, v)| v);
+                let content = attributes.iter().find(|(k, _)| k == "content").map(|(_, v)| v);
+                if let (Some(name), Some(content)) = (name, content) {
+                    if name.eq_ignore_ascii_case("description")
+                        || name.eq_ignore_ascii_case("keywords")
+                    {
+                        text.summary.push_str(&decode_entities(content));
+                        text.summary.push(' ');
+                    }
+                }
+            }
+            name if HIDDEN.contains(&name) && !tag.ends_with('/') => {
+                rest = skip_element(rest, name);
+            }
+            _ => text.body.push(' '),
+        }
+    }
+    push_text(&mut text, &mut title, rest);
+    // A `<title>` that is never closed still names the page
+    if let Some(title) = title {
+        let title = collapse_whitespace(&title);
+        text.title = (!title.is_empty()).then_some(title);
+    }
+    text
+}
+
+/// Text between tags goes to the title while one is open, otherwise the body
+fn push_text(text: &mut HtmlText, title: &mut Option<String>, s: &str) {
+    let s = decode_entities(s);
+    match title {
+        Some(title) => title.push_str(&s),
+        None => text.body.push_str(&s),
+    }
+}
+
+/// Everything after `</name>`, matched case-insensitively; nothing if the
+/// element is never closed
+fn skip_element<'a>(html: &'a str, name: &str) -> &'a str {
+    let end_tag = format!("</{name}");
+    let lower = html.to_ascii_lowercase();
+    let Some(start) = lower.find(&end_tag) else {
+        return "";
+    };
+    let after = &html[start..];
+    after.find('>').map_or("", |close| &after[close + 1..])
+}
+
+/// `key="value"`, `key='value'` and `key=value` pairs, keys lowercased.
+/// Attributes without a value are left out.
+fn parse_attributes(s: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut rest = s.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].split_whitespace().last().unwrap_or("");
+        let key = key.trim_end_matches('/').to_ascii_lowercase();
+        let value_start = rest[eq + 1..].trim_start();
+        let (value, after) = match value_start.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let inner = &value_start[1..];
+                match inner.find(quote) {
+                    Some(end) => (&inner[..end], &inner[end + 1..]),
+                    None => (inner, ""),
+                }
+            }
+            _ => {
+                let end = value_start
+                    .find(|c: char| c.is_whitespace())
+                    .unwrap_or(value_start.len());
+                (&value_start[..end], &value_start[end..])
+            }
+        };
+        attributes.push((key, value.trim_end_matches('/').to_owned()));
+        rest = after;
+    }
+    attributes
+}
+
+/// Decodes the named entities that show up in ordinary prose plus numeric
+/// ones; anything else is left as written
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let number = entity.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (entity, c) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
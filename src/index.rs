@@ -0,0 +1,488 @@
+use axum::{
+    extract::{OriginalUri, Path, Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+
+use crate::analyzer;
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+use crate::intern::get_str;
+use crate::paging;
+use crate::search::rounded;
+use crate::{DocShared, Document, DocumentInfo, Term, TfIdf};
+
+pub fn routes(docs: DocShared) -> Router {
+    Router::new()
+        .route("/suggest", get(suggest))
+        .route("/:term/documents", get(term_documents))
+        .route("/:term/cooccurring", get(cooccurring))
+        .route("/:term/top_documents", get(top_documents))
+        .with_state(docs)
+}
+
+pub fn info_routes(docs: DocShared) -> Router {
+    Router::new().route("/info", get(index_info)).with_state(docs)
+}
+
+// The inverted index (`postings`), `titles` and `surfaces` are derived from `documents`:
+// they are never written to the cache but built once after loading it
+// (`build_derived_indexes`), and from then on every change to `documents`
+// goes through `insert_document`/`remove_document` so they stay in step, or,
+// for a document changed in place (`set_notes`), `unindex_document` before
+// the change and `index_document` after it.
+//
+// Those two, `document_by_id_mut` and the admin operations also bump
+// `generation` (through `changed`), which `/api/index/info` reports so clients
+// can tell when results they cached went stale.
+impl TfIdf {
+    pub fn changed(&mut self) {
+        self.generation += 1;
+    }
+
+    pub(crate) fn index_document(&mut self, doc: &Document) {
+        for term in doc.term_frequency.keys() {
+            self.postings.entry(*term).or_default().insert(doc.id);
+        }
+        self.titles.insert(doc.id, doc.title.clone());
+        for (stem, forms) in &doc.surface_forms {
+            for surface in forms.keys() {
+                self.surfaces.insert(get_str(*surface), *stem);
+            }
+        }
+    }
+
+    /// `surfaces` is left alone: other documents may share the spellings,
+    /// and lookups skip stems without postings anyway
+    pub(crate) fn unindex_document(&mut self, doc: &Document) {
+        for term in doc.term_frequency.keys() {
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(&doc.id);
+                if ids.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        self.titles.remove(&doc.id);
+    }
+
+    /// Inserts `doc` under its title. A document already under that title is
+    /// replaced and `doc` takes over its id, so links to it survive a
+    /// reindex; otherwise it gets the next free id.
+    pub fn insert_document(&mut self, mut doc: Document) {
+        match self.remove_document(&doc.title.clone()) {
+            Some(old) => doc.id = old.id,
+            None => {
+                doc.id = self.next_id;
+                self.next_id += 1;
+            }
+        }
+        self.index_document(&doc);
+        self.documents.insert(doc.title.clone(), doc);
+        self.changed();
+    }
+
+    /// Removes a document from `documents` and the inverted index. Its terms
+    /// stay counted in `global_term_count`.
+    pub fn remove_document(&mut self, title: &str) -> Option<Document> {
+        let doc = self.documents.remove(title)?;
+        self.unindex_document(&doc);
+        self.changed();
+        Some(doc)
+    }
+
+    /// Adds documents read from the cache, each with the id it was saved
+    /// with. Caches from before ids were saved, and ids that somehow clash,
+    /// get fresh ones past every id in use. The derived indexes are left to
+    /// `build_derived_indexes`.
+    pub fn load_documents(&mut self, documents: Vec<(Document, Option<u32>)>) {
+        let mut used = HashSet::new();
+        let mut fresh = vec![];
+        for (mut doc, id) in documents {
+            match id {
+                Some(id) if used.insert(id) => {
+                    doc.id = id;
+                    self.next_id = self.next_id.max(id + 1);
+                    self.documents.insert(doc.title.clone(), doc);
+                }
+                _ => fresh.push(doc),
+            }
+        }
+        for mut doc in fresh {
+            doc.id = self.next_id;
+            self.next_id += 1;
+            self.documents.insert(doc.title.clone(), doc);
+        }
+    }
+
+    /// Builds `postings`, `titles` and `surfaces` from scratch out of
+    /// `documents`. Must run after loading a cache, before the first search.
+    pub fn build_derived_indexes(&mut self) {
+        self.postings.clear();
+        self.titles.clear();
+        self.surfaces.clear();
+        let documents = std::mem::take(&mut self.documents);
+        for doc in documents.values() {
+            self.index_document(doc);
+        }
+        self.documents = documents;
+    }
+
+    /// Ids of the documents whose body contains `term`
+    pub fn postings(&self, term: Term) -> impl Iterator<Item = u32> + '_ {
+        self.postings.get(&term).into_iter().flatten().copied()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexInfo {
+    /// Changes whenever the index does; equal values mean identical results
+    generation: u64,
+    documents: usize,
+    /// Unix seconds of the last cache save this run
+    saved_at: Option<u64>,
+    /// Changed since the last save (or since loading, before any save)
+    dirty: bool,
+}
+
+/// `GET /api/index/info`: a cheap check of whether the index changed
+pub async fn index_info(State(docs): State<DocShared>) -> Result<Json<IndexInfo>, ApiError> {
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    Ok(Json(IndexInfo {
+        generation: docs.generation,
+        documents: docs.documents.len(),
+        saved_at: docs.saved_at,
+        dirty: docs.generation != docs.saved_generation,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TermPosting {
+    document: DocumentInfo,
+    frequency: f64,
+    /// Each spelling of the term in the document, with its count
+    surface_forms: HashMap<Term, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TermDocuments {
+    term: String,
+    stem: Term,
+    /// Highest frequency first
+    documents: Vec<TermPosting>,
+}
+
+/// `GET /api/term/{term}/documents`: every document containing `term`'s stem
+pub async fn term_documents(
+    Path(term): Path<String>,
+    State(docs): State<DocShared>,
+) -> Result<Json<TermDocuments>, ApiError> {
+    let Some(token) = analyzer::tokenize(&term).next() else {
+        return Err(ApiError::invalid_param(format!("{term:?} is not a searchable term")));
+    };
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let mut documents = docs
+        .postings(token.stem)
+        .filter_map(|id| docs.document_by_id(id))
+        .map(|doc| TermPosting {
+            document: doc.into(),
+            frequency: doc.term_frequency.get(&token.stem).copied().unwrap_or(0.0),
+            surface_forms: doc.surface_forms.get(&token.stem).cloned().unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    if documents.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("No document contains {term:?}"),
+        ));
+    }
+    documents.sort_by(|a, b| {
+        b.frequency
+            .total_cmp(&a.frequency)
+            .then_with(|| a.document.attributes.title.cmp(&b.document.attributes.title))
+    });
+    Ok(Json(TermDocuments {
+        term,
+        stem: token.stem,
+        documents,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct FocusedDocument {
+    document: DocumentInfo,
+    /// Times the term occurs in the document, in any spelling
+    occurrences: usize,
+    /// `occurrences` over the document's word count: how much of its text
+    /// is the term
+    #[serde(serialize_with = "rounded")]
+    share: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopDocuments {
+    term: String,
+    stem: Term,
+    /// Documents containing the term, across all pages
+    total: usize,
+    offset: usize,
+    /// Largest `share` first
+    documents: Vec<FocusedDocument>,
+}
+
+/// `GET /api/term/{term}/top_documents?offset=0&limit=20`: the documents
+/// most about `term`, ranked by the share of their words that are its stem.
+/// Unlike a search there's no IDF or field weighting: a short note that is
+/// all about the term beats a long paper mentioning it often. Pages are
+/// `limit` (default 20) documents, with the usual paging headers.
+pub async fn top_documents(
+    Path(term): Path<String>,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Response, ApiError> {
+    let Some(token) = analyzer::tokenize(&term).next() else {
+        return Err(ApiError::invalid_param(format!("{term:?} is not a searchable term")));
+    };
+    let offset = crate::parse_param(&params, "offset")?.unwrap_or(0);
+    let limit = crate::parse_param(&params, "limit")?.unwrap_or(20);
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let mut documents = docs
+        .postings(token.stem)
+        .filter_map(|id| docs.document_by_id(id))
+        .map(|doc| {
+            let occurrences = doc
+                .surface_forms
+                .get(&token.stem)
+                .map_or(0, |forms| forms.values().sum());
+            FocusedDocument {
+                document: doc.into(),
+                occurrences,
+                share: occurrences as f64 / doc.token_count.max(1) as f64,
+            }
+        })
+        .collect::<Vec<_>>();
+    if documents.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("No document contains {term:?}"),
+        ));
+    }
+    documents.sort_by(|a, b| {
+        b.share
+            .total_cmp(&a.share)
+            .then_with(|| a.document.attributes.title.cmp(&b.document.attributes.title))
+    });
+
+    let total = documents.len();
+    let headers = paging::headers(uri.path(), &params, offset, limit, total);
+    let documents = documents.into_iter().skip(offset).take(limit).collect();
+    let top = TopDocuments {
+        term,
+        stem: token.stem,
+        total,
+        offset,
+        documents,
+    };
+    Ok((headers, Json(top)).into_response())
+}
+
+/// `min_docs` and `max_share`, which keep rare and near-universal stems out
+/// of `/suggest` and `/{term}/cooccurring`
+fn frequency_bounds(params: &HashMap<String, String>) -> Result<(usize, f64), ApiError> {
+    let min_docs = crate::parse_param(params, "min_docs")?.unwrap_or(CONFIG.suggest_min_docs);
+    let max_share =
+        crate::parse_param(params, "max_share")?.unwrap_or(CONFIG.suggest_max_doc_share);
+    if !(0.0..=1.0).contains(&max_share) {
+        return Err(ApiError::invalid_param(format!(
+            "Invalid `max_share` {max_share}; must be between 0 and 1"
+        )));
+    }
+    Ok((min_docs, max_share))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    /// The first indexed spelling starting with the prefix
+    term: &'static str,
+    stem: Term,
+    /// Documents containing the stem
+    doc_frequency: usize,
+}
+
+/// `GET /api/term/suggest?prefix=comp`: indexed words starting with `prefix`
+/// for autocomplete, one per stem, those in the most documents first.
+///
+/// Stems in fewer than `min_docs` documents (mostly OCR garbage and typos)
+/// or in more than `max_share` of them (words like "the", useless for
+/// narrowing a search) are left out; the defaults are
+/// `PAPER_ENGINE_SUGGEST_MIN_DOCS` and `PAPER_ENGINE_SUGGEST_MAX_DOC_SHARE`.
+pub async fn suggest(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Vec<Suggestion>>, ApiError> {
+    let prefix = params
+        .get("prefix")
+        .map(|prefix| analyzer::fold_case(prefix.trim()))
+        .filter(|prefix| !prefix.is_empty())
+        .ok_or_else(|| ApiError::missing_param("Missing `prefix` parameter; give a word start"))?;
+    let limit = crate::parse_param(&params, "limit")?.unwrap_or(10);
+    let (min_docs, max_share) = frequency_bounds(&params)?;
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let max_docs = (max_share * docs.documents.len() as f64).floor() as usize;
+    let mut seen = HashSet::new();
+    let mut suggestions = vec![];
+    let from = (Bound::Included(prefix.as_str()), Bound::Unbounded);
+    for (surface, stem) in docs.surfaces.range::<str, _>(from) {
+        if !surface.starts_with(prefix.as_str()) {
+            break;
+        }
+        if !seen.insert(*stem) {
+            continue;
+        }
+        let doc_frequency = docs.postings(*stem).count();
+        if (min_docs..=max_docs).contains(&doc_frequency) {
+            suggestions.push(Suggestion {
+                term: *surface,
+                stem: *stem,
+                doc_frequency,
+            });
+        }
+    }
+    // Stable, so equally common stems stay in spelling order
+    suggestions.sort_by(|a, b| b.doc_frequency.cmp(&a.doc_frequency));
+    suggestions.truncate(limit);
+    Ok(Json(suggestions))
+}
+
+/// Most terms `/{term}/cooccurring` lists
+const MAX_COOCCURRING: usize = 100;
+
+#[derive(Debug, Serialize)]
+pub struct Cooccurrence {
+    /// The stem's most common spelling in the documents shared with the term
+    term: &'static str,
+    stem: Term,
+    /// Documents containing both
+    documents: usize,
+    /// Documents containing the stem
+    doc_frequency: usize,
+    /// Normalized pointwise mutual information, in [-1, 1]: 1 when the two
+    /// only ever appear together, 0 when they're independent
+    #[serde(serialize_with = "rounded")]
+    score: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CooccurringTerms {
+    term: String,
+    stem: Term,
+    doc_frequency: usize,
+    /// Strongest first
+    cooccurring: Vec<Cooccurrence>,
+}
+
+/// `GET /api/term/{term}/cooccurring?limit=20`: the stems that most often
+/// share documents with `term`'s, ranked by normalized pointwise mutual
+/// information over document frequencies, so words common everywhere don't
+/// crowd out the term's topical neighbors. `limit` defaults to 20, at most
+/// 100; `min_docs` and `max_share` bound the stems' document frequency as
+/// for `/suggest`.
+pub async fn cooccurring(
+    Path(term): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<CooccurringTerms>, ApiError> {
+    let Some(token) = analyzer::tokenize(&term).next() else {
+        return Err(ApiError::invalid_param(format!("{term:?} is not a searchable term")));
+    };
+    let limit = crate::parse_param::<usize>(&params, "limit")?
+        .unwrap_or(20)
+        .min(MAX_COOCCURRING);
+    let (min_docs, max_share) = frequency_bounds(&params)?;
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let shared = docs
+        .postings(token.stem)
+        .filter_map(|id| docs.document_by_id(id))
+        .collect::<Vec<_>>();
+    if shared.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("No document contains {term:?}"),
+        ));
+    }
+    let mut together: HashMap<Term, usize> = HashMap::new();
+    for doc in &shared {
+        for stem in doc.term_frequency.keys().filter(|stem| **stem != token.stem) {
+            *together.entry(*stem).or_default() += 1;
+        }
+    }
+
+    let total = docs.documents.len() as f64;
+    let max_docs = (max_share * total).floor() as usize;
+    let mut cooccurring = together
+        .into_iter()
+        .filter_map(|(stem, documents)| {
+            let doc_frequency = docs.postings(stem).count();
+            if !(min_docs..=max_docs).contains(&doc_frequency) {
+                return None;
+            }
+            let joint = documents as f64 / total;
+            let expected = shared.len() as f64 / total * doc_frequency as f64 / total;
+            let pmi = (joint / expected).ln();
+            // In every document, the two can't be any more together
+            let score = if joint == 1.0 { 1.0 } else { pmi / -joint.ln() };
+            Some(Cooccurrence {
+                term: get_str(stem),
+                stem,
+                documents,
+                doc_frequency,
+                score,
+            })
+        })
+        .collect::<Vec<_>>();
+    cooccurring.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.documents.cmp(&a.documents))
+            .then_with(|| a.term.cmp(b.term))
+    });
+    cooccurring.truncate(limit);
+
+    // Only the stems listed are worth finding a spelling for
+    for entry in &mut cooccurring {
+        let mut spellings: HashMap<Term, usize> = HashMap::new();
+        for forms in shared.iter().filter_map(|doc| doc.surface_forms.get(&entry.stem)) {
+            for (surface, count) in forms {
+                *spellings.entry(*surface).or_default() += count;
+            }
+        }
+        if let Some((surface, _)) = spellings.into_iter().max_by(|(a, a_count), (b, b_count)| {
+            a_count.cmp(b_count).then_with(|| get_str(*b).cmp(get_str(*a)))
+        }) {
+            entry.term = get_str(surface);
+        }
+    }
+    Ok(Json(CooccurringTerms {
+        term,
+        stem: token.stem,
+        doc_frequency: shared.len(),
+        cooccurring,
+    }))
+}
@@ -0,0 +1,404 @@
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+use crate::extract;
+use crate::intern;
+use crate::{DocShared, Document, Submitted};
+
+/// Extensions picked up by a directory ingest; other files are skipped
+/// rather than tried as PDFs
+const EXTENSIONS: &[&str] = &["pdf", "txt", "md", "markdown", "html", "htm"];
+
+/// One `progress` event, sent after each file
+#[derive(Debug, Serialize)]
+struct Progress {
+    file: String,
+    ok: bool,
+    /// Indexed already from the same bytes, so left alone; see `resume`
+    skipped: bool,
+    /// How `dupe` played out, when the file was indexed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    submitted: Option<Submitted>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiError>,
+    /// Time spent on this file, including waiting for the index lock
+    elapsed_ms: u64,
+    /// Files handled so far, including this one
+    done: usize,
+    failed: usize,
+    total: usize,
+}
+
+/// The final `done` event
+#[derive(Debug, Serialize)]
+struct Summary {
+    /// Files indexed, including replacements and renames
+    indexed: usize,
+    replaced: usize,
+    renamed: usize,
+    /// Titles already indexed and left alone under `dupe=ignore`
+    ignored: usize,
+    /// Files indexed already from the same bytes, by an earlier ingest
+    skipped: usize,
+    failed: usize,
+    total: usize,
+    /// Wall-clock time for the whole directory
+    elapsed_ms: u64,
+    concurrency: usize,
+}
+
+/// Every supported file under `dir`, recursively, in a stable order
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file at `file` is indexed already, under the path a submit
+/// would store, from the same bytes, going by `indexed` (path to content hash)
+fn already_indexed(indexed: &HashMap<String, u64>, file: String) -> bool {
+    let Ok(path) = crate::resolve_path(file) else {
+        return false;
+    };
+    let Some(&hash) = indexed.get(&path) else {
+        return false;
+    };
+    std::fs::read(&path).is_ok_and(|bytes| extract::content_hash(&bytes) == hash)
+}
+
+/// `GET /api/document/submit_dir?path=..`
+///
+/// Indexes every supported file under `path` on a background task and streams
+/// a `progress` event per file, then one `done` event. The other `/submit`
+/// parameters (`dupe`, page range, ...) apply to each file, so one `dupe`
+/// policy covers every collision in the batch; each `progress` event says
+/// what it did. Closing the stream doesn't stop the ingest.
+///
+/// Up to `concurrency` files (at most `PAPER_ENGINE_INGEST_CONCURRENCY`) are
+/// extracted at once, each on the blocking pool; the index is only locked to
+/// add each finished file. Progress events come in the order files finish.
+///
+/// With `resume` (the default), a file already indexed from the same path
+/// and bytes is skipped, so an ingest that was interrupted can be run again
+/// and picks up where it stopped. The index itself records what's done: what
+/// reached the last cache save, and with `PAPER_ENGINE_WAL` everything
+/// indexed before a crash. `resume=false` submits every file again.
+pub async fn submit_dir(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let dir = params
+        .get("path")
+        .ok_or_else(|| ApiError::missing_param("Missing `path` parameter; give a directory"))?
+        .to_owned();
+    let concurrency = crate::parse_param::<usize>(&params, "concurrency")?
+        .unwrap_or(CONFIG.ingest_concurrency)
+        .clamp(1, CONFIG.ingest_concurrency);
+    let resume = crate::bool_param(&params, "resume")?.unwrap_or(true);
+    // Checked up front, rather than failing on each collision
+    if let Some(dupe) = params.get("dupe") {
+        if !["replace", "rename", "ignore"].contains(&dupe.as_str()) {
+            return Err(ApiError::invalid_param(format!(
+                "Invalid `dupe` {dupe:?}; expected \"replace\", \"rename\" or \"ignore\""
+            )));
+        }
+    }
+    if !Path::new(&dir).is_dir() {
+        return Err(ApiError::new(ErrorCode::NotAFile, format!("{dir:?} is not a directory")));
+    }
+    let files = {
+        let dir = dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut files = vec![];
+            collect_files(Path::new(&dir), &mut files).map(|_| files)
+        })
+        .await
+        .map_err(|e| ApiError::internal(format!("Directory walk failed: {e}")))?
+        .map_err(|e| ApiError::internal(format!("Could not read {dir:?}: {e}")))?
+    };
+    // What was indexed before this ingest; nothing it adds is walked twice
+    let indexed: HashMap<String, u64> = if resume {
+        let docs = docs
+            .read()
+            .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+        docs.documents
+            .values()
+            .filter_map(|doc| Some((doc.path.clone(), doc.content_hash?)))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    let indexed = Arc::new(indexed);
+
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let total = files.len();
+        let (results_tx, mut results) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let params = Arc::new(params);
+            let semaphore = Arc::new(Semaphore::new(concurrency));
+            for path in files {
+                let permit = Arc::clone(&semaphore)
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed");
+                let (docs, params, indexed, results_tx) = (
+                    Arc::clone(&docs),
+                    Arc::clone(&params),
+                    Arc::clone(&indexed),
+                    results_tx.clone(),
+                );
+                tokio::spawn(async move {
+                    // Outlives the request, so it needs its own
+                    let _pin = intern::pin().await;
+                    let file = path.to_string_lossy().into_owned();
+                    let started = Instant::now();
+                    let skip = {
+                        let file = file.clone();
+                        tokio::task::spawn_blocking(move || already_indexed(&indexed, file))
+                            .await
+                            .unwrap_or(false)
+                    };
+                    let result = if skip {
+                        Ok(None)
+                    } else {
+                        crate::submit_path(&docs, file.clone(), &params)
+                            .await
+                            .map(Some)
+                    };
+                    drop(permit);
+                    let _ = results_tx.send((file, result, started.elapsed()));
+                });
+            }
+        });
+
+        // Ends once every worker has reported (or died trying)
+        let (mut done, mut failed) = (0, 0);
+        let (mut replaced, mut renamed, mut ignored, mut skipped) = (0, 0, 0, 0);
+        while let Some((file, result, elapsed)) = results.recv().await {
+            done += 1;
+            let was_skipped = matches!(result, Ok(None));
+            let (submitted, error) = match result {
+                Ok(submitted) => (submitted, None),
+                Err(e) => (None, Some(e)),
+            };
+            match &submitted {
+                Some(Submitted::Replaced) => replaced += 1,
+                Some(Submitted::Renamed { .. }) => renamed += 1,
+                Some(Submitted::Ignored) => ignored += 1,
+                Some(Submitted::Added) => {}
+                None if was_skipped => skipped += 1,
+                None => failed += 1,
+            }
+            let progress = Progress {
+                file,
+                ok: error.is_none(),
+                skipped: was_skipped,
+                submitted,
+                error,
+                elapsed_ms: elapsed.as_millis() as u64,
+                done,
+                failed,
+                total,
+            };
+            // A dropped receiver just means nobody is watching any more
+            let _ = tx.send(Event::default().event("progress").json_data(progress)).await;
+        }
+        let summary = Summary {
+            indexed: done - failed - ignored - skipped,
+            replaced,
+            renamed,
+            ignored,
+            skipped,
+            failed,
+            total,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            concurrency,
+        };
+        tracing::info!("Directory ingest finished: {summary:?}");
+        let _ = tx.send(Event::default().event("done").json_data(summary)).await;
+    });
+
+    let stream = ReceiverStream::new(rx).filter_map(|event| event.ok().map(Ok));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Body of `/check_dupes`: files to check, a directory to walk, or both
+#[derive(Debug, Deserialize)]
+pub struct DupeCheckRequest {
+    #[serde(default)]
+    paths: Vec<String>,
+    dir: Option<String>,
+}
+
+/// An indexed document a file would collide with
+#[derive(Debug, Serialize)]
+struct Existing {
+    id: u32,
+    title: String,
+    path: String,
+}
+
+impl From<&Document> for Existing {
+    fn from(doc: &Document) -> Self {
+        Self {
+            id: doc.id,
+            title: doc.title.clone(),
+            path: doc.path.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DupeOutcome {
+    New,
+    /// The title is taken; `dupe` decides what a submit does about it
+    TitleCollision,
+    /// A document with another title was indexed from the same bytes; a
+    /// submit would add the file again
+    ContentMatch,
+    /// The file couldn't be read, so a submit would fail too
+    Error,
+}
+
+/// What submitting one file would run into
+#[derive(Debug, Serialize)]
+struct DupeCheck {
+    file: String,
+    outcome: DupeOutcome,
+    /// The title the file would be indexed under
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_collision: Option<Existing>,
+    /// Reported alongside a title collision too, when the bytes also match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_match: Option<Existing>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiError>,
+}
+
+/// `POST /api/document/check_dupes`: for each of `paths`, and every
+/// supported file under `dir`, whether submitting it would add a new
+/// document, hit a title already indexed, or add bytes already indexed
+/// under another title. Nothing is indexed; only each file's title is read,
+/// so this is much cheaper than the ingest it previews. Takes `password` for
+/// encrypted PDFs, like `/submit`.
+pub async fn check_dupes(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+    Json(request): Json<DupeCheckRequest>,
+) -> Result<Json<Vec<DupeCheck>>, ApiError> {
+    let DupeCheckRequest { mut paths, dir } = request;
+    if let Some(dir) = dir {
+        if !Path::new(&dir).is_dir() {
+            return Err(ApiError::new(ErrorCode::NotAFile, format!("{dir:?} is not a directory")));
+        }
+        let files = tokio::task::spawn_blocking(move || {
+            let mut files = vec![];
+            collect_files(Path::new(&dir), &mut files)
+                .map(|_| files)
+                .map_err(|e| ApiError::internal(format!("Could not read {dir:?}: {e}")))
+        })
+        .await
+        .map_err(|e| ApiError::internal(format!("Directory walk failed: {e}")))??;
+        paths.extend(files.iter().map(|path| path.to_string_lossy().into_owned()));
+    }
+    if paths.is_empty() {
+        return Err(ApiError::missing_param(
+            "Nothing to check; give `paths`, a `dir`, or both in the body",
+        ));
+    }
+    let password = params.get("password").cloned();
+
+    // Titles need poppler, so the files are read on the blocking pool and
+    // outside the index lock
+    let peeked = tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .map(|file| {
+                let peek = crate::resolve_path(file.clone()).and_then(|path| {
+                    let title = extract::peek_title(&path, password.as_deref())?;
+                    let bytes = std::fs::read(&path).map_err(|e| {
+                        let message = format!("Could not read file: {path:?}: {e}");
+                        ApiError::new(ErrorCode::OpenFailed, message)
+                    })?;
+                    // A derived title depends on what's indexed; see below
+                    Ok((title, path, extract::content_hash(&bytes)))
+                });
+                (file, peek)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Duplicate check task failed: {e}")))?;
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let checks = peeked
+        .into_iter()
+        .map(|(file, peek)| match peek {
+            Ok((title, path, hash)) => {
+                let title = title.unwrap_or_else(|| docs.derived_title(&path));
+                let title_collision = docs.documents.get(&title).map(Existing::from);
+                let content_match = docs
+                    .documents
+                    .values()
+                    .filter(|doc| doc.content_hash == Some(hash))
+                    .min_by_key(|doc| doc.id)
+                    .map(Existing::from);
+                let outcome = match (&title_collision, &content_match) {
+                    (Some(_), _) => DupeOutcome::TitleCollision,
+                    (None, Some(_)) => DupeOutcome::ContentMatch,
+                    (None, None) => DupeOutcome::New,
+                };
+                DupeCheck {
+                    file,
+                    outcome,
+                    title: Some(title),
+                    title_collision,
+                    content_match,
+                    error: None,
+                }
+            }
+            Err(e) => DupeCheck {
+                file,
+                outcome: DupeOutcome::Error,
+                title: None,
+                title_collision: None,
+                content_match: None,
+                error: Some(e),
+            },
+        })
+        .collect();
+    Ok(Json(checks))
+}
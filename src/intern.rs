@@ -1,11 +1,29 @@
+use axum::{extract::Request, middleware::Next, response::Response};
 use std::mem::MaybeUninit;
 use std::sync::{Once, RwLock};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
+use tokio::sync::{RwLock as AsyncRwLock, RwLockReadGuard, RwLockWriteGuard};
 
 static mut POOL: MaybeUninit<RwLock<StringPool>> = MaybeUninit::uninit();
 static ONCE: Once = Once::new();
 
+// Ids, and the strings `get_str` hands out, stay valid until the pool is
+// collected (see `gc.rs`), which frees unused strings and renumbers the rest.
+// Whatever holds them outside a collection's lock (a request serializing its
+// response, an extraction on its way to the index) holds a `Pin`, and a
+// collection waits for every pin to go first.
+
+lazy_static::lazy_static! {
+    static ref GATE: AsyncRwLock<()> = AsyncRwLock::new(());
+}
+
+/// Keeps the pool from being collected while held
+pub type Pin = RwLockReadGuard<'static, ()>;
+
+/// Held by a collection; no `Pin` is held meanwhile
+pub type Exclusive = RwLockWriteGuard<'static, ()>;
+
 #[derive(Clone, Copy, Eq, PartialEq, Hash)]
 pub struct PoolId (usize);
 
@@ -29,6 +47,13 @@ impl std::fmt::Display for PoolId {
     }
 }
 
+/// Serialized as the string itself; ids are only meaningful to this process
+impl serde::Serialize for PoolId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(get_str(*self))
+    }
+}
+
 pub fn intern(s: impl AsRef<str>) -> PoolId {
     ONCE.call_once(|| unsafe {
         POOL.write(RwLock::new(StringPool {
@@ -57,3 +82,88 @@ pub fn get_str(PoolId(id): PoolId) -> &'static str {
     let s = pool.read().unwrap().flat_pool[id];
     s
 }
+
+/// Number of interned strings and the bytes of text they hold. Strings are
+/// leaked, so this only grows until the pool is collected.
+pub fn pool_stats() -> (usize, usize) {
+    if !ONCE.is_completed() {
+        return (0, 0);
+    }
+    let pool = unsafe { POOL.assume_init_ref() };
+    let pool = pool.read().unwrap();
+    let bytes = pool.flat_pool.iter().map(|s| s.len()).sum();
+    (pool.flat_pool.len(), bytes)
+}
+
+pub async fn pin() -> Pin {
+    GATE.read().await
+}
+
+/// Middleware pinning the pool for the whole of a request, so strings it
+/// serializes after letting go of a collection's lock stay valid
+pub async fn pin_request(request: Request, next: Next) -> Response {
+    let _pin = pin().await;
+    next.run(request).await
+}
+
+/// Waits for every `Pin` to go. New pins wait behind it, so whoever calls
+/// this mustn't hold one.
+pub async fn exclusive() -> Exclusive {
+    GATE.write().await
+}
+
+/// Where `collect` moved each id, by old id
+pub struct Remap(Vec<Option<usize>>);
+
+impl Remap {
+    /// Panics for an id that was freed: every id still in use has to have
+    /// been in `live`
+    pub fn get(&self, PoolId(id): PoolId) -> PoolId {
+        PoolId(self.0[id].expect("an id still in use was collected"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Collected {
+    pub entries_removed: usize,
+    pub bytes_reclaimed: usize,
+    /// What the pool holds afterwards
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// Frees every string not in `live` and renumbers the rest, keeping their
+/// order. Every id held anywhere has to be in `live`, and be remapped before
+/// it's used again, so the caller holds every collection's write lock
+/// throughout as well as `Exclusive`.
+pub fn collect(_exclusive: &Exclusive, live: &HashSet<PoolId>) -> (Remap, Collected) {
+    if !ONCE.is_completed() {
+        return (Remap(vec![]), Collected::default());
+    }
+    let pool = unsafe { POOL.assume_init_ref() };
+    let mut pool = pool.write().unwrap();
+    let old = std::mem::take(&mut pool.flat_pool);
+    pool.map_pool.clear();
+    let mut remap = Vec::with_capacity(old.len());
+    let mut collected = Collected::default();
+    for (id, s) in old.into_iter().enumerate() {
+        if live.contains(&PoolId(id)) {
+            let new_id = pool.flat_pool.len();
+            pool.flat_pool.push(s);
+            pool.map_pool.insert(s, new_id);
+            remap.push(Some(new_id));
+            collected.bytes += s.len();
+        } else {
+            remap.push(None);
+            collected.entries_removed += 1;
+            collected.bytes_reclaimed += s.len();
+            // SAFETY: leaked by `intern`, and with nothing holding its id or
+            // the string any more it's never read again
+            unsafe { drop(Box::from_raw(s as *const str as *mut str)) };
+        }
+    }
+    pool.flat_pool.shrink_to_fit();
+    pool.map_pool.shrink_to_fit();
+    collected.entries = pool.flat_pool.len();
+    (Remap(remap), collected)
+}
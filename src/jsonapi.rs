@@ -0,0 +1,122 @@
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+use crate::search::{Field, QueryEcho, ScoreBucket, SearchOutcome};
+use crate::spellcheck::DidYouMean;
+use crate::{DocumentAttributes, DocumentInfo};
+
+/// JSON:API's own media type, rather than plain `application/json`
+pub const MEDIA_TYPE: &str = "application/vnd.api+json";
+
+/// A JSON:API resource object. Documents and search results are both of type
+/// `documents`, identified by the document id as a string.
+#[derive(Debug, Serialize)]
+pub struct Resource<A> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+    attributes: A,
+}
+
+/// A JSON:API top-level document
+#[derive(Debug, Serialize)]
+struct TopLevel<D, M> {
+    data: D,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<M>,
+}
+
+/// What the plain `/list` response only has in its `X-Total-Count` header
+#[derive(Debug, Serialize)]
+pub struct ListMeta {
+    pub total: usize,
+    pub offset: usize,
+}
+
+/// Everything in the plain `/search` envelope except `results`
+#[derive(Debug, Serialize)]
+struct SearchMeta {
+    query: QueryEcho,
+    total: usize,
+    offset: usize,
+    limit: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    clipped: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collapsed: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    near_duplicates: Option<usize>,
+    min_score: u64,
+    below_min_score: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field_matches: Option<BTreeMap<Field, usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score_histogram: Option<Vec<ScoreBucket>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    did_you_mean: Option<DidYouMean>,
+}
+
+pub fn document(info: DocumentInfo) -> Resource<DocumentAttributes> {
+    Resource {
+        kind: "documents",
+        id: info.id.to_string(),
+        attributes: info.attributes,
+    }
+}
+
+/// `data` (and `meta`) as a JSON:API response, alongside `headers`
+pub fn response<D: serde::Serialize, M: serde::Serialize>(
+    mut headers: HeaderMap,
+    data: D,
+    meta: Option<M>,
+) -> Response {
+    headers.insert(header::CONTENT_TYPE, MEDIA_TYPE.parse().unwrap());
+    (headers, Json(TopLevel { data, meta })).into_response()
+}
+
+/// `data` as a JSON:API response with no `meta`
+pub fn data_response<D: serde::Serialize>(data: D) -> Response {
+    response::<D, ()>(HeaderMap::new(), data, None)
+}
+
+/// A search as JSON:API: each result is a `documents` resource with the
+/// result fields as attributes, and the rest of the envelope goes in `meta`.
+/// `id_of` finds the document id for a result, whole or sparse.
+pub fn search_response<R: serde::Serialize>(
+    headers: HeaderMap,
+    outcome: SearchOutcome<R>,
+    offset: usize,
+    id_of: impl Fn(&R) -> Option<u32>,
+) -> Response {
+    let data = outcome
+        .results
+        .into_iter()
+        .filter_map(|result| {
+            Some(Resource {
+                kind: "documents",
+                id: id_of(&result)?.to_string(),
+                attributes: result,
+            })
+        })
+        .collect::<Vec<_>>();
+    let meta = SearchMeta {
+        query: outcome.query,
+        total: outcome.total,
+        offset,
+        limit: outcome.limit,
+        clipped: outcome.clipped,
+        collapsed: outcome.collapsed,
+        near_duplicates: outcome.near_duplicates,
+        min_score: outcome.min_score,
+        below_min_score: outcome.below_min_score,
+        field_matches: outcome.field_matches,
+        score_histogram: outcome.score_histogram,
+        did_you_mean: outcome.did_you_mean,
+    };
+    response(headers, data, Some(meta))
+}
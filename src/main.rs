@@ -1,316 +1,1590 @@
 #![allow(dead_code, unused_imports)]
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    extract::{
+        multipart::MultipartError, DefaultBodyLimit, Multipart, OriginalUri, Path, Query, Request,
+        State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use poppler::{PopplerPage, PopplerDocument};
 use rust_stemmers::{Algorithm, Stemmer};
-use serde::{Deserialize, Serialize};
+use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::collections::BinaryHeap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::io::Read;
 use std::net::{TcpListener, TcpStream};
 use std::path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::RwLock;
-use tokio::signal;
 use tower::ServiceExt;
 use tower_http::{
-    services::{ServeDir, ServeFile},
+    services::ServeDir,
     trace::TraceLayer,
 };
 
+use crate::collection::Collection;
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+use crate::extract::Extraction;
+use crate::format::Format;
 use crate::intern::{get_str, intern, PoolId};
+use crate::ranking::RankingConfig;
+use crate::readability::ListSort;
+use crate::search::{ResultFields, SearchOutcome, SearchRequest, SparseResult};
+use crate::title::TitleSource;
 
+mod admin;
+mod analyzer;
+mod blocklist;
+mod cli;
+mod collection;
+mod config;
+mod describe;
+mod error;
+mod extract;
+mod feed;
 mod file_format;
+mod format;
+mod gc;
+mod highlight;
+mod html;
+mod index;
+mod ingest;
 mod intern;
+mod jsonapi;
+mod math;
+mod notes;
+mod paging;
+mod pdf;
+mod pdf_pool;
+mod query;
+mod ranking;
+mod readability;
+mod redact;
+mod result_cache;
+mod search;
+mod shutdown;
+mod similar;
+mod spellcheck;
+mod stats;
+mod tags;
+mod thumbnail;
+mod title;
+mod verify;
+mod wal;
+mod why;
+mod zip;
 
 const CACHE_PATH: &str = "paper-engine-cache.pec";
 
 lazy_static::lazy_static! {
     pub static ref STEMMER: Stemmer = Stemmer::create(Algorithm::English);
+    /// Unix seconds the server started at; see `response_etag`
+    static ref STARTED_AT: u64 = unix_now();
+    /// Held while a cache is written; see `save_cache`
+    static ref SAVING: std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
 }
 
 fn log<T: std::fmt::Debug>(msg: T) -> T {
-    eprintln!("{msg:#?}");
+    tracing::info!("{msg:?}");
     msg
 }
 
+// The UI is compiled in, so the binary runs from anywhere
 async fn root() -> Html<&'static str> {
     include_str!("index.html").into()
 }
 
-#[derive(Debug, Default)]
+async fn stylesheet() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/css")], include_str!("style.css"))
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct TfIdf {
     global_term_count: HashMap<Term, usize>,
     documents: HashMap<String, Document>,
+    /// Never handed out twice, even after the document holding an id is
+    /// removed; saved in the cache
+    next_id: u32,
+    /// Ids of the documents containing each term; derived, see `index.rs`
+    postings: HashMap<Term, BTreeSet<u32>>,
+    /// Title of each document id; derived, see `index.rs`
+    titles: HashMap<u32, String>,
+    /// Every spelling seen, with the stem it's indexed under, sorted for
+    /// `prefix*` lookups; derived, see `index.rs`
+    surfaces: BTreeMap<&'static str, Term>,
+    ranking: RankingConfig,
+    /// Stems kept out of indexing and search; see `blocklist.rs`
+    blocklist: HashSet<Term>,
+    /// `analyzer::fingerprint` of the settings the loaded cache was built
+    /// with; `None` for a new index or a cache from before it was recorded
+    analyzer: Option<String>,
+    /// Bumped by every change to the documents or ranking; not saved, so it
+    /// starts from 0 each run. See `index.rs`.
+    generation: u64,
+    /// `generation` as of the last cache save
+    saved_generation: u64,
+    /// Unix seconds of the last cache save; `None` until one this run
+    saved_at: Option<u64>,
+    /// Where changes are logged before they're made, with
+    /// `PAPER_ENGINE_WAL`; shared by snapshots so a save can truncate it
+    wal: Option<Arc<wal::Wal>>,
+    /// Sequence number of the last write-ahead log entry applied; saved in
+    /// the cache
+    wal_sequence: u64,
+    /// Recent search outcomes; shared by snapshots, see `result_cache.rs`
+    result_cache: Arc<result_cache::ResultCache>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Document {
+    /// Assigned by `TfIdf::insert_document` and saved in the cache, so it
+    /// stays the same across restarts and reindexes
+    id: u32,
     title: String,
     path: String,
-    // TODO: Add notes and tags
+    /// The user's own notes; see `notes.rs`
+    notes: String,
+    /// Lowercased; see `tags::normalize_tag`
+    tags: BTreeSet<String>,
     term_frequency: HashMap<Term, f64>,
+    /// Frequencies over just the first few pages (see `Config::abstract_pages`);
+    /// these terms are also counted in `term_frequency`
+    abstract_frequency: HashMap<Term, f64>,
+    /// Frequencies over PDF highlights and notes, when submitted with
+    /// `annotations=true`; these terms are also counted in `term_frequency`
+    annotation_frequency: HashMap<Term, f64>,
+    /// What the notes added to each term in `term_frequency`, to take back
+    /// out when they change
+    note_frequency: HashMap<Term, f64>,
+    /// Every lowercased, unstemmed spelling seen for each stem, with how many
+    /// times it occurred; lets `exact=true` searches skip stemming
+    surface_forms: HashMap<Term, HashMap<Term, usize>>,
+    /// Zero-based page each term first appears on, for `position_weight`;
+    /// empty for HTML and for documents indexed before it was recorded
+    first_page: HashMap<Term, u32>,
+    /// Total number of words indexed from the document (not unique terms)
+    token_count: usize,
+    /// 1-based inclusive pages that were indexed; `None` means the whole document
+    page_range: Option<(u32, u32)>,
+    favorite: bool,
+    read: bool,
+    /// Kept out of IDF and, unless asked for, out of search results, e.g.
+    /// for templates and cover sheets. Its terms still count in
+    /// `global_term_count`, which doesn't feed IDF.
+    exclude_from_corpus: bool,
+    /// Only its `PAPER_ENGINE_MAX_TERMS_PER_DOCUMENT` most frequent terms
+    /// were kept
+    truncated: bool,
+    /// The PDF needed a password. Its text is always stored, since the
+    /// password isn't.
+    encrypted: bool,
+    /// The file had no title of its own, so `title` was made from its file
+    /// name; see `extract::title_from_path`
+    title_derived: bool,
+    /// Where `title` came from; `None` for documents indexed before it was
+    /// recorded
+    title_source: Option<TitleSource>,
+    /// The PDF has little or no text for its pages, so is probably scanned
+    /// images and wants OCR; see `extract::likely_scanned`
+    likely_scanned: bool,
+    /// Unix seconds when the document was submitted; `None` for documents
+    /// loaded from caches written before this was recorded
+    indexed_at: Option<u64>,
+    /// The extracted text, for documents submitted with `store_text=true`
+    text: Option<String>,
+    /// The PDF's bookmarks; empty for other formats and PDFs without any
+    outline: Vec<pdf::OutlineEntry>,
+    /// `extract::content_hash` of the submitted file; `None` for documents
+    /// from caches written before it was recorded
+    content_hash: Option<u64>,
+    /// Words, letters, syllables and sentences in the text; `None` for
+    /// documents from caches written before they were counted
+    text_stats: Option<readability::TextStats>,
 }
 
-type Term = PoolId;
-
-type DocShared = Arc<RwLock<TfIdf>>;
+/// What `document_info` and `list_documents` return for each document
+#[derive(Debug, Serialize)]
+pub struct DocumentInfo {
+    id: u32,
+    /// Everything but the id, inline; kept apart for `format=jsonapi`, where
+    /// the id goes beside the attributes
+    #[serde(flatten)]
+    attributes: DocumentAttributes,
+}
 
-impl TfIdf {
-    // TODO: Give higher weights to exact matches over stemmed matches
-    //
-    // Also normalize to not favor longer documents ("the")
-    pub fn sort_documents(&self, terms: &[Term]) -> Vec<(u64, String, String)> {
-        let mut documents = BTreeMap::new();
-        for term in terms {
-            let mut term_contains_all = 0;
-            for (_, doc) in &self.documents {
-                term_contains_all += doc.term_frequency.contains_key(term) as usize;
-            }
+#[derive(Debug, Serialize)]
+pub struct DocumentAttributes {
+    title: String,
+    path: String,
+    token_count: usize,
+    page_range: Option<(u32, u32)>,
+    tags: BTreeSet<String>,
+    notes: String,
+    favorite: bool,
+    read: bool,
+    exclude_from_corpus: bool,
+    truncated: bool,
+    encrypted: bool,
+    title_derived: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_source: Option<TitleSource>,
+    likely_scanned: bool,
+    indexed_at: Option<u64>,
+    /// Whether `/document/{id}/text` has the text
+    stored_text: bool,
+    /// Words in the text, as `readability::TextStats` counts them. This and
+    /// the averages are `None` for documents indexed before they were counted.
+    word_count: Option<u64>,
+    /// Distinct stems indexed
+    unique_terms: usize,
+    #[serde(serialize_with = "search::rounded_option")]
+    average_word_length: Option<f64>,
+    /// Flesch reading ease; also `None` for too little text to tell
+    #[serde(serialize_with = "search::rounded_option")]
+    reading_ease: Option<f64>,
+}
 
-            let idf =
-                ((self.documents.len() as f64 + 1.0) / (term_contains_all as f64 + 1.0)).log10();
-
-            for (_, doc) in &self.documents {
-                if let Some(freq) = doc.term_frequency.get(term) {
-                    eprintln!(
-                        "freq: {freq}, idf: {idf}, title: {}, term: {term}",
-                        doc.title
-                    );
-                    let score = (100000.0 * idf * freq) as u64;
-                    documents
-                        .entry(&doc.title)
-                        .and_modify(|v| *v += score)
-                        .or_insert(score);
-                }
-            }
+impl From<&Document> for DocumentInfo {
+    fn from(doc: &Document) -> Self {
+        let attributes = DocumentAttributes {
+            title: doc.title.clone(),
+            path: doc.path.clone(),
+            token_count: doc.token_count,
+            page_range: doc.page_range,
+            tags: doc.tags.clone(),
+            notes: doc.notes.clone(),
+            favorite: doc.favorite,
+            read: doc.read,
+            exclude_from_corpus: doc.exclude_from_corpus,
+            truncated: doc.truncated,
+            encrypted: doc.encrypted,
+            title_derived: doc.title_derived,
+            title_source: doc.title_source,
+            likely_scanned: doc.likely_scanned,
+            indexed_at: doc.indexed_at,
+            stored_text: doc.text.is_some(),
+            word_count: doc.text_stats.map(|stats| stats.words),
+            unique_terms: doc.term_frequency.len(),
+            average_word_length: doc.text_stats.and_then(|stats| stats.average_word_length()),
+            reading_ease: doc.text_stats.and_then(|stats| stats.reading_ease()),
+        };
+        Self {
+            id: doc.id,
+            attributes,
         }
+    }
+}
+
+/// Metadata filters shared by search and list; `None` means "don't care"
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct DocumentFilter {
+    favorite: Option<bool>,
+    read: Option<bool>,
+    /// Has at least one tag
+    tagged: Option<bool>,
+    /// Lowercased; the path contains it, ignoring case
+    path: Option<String>,
+    /// Only these documents, such as candidates retrieved elsewhere to be
+    /// re-ranked here
+    ids: Option<BTreeSet<u32>>,
+}
+
+impl DocumentFilter {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        Ok(Self {
+            favorite: bool_param(params, "favorite")?,
+            read: bool_param(params, "read")?,
+            tagged: bool_param(params, "tagged")?,
+            path: params
+                .get("path")
+                .map(|path| path.to_lowercase())
+                .filter(|path| !path.is_empty()),
+            ids: ids_param(params)?,
+        })
+    }
+
+    fn matches(&self, doc: &Document) -> bool {
+        self.favorite.map_or(true, |v| v == doc.favorite)
+            && self.read.map_or(true, |v| v == doc.read)
+            && self.tagged.map_or(true, |v| v == !doc.tags.is_empty())
+            && self.allows_id(doc.id)
+            && self.path_position(doc).is_some()
+    }
 
-        let mut doc_list = vec![];
-        for (title, tf_idf) in documents {
-            let path = self.documents.get(title).unwrap().path.clone();
-            doc_list.push((tf_idf / terms.len() as u64, path, title.to_owned()));
+    /// Whether `ids`, if given, has `id`
+    fn allows_id(&self, id: u32) -> bool {
+        self.ids.as_ref().map_or(true, |ids| ids.contains(&id))
+    }
+
+    /// The `ids` no document has
+    fn unknown_ids(&self, docs: &TfIdf) -> Vec<u32> {
+        self.ids
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|id| docs.document_by_id(*id).is_none())
+            .collect()
+    }
+
+    /// Byte offset of `path` in the document's lowercased path; `Some(0)`
+    /// when there's no `path` to look for
+    fn path_position(&self, doc: &Document) -> Option<usize> {
+        match &self.path {
+            Some(path) => doc.path.to_lowercase().find(path.as_str()),
+            None => Some(0),
         }
-        doc_list.sort_by(|a, b| b.cmp(a));
-        doc_list
     }
 }
 
-pub fn drop_pdf(doc: PopplerDocument) {
-    struct Layout (*mut u8);
-    unsafe {
-        gobject_sys::g_object_unref(std::mem::transmute::<_, Layout>(doc).0 as *mut gobject_sys::GObject);
+/// The comma-separated document ids of `ids`; `None` when it's missing or
+/// empty
+fn ids_param(params: &HashMap<String, String>) -> Result<Option<BTreeSet<u32>>, ApiError> {
+    let Some(ids) = params.get("ids") else {
+        return Ok(None);
+    };
+    let ids = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse().map_err(|e| {
+                ApiError::invalid_param(format!("Invalid document id {id:?} in `ids`: {e}"))
+            })
+        })
+        .collect::<Result<BTreeSet<u32>, _>>()?;
+    Ok((!ids.is_empty()).then_some(ids))
+}
+
+fn parse_param<T: std::str::FromStr>(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<T>, ApiError>
+where
+    T::Err: std::fmt::Display,
+{
+    params
+        .get(name)
+        .map(|v| {
+            v.parse().map_err(|e| {
+                ApiError::invalid_param(format!("Invalid `{name}` parameter {v:?}: {e}"))
+            })
+        })
+        .transpose()
+}
+
+fn bool_param(params: &HashMap<String, String>, name: &str) -> Result<Option<bool>, ApiError> {
+    match params.get(name).map(|v| v.as_str()) {
+        None => Ok(None),
+        Some("true") => Ok(Some(true)),
+        Some("false") => Ok(Some(false)),
+        Some(s) => Err(ApiError::invalid_param(format!(
+            "Invalid `{name}` parameter {s:?}; use \"true\" or \"false\""
+        ))),
     }
 }
 
-pub fn drop_page(page: PopplerPage) {
-    struct Layout (*mut u8);
-    unsafe {
-        gobject_sys::g_object_unref(std::mem::transmute::<_, Layout>(page).0 as *mut gobject_sys::GObject);
+type Term = PoolId;
+
+type DocShared = Arc<RwLock<TfIdf>>;
+
+impl TfIdf {
+    pub fn document_by_id(&self, id: u32) -> Option<&Document> {
+        self.documents.get(self.titles.get(&id)?)
+    }
+
+    /// Counts as a change to the index, since the caller is about to make one
+    pub fn document_by_id_mut(&mut self, id: u32) -> Option<&mut Document> {
+        let doc = self.documents.get_mut(self.titles.get(&id)?)?;
+        self.generation += 1;
+        Some(doc)
+    }
+
+    /// The title for `path` when the file has none of its own:
+    /// `extract::title_from_path`, unless another file's document has it.
+    /// Then the parent directory is added ("Notes (drafts)"), and failing
+    /// that the title is the path itself, which is how untitled files were
+    /// always titled. The same file submitted again gets the same title, for
+    /// `dupe` to handle.
+    pub fn derived_title(&self, path: &str) -> String {
+        let free = |title: &str| self.documents.get(title).map_or(true, |doc| doc.path == path);
+        let title = extract::title_from_path(path);
+        if free(&title) {
+            return title;
+        }
+        let parent = std::path::Path::new(path)
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .map(|dir| format!("{title} ({})", dir.to_string_lossy()));
+        match parent {
+            Some(title) if free(&title) => title,
+            _ => path.to_owned(),
+        }
     }
 }
 
 async fn submit_document(
     Query(params): Query<HashMap<String, String>>,
     State(docs): State<DocShared>,
-) -> Result<(), String> {
+) -> Result<(), ApiError> {
     let path = params
         .get("path")
-        .ok_or_else(|| log("Missing `path` parameter; give path to document"))?;
-    eprintln!("Submitting document... \"{path}\"");
+        .ok_or_else(|| ApiError::missing_param("Missing `path` parameter; give path to document"))?
+        .to_owned();
+    submit_path(&docs, path, &params).await.map(|_| ())
+}
 
-    if !path::Path::new(path).is_file() {
-        return Err(log(format!("{path:?} is not a file")));
+/// Checks that `path` is a file and, with `PAPER_ENGINE_CANONICALIZE_PATHS`,
+/// makes it absolute
+fn resolve_path(path: String) -> Result<String, ApiError> {
+    if !path::Path::new(&path).is_file() {
+        return Err(ApiError::new(ErrorCode::NotAFile, format!("{path:?} is not a file")));
+    }
+    // `./paper.pdf` and `/abs/path/paper.pdf` are the same document
+    if !CONFIG.canonicalize_paths {
+        return Ok(path);
     }
+    std::fs::canonicalize(&path)
+        .map_err(|e| {
+            ApiError::new(ErrorCode::NotAFile, format!("Could not resolve {path:?}: {e}"))
+        })?
+        .into_os_string()
+        .into_string()
+        .map_err(|p| ApiError::invalid_param(format!("Path {p:?} is not valid UTF-8")))
+}
+
+/// Extracts and indexes one file; `params` carries the `/submit` options
+/// (`dupe`, page range, ...) so bulk ingest can share them
+async fn submit_path(
+    docs: &DocShared,
+    path: String,
+    params: &HashMap<String, String>,
+) -> Result<Submitted, ApiError> {
+    tracing::info!("Submitting document... {}", redact::path(&path));
+    let path = resolve_path(path)?;
 
-    let pdf = PopplerDocument::new_from_file(path, None)
-        .map_err(|e| log(format!("Could not open file: {path:?}: {e}")))?;
-    let mut title = pdf.get_title().unwrap_or(path.to_string());
-    if title.is_empty() {
-        title = path.to_string();
+    let options = extract::ExtractOptions::from_params(params)?;
+    let mut extraction = {
+        let path = path.clone();
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || extract::extract(&path, options))
+            .await
+            .map_err(|e| ApiError::internal(format!("Extraction task failed: {e}")))??
+    };
+    if options.index_title {
+        extraction.add_title_terms(&path);
     }
-    {
-        let mut docs = docs
-            .write()
-            .map_err(|e| log(format!("Could not take `DocShared` lock: {e}")))?;
-        if let Some(doc) = docs.documents.get(&title) {
-            let s = params.get("dupe");
-            let s = s.map(|v| v.as_str());
-            match s {
-                Some("replace") => {
-                    // TODO: Need to update counts
-                    docs.documents.remove(&title);
-                    log(format!("Removing title... {title:?}"));
-                }
-                Some("rename") => {
-                    // TODO: This can collide
-                    title = format!("{title}-1");
-                }
-                Some("ignore") => {
-                    drop_pdf(pdf);
-                    return Ok(());
-                }
-                _ => {
-                    let err_msg = format!(
-                        r#"Found document with identical titles: {:?}: you submitted {:?}, but found {:?}; use query parameters "dupe={{replace,rename,ignore}}" to handle this"#,
-                        title, path, doc.path
-                    );
-                    drop_pdf(pdf);
-                    return Err(log(err_msg));
-                }
-            }
-        }
+    add_extraction(docs, path, extraction, params)
+}
+
+/// What [`add_extraction`] did with a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Submitted {
+    Added,
+    /// `dupe=replace`: took the place of the document with the same title
+    Replaced,
+    /// `dupe=rename`: added under another title
+    Renamed { title: String },
+    /// `dupe=ignore`: the title was taken, so nothing changed
+    Ignored,
+}
+
+/// Indexes an extracted document under `path`, handling `dupe` from `params`
+fn add_extraction(
+    docs: &DocShared,
+    path: String,
+    mut extraction: Extraction,
+    params: &HashMap<String, String>,
+) -> Result<Submitted, ApiError> {
+    let min_doc_tf = parse_param(params, "min_doc_tf")?.unwrap_or(CONFIG.min_doc_tf);
+    if min_doc_tf == 0 {
+        return Err(ApiError::invalid_param("Invalid `min_doc_tf` 0; 1 keeps every term"));
+    }
+    // The extractors refuse text-free files, but an empty document would
+    // still be unsearchable, so nothing gets in without a term
+    if extraction.term_count.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NoText,
+            format!("{path:?} has no extractable text; if it is scanned, try OCR first"),
+        ));
+    }
+    // Duplicate check and insert happen under one lock so concurrent submits
+    // of the same title can't both get in, and the blocklist can't change
+    // between dropping its terms and inserting
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    // Counted before any terms are dropped: the words were all in the document
+    let token_count = extraction.term_count.values().sum();
+    let blocked = extraction.drop_terms(&docs.blocklist);
+    if blocked > 0 {
+        tracing::info!("Dropped {blocked} blocklisted terms from {}", redact::path(&path));
     }
+    if min_doc_tf > 1 {
+        let dropped = extraction.drop_rare_terms(min_doc_tf);
+        tracing::info!(
+            "Dropped {dropped} terms seen fewer than {min_doc_tf} times in {}",
+            redact::path(&path)
+        );
+    }
+    let truncated = CONFIG
+        .max_terms_per_document
+        .is_some_and(|max| extraction.cap_terms(max));
+    let Extraction {
+        title,
+        title_source,
+        term_count,
+        abstract_count,
+        annotation_count,
+        surface_count,
+        heading_count,
+        first_page,
+        page_range,
+        text,
+        encrypted,
+        outline,
+        content_hash,
+        trimmed_lines,
+        likely_scanned,
+        text_stats,
+    } = extraction;
+    if trimmed_lines > 0 {
+        tracing::info!(
+            "Trimmed {trimmed_lines} repeated header and footer lines from {}",
+            redact::path(&path)
+        );
+    }
+    let title_derived = title.is_none();
+    let title_source = match title_derived {
+        true => TitleSource::Filename,
+        false => title_source.unwrap_or(TitleSource::Metadata),
+    };
+    let mut title = title.unwrap_or_else(|| docs.derived_title(&path));
+    let mut submitted = Submitted::Added;
 
-    let mut term_count = HashMap::new();
-    {
-        let mut docs = docs
-            .write()
-            .map_err(|e| log(format!("Could not take `DocShared` lock: {e}")))?;
-        for page in pdf.pages() {
-            if let Some(text) = page.get_text() {
-                for word in text.split_whitespace() {
-                    let word = word.to_lowercase();
-                    let word = STEMMER.stem(&word);
-                    let id = intern(word);
-                    term_count
-                        .entry(id)
-                        .and_modify(|v| *v += 1)
-                        .or_insert(1);
-                    docs.global_term_count
-                        .entry(id)
-                        .and_modify(|v| *v += 1)
-                        .or_insert(1);
-                }
+    if let Some(doc) = docs.documents.get(&title) {
+        let s = params.get("dupe");
+        let s = s.map(|v| v.as_str());
+        match s {
+            Some("replace") => {
+                // TODO: Need to update counts
+                // `insert_document` replaces it, keeping its id
+                log(format!("Replacing title... {}", redact::text(&title)));
+                submitted = Submitted::Replaced;
+            }
+            Some("rename") => {
+                // The first free one, so ingesting the same files again
+                // adds copies rather than replacing earlier ones
+                title = (1..)
+                    .map(|n| format!("{title}-{n}"))
+                    .find(|renamed| !docs.documents.contains_key(renamed))
+                    .unwrap();
+                submitted = Submitted::Renamed {
+                    title: title.clone(),
+                };
+            }
+            Some("ignore") => {
+                return Ok(Submitted::Ignored);
+            }
+            _ => {
+                let err_msg = format!(
+                    r#"Found document with identical titles: {:?}: you submitted {:?}, but found {:?}; use query parameters "dupe={{replace,rename,ignore}}" to handle this"#,
+                    title, path, doc.path
+                );
+                return Err(ApiError::new(ErrorCode::DuplicateTitle, err_msg));
             }
-            drop_page(page);
         }
     }
 
-    drop_pdf(pdf);
+    // Extraction already refuses documents without words; never divide by
+    // zero regardless
+    let distinct_terms = term_count.len().max(1) as f64;
     let mut term_frequency = HashMap::new();
     for (term, n) in &term_count {
+        // Heading occurrences count `heading_weight` times instead of once
+        let headings = heading_count.get(term).copied().unwrap_or(0) as f64;
+        let weighted = *n as f64 + headings * (CONFIG.heading_weight - 1.0);
         assert!(term_frequency
-            .insert(term.to_owned(), *n as f64 / term_count.len() as f64)
+            .insert(term.to_owned(), weighted / distinct_terms)
             .is_none());
     }
+    let abstract_frequency = abstract_count
+        .iter()
+        .map(|(term, n)| (*term, *n as f64 / abstract_count.len() as f64))
+        .collect();
+    let annotation_frequency = annotation_count
+        .iter()
+        .map(|(term, n)| (*term, *n as f64 / annotation_count.len() as f64))
+        .collect();
 
     let document = Document {
-        path: path.to_string(),
-        title: title.clone(),
+        id: 0,
+        path,
+        title,
+        notes: String::new(),
         term_frequency,
+        abstract_frequency,
+        annotation_frequency,
+        note_frequency: HashMap::new(),
+        surface_forms: surface_count,
+        first_page,
+        token_count,
+        page_range,
+        tags: BTreeSet::new(),
+        favorite: false,
+        read: false,
+        exclude_from_corpus: false,
+        truncated,
+        encrypted,
+        title_derived,
+        title_source: Some(title_source),
+        likely_scanned,
+        indexed_at: Some(unix_now()),
+        text,
+        outline,
+        content_hash,
+        text_stats: Some(text_stats),
     };
-
-    {
-        docs
-            .write()
-            .map_err(|e| log(format!("Could not take `DocShared` lock: {e}")))?
-            .documents.insert(title, document);
+    docs.log_change(wal::Entry::Submit {
+        document: &document,
+        term_count: &term_count,
+    })?;
+    for (term, n) in &term_count {
+        docs.global_term_count
+            .entry(*term)
+            .and_modify(|v| *v += n)
+            .or_insert(*n);
     }
-    Ok(())
+    docs.insert_document(document);
+    Ok(submitted)
 }
 
-pub async fn document_info(Path(_document_id): Path<u32>) -> Result<(), String> {
-    todo!()
+pub async fn document_info(
+    request_headers: HeaderMap,
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Response, ApiError> {
+    let format = format::negotiate(&request_headers, &params, format::JSON_OR_JSONAPI)?;
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let doc = docs.document_by_id(document_id).ok_or_else(|| {
+        ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+    })?;
+    let info = DocumentInfo::from(doc);
+    if format == Format::JsonApi {
+        return Ok(jsonapi::data_response(jsonapi::document(info)));
+    }
+    Ok(Json(info).into_response())
 }
 
-pub async fn search_document(
+/// `GET /api/document/{id}/text`: the document's text as plain text, for
+/// screen readers and the like. It's the text stored with `store_text=true`
+/// when there is some, and is extracted from the file again otherwise.
+///
+/// `page` (1-based) gives just that page. Pages are separated by a blank line
+/// rather than the form feeds they're stored with.
+pub async fn document_text(
+    Path(document_id): Path<u32>,
     Query(params): Query<HashMap<String, String>>,
     State(docs): State<DocShared>,
-) -> Result<impl IntoResponse, String> {
-    let terms = params
-        .get("s")
-        .ok_or_else(|| log("Missing `s` parameter; give search terms"))?;
+) -> Result<impl IntoResponse, ApiError> {
+    let page = parse_param::<usize>(&params, "page")?;
+    if page == Some(0) {
+        return Err(ApiError::invalid_param("Invalid `page` 0; pages start at 1"));
+    }
+    let (path, stored, page_range) = {
+        let docs = docs
+            .read()
+            .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+        let doc = docs.document_by_id(document_id).ok_or_else(|| {
+            ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+        })?;
+        (doc.path.clone(), doc.text.clone(), doc.page_range)
+    };
 
+    let text = match stored {
+        Some(text) => {
+            let pages = text.split('\x0C').collect::<Vec<_>>();
+            // Stored text only has the indexed pages
+            let first = page_range.map_or(1, |(start, _)| start as usize);
+            match page {
+                None => plain_text(&pages),
+                Some(page) => {
+                    let last = first + pages.len() - 1;
+                    let text = page
+                        .checked_sub(first)
+                        .and_then(|i| pages.get(i))
+                        .ok_or_else(|| {
+                            ApiError::invalid_param(format!(
+                                "Invalid `page` {page}; document {document_id} has text for \
+                                 pages {first}..={last}"
+                            ))
+                        })?;
+                    plain_text(&[text])
+                }
+            }
+        }
+        None => {
+            let options = extract::ExtractOptions::text_only(page, params.get("password").cloned());
+            let extraction = tokio::task::spawn_blocking(move || extract::extract(&path, options))
+                .await
+                .map_err(|e| ApiError::internal(format!("Extraction task failed: {e}")))??;
+            let text = extraction.text.unwrap_or_default();
+            plain_text(&text.split('\x0C').collect::<Vec<_>>())
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text))
+}
+
+/// `pages` as one plain text: Unix line endings, no trailing blanks on a
+/// page, a blank line between pages and a newline at the end
+fn plain_text(pages: &[&str]) -> String {
+    let mut text = pages
+        .iter()
+        .map(|page| page.replace("\r\n", "\n").trim_end().to_owned())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    text.push('\n');
+    text
+}
+
+/// `GET /api/document/{id}/outline`: the PDF's bookmarks as a tree of
+/// `title`, 1-based `page` and `children`; empty when it has none
+pub async fn document_outline(
+    Path(document_id): Path<u32>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Vec<pdf::OutlineEntry>>, ApiError> {
     let docs = docs
         .read()
-        .map_err(|e| log(format!("Could not get `DocShared` read lock: {e}")))?;
-    let terms = terms.split_whitespace().map(|v| v.to_lowercase());
-    let terms = terms
-        .map(|v| intern(STEMMER.stem(&v)))
-        .collect::<Vec<PoolId>>();
-    return Ok(Json(docs.sort_documents(&terms)));
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let doc = docs.document_by_id(document_id).ok_or_else(|| {
+        ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+    })?;
+    Ok(Json(doc.outline.clone()))
 }
 
-async fn shutdown(docs: DocShared) {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
+/// An `ETag` for the response to `uri` in `format` from the index at
+/// `generation`: the same request gets the same tag until the index changes. Generations
+/// restart from 0 with the server, so its start time is part of the tag too.
+fn response_etag(uri: &Uri, format: Format, generation: u64) -> HeaderValue {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (uri.path(), uri.query(), format.alias()).hash(&mut hasher);
+    format!("\"{:x}-{generation:x}-{:x}\"", *STARTED_AT, hasher.finish())
+        .parse()
+        .unwrap()
+}
+
+/// Whether the request's `If-None-Match` already names `etag`, so a `304`
+/// will do
+fn not_modified(request_headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(Ok(tags)) = request_headers.get(header::IF_NONE_MATCH).map(|v| v.to_str()) else {
+        return false;
     };
+    let etag = etag.to_str().unwrap_or("");
+    tags.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+pub async fn list_documents(
+    request_headers: HeaderMap,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Response, ApiError> {
+    let filter = DocumentFilter::from_params(&params)?;
+    let sort = ListSort::from_params(&params)?;
+    let order = sort.order(&params)?;
+    let offset = parse_param(&params, "offset")?.unwrap_or(0);
+    let limit = parse_param(&params, "limit")?.unwrap_or(usize::MAX);
+    let format = format::negotiate(&request_headers, &params, format::JSON_OR_JSONAPI)?;
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let etag = response_etag(&uri, format, docs.generation);
+    if not_modified(&request_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    let mut list = docs
+        .documents
+        .values()
+        .filter(|doc| filter.matches(doc))
+        .map(DocumentInfo::from)
+        .collect::<Vec<_>>();
+    sort.sort(&mut list, order);
 
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
+    let mut headers = HeaderMap::new();
+    if paging::is_paginated(&params) {
+        headers = paging::headers(uri.path(), &params, offset, limit, list.len());
+    }
+    headers.insert(header::ETAG, etag);
+    headers.insert(header::VARY, "accept".parse().unwrap());
+    let total = list.len();
+    let list = list.into_iter().skip(offset).take(limit).collect::<Vec<_>>();
+    if format == Format::JsonApi {
+        let data = list.into_iter().map(jsonapi::document).collect::<Vec<_>>();
+        let meta = jsonapi::ListMeta { total, offset };
+        return Ok(jsonapi::response(headers, data, Some(meta)));
+    }
+    Ok((headers, Json(list)).into_response())
+}
+
+/// SplitMix64: small and good enough for picking documents, so no RNG crate
+/// is needed
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// `GET /api/document/random?count=N`: `N` (default 1) documents picked at
+/// random among those matching `favorite`, `read`, `tagged` and `tag`. The
+/// same `seed` gives the same picks while the index doesn't change.
+pub async fn random_documents(
+    request_headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Response, ApiError> {
+    let filter = DocumentFilter::from_params(&params)?;
+    let count = parse_param(&params, "count")?.unwrap_or(1);
+    let format = format::negotiate(&request_headers, &params, format::JSON_OR_JSONAPI)?;
+    let tag = params.get("tag").and_then(|tag| tags::normalize_tag(tag));
+    let mut state = match parse_param::<u64>(&params, "seed")? {
+        Some(seed) => seed,
+        None => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64),
     };
 
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let mut candidates = docs
+        .documents
+        .values()
+        .filter(|doc| filter.matches(doc))
+        .filter(|doc| tag.as_ref().map_or(true, |tag| doc.tags.contains(tag)))
+        .collect::<Vec<_>>();
+    // Map order differs between runs; a seed should not
+    candidates.sort_by_key(|doc| doc.id);
+    let count = count.min(candidates.len());
+    // The first `count` steps of a Fisher-Yates shuffle
+    for i in 0..count {
+        let j = i + (next_random(&mut state) % (candidates.len() - i) as u64) as usize;
+        candidates.swap(i, j);
+    }
+    let picks = candidates[..count]
+        .iter()
+        .map(|doc| DocumentInfo::from(*doc))
+        .collect::<Vec<_>>();
+    if format == Format::JsonApi {
+        let data = picks.into_iter().map(jsonapi::document).collect::<Vec<_>>();
+        return Ok(jsonapi::data_response(data));
+    }
+    Ok(Json(picks).into_response())
+}
 
-    tokio::select! {
-        _ = ctrl_c => {},
-        _ = terminate => {},
+/// `GET /api/document/untagged`: `/list` with `tagged=false`, for finding
+/// what still needs tags
+pub async fn list_untagged(
+    request_headers: HeaderMap,
+    uri: OriginalUri,
+    Query(mut params): Query<HashMap<String, String>>,
+    docs: State<DocShared>,
+) -> Result<Response, ApiError> {
+    params.insert("tagged".to_owned(), "false".to_owned());
+    list_documents(request_headers, uri, Query(params), docs).await
+}
+
+/// Sets the flag `bit` (see `Document::flags`) to `value`, or flips it when
+/// `value` isn't given
+fn set_flag(
+    params: &HashMap<String, String>,
+    docs: &DocShared,
+    document_id: u32,
+    bit: u8,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    let value = bool_param(params, "value")?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    let not_found =
+        || ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"));
+    let flags = docs.document_by_id(document_id).ok_or_else(not_found)?.flags();
+    let flags = match value.unwrap_or(flags & bit == 0) {
+        true => flags | bit,
+        false => flags & !bit,
+    };
+    docs.log_change(wal::Entry::Flags {
+        id: document_id,
+        flags,
+    })?;
+    let doc = docs.document_by_id_mut(document_id).ok_or_else(not_found)?;
+    doc.set_flags(flags);
+    Ok(Json((&*doc).into()))
+}
+
+pub async fn set_favorite(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    set_flag(&params, &docs, document_id, file_format::FAVORITE)
+}
+
+pub async fn set_read(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    set_flag(&params, &docs, document_id, file_format::READ)
+}
+
+pub async fn set_exclude_from_corpus(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    set_flag(&params, &docs, document_id, file_format::EXCLUDED)
+}
+
+/// Answers `If-None-Match` with `304 Not Modified` while the index hasn't
+/// changed since the tag was handed out
+pub async fn search_document(
+    request_headers: HeaderMap,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Response, ApiError> {
+    // With a `path`, leaving out `s` searches paths alone
+    let query = match params.get("s") {
+        Some(query) => query.as_str(),
+        None if params.contains_key("path") => "",
+        None => {
+            return Err(ApiError::missing_param(
+                "Missing `s` parameter; give search terms, or a `path` to look for",
+            ))
+        }
+    };
+    let request = SearchRequest::from_params(&params)?;
+    let fields = ResultFields::from_params(&params)?;
+    let bare = bool_param(&params, "bare")?.unwrap_or(false);
+    let format = format::negotiate(
+        &request_headers,
+        &params,
+        &[Format::Json, Format::JsonApi, Format::Csv, Format::Atom],
+    )?;
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let etag = response_etag(&uri, format, docs.generation);
+    if not_modified(&request_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
     }
+    stats::record_query(query);
+    let outcome = docs.cached_search(query, &request)?;
 
-    let mut f = match std::fs::File::create(CACHE_PATH) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Failed to create file: {e}");
-            return;
+    let mut headers = HeaderMap::new();
+    if paging::is_paginated(&params) {
+        headers = paging::headers(
+            uri.path(),
+            &params,
+            request.offset,
+            outcome.limit,
+            outcome.total,
+        );
+    }
+    headers.insert(header::ETAG, etag);
+    headers.insert(header::VARY, "accept".parse().unwrap());
+    if let Some(collapsed) = outcome.collapsed {
+        headers.insert("x-collapsed-count", collapsed.into());
+    }
+    match format {
+        Format::Csv => {
+            headers.insert(header::CONTENT_TYPE, "text/csv; charset=utf-8".parse().unwrap());
+            headers.insert(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"search-results.csv\"".parse().unwrap(),
+            );
+            return Ok((headers, search::results_csv(&outcome.results)).into_response());
+        }
+        Format::Atom => {
+            // Entries link to `/{id}` next to this route, as from `/search.atom`
+            let base = uri.path().trim_end_matches("search");
+            return Ok(feed::response(headers, &docs, query, &outcome, base).into_response());
         }
+        Format::Json | Format::JsonApi | Format::Tsv => {}
+    }
+    let jsonapi = format == Format::JsonApi;
+    let Some(fields) = fields else {
+        return Ok(search_response(headers, outcome, jsonapi, bare, request.offset, |result| {
+            docs.documents.get(&result.title).map(|doc| doc.id)
+        }));
     };
-    match docs.read() {
-        Ok(v) => {
-            v.serialize(&mut f).map_err(|e| eprintln!("{e}")).ok();
+    // JSON:API has the id on the resource itself
+    let fields = if jsonapi { fields.without("id") } else { fields };
+    let outcome = outcome.map_results(|result| {
+        let doc = docs.documents.get(&result.title);
+        SparseResult {
+            id: doc.map(|doc| doc.id),
+            tags: doc.map_or(vec![], |doc| doc.tags.iter().cloned().collect()),
+            result,
+            fields,
         }
-        Err(e) => {
-            eprintln!("Could not get read lock to serialize `DocShared`: {e}");
-            return;
+    });
+    Ok(search_response(headers, outcome, jsonapi, bare, request.offset, |result| result.id))
+}
+
+/// Writes `outcome` as JSON:API, as just its results (`bare`), or whole
+fn search_response<R: serde::Serialize>(
+    headers: HeaderMap,
+    outcome: SearchOutcome<R>,
+    jsonapi: bool,
+    bare: bool,
+    offset: usize,
+    id_of: impl Fn(&R) -> Option<u32>,
+) -> Response {
+    if jsonapi {
+        return jsonapi::search_response(headers, outcome, offset, id_of);
+    }
+    if bare {
+        return (headers, Json(outcome.results)).into_response();
+    }
+    (headers, Json(outcome)).into_response()
+}
+
+/// Maps a multipart read failure to 413 when it hit the body limit
+fn multipart_error(context: String, e: MultipartError) -> ApiError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return ApiError::new(
+            ErrorCode::PayloadTooLarge,
+            format!("{context}: upload is over {} bytes", CONFIG.upload_max_bytes),
+        );
+    }
+    ApiError::invalid_param(format!("{context}: {e}"))
+}
+
+/// Every file in the form as (file name, bytes); other fields are skipped
+async fn read_uploads(mut multipart: Multipart) -> Result<Vec<(String, Vec<u8>)>, ApiError> {
+    let mut files = vec![];
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| multipart_error("Malformed multipart body".to_owned(), e))?
+    {
+        let Some(name) = field.file_name().map(|name| name.to_owned()) else {
+            continue;
+        };
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| multipart_error(format!("Could not read upload {name:?}"), e))?
+            .to_vec();
+        files.push((name, data));
+    }
+    Ok(files)
+}
+
+/// `POST /api/document/upload`: indexes each file in a multipart form straight
+/// from memory. The bytes aren't kept, so the stored path is just
+/// `upload:{file name}`.
+///
+/// The whole body must arrive within `PAPER_ENGINE_UPLOAD_TIMEOUT_SECS` and
+/// fit in `PAPER_ENGINE_UPLOAD_MAX_MB`; both are checked before any file is
+/// handed to poppler.
+async fn upload_documents(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+    multipart: Multipart,
+) -> Result<(), ApiError> {
+    let files = tokio::time::timeout(CONFIG.upload_timeout, read_uploads(multipart))
+        .await
+        .map_err(|_| {
+            ApiError::new(
+                ErrorCode::Timeout,
+                format!("Upload not received within {:?}", CONFIG.upload_timeout),
+            )
+        })??;
+
+    for (name, data) in files {
+        tracing::info!("Submitting upload... {} ({} bytes)", redact::path(&name), data.len());
+        let options = extract::ExtractOptions::from_params(&params)?;
+        let mut extraction = {
+            let name = name.clone();
+            let options = options.clone();
+            tokio::task::spawn_blocking(move || extract::extract_bytes(&name, data, options))
+                .await
+                .map_err(|e| ApiError::internal(format!("Extraction task failed: {e}")))??
+        };
+        if options.index_title {
+            extraction.add_title_terms(&name);
         }
+        add_extraction(&docs, format!("upload:{name}"), extraction, &params)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    elapsed_us: u128,
+    #[serde(flatten)]
+    outcome: SearchOutcome,
+}
+
+/// Runs every query in the JSON array body under one read lock; the query
+/// string parameters (`sort`, `limit`, ...) apply to each of them
+pub async fn search_batch(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+    Json(queries): Json<Vec<String>>,
+) -> Result<Json<Vec<BatchResult>>, ApiError> {
+    let request = SearchRequest::from_params(&params)?;
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let results = queries
+        .into_iter()
+        .map(|query| {
+            stats::record_query(&query);
+            let started = std::time::Instant::now();
+            let outcome = docs.search(&query, &request)?;
+            Ok(BatchResult {
+                elapsed_us: started.elapsed().as_micros(),
+                outcome,
+            })
+        })
+        .collect::<Result<_, ApiError>>()?;
+    Ok(Json(results))
+}
+
+/// Saves every collection's cache, once the server has stopped
+fn save_all(collections: &[Collection]) {
+    for collection in collections {
+        match save_cache(collection) {
+            Ok(()) => log(format!("Successfully wrote cache for {:?}", collection.name)),
+            Err(e) => log(format!("Could not write cache for {:?}: {e}", collection.name)),
+        };
+    }
+}
+
+/// Writes a collection's index to its cache file from a snapshot.
+///
+/// The read lock is only held while the saved data is copied (see
+/// `TfIdf::save_snapshot`); serializing and writing happen on the copy, so
+/// searches and submits carry on meanwhile and the file reflects the index
+/// as of the moment it was copied. The bytes go to a temporary file that is
+/// renamed over the cache, so a crash mid-save leaves the previous cache
+/// intact. The copy briefly adds the documents' size to memory use. Saves run
+/// one at a time, since they share the temporary file.
+fn save_cache(collection: &Collection) -> error::Result<()> {
+    let _saving = SAVING.lock()?;
+    let started = std::time::Instant::now();
+    let snapshot = collection.docs.read()?.save_snapshot();
+    // How long submits waited on this save, at most
+    tracing::debug!(
+        collection = %collection.name,
+        locked = ?started.elapsed(),
+        "Took cache snapshot"
+    );
+
+    let path = collection.cache_path();
+    let tmp_path = format!("{path}.tmp");
+    // Which step failed, on which file, so the log says more than "IO error"
+    fn failed<E: std::fmt::Display>(step: String) -> impl FnOnce(E) -> error::Error {
+        move |e| error::Error::Format(format!("{step}: {e}"))
     }
-    log("Successfully wrote cache");
+    let file = std::fs::File::create(&tmp_path)
+        .map_err(failed(format!("Could not create {tmp_path:?}")))?;
+    let mut writer = std::io::BufWriter::new(file);
+    snapshot
+        .serialize(&mut writer)
+        .map_err(failed(format!("Could not write the cache to {tmp_path:?}")))?;
+    // On disk before the rename, or a crash could leave the new name on a
+    // file whose contents never made it
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .and_then(|file| file.sync_all())
+        .map_err(failed(format!("Could not write the cache to {tmp_path:?}")))?;
+    std::fs::rename(&tmp_path, &path)
+        .map_err(failed(format!("Could not rename {tmp_path:?} to {path:?}")))?;
+    // Only now that the cache has them can their log entries go, once the
+    // rename itself is durable
+    if let Some(wal) = &snapshot.wal {
+        let dir = match std::path::Path::new(&path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        std::fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(failed(format!("Could not sync the directory of {path:?}")))?;
+        wal.truncate_through(snapshot.wal_sequence)
+            .map_err(failed(format!("Saved {path:?} but could not truncate the log")))?;
+    }
+
+    // Changes made while writing aren't in the file, so only the snapshot's
+    // generation counts as saved
+    let mut docs = collection.docs.write()?;
+    docs.saved_generation = docs.saved_generation.max(snapshot.generation);
+    docs.saved_at = Some(unix_now());
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let tf_idf = match std::fs::File::open(CACHE_PATH) {
-        Ok(mut f) => {
-            // TODO: Buffer this in small chunks to be able to handle larger files
-            //
-            // But honestly, at that point just use a database
-            let mut data = vec![];
-            f.read_to_end(&mut data)?;
-            TfIdf::deserialize(&data)?
+/// Saves every collection's cache every `PAPER_ENGINE_AUTOSAVE_SECS`, so a
+/// crash loses at most that much work
+async fn autosave(collections: Arc<Vec<Collection>>, every: std::time::Duration) {
+    let mut interval = tokio::time::interval(every);
+    // The first tick is immediate, and there's nothing new to save yet
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        for collection in collections.iter() {
+            let name = &collection.name;
+            let unchanged = collection
+                .docs
+                .read()
+                .is_ok_and(|docs| docs.generation == docs.saved_generation);
+            if unchanged {
+                continue;
+            }
+            let started = std::time::Instant::now();
+            let collection = collection.clone();
+            match tokio::task::spawn_blocking(move || save_cache(&collection)).await {
+                Ok(Ok(())) => tracing::debug!(
+                    collection = %name,
+                    elapsed = ?started.elapsed(),
+                    "Autosaved cache"
+                ),
+                Ok(Err(e)) => tracing::warn!("Autosave of {name:?} failed: {e}"),
+                Err(e) => tracing::warn!("Autosave task for {name:?} failed: {e}"),
+            }
         }
-        _ => TfIdf::default(),
-    };
-    let docs: DocShared = Arc::new(RwLock::new(tf_idf));
-    let docs_resource = Arc::clone(&docs);
+    }
+}
+
+/// Every `/api` route, serving one collection; keep `describe.rs` in step
+fn api_routes(collection: &Collection) -> Router {
+    let docs = Arc::clone(&collection.docs);
     let document_routes = Router::new()
         .route("/submit", get(submit_document))
+        .route("/submit_dir", get(ingest::submit_dir))
+        .route("/check_dupes", post(ingest::check_dupes))
+        .route(
+            "/upload",
+            post(upload_documents).layer(DefaultBodyLimit::max(CONFIG.upload_max_bytes)),
+        )
         .route("/search", get(search_document))
-        .with_state(docs_resource);
+        .route("/search.atom", get(feed::search_feed))
+        .route("/search_batch", post(search_batch))
+        .route("/validate_query", get(query::validate_query))
+        .route("/spellcheck", get(spellcheck::spellcheck))
+        .route("/similarity", get(similar::similarity))
+        .route("/list", get(list_documents))
+        .route("/untagged", get(list_untagged))
+        .route("/tag_by_query", post(tags::tag_by_query))
+        .route("/random", get(random_documents))
+        .route("/:id", get(document_info))
+        .route("/:id/text", get(document_text))
+        .route("/:id/outline", get(document_outline))
+        .route("/:id/thumbnail", get(thumbnail::thumbnail))
+        .route("/:id/favorite", post(set_favorite))
+        .route("/:id/read", post(set_read))
+        .route("/:id/exclude_from_corpus", post(set_exclude_from_corpus))
+        .route("/:id/tags", post(tags::set_tags))
+        .route("/:id/notes", post(notes::set_notes))
+        .route("/:id/siblings", get(tags::siblings))
+        .route("/:id/similar_by_terms", get(similar::similar_by_terms))
+        .route("/:id/why", get(why::why))
+        .with_state(Arc::clone(&docs));
+
+    Router::new()
+        .route("/", get(describe::describe))
+        .route("/analyze", post(analyzer::analyze))
+        .nest("/document", document_routes)
+        .nest("/index", index::info_routes(Arc::clone(&docs)))
+        .nest("/admin", admin::routes(collection.clone()))
+        .nest("/stats", stats::routes(Arc::clone(&docs)))
+        .nest("/tags", tags::routes(Arc::clone(&docs)))
+        .nest("/term", index::routes(docs))
+}
+
+/// Refuses requests over the global size limits before any handler runs: a
+/// query string over `PAPER_ENGINE_MAX_QUERY_BYTES` with 414, and a body
+/// declared over `PAPER_ENGINE_MAX_BODY_KB` with 413. `/upload` has its own
+/// limit, `PAPER_ENGINE_UPLOAD_MAX_MB`. Bodies sent without a length are cut
+/// off by `DefaultBodyLimit` as they're read instead.
+async fn limit_request_size(request: Request, next: Next) -> Response {
+    let query_len = request.uri().query().map_or(0, |query| query.len());
+    if query_len > CONFIG.max_query_bytes {
+        return ApiError::new(
+            ErrorCode::UriTooLong,
+            format!(
+                "Query string is {query_len} bytes, over the limit of {}",
+                CONFIG.max_query_bytes
+            ),
+        )
+        .into_response();
+    }
+    let body_len = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<usize>().ok());
+    let upload = request.uri().path().ends_with("/document/upload");
+    if let Some(body_len) = body_len.filter(|len| *len > CONFIG.max_body_bytes && !upload) {
+        return ApiError::new(
+            ErrorCode::PayloadTooLarge,
+            format!(
+                "Request body is {body_len} bytes, over the limit of {}",
+                CONFIG.max_body_bytes
+            ),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Takes `--log-format text|json` out of `args`; true for `json`
+fn json_logs(args: &mut Vec<String>) -> Result<bool, String> {
+    let Some(i) = args.iter().position(|arg| arg == "--log-format") else {
+        return Ok(false);
+    };
+    let format = args.get(i + 1).cloned();
+    args.drain(i..(i + 2).min(args.len()));
+    match format.as_deref() {
+        Some("text") => Ok(false),
+        Some("json") => Ok(true),
+        format => Err(format!("`--log-format` takes `text` or `json`, got {format:?}")),
+    }
+}
 
-    let api_routes = Router::new().nest("/document", document_routes);
+/// Gives every request a span with an id, method and route, and logs its
+/// status and latency when it finishes
+fn request_tracing() -> TraceLayer<
+    tower_http::classify::SharedClassifier<tower_http::classify::ServerErrorsAsFailures>,
+    impl Fn(&axum::http::Request<axum::body::Body>) -> tracing::Span + Clone,
+    tower_http::trace::DefaultOnRequest,
+    impl Fn(&Response, std::time::Duration, &tracing::Span) + Clone,
+> {
+    static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+    TraceLayer::new_for_http()
+        .make_span_with(|request: &axum::http::Request<axum::body::Body>| {
+            let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+            tracing::info_span!(
+                "request",
+                id,
+                method = %request.method(),
+                route = %redact::route(request.uri().path()),
+            )
+        })
+        .on_response(|response: &Response, latency: std::time::Duration, _: &tracing::Span| {
+            tracing::info!(
+                status = response.status().as_u16(),
+                latency_ms = latency.as_millis() as u64,
+                "Request finished"
+            );
+        })
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    // JSON is one object per line for log collectors; text is for people
+    if json_logs(&mut args)? {
+        tracing_subscriber::fmt().json().with_writer(std::io::stderr).init();
+    } else {
+        tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+    }
+
+    match args.first().map(|arg| arg.as_str()) {
+        None => {}
+        Some("verify") => return verify::cli(args.iter().any(|arg| arg == "--repair")),
+        Some("index") => return cli::index(&args[1..]),
+        Some("query") => return cli::query(&args[1..]),
+        Some(arg) => {
+            return Err(
+                format!("Unknown command {arg:?}; expected `verify`, `index` or `query`").into(),
+            )
+        }
+    }
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(n) = CONFIG.worker_threads {
+        runtime.worker_threads(n);
+    }
+    if let Some(n) = CONFIG.max_blocking_threads {
+        runtime.max_blocking_threads(n);
+    }
+    let runtime = runtime.build()?;
+    let served = runtime.block_on(serve());
+    // Blocking work a cancelled request left behind gets the grace period
+    // again, rather than holding the process open
+    runtime.shutdown_timeout(CONFIG.shutdown_grace);
+    served
+}
+
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    tracing::info!("Using poppler {}", pdf::poppler_version());
+    pdf::check_poppler()?;
+
+    let collections = Arc::new(collection::load_all(CONFIG.load_check)?);
+    if let Some(every) = CONFIG.autosave_interval {
+        tokio::spawn(autosave(Arc::clone(&collections), every));
+    }
+    if let Some(threshold) = CONFIG.intern_gc_entries {
+        tokio::spawn(gc::watch(Arc::clone(&collections), threshold));
+    }
+
+    // The default collection stays at `/api` as well as under its name
+    let mut api = api_routes(&collections[0])
+        .nest("/collections", collection::routes(Arc::clone(&collections)));
+    for collection in collections.iter() {
+        api = api.nest(
+            &format!("/collection/{}", collection.name),
+            api_routes(collection),
+        );
+    }
+    // Added after the layer: a request holding a pin would wait on itself
+    let api = api.layer(middleware::from_fn(intern::pin_request)).route(
+        "/admin/intern_gc",
+        post(gc::intern_gc).with_state(Arc::clone(&collections)),
+    );
 
     let app = Router::new()
-        .route_service("/", ServeFile::new("src/index.html"))
-        .nest("/api", api_routes);
+        .route("/", get(root))
+        .route("/css/style.css", get(stylesheet))
+        .nest("/api", api)
+        // `/upload`'s own `DefaultBodyLimit` is closer to it, so it wins
+        .layer(DefaultBodyLimit::max(CONFIG.max_body_bytes))
+        .layer(middleware::from_fn(limit_request_size))
+        .layer(middleware::from_fn(shutdown::track_requests))
+        .layer(request_tracing());
 
     let addr = "127.0.0.1:42069";
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    eprintln!("Now serving at: {addr}");
+    tracing::info!("Now serving at: {addr}");
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown(docs))
+        .with_graceful_shutdown(shutdown::signal())
         .await?;
+    save_all(&collections);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty index, with no write-ahead log
+    pub fn empty() -> DocShared {
+        DocShared::default()
+    }
+
+    /// Submits `text` as `{title}.txt`, with `# {title}` first so the
+    /// document gets `title` as its title
+    pub fn submit_text(docs: &DocShared, title: &str, text: &str) -> Submitted {
+        let path = format!("{title}.txt");
+        let bytes = format!("# {title}\n{text}").into_bytes();
+        let options = extract::ExtractOptions::from_params(&HashMap::new()).unwrap();
+        let extraction = extract::extract_bytes(&path, bytes, options).unwrap();
+        add_extraction(docs, path, extraction, &HashMap::new()).unwrap()
+    }
+
+    /// An index of `(title, text)` documents; see `submit_text`
+    pub fn index(documents: &[(&str, &str)]) -> DocShared {
+        let docs = empty();
+        for (title, text) in documents {
+            submit_text(&docs, title, text);
+        }
+        docs
+    }
+
+    /// Titles of every match for `query`, best first, with the default
+    /// `/search` parameters
+    pub fn search_titles(docs: &DocShared, query: &str) -> Vec<String> {
+        let request = SearchRequest::from_params(&HashMap::new()).unwrap();
+        let outcome = docs.read().unwrap().search(query, &request).unwrap();
+        outcome.results.into_iter().map(|result| result.title).collect()
+    }
+
+    #[test]
+    fn rename_takes_the_first_free_title() {
+        let docs = empty();
+        let rename = HashMap::from([("dupe".to_owned(), "rename".to_owned())]);
+        let submit = || {
+            let bytes = b"# Notes\nsome words".to_vec();
+            let options = extract::ExtractOptions::from_params(&HashMap::new()).unwrap();
+            let extraction = extract::extract_bytes("notes.txt", bytes, options).unwrap();
+            add_extraction(&docs, "notes.txt".to_owned(), extraction, &rename).unwrap()
+        };
+        assert_eq!(submit(), Submitted::Added);
+        for n in 1..=3 {
+            let title = format!("Notes-{n}");
+            assert_eq!(submit(), Submitted::Renamed { title });
+        }
+        assert_eq!(docs.read().unwrap().documents.len(), 4);
+    }
+
+    #[test]
+    fn same_file_name_in_two_directories_gets_two_titles() {
+        let docs = empty();
+        let submit = |path: &str| {
+            let options = extract::ExtractOptions::from_params(&HashMap::new()).unwrap();
+            let extraction = extract::extract_bytes(path, b"some words".to_vec(), options).unwrap();
+            add_extraction(&docs, path.to_owned(), extraction, &HashMap::new()).unwrap()
+        };
+        submit("a/notes.txt");
+        submit("b/notes.txt");
+        submit("c/b/notes.txt");
+        let docs = docs.read().unwrap();
+        let titles = docs.documents.keys().map(String::as_str).collect::<BTreeSet<_>>();
+        assert_eq!(titles, BTreeSet::from(["Notes", "Notes (b)", "c/b/notes.txt"]));
+        // Resubmitting one is still a duplicate
+        assert_eq!(docs.derived_title("b/notes.txt"), "Notes (b)");
+    }
+
+    #[test]
+    fn document_without_text_is_refused() {
+        let docs = empty();
+        let path = "scanned.pdf".to_owned();
+        let extraction = Extraction::default();
+        let err = add_extraction(&docs, path, extraction, &HashMap::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::NoText);
+        assert!(err.message.contains("no extractable text"));
+        let docs = docs.read().unwrap();
+        assert!(docs.documents.is_empty());
+        assert!(docs.global_term_count.is_empty());
+    }
+
+    #[test]
+    fn word_only_in_the_file_name_finds_the_document() {
+        let docs = empty();
+        let path = "papers/quantum_widgets.txt".to_owned();
+        let bytes = b"small machines that spin".to_vec();
+        let options = extract::ExtractOptions::from_params(&HashMap::new()).unwrap();
+        let mut extraction = extract::extract_bytes(&path, bytes, options).unwrap();
+        extraction.add_title_terms(&path);
+        add_extraction(&docs, path, extraction, &HashMap::new()).unwrap();
+
+        let title = docs.read().unwrap().titles.values().next().unwrap().clone();
+        assert_eq!(search_titles(&docs, "quantum"), [title.clone()]);
+        assert_eq!(search_titles(&docs, "widgets"), [title]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::Path;
+
+// Math notation, with `PAPER_ENGINE_MATH`. A word with math in it (operators,
+// the styled letters of the Unicode math block, LaTeX commands, the private
+// use glyphs math fonts extract as) is rewritten before the analyzer sees it:
+// named symbols become their LaTeX names, so "∇" and "\nabla" are both
+// nabla, Greek letters are spelled out, and what's left is split at the
+// operators. Lone letters and numbers are dropped from it, as variables,
+// indices and exponents that would only pollute the index. Words without
+// math are left alone.
+//
+// PDF text has lost the layout of its equations: fractions, sub- and
+// superscripts and radicals come out flattened into one line, often in the
+// wrong order, and fonts without a Unicode mapping give private use glyphs or
+// wrong letters. So only the named symbols and multi-letter words in a
+// formula can be relied on, and `E = mc^2` isn't searchable as written. A
+// `.tex` file next to a PDF fills in what it can: its formulas are indexed
+// with the PDF (see `tex_math`).
+
+/// Environments whose body is a formula, each also starred
+const MATH_ENVIRONMENTS: &[&str] = &[
+    "equation",
+    "align",
+    "gather",
+    "multline",
+    "eqnarray",
+    "displaymath",
+    "math",
+];
+
+/// Greek letters from α (or Α) on, by offset; final sigma is a sigma, and
+/// the gap in the capitals never occurs
+const GREEK: [&str; 25] = [
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "omicron", "pi", "rho", "sigma", "sigma", "tau", "upsilon", "phi",
+    "chi", "psi", "omega",
+];
+
+fn greek_name(c: char) -> Option<&'static str> {
+    match c {
+        'α'..='ω' => Some(GREEK[c as usize - 'α' as usize]),
+        'Α'..='Ω' => Some(GREEK[c as usize - 'Α' as usize]),
+        'ϵ' => Some("epsilon"),
+        'ϑ' | 'ϴ' => Some("theta"),
+        'ϰ' => Some("kappa"),
+        'ϕ' => Some("phi"),
+        'ϱ' => Some("rho"),
+        'ϖ' => Some("pi"),
+        'µ' => Some("mu"),
+        _ => None,
+    }
+}
+
+/// Symbols indexed under their LaTeX names
+fn symbol_name(c: char) -> Option<&'static str> {
+    Some(match c {
+        '∇' => "nabla",
+        '∂' => "partial",
+        '∑' => "sum",
+        '∏' => "prod",
+        '∐' => "coprod",
+        '∫' => "int",
+        '∬' => "iint",
+        '∭' => "iiint",
+        '∮' => "oint",
+        '∞' => "infty",
+        '√' => "sqrt",
+        '∀' => "forall",
+        '∃' => "exists",
+        '∄' => "nexists",
+        '∅' => "emptyset",
+        '≈' => "approx",
+        '≡' => "equiv",
+        '∝' => "propto",
+        '⊗' => "otimes",
+        '⊕' => "oplus",
+        '⊂' => "subset",
+        '⊆' => "subseteq",
+        '⊃' => "supset",
+        '⊇' => "supseteq",
+        '∪' => "cup",
+        '∩' => "cap",
+        '∧' => "wedge",
+        '∨' => "vee",
+        '¬' => "neg",
+        '⊥' => "perp",
+        '†' => "dagger",
+        'ℏ' => "hbar",
+        'ℓ' => "ell",
+        'ℵ' => "aleph",
+        _ => return None,
+    })
+}
+
+/// The plain letter or digit a styled one from the Mathematical Alphanumeric
+/// Symbols block stands for (𝑥 is x, 𝛂 is α)
+fn unstyled(c: char) -> Option<char> {
+    const GREEK_EXTRAS: [char; 6] = ['ϵ', 'ϑ', 'ϰ', 'ϕ', 'ϱ', 'ϖ'];
+    let code = c as u32;
+    let offset = |start: u32, span: u32| (code - start) % span;
+    let shifted = |base: char, by: u32| char::from_u32(base as u32 + by);
+    match code {
+        // Thirteen styles of A-Z then a-z
+        0x1D400..=0x1D6A3 => match offset(0x1D400, 52) {
+            i @ 0..=25 => shifted('A', i),
+            i => shifted('a', i - 26),
+        },
+        0x1D6A4 => Some('i'),
+        0x1D6A5 => Some('j'),
+        // Five styles of Α-Ω, ∇, α-ω, ∂ and the letter variants
+        0x1D6A8..=0x1D7C9 => match offset(0x1D6A8, 58) {
+            17 => Some('ϴ'),
+            i @ 0..=24 => shifted('Α', i),
+            25 => Some('∇'),
+            i @ 26..=50 => shifted('α', i - 26),
+            51 => Some('∂'),
+            i => GREEK_EXTRAS.get(i as usize - 52).copied(),
+        },
+        // Five styles of 0-9
+        0x1D7CE..=0x1D7FF => shifted('0', offset(0x1D7CE, 10)),
+        _ => None,
+    }
+}
+
+/// LaTeX commands that only lay out or style what follows
+const LAYOUT_COMMANDS: &str = "begin end label left right big Big bigg Bigg mathrm mathbf \
+    mathit mathcal mathbb mathsf mathfrak boldsymbol operatorname mathop text textrm textbf \
+    textit displaystyle textstyle scriptstyle limits nolimits nonumber notag quad qquad frac \
+    dfrac tfrac cdot cdots ldots dots hat bar tilde vec dot ddot";
+
+/// Symbols that mark a word as math even when they have no name
+fn is_math_symbol(c: char) -> bool {
+    matches!(c,
+        '=' | '^' | '{' | '}' | '\\' | '±' | '×' | '÷' | '−' | '′' | '″'
+        | '\u{2100}'..='\u{214F}'   // Letterlike Symbols: ℝ, ℏ, ℓ
+        | '\u{2200}'..='\u{22FF}'   // Mathematical Operators
+        | '\u{27C0}'..='\u{27EF}'   // Miscellaneous Mathematical Symbols-A
+        | '\u{2980}'..='\u{2AFF}'   // Miscellaneous Mathematical Symbols-B, Supplemental
+        | '\u{E000}'..='\u{F8FF}'   // Private use, where math fonts often land
+        | '\u{1D400}'..='\u{1D7FF}' // Mathematical Alphanumeric Symbols
+    )
+}
+
+/// Whether `word` gets rewritten: it has a math symbol in it, or it's a lone
+/// Greek letter. Greek words are left alone.
+pub fn is_math(word: &str) -> bool {
+    if word.chars().any(is_math_symbol) {
+        return true;
+    }
+    let mut letters = word.chars().filter(|c| c.is_alphabetic());
+    matches!((letters.next(), letters.next()), (Some(c), None) if greek_name(c).is_some())
+}
+
+/// The words `word` stands for as math, for the analyzer to tokenize in its
+/// place; `None` when it isn't math. Empty when nothing in it is worth
+/// indexing.
+pub fn words(word: &str) -> Option<Vec<String>> {
+    if !is_math(word) {
+        return None;
+    }
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            words.push(std::mem::take(&mut current));
+            let mut command = String::new();
+            while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                command.push(c);
+            }
+            if !LAYOUT_COMMANDS.split_whitespace().any(|layout| layout == command) {
+                words.push(command);
+            }
+            continue;
+        }
+        let c = unstyled(c).unwrap_or(c);
+        if let Some(name) = symbol_name(c).or_else(|| greek_name(c)) {
+            words.push(std::mem::take(&mut current));
+            words.push(name.to_owned());
+        } else if c.is_alphanumeric() && !is_math_symbol(c) {
+            current.push(c);
+        } else {
+            words.push(std::mem::take(&mut current));
+        }
+    }
+    words.push(current);
+    // Lone letters are variables, and numbers are indices and exponents
+    words.retain(|word| word.chars().count() > 1 && word.chars().any(char::is_alphabetic));
+    Some(words)
+}
+
+/// The `.tex` file next to `path` (`paper.pdf` has `paper.tex`), if there
+/// is one
+pub fn sidecar(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("tex")) {
+        return None;
+    }
+    std::fs::read_to_string(path.with_extension("tex")).ok()
+}
+
+/// The formulas of a LaTeX source, one per line: inline `$..$` and `\(..\)`,
+/// display `$$..$$` and `\[..\]`, and the math environments. The prose around
+/// them is left out, since the PDF has it already.
+pub fn tex_math(source: &str) -> String {
+    let source = source
+        .lines()
+        .map(|line| strip_comment(line).replace("\\$", " "))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut math = String::new();
+    let mut rest = source.as_str();
+    while let Some((at, open, close)) = next_formula(rest) {
+        let body = &rest[at + open.len()..];
+        let end = body.find(&close).unwrap_or(body.len());
+        math.push_str(&body[..end]);
+        math.push('\n');
+        rest = body.get(end + close.len()..).unwrap_or("");
+    }
+    math
+}
+
+/// `line` up to a `%` that isn't escaped
+fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (at, c) in line.char_indices() {
+        match c {
+            '%' if !escaped => return &line[..at],
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// Where the first formula in `text` starts, with its opening and closing
+/// delimiters
+fn next_formula(text: &str) -> Option<(usize, String, String)> {
+    let mut delimiters = vec![
+        ("$$".to_owned(), "$$".to_owned()),
+        ("$".to_owned(), "$".to_owned()),
+        ("\\(".to_owned(), "\\)".to_owned()),
+        ("\\[".to_owned(), "\\]".to_owned()),
+    ];
+    for environment in MATH_ENVIRONMENTS {
+        for star in ["", "*"] {
+            delimiters.push((
+                format!("\\begin{{{environment}{star}}}"),
+                format!("\\end{{{environment}{star}}}"),
+            ));
+        }
+    }
+    // `$$` comes before `$`, so it wins where both start
+    let mut first: Option<(usize, String, String)> = None;
+    for (open, close) in delimiters {
+        if let Some(at) = text.find(&open) {
+            if first.as_ref().map_or(true, |(earliest, ..)| at < *earliest) {
+                first = Some((at, open, close));
+            }
+        }
+    }
+    first
+}
@@ -0,0 +1,213 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::analyzer::{self, Token};
+use crate::error::{ApiError, ErrorCode};
+use crate::wal;
+use crate::{DocShared, DocumentInfo, Term, TfIdf};
+
+// A document's notes are text of the user's own about it, indexed with the
+// body so searches find the document by them. What they added to the
+// document's statistics is kept apart in `note_frequency`, so editing them
+// takes the old notes' share back out and puts the new one in, under a brief
+// write lock, without reading the file again. Tags aren't indexed as terms,
+// so changing those never touches the statistics at all.
+//
+// Each occurrence of a note term adds 1 / (distinct terms in the document)
+// to its frequency, as submitted text does, and is counted in
+// `global_term_count`, `token_count` and `surface_forms`. The old notes are
+// tokenized again to take their counts back out, which gives the same
+// tokens as long as the analyzer settings are the ones the cache was built
+// with (see `TfIdf::check_analyzer`). Resubmitting the file with
+// `dupe=replace` starts the document over without notes, as it does without
+// tags.
+
+/// What's left of a frequency once a note's share is taken out, when the
+/// term was only in the notes
+const LEFTOVER: f64 = 1e-12;
+
+/// What a document's notes count, as `extract::Extraction` counts a file
+#[derive(Default)]
+struct NoteCounts {
+    terms: HashMap<Term, usize>,
+    surfaces: HashMap<Term, HashMap<Term, usize>>,
+    /// Every word, blocklisted or not, as `token_count` counts them
+    tokens: usize,
+}
+
+impl NoteCounts {
+    fn of(notes: &str, blocklist: &HashSet<Term>) -> Self {
+        let mut counts = Self::default();
+        for Token { surface, stem } in analyzer::tokenize(notes) {
+            counts.tokens += 1;
+            if blocklist.contains(&stem) {
+                continue;
+            }
+            *counts.terms.entry(stem).or_insert(0) += 1;
+            *counts.surfaces.entry(stem).or_default().entry(surface).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+impl TfIdf {
+    /// Replaces the notes of document `id`, moving only their terms' share
+    /// of the statistics; `false` when there's no such document
+    pub fn set_notes(&mut self, id: u32, notes: String) -> bool {
+        let Some(title) = self.titles.get(&id).cloned() else {
+            return false;
+        };
+        // Taken out and put back under the same id, rather than through
+        // `remove_document`/`insert_document`, which would hand it a new one
+        let Some(mut doc) = self.documents.remove(&title) else {
+            return false;
+        };
+        self.unindex_document(&doc);
+
+        let old = NoteCounts::of(&doc.notes, &self.blocklist);
+        for (term, freq) in doc.note_frequency.drain() {
+            if let Some(f) = doc.term_frequency.get_mut(&term) {
+                *f -= freq;
+                if *f < LEFTOVER {
+                    doc.term_frequency.remove(&term);
+                }
+            }
+        }
+        for (term, n) in old.terms {
+            if let Some(count) = self.global_term_count.get_mut(&term) {
+                *count = count.saturating_sub(n);
+                if *count == 0 {
+                    self.global_term_count.remove(&term);
+                }
+            }
+        }
+        for (stem, forms) in old.surfaces {
+            let Some(doc_forms) = doc.surface_forms.get_mut(&stem) else {
+                continue;
+            };
+            for (surface, n) in forms {
+                if let Some(count) = doc_forms.get_mut(&surface) {
+                    *count = count.saturating_sub(n);
+                    if *count == 0 {
+                        doc_forms.remove(&surface);
+                    }
+                }
+            }
+            if doc_forms.is_empty() {
+                doc.surface_forms.remove(&stem);
+            }
+        }
+        doc.token_count = doc.token_count.saturating_sub(old.tokens);
+
+        let new = NoteCounts::of(&notes, &self.blocklist);
+        let distinct_terms = doc.term_frequency.len().max(1) as f64;
+        for (term, n) in &new.terms {
+            let freq = *n as f64 / distinct_terms;
+            *doc.term_frequency.entry(*term).or_insert(0.0) += freq;
+            doc.note_frequency.insert(*term, freq);
+            *self.global_term_count.entry(*term).or_insert(0) += n;
+        }
+        for (stem, forms) in new.surfaces {
+            let doc_forms = doc.surface_forms.entry(stem).or_default();
+            for (surface, n) in forms {
+                *doc_forms.entry(surface).or_insert(0) += n;
+            }
+        }
+        doc.token_count += new.tokens;
+        doc.notes = notes;
+
+        self.index_document(&doc);
+        self.documents.insert(title, doc);
+        self.changed();
+        true
+    }
+}
+
+/// `POST /api/document/{id}/notes` with the notes as the body, in place of
+/// any the document had; an empty body clears them
+pub async fn set_notes(
+    Path(document_id): Path<u32>,
+    State(docs): State<DocShared>,
+    notes: String,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    let not_found =
+        || ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"));
+    docs.document_by_id(document_id).ok_or_else(not_found)?;
+    docs.log_change(wal::Entry::Notes {
+        id: document_id,
+        notes: &notes,
+    })?;
+    if !docs.set_notes(document_id, notes) {
+        return Err(not_found());
+    }
+    let doc = docs.document_by_id(document_id).ok_or_else(not_found)?;
+    Ok(Json(doc.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analyzer;
+    use crate::intern::get_str;
+    use crate::tests::{index, search_titles};
+    use crate::TfIdf;
+    use std::collections::BTreeMap;
+
+    /// The document's term frequencies by stem
+    fn frequencies(docs: &TfIdf, title: &str) -> BTreeMap<&'static str, f64> {
+        docs.documents[title]
+            .term_frequency
+            .iter()
+            .map(|(term, freq)| (get_str(*term), *freq))
+            .collect()
+    }
+
+    fn stem(word: &str) -> &'static str {
+        get_str(analyzer::word_tokens(word)[0].stem)
+    }
+
+    fn global_counts(docs: &TfIdf) -> BTreeMap<&'static str, usize> {
+        docs.global_term_count
+            .iter()
+            .map(|(term, count)| (get_str(*term), *count))
+            .collect()
+    }
+
+    #[test]
+    fn notes_only_move_their_own_terms() {
+        let shared = index(&[("Graphs", "shortest paths in sparse graphs")]);
+        let mut docs = shared.write().unwrap();
+        let id = docs.documents["Graphs"].id;
+        let body = frequencies(&docs, "Graphs");
+        let body_counts = global_counts(&docs);
+
+        assert!(docs.set_notes(id, "spectral methods".to_owned()));
+        assert_eq!(docs.documents["Graphs"].id, id);
+        let noted = frequencies(&docs, "Graphs");
+        for (stem, freq) in &body {
+            assert_eq!(noted[stem], *freq, "{stem} changed");
+        }
+        assert!(noted.contains_key(stem("spectral")));
+        assert_eq!(noted.len(), body.len() + 2);
+
+        assert!(docs.set_notes(id, "eigenvalues".to_owned()));
+        assert_eq!(docs.documents["Graphs"].id, id);
+        assert_eq!(docs.document_by_id(id).unwrap().title, "Graphs");
+        let renoted = frequencies(&docs, "Graphs");
+        assert!(!renoted.contains_key(stem("spectral")));
+        assert!(!global_counts(&docs).contains_key(stem("spectral")));
+        assert!(renoted.contains_key(stem("eigenvalues")));
+        drop(docs);
+        assert_eq!(search_titles(&shared, "eigenvalues"), ["Graphs"]);
+
+        let mut docs = shared.write().unwrap();
+        assert!(docs.set_notes(id, String::new()));
+        assert_eq!(frequencies(&docs, "Graphs"), body);
+        assert_eq!(global_counts(&docs), body_counts);
+    }
+}
@@ -0,0 +1,59 @@
+use axum::http::{HeaderMap, HeaderValue};
+use std::collections::{BTreeMap, HashMap};
+
+/// Whether the client asked for a page rather than everything
+pub fn is_paginated(params: &HashMap<String, String>) -> bool {
+    params.contains_key("offset") || params.contains_key("limit")
+}
+
+/// `X-Total-Count` plus a `Link` header with `first`, `prev`, `next` and
+/// `last` URLs, built from the request's `path` and `params` with only
+/// `offset` and `limit` changed; without a `limit` in `params` the page runs
+/// to the end, so there's no `next` or `last`
+pub fn headers(
+    path: &str,
+    params: &HashMap<String, String>,
+    offset: usize,
+    limit: usize,
+    total: usize,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-total-count", total.into());
+    if limit == 0 {
+        return headers;
+    }
+
+    let url = |offset: usize| {
+        // Sorted so the same page always gets the same URL
+        let mut params = params
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .collect::<BTreeMap<_, _>>();
+        params.insert("offset", offset.to_string());
+        params.insert("limit", limit.to_string());
+        let query = serde_urlencoded::to_string(&params).unwrap_or_default();
+        format!("{path}?{query}")
+    };
+    let last = total.saturating_sub(1) / limit * limit;
+    let mut links = vec![(url(0), "first")];
+    if offset > 0 {
+        links.push((url(offset.saturating_sub(limit)), "prev"));
+    }
+    if params.contains_key("limit") {
+        let next = offset.saturating_add(limit);
+        if next < total {
+            links.push((url(next), "next"));
+        }
+        links.push((url(last), "last"));
+    }
+
+    let link = links
+        .iter()
+        .map(|(url, rel)| format!("<{url}>; rel=\"{rel}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Ok(link) = HeaderValue::from_str(&link) {
+        headers.insert("link", link);
+    }
+    headers
+}
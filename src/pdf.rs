@@ -0,0 +1,647 @@
+use poppler::{PopplerDocument, PopplerPage};
+use serde_derive::Serialize;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::time::Duration;
+
+use crate::error::{self, ApiError, Error, ErrorCode};
+use crate::redact;
+
+#[repr(C)]
+struct GList {
+    data: *mut c_void,
+    next: *mut GList,
+    prev: *mut GList,
+}
+
+#[repr(C)]
+struct PopplerColor {
+    red: u16,
+    green: u16,
+    blue: u16,
+}
+
+/// Layout of poppler-glib's `PopplerTextAttributes`; the indices are in
+/// characters of `poppler_page_get_text`, inclusive
+#[repr(C)]
+struct PopplerTextAttributes {
+    font_name: *mut c_char,
+    font_size: f64,
+    is_underlined: c_int,
+    color: PopplerColor,
+    start_index: c_int,
+    end_index: c_int,
+}
+
+#[repr(C)]
+struct PopplerRectangle {
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+}
+
+/// Layout of poppler-glib's `PopplerAnnotMapping`
+#[repr(C)]
+struct PopplerAnnotMapping {
+    area: PopplerRectangle,
+    annot: *mut c_void,
+}
+
+/// Layout of poppler-glib's `PopplerActionGotoDest`. Every `PopplerAction`
+/// starts with the type and title, so those can be read from any action.
+#[repr(C)]
+struct PopplerActionGotoDest {
+    kind: c_int,
+    title: *mut c_char,
+    dest: *mut PopplerDest,
+}
+
+/// Start of poppler-glib's `PopplerDest`; only ever read through a pointer,
+/// so the bit fields after `named_dest` are left out
+#[repr(C)]
+struct PopplerDest {
+    kind: c_int,
+    /// 1-based
+    page_num: c_int,
+    left: f64,
+    bottom: f64,
+    right: f64,
+    top: f64,
+    zoom: f64,
+    named_dest: *mut c_char,
+}
+
+/// `POPPLER_ACTION_GOTO_DEST`
+const ACTION_GOTO_DEST: c_int = 2;
+/// `POPPLER_DEST_NAMED`: the page has to be looked up by name
+const DEST_NAMED: c_int = 9;
+
+/// Outlines nested deeper than this are cut off there
+const MAX_OUTLINE_DEPTH: usize = 32;
+
+/// `PopplerAnnotType` values for markup over the page's own text
+const ANNOT_HIGHLIGHT: c_int = 9;
+const ANNOT_UNDERLINE: c_int = 10;
+const ANNOT_SQUIGGLY: c_int = 11;
+
+extern "C" {
+    fn poppler_get_version() -> *const c_char;
+    fn poppler_page_get_text_attributes(page: *mut c_void) -> *mut GList;
+    fn poppler_page_free_text_attributes(list: *mut GList);
+    fn poppler_page_get_annot_mapping(page: *mut c_void) -> *mut GList;
+    fn poppler_page_free_annot_mapping(list: *mut GList);
+    fn poppler_page_get_text_for_area(
+        page: *mut c_void,
+        area: *mut PopplerRectangle,
+    ) -> *mut c_char;
+    fn poppler_annot_get_annot_type(annot: *mut c_void) -> c_int;
+    fn poppler_annot_get_contents(annot: *mut c_void) -> *mut c_char;
+    fn poppler_page_get_size(page: *mut c_void, width: *mut f64, height: *mut f64);
+    fn poppler_page_render(page: *mut c_void, cairo: *mut c_void);
+    fn poppler_index_iter_new(document: *mut c_void) -> *mut c_void;
+    fn poppler_index_iter_get_child(iter: *mut c_void) -> *mut c_void;
+    fn poppler_index_iter_next(iter: *mut c_void) -> c_int;
+    fn poppler_index_iter_get_action(iter: *mut c_void) -> *mut PopplerActionGotoDest;
+    fn poppler_index_iter_free(iter: *mut c_void);
+    fn poppler_action_free(action: *mut PopplerActionGotoDest);
+    fn poppler_document_find_dest(document: *mut c_void, name: *const c_char) -> *mut PopplerDest;
+    fn poppler_dest_free(dest: *mut PopplerDest);
+    fn g_free(mem: *mut c_void);
+}
+
+/// `CAIRO_FORMAT_RGB24`: no alpha, since pages get a white background
+const CAIRO_FORMAT_RGB24: c_int = 1;
+const CAIRO_STATUS_SUCCESS: c_int = 0;
+
+// poppler-glib renders through cairo, so it's already installed alongside
+#[link(name = "cairo")]
+extern "C" {
+    fn cairo_image_surface_create(format: c_int, width: c_int, height: c_int) -> *mut c_void;
+    fn cairo_surface_status(surface: *mut c_void) -> c_int;
+    fn cairo_surface_write_to_png(surface: *mut c_void, filename: *const c_char) -> c_int;
+    fn cairo_surface_destroy(surface: *mut c_void);
+    fn cairo_create(surface: *mut c_void) -> *mut c_void;
+    fn cairo_scale(cr: *mut c_void, sx: f64, sy: f64);
+    fn cairo_set_source_rgb(cr: *mut c_void, red: f64, green: f64, blue: f64);
+    fn cairo_paint(cr: *mut c_void);
+    fn cairo_destroy(cr: *mut c_void);
+}
+
+// poppler-rs wraps a document or page as nothing but its `GObject` pointer,
+// with no way to get at it, so the raw calls here read it out of the wrapper
+// with `raw`. At the poppler-rs revision this builds against, the wrappers
+// don't unref their object when dropped, which would leak every PDF opened;
+// `drop_pdf` and `drop_page` do it instead. They forget
+// the wrapper rather than drop it, so a poppler-rs that did start unreffing
+// on drop would still see each object unreffed once, not twice.
+
+/// The `GObject` pointer inside a poppler-rs wrapper
+fn raw<T>(wrapper: &T) -> *mut c_void {
+    // Fails loudly, instead of reading garbage, if poppler-rs ever changes
+    // what its wrappers hold
+    assert_eq!(
+        std::mem::size_of::<T>(),
+        std::mem::size_of::<*mut c_void>(),
+        "poppler-rs wrapper isn't a bare pointer"
+    );
+    unsafe { std::ptr::read_unaligned(wrapper as *const T as *const *mut c_void) }
+}
+
+/// Releases `pdf`'s document; dropping the wrapper alone would leak it
+pub fn drop_pdf(pdf: PopplerDocument) {
+    let object = raw(&pdf);
+    std::mem::forget(pdf);
+    unsafe { gobject_sys::g_object_unref(object as *mut gobject_sys::GObject) }
+}
+
+/// Releases `page`; dropping the wrapper alone would leak it
+pub fn drop_page(page: PopplerPage) {
+    let object = raw(&page);
+    std::mem::forget(page);
+    unsafe { gobject_sys::g_object_unref(object as *mut gobject_sys::GObject) }
+}
+
+/// Takes ownership of a string poppler allocated
+unsafe fn take_gstring(s: *mut c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let string = CStr::from_ptr(s).to_string_lossy().into_owned();
+    g_free(s as *mut c_void);
+    Some(string)
+}
+
+/// Text set this much larger than the page's body text counts as a heading
+const HEADING_RATIO: f64 = 1.2;
+
+/// One page whose only text is "paper engine probe"
+const PROBE_PDF: &[u8] = include_bytes!("probe.pdf");
+
+/// Version of the poppler library actually loaded at runtime
+pub fn poppler_version() -> String {
+    unsafe { CStr::from_ptr(poppler_get_version()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Extracts the embedded probe PDF, so a broken poppler/glib install fails
+/// at startup with an actionable message rather than on the first submit
+pub fn check_poppler() -> error::Result<()> {
+    const HINT: &str = "check that poppler-glib and glib are installed and that \
+        `pkg-config --modversion poppler-glib` matches the version paper-engine was built against";
+
+    let mut data = PROBE_PDF.to_vec();
+    let pdf = PopplerDocument::new_from_data(&mut data, None)
+        .map_err(|e| {
+            Error::Poppler(format!("Poppler could not open its built-in test PDF: {e}; {HINT}"))
+        })?;
+    let text = pdf.get_page(0).and_then(|page| {
+        let text = page.get_text().map(|t| t.to_owned());
+        drop_page(page);
+        text
+    });
+    drop_pdf(pdf);
+
+    match text {
+        Some(text) if text.contains("paper engine probe") => Ok(()),
+        text => Err(Error::Poppler(format!(
+            "Poppler opened its built-in test PDF but extracted {text:?}; {HINT}"
+        ))),
+    }
+}
+
+/// Poppler only reports encryption through the error message
+fn is_encrypted(e: &impl std::fmt::Display) -> bool {
+    e.to_string().to_lowercase().contains("encrypted")
+}
+
+/// The error for an encrypted document that `password` didn't open
+fn encrypted_error(what: &str, password: Option<&str>, e: impl std::fmt::Display) -> ApiError {
+    let hint = match password {
+        Some(_) => "the `password` given doesn't open it",
+        None => "give its `password`",
+    };
+    ApiError::new(ErrorCode::Encrypted, format!("Could not open {what}: {e}; {hint}"))
+}
+
+/// Opens a PDF held in memory; `name` is only used in errors. `data` must
+/// outlive the returned document. The flag is whether it took `password`.
+pub fn open_pdf_data(
+    name: &str,
+    data: &mut [u8],
+    password: Option<&str>,
+) -> Result<(PopplerDocument, bool), ApiError> {
+    let what = format!("upload: {name:?}");
+    match PopplerDocument::new_from_data(data, None) {
+        Ok(pdf) => Ok((pdf, false)),
+        Err(e) if is_encrypted(&e) => match password {
+            Some(password) => PopplerDocument::new_from_data(data, Some(password))
+                .map(|pdf| (pdf, true))
+                .map_err(|e| encrypted_error(&what, Some(password), e)),
+            None => Err(encrypted_error(&what, None, e)),
+        },
+        Err(e) => Err(ApiError::new(ErrorCode::OpenFailed, format!("Could not open {what}: {e}"))),
+    }
+}
+
+/// Opens `path`, retrying once after a short pause since a file that is still
+/// being written (or a busy network mount) often opens fine a moment later.
+/// An encrypted document is opened with `password`; the flag is whether it
+/// took one.
+pub fn open_pdf(path: &str, password: Option<&str>) -> Result<(PopplerDocument, bool), ApiError> {
+    let e = match PopplerDocument::new_from_file(path, None) {
+        Ok(pdf) => return Ok((pdf, false)),
+        Err(e) => e,
+    };
+    // No point retrying these
+    if is_encrypted(&e) {
+        let what = format!("file: {path:?}");
+        return match password {
+            Some(password) => PopplerDocument::new_from_file(path, Some(password))
+                .map(|pdf| (pdf, true))
+                .map_err(|e| encrypted_error(&what, Some(password), e)),
+            None => Err(encrypted_error(&what, None, e)),
+        };
+    }
+
+    tracing::warn!("Could not open {} ({e}); retrying once", redact::path(path));
+    std::thread::sleep(Duration::from_millis(200));
+    PopplerDocument::new_from_file(path, None)
+        .map(|pdf| (pdf, false))
+        .map_err(|e| {
+            ApiError::new(
+                ErrorCode::OpenFailed,
+                format!("Could not open file: {path:?}: {e}"),
+            )
+        })
+}
+
+/// Renders `page` at `dpi` into a PNG file at `out`, scaled down further if
+/// its longer side would be over `max_px` pixels
+pub fn render_png(
+    page: &PopplerPage,
+    dpi: f64,
+    max_px: u32,
+    out: &std::path::Path,
+) -> Result<(), String> {
+    let out = CString::new(out.to_string_lossy().into_owned()).map_err(|e| e.to_string())?;
+    unsafe {
+        let page = raw(page);
+        let (mut width, mut height) = (0.0, 0.0);
+        poppler_page_get_size(page, &mut width, &mut height);
+        // Page sizes are in points, 72 to the inch
+        let mut scale = dpi / 72.0;
+        let longest = width.max(height) * scale;
+        if longest > max_px as f64 {
+            scale *= max_px as f64 / longest;
+        }
+        let surface = cairo_image_surface_create(
+            CAIRO_FORMAT_RGB24,
+            (width * scale).ceil().max(1.0) as c_int,
+            (height * scale).ceil().max(1.0) as c_int,
+        );
+        let status = cairo_surface_status(surface);
+        if status != CAIRO_STATUS_SUCCESS {
+            cairo_surface_destroy(surface);
+            return Err(format!("cairo could not create the image (status {status})"));
+        }
+        let cr = cairo_create(surface);
+        // Poppler only draws what's on the page, not the paper under it
+        cairo_set_source_rgb(cr, 1.0, 1.0, 1.0);
+        cairo_paint(cr);
+        cairo_scale(cr, scale, scale);
+        poppler_page_render(page, cr);
+        cairo_destroy(cr);
+        let status = cairo_surface_write_to_png(surface, out.as_ptr());
+        cairo_surface_destroy(surface);
+        if status != CAIRO_STATUS_SUCCESS {
+            return Err(format!("cairo could not write the PNG (status {status})"));
+        }
+    }
+    Ok(())
+}
+
+/// Character ranges of the page's text (inclusive) with the font size each
+/// is set in, in reading order; empty when poppler has no font information
+fn font_spans(page: &PopplerPage) -> Vec<(usize, usize, f64)> {
+    let mut spans = vec![];
+    unsafe {
+        let page = raw(page);
+        let list = poppler_page_get_text_attributes(page);
+        let mut node = list;
+        while !node.is_null() {
+            let attrs = &*((*node).data as *const PopplerTextAttributes);
+            spans.push((
+                attrs.start_index.max(0) as usize,
+                attrs.end_index.max(0) as usize,
+                attrs.font_size,
+            ));
+            node = (*node).next;
+        }
+        if !list.is_null() {
+            poppler_page_free_text_attributes(list);
+        }
+    }
+    spans
+}
+
+/// Body text is whichever size covers the most characters
+fn body_size(spans: &[(usize, usize, f64)]) -> Option<f64> {
+    let mut coverage: Vec<(f64, usize)> = vec![];
+    for (start, end, size) in spans {
+        let len = end.saturating_sub(*start) + 1;
+        match coverage.iter_mut().find(|(s, _)| s == size) {
+            Some((_, n)) => *n += len,
+            None => coverage.push((*size, len)),
+        }
+    }
+    coverage.into_iter().max_by_key(|(_, n)| *n).map(|(size, _)| size)
+}
+
+/// The parts of `text` (the page's `get_text()`) set noticeably larger than
+/// the page's body text, joined by spaces. `None` when poppler has no font
+/// information for the page, in which case every word weighs the same.
+pub fn heading_text(page: &PopplerPage, text: &str) -> Option<String> {
+    let spans = font_spans(page);
+    let body_size = body_size(&spans)?;
+
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut headings = String::new();
+    for (start, end, size) in spans {
+        if size < body_size * HEADING_RATIO || start >= chars.len() {
+            continue;
+        }
+        headings.extend(&chars[start..=end.min(chars.len() - 1)]);
+        headings.push(' ');
+    }
+    Some(headings)
+}
+
+/// Title candidates from the fonts of the first page: the first heading
+/// (text at least `HEADING_RATIO` times the body size) and the first run of
+/// the largest text, if that's larger than the body. Each is the run of
+/// consecutive spans at that size, lines joined by spaces. `None`s when the
+/// page has no text or font information.
+pub fn first_page_titles(pdf: &PopplerDocument) -> (Option<String>, Option<String>) {
+    let Some(page) = pdf.get_page(0) else {
+        return (None, None);
+    };
+    let text = page.get_text().map(str::to_owned);
+    let spans = font_spans(&page);
+    drop_page(page);
+    let (Some(text), Some(body_size)) = (text, body_size(&spans)) else {
+        return (None, None);
+    };
+    let chars = text.chars().collect::<Vec<_>>();
+    let span_text = |&(start, end, _): &(usize, usize, f64)| -> String {
+        match start < chars.len() {
+            true => chars[start..=end.min(chars.len() - 1)].iter().collect(),
+            false => String::new(),
+        }
+    };
+    // Sizes of spaces and line breaks say nothing
+    let spans = spans
+        .into_iter()
+        .filter(|span| !span_text(span).trim().is_empty())
+        .collect::<Vec<_>>();
+    let first_run = |at_size: &dyn Fn(f64) -> bool| {
+        let start = spans.iter().position(|(_, _, size)| at_size(*size))?;
+        let run = spans[start..]
+            .iter()
+            .take_while(|(_, _, size)| at_size(*size))
+            .map(span_text)
+            .collect::<String>();
+        Some(run.split_whitespace().collect::<Vec<_>>().join(" "))
+    };
+    let heading = first_run(&|size| size >= body_size * HEADING_RATIO);
+    let largest = spans.iter().map(|(_, _, size)| *size).fold(body_size, f64::max);
+    let largest = (largest > body_size)
+        .then(|| first_run(&|size| size == largest))
+        .flatten();
+    (heading, largest)
+}
+
+/// One bookmark of a PDF's outline
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineEntry {
+    pub title: String,
+    /// 1-based page it points to; `None` for bookmarks that go elsewhere
+    /// (a link, another file) or to a destination the PDF doesn't define
+    pub page: Option<u32>,
+    pub children: Vec<OutlineEntry>,
+}
+
+/// The bookmark tree of `pdf`; empty when it has none
+pub fn outline(pdf: &PopplerDocument) -> Vec<OutlineEntry> {
+    unsafe {
+        let document = raw(pdf);
+        let iter = poppler_index_iter_new(document);
+        if iter.is_null() {
+            return vec![];
+        }
+        let outline = outline_level(document, iter, 0);
+        poppler_index_iter_free(iter);
+        outline
+    }
+}
+
+/// The entries at `iter`'s level and, recursively, their children
+unsafe fn outline_level(
+    document: *mut c_void,
+    iter: *mut c_void,
+    depth: usize,
+) -> Vec<OutlineEntry> {
+    let mut entries = vec![];
+    loop {
+        let action = poppler_index_iter_get_action(iter);
+        let (title, page) = if action.is_null() {
+            (String::new(), None)
+        } else {
+            let title = match (*action).title.is_null() {
+                true => String::new(),
+                false => CStr::from_ptr((*action).title).to_string_lossy().into_owned(),
+            };
+            let page = match (*action).kind {
+                ACTION_GOTO_DEST => dest_page(document, (*action).dest),
+                _ => None,
+            };
+            poppler_action_free(action);
+            (title, page)
+        };
+        let child = match depth + 1 < MAX_OUTLINE_DEPTH {
+            true => poppler_index_iter_get_child(iter),
+            false => std::ptr::null_mut(),
+        };
+        let children = if child.is_null() {
+            vec![]
+        } else {
+            let children = outline_level(document, child, depth + 1);
+            poppler_index_iter_free(child);
+            children
+        };
+        entries.push(OutlineEntry {
+            title,
+            page,
+            children,
+        });
+        if poppler_index_iter_next(iter) == 0 {
+            break;
+        }
+    }
+    entries
+}
+
+/// The page `dest` points to, looking named destinations up in `document`
+unsafe fn dest_page(document: *mut c_void, dest: *mut PopplerDest) -> Option<u32> {
+    if dest.is_null() {
+        return None;
+    }
+    let page_num = if (*dest).kind == DEST_NAMED {
+        if (*dest).named_dest.is_null() {
+            return None;
+        }
+        let named = poppler_document_find_dest(document, (*dest).named_dest);
+        if named.is_null() {
+            return None;
+        }
+        let page_num = (*named).page_num;
+        poppler_dest_free(named);
+        page_num
+    } else {
+        (*dest).page_num
+    };
+    (page_num > 0).then_some(page_num as u32)
+}
+
+/// What the reader added to a page, each part joined by spaces
+#[derive(Debug, Default)]
+pub struct Annotations {
+    /// Page text under highlight, underline and squiggly annotations
+    pub marked: String,
+    /// Contents of every annotation: sticky notes, comments on highlights,
+    /// free text. Unlike `marked`, this isn't part of the page text.
+    pub notes: String,
+}
+
+pub fn annotations(page: &PopplerPage) -> Annotations {
+    let mut annotations = Annotations::default();
+    unsafe {
+        let page = raw(page);
+        let list = poppler_page_get_annot_mapping(page);
+        let mut node = list;
+        while !node.is_null() {
+            let mapping = &mut *((*node).data as *mut PopplerAnnotMapping);
+            let kind = poppler_annot_get_annot_type(mapping.annot);
+            if matches!(kind, ANNOT_HIGHLIGHT | ANNOT_UNDERLINE | ANNOT_SQUIGGLY) {
+                if let Some(marked) =
+                    take_gstring(poppler_page_get_text_for_area(page, &mut mapping.area))
+                {
+                    annotations.marked.push_str(&marked);
+                    annotations.marked.push(' ');
+                }
+            }
+            if let Some(contents) = take_gstring(poppler_annot_get_contents(mapping.annot)) {
+                annotations.notes.push_str(&contents);
+                annotations.notes.push(' ');
+            }
+            node = (*node).next;
+        }
+        if !list.is_null() {
+            poppler_page_free_annot_mapping(list);
+        }
+    }
+    annotations
+}
+
+/// Rejoins words that justified text split over two lines, "exper-\niment"
+/// to "experiment", using the line breaks poppler puts in a page's text.
+///
+/// A hyphen only counts as a break when it ends a line, follows a letter and
+/// the next line carries on in lowercase; "well-known" in the middle of a
+/// line, or a line ending in "-" before a capitalized word, keeps its hyphen.
+/// Soft hyphens (U+00AD) are dropped wherever they are, since they never
+/// show.
+pub fn join_hyphenated(text: &str) -> String {
+    let mut joined = String::with_capacity(text.len());
+    let mut lines = text.split('\n').map(|line| line.trim_end_matches('\r')).peekable();
+    // Whether the previous line ended in a hyphen that was taken out
+    let mut glued = false;
+    while let Some(line) = lines.next() {
+        let line = if glued { line.trim_start() } else { line };
+        let continues = lines.peek().is_some_and(|next| {
+            next.trim_start()
+                .chars()
+                .next()
+                .is_some_and(char::is_lowercase)
+        });
+        let broken = line
+            .strip_suffix('-')
+            .or_else(|| line.strip_suffix('\u{AD}'))
+            .filter(|rest| continues && rest.chars().last().is_some_and(char::is_alphabetic));
+        glued = broken.is_some();
+        joined.extend(broken.unwrap_or(line).chars().filter(|c| *c != '\u{AD}'));
+        if !glued && lines.peek().is_some() {
+            joined.push('\n');
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts, in the `AtomicUsize` behind `data`, an object being freed
+    unsafe extern "C" fn count_freed(data: *mut c_void, _: *mut gobject_sys::GObject) {
+        (*(data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Has `freed` counted when the object inside `wrapper` is freed
+    fn watch<T>(wrapper: &T, freed: &AtomicUsize) {
+        let object = raw(wrapper) as *mut gobject_sys::GObject;
+        let data = freed as *const AtomicUsize as *mut c_void;
+        unsafe { gobject_sys::g_object_weak_ref(object, Some(count_freed), data) }
+    }
+
+    #[test]
+    fn dropped_documents_and_pages_are_freed_once() {
+        let freed = AtomicUsize::new(0);
+        for n in 1..=200 {
+            let mut data = PROBE_PDF.to_vec();
+            let pdf = PopplerDocument::new_from_data(&mut data, None).unwrap();
+            let page = pdf.get_page(0).unwrap();
+            // `raw` found the real objects, or registering would have crashed
+            watch(&pdf, &freed);
+            watch(&page, &freed);
+            assert!(page.get_text().is_some_and(|text| text.contains("paper engine probe")));
+            drop_page(page);
+            drop_pdf(pdf);
+            // Both gone, and neither unreffed again later
+            assert_eq!(freed.load(Ordering::SeqCst), 2 * n);
+        }
+    }
+
+    #[test]
+    fn hyphen_at_a_line_break_is_joined() {
+        assert_eq!(
+            join_hyphenated("a well-behaved algo-\nrithm runs"),
+            "a well-behaved algorithm runs"
+        );
+        assert_eq!(join_hyphenated("algo-\n  rithm"), "algorithm");
+        assert_eq!(join_hyphenated("soft\u{AD}\nhyphen"), "softhyphen");
+    }
+
+    #[test]
+    fn hyphens_inside_a_line_are_kept() {
+        assert_eq!(join_hyphenated("state-of-the-art methods"), "state-of-the-art methods");
+        assert_eq!(
+            join_hyphenated("state-of-the-art\nmethods"),
+            "state-of-the-art\nmethods"
+        );
+        // Not a break: the next line is capitalized
+        assert_eq!(join_hyphenated("Anglo-\nSaxon"), "Anglo-\nSaxon");
+    }
+}
@@ -0,0 +1,159 @@
+use poppler::PopplerDocument;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::pdf::{self, drop_pdf};
+
+// Open PDFs kept between requests that read the same file again, such as
+// thumbnails of one document's pages, so poppler doesn't parse it every time.
+// Shared by every collection and keyed by path; a file modified since it was
+// opened is opened afresh.
+//
+// A handle is taken out of the pool while it's in use and put back after, so
+// no two threads ever touch one document at once; two requests for the same
+// file at the same time just open it twice, and the second handle back is
+// released. Every handle leaves through `drop_pdf`, on eviction too.
+// Encrypted documents are never kept, so each use needs the password again.
+//
+// At most `PAPER_ENGINE_PDF_POOL_SIZE` documents are kept; past that the
+// least recently used one goes. 0 turns the pool off.
+
+/// A pooled document. poppler-glib objects may move between threads as long
+/// as only one uses them at a time, which checking them out guarantees.
+struct Handle(PopplerDocument);
+
+unsafe impl Send for Handle {}
+
+struct Entry {
+    handle: Handle,
+    /// The file's modification time when it was opened
+    modified: Option<SystemTime>,
+    /// `Pool::clock` when last returned
+    used: u64,
+}
+
+#[derive(Default)]
+struct Pool {
+    entries: HashMap<String, Entry>,
+    /// Counts returns, to order entries by use
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref POOL: Mutex<Pool> = Mutex::new(Pool::default());
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolStats {
+    /// Documents open in the pool right now
+    open: usize,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+    /// Documents closed to make room
+    evictions: u64,
+}
+
+pub fn stats() -> PoolStats {
+    let pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    PoolStats {
+        open: pool.entries.len(),
+        capacity: CONFIG.pdf_pool_size,
+        hits: pool.hits,
+        misses: pool.misses,
+        evictions: pool.evictions,
+    }
+}
+
+/// The pooled handle for `path`, if it's still current
+fn check_out(path: &str, modified: Option<SystemTime>) -> Option<PopplerDocument> {
+    let mut pool = POOL.lock().ok()?;
+    match pool.entries.remove(path) {
+        Some(entry) if entry.modified == modified && modified.is_some() => {
+            pool.hits += 1;
+            Some(entry.handle.0)
+        }
+        stale => {
+            pool.misses += 1;
+            drop(pool);
+            if let Some(entry) = stale {
+                drop_pdf(entry.handle.0);
+            }
+            None
+        }
+    }
+}
+
+/// Puts `pdf` back, closing whatever that pushes out
+fn check_in(path: &str, modified: Option<SystemTime>, pdf: PopplerDocument) {
+    let Ok(mut pool) = POOL.lock() else {
+        drop_pdf(pdf);
+        return;
+    };
+    let mut closing = vec![];
+    if let Some(entry) = pool.entries.remove(path) {
+        // Another request's copy came back first; keep the one just used
+        closing.push(entry.handle.0);
+    }
+    while pool.entries.len() >= CONFIG.pdf_pool_size {
+        let Some(oldest) = pool
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.used)
+            .map(|(path, _)| path.clone())
+        else {
+            break;
+        };
+        if let Some(entry) = pool.entries.remove(&oldest) {
+            closing.push(entry.handle.0);
+            pool.evictions += 1;
+        }
+    }
+    pool.clock += 1;
+    let entry = Entry {
+        handle: Handle(pdf),
+        modified,
+        used: pool.clock,
+    };
+    pool.entries.insert(path.to_owned(), entry);
+    drop(pool);
+    // Outside the lock, since freeing a big document takes a moment
+    for pdf in closing {
+        drop_pdf(pdf);
+    }
+}
+
+/// Runs `f` on the PDF at `path`, from the pool when it's there and opened
+/// with `pdf::open_pdf` (and `password`) when it isn't
+pub fn with_pdf<R>(
+    path: &str,
+    password: Option<&str>,
+    f: impl FnOnce(&PopplerDocument) -> R,
+) -> Result<R, ApiError> {
+    if CONFIG.pdf_pool_size == 0 {
+        let (pdf, _) = pdf::open_pdf(path, password)?;
+        let result = f(&pdf);
+        drop_pdf(pdf);
+        return Ok(result);
+    }
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let (pdf, encrypted) = match check_out(path, modified) {
+        Some(pdf) => (pdf, false),
+        None => pdf::open_pdf(path, password)?,
+    };
+    let result = f(&pdf);
+    // Without a modification time there's no telling if it goes stale
+    if encrypted || modified.is_none() {
+        drop_pdf(pdf);
+    } else {
+        check_in(path, modified, pdf);
+    }
+    Ok(result)
+}
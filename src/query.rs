@@ -0,0 +1,521 @@
+use axum::{extract::Query as QueryParams, Json};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+use crate::analyzer::{self, Dropped, Token};
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::search::Field;
+use crate::tags;
+use crate::Document;
+
+/// Largest `^boost` a term may have. Scores are whole numbers, so a much
+/// larger boost would only push them towards `u64::MAX` and flatten the
+/// ranking of everything it matched.
+pub const MAX_BOOST: f64 = 1000.0;
+
+/// What terms written side by side mean
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    And,
+    Or,
+}
+
+impl std::str::FromStr for Operator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "and" => Ok(Self::And),
+            "or" => Ok(Self::Or),
+            _ => Err(format!("expected \"and\" or \"or\", got {s:?}")),
+        }
+    }
+}
+
+impl Operator {
+    /// The `op` parameter, defaulting to `PAPER_ENGINE_DEFAULT_OPERATOR`
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        match params.get("op") {
+            None => Ok(CONFIG.default_operator),
+            Some(s) => s
+                .parse()
+                .map_err(|e| ApiError::invalid_param(format!("Invalid `op` parameter: {e}"))),
+        }
+    }
+}
+
+/// A parsed boolean query.
+///
+/// `AND`, `OR` and `NOT` must be uppercase, so lowercase "and"/"or" are
+/// ordinary words. Precedence is `NOT` > `AND` > `OR`. Terms written side by
+/// side are joined by the default [`Operator`]; with `And` they bind like an
+/// explicit `AND`, so `a b OR c` is `(a AND b) OR c`.
+///
+/// `field:word` only looks for the word in that field, and `tag:name` only
+/// matches documents with that tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Query {
+    Term {
+        token: Token,
+        /// From a `^boost` suffix; multiplies the term's score
+        #[serde(skip_serializing_if = "is_default_boost")]
+        boost: f64,
+        /// From a `field:` qualifier; `None` searches the default fields
+        #[serde(skip_serializing_if = "Option::is_none")]
+        field: Option<Field>,
+    },
+    /// `prefix*`: every indexed spelling starting with `prefix`, unstemmed.
+    /// Search replaces it with an `Or` of those terms before matching (see
+    /// `TfIdf::expand_prefixes`); until then it matches nothing.
+    Prefix {
+        prefix: String,
+        #[serde(skip_serializing_if = "is_default_boost")]
+        boost: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        field: Option<Field>,
+    },
+    /// `tag:name`; narrows the results but isn't scored
+    Tag { tag: String },
+    And { children: Vec<Query> },
+    Or { children: Vec<Query> },
+    Not { child: Box<Query> },
+}
+
+impl Query {
+    /// Every leaf term, left to right
+    pub fn terms(&self) -> Vec<Token> {
+        let mut terms = vec![];
+        self.collect_terms(&mut terms);
+        terms
+    }
+
+    /// Leaf terms that aren't under a `NOT`; the ones worth scoring
+    pub fn positive_terms(&self) -> Vec<QueryTerm> {
+        let mut terms = vec![];
+        self.collect_positive_terms(&mut terms);
+        terms
+    }
+
+    /// The `tag:` and `NOT` clauses, which narrow the results rather than
+    /// score them
+    pub fn restrictions(&self) -> Vec<&Query> {
+        match self {
+            Self::Tag { .. } | Self::Not { .. } => vec![self],
+            Self::Term { .. } | Self::Prefix { .. } => vec![],
+            Self::And { children } | Self::Or { children } => {
+                children.iter().flat_map(|child| child.restrictions()).collect()
+            }
+        }
+    }
+
+    fn collect_terms(&self, terms: &mut Vec<Token>) {
+        match self {
+            Self::Term { token, .. } => terms.push(*token),
+            Self::Prefix { .. } | Self::Tag { .. } => {}
+            Self::And { children } | Self::Or { children } => {
+                children.iter().for_each(|child| child.collect_terms(terms))
+            }
+            Self::Not { child } => child.collect_terms(terms),
+        }
+    }
+
+    fn collect_positive_terms(&self, terms: &mut Vec<QueryTerm>) {
+        match self {
+            &Self::Term { token, boost, field } => terms.push(QueryTerm {
+                token,
+                boost,
+                field,
+            }),
+            Self::Prefix { .. } | Self::Tag { .. } => {}
+            Self::And { children } | Self::Or { children } => children
+                .iter()
+                .for_each(|child| child.collect_positive_terms(terms)),
+            Self::Not { .. } => {}
+        }
+    }
+
+    /// Whether `doc` satisfies the query. With `exact`, a term only matches
+    /// the spelling that was typed.
+    pub fn matches(&self, doc: &Document, exact: bool) -> bool {
+        match self {
+            Self::Term { token, field, .. } => doc.match_share(*token, *field, exact) > 0.0,
+            Self::Prefix { .. } => false,
+            Self::Tag { tag } => doc.tags.contains(tag),
+            Self::And { children } => children.iter().all(|child| child.matches(doc, exact)),
+            Self::Or { children } => children.iter().any(|child| child.matches(doc, exact)),
+            Self::Not { child } => !child.matches(doc, exact),
+        }
+    }
+}
+
+/// A term to score, as [`Query::positive_terms`] gives them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryTerm {
+    pub token: Token,
+    pub boost: f64,
+    /// Only this field is scored; `None` scores the default fields
+    pub field: Option<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParseError {
+    pub message: String,
+    /// Character offset into the query where the problem is
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Lexeme {
+    Word(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+/// Splits `s` into lexemes, each with its character offset
+fn lex(s: &str) -> Vec<(Lexeme, usize)> {
+    let mut lexemes = vec![];
+    let mut chars = s.chars().enumerate().peekable();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => lexemes.push((Lexeme::Open, pos)),
+            ')' => lexemes.push((Lexeme::Close, pos)),
+            c => {
+                let mut word = String::from(c);
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| !c.is_whitespace() && *c != '(' && *c != ')')
+                {
+                    word.push(c);
+                }
+                let lexeme = match word.as_str() {
+                    "AND" => Lexeme::And,
+                    "OR" => Lexeme::Or,
+                    "NOT" => Lexeme::Not,
+                    _ => Lexeme::Word(word),
+                };
+                lexemes.push((lexeme, pos));
+            }
+        }
+    }
+    lexemes
+}
+
+struct Parser {
+    lexemes: Vec<(Lexeme, usize)>,
+    next: usize,
+    default_op: Operator,
+    /// Where the query ends, for "expected ..." errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.next).map(|(lexeme, _)| lexeme)
+    }
+
+    fn position(&self) -> usize {
+        self.lexemes.get(self.next).map_or(self.end, |(_, pos)| *pos)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            message: message.into(),
+            position: self.position(),
+        })
+    }
+
+    /// Whether the next lexeme starts a term without an operator before it
+    fn at_juxtaposed(&self, op: Operator) -> bool {
+        self.default_op == op
+            && matches!(self.peek(), Some(Lexeme::Word(_) | Lexeme::Not | Lexeme::Open))
+    }
+
+    /// `or := and (OR and)*`, where `OR` may be left out if it's the default
+    fn or(&mut self) -> Result<Query, ParseError> {
+        let mut children = vec![self.and()?];
+        loop {
+            if self.peek() == Some(&Lexeme::Or) {
+                self.next += 1;
+            } else if !self.at_juxtaposed(Operator::Or) {
+                break;
+            }
+            children.push(self.and()?);
+        }
+        Ok(flatten(children, |children| Query::Or { children }))
+    }
+
+    /// `and := not (AND not)*`, where `AND` may be left out if it's the default
+    fn and(&mut self) -> Result<Query, ParseError> {
+        let mut children = vec![self.not()?];
+        loop {
+            if self.peek() == Some(&Lexeme::And) {
+                self.next += 1;
+            } else if !self.at_juxtaposed(Operator::And) {
+                break;
+            }
+            children.push(self.not()?);
+        }
+        Ok(flatten(children, |children| Query::And { children }))
+    }
+
+    /// `not := NOT not | ( or ) | [field:]word[*][^boost] | tag:name`
+    fn not(&mut self) -> Result<Query, ParseError> {
+        let open_pos = self.position();
+        match self.peek().cloned() {
+            Some(Lexeme::Not) => {
+                self.next += 1;
+                Ok(Query::Not {
+                    child: Box::new(self.not()?),
+                })
+            }
+            Some(Lexeme::Open) => {
+                self.next += 1;
+                let query = self.or()?;
+                if self.peek() != Some(&Lexeme::Close) {
+                    return self.error(format!("Expected `)` to close the `(` at {open_pos}"));
+                }
+                self.next += 1;
+                Ok(query)
+            }
+            Some(Lexeme::Word(word)) => {
+                let (field, word) = match qualifier(&word) {
+                    Some(("tag", tag)) => {
+                        self.next += 1;
+                        return Ok(Query::Tag {
+                            tag: tags::normalize_tag(tag).unwrap_or_default(),
+                        });
+                    }
+                    Some((name, word)) => match Field::parse(name) {
+                        Some(field) => (Some(field), word),
+                        None => {
+                            return self.error(format!(
+                                "Unknown field {name:?}; use \"body\", \"abstract\", \
+                                 \"annotations\" or \"tag\""
+                            ))
+                        }
+                    },
+                    None => (None, word.as_str()),
+                };
+                let (word, boost) = match word.rsplit_once('^') {
+                    Some((word, boost)) => match boost.parse::<f64>() {
+                        Ok(boost) if (0.0..=MAX_BOOST).contains(&boost) => (word, boost),
+                        _ => {
+                            return self.error(format!(
+                                "Invalid boost {boost:?}; must be a number from 0 to {MAX_BOOST}"
+                            ))
+                        }
+                    },
+                    None => (word, 1.0),
+                };
+                if let Some(prefix) = word.strip_suffix('*') {
+                    if prefix.is_empty() || prefix.contains('*') {
+                        return self.error(format!(
+                            "Invalid wildcard {word:?}; `*` only goes at the end of a word"
+                        ));
+                    }
+                    self.next += 1;
+                    return Ok(Query::Prefix {
+                        prefix: analyzer::fold_case(prefix),
+                        boost,
+                        field,
+                    });
+                }
+                // CJK words come out as several bigrams, which are joined like
+                // separate words and share the boost
+                let term = |token| Query::Term {
+                    token,
+                    boost,
+                    field,
+                };
+                let terms = analyzer::unexpanded_tokens(word)
+                    .into_iter()
+                    .map(term)
+                    .collect::<Vec<_>>();
+                if terms.is_empty() {
+                    return self.error(format!(
+                        "{word:?} is not a searchable term: {}",
+                        unsearchable_reason(word)
+                    ));
+                }
+                self.next += 1;
+                let query = match self.default_op {
+                    Operator::And => flatten(terms, |children| Query::And { children }),
+                    Operator::Or => flatten(terms, |children| Query::Or { children }),
+                };
+                // A registered abbreviation also matches its expansion written
+                // out, every word of it
+                let expansion = analyzer::expansion(word)
+                    .into_iter()
+                    .flat_map(str::split_whitespace)
+                    .flat_map(analyzer::unexpanded_tokens)
+                    .map(term)
+                    .collect::<Vec<_>>();
+                if expansion.is_empty() {
+                    return Ok(query);
+                }
+                Ok(Query::Or {
+                    children: vec![query, flatten(expansion, |children| Query::And { children })],
+                })
+            }
+            Some(Lexeme::Close) => self.error("Unexpected `)`"),
+            Some(Lexeme::And | Lexeme::Or) => self.error("Expected a term before the operator"),
+            None => self.error("Expected a term"),
+        }
+    }
+}
+
+/// Splits `name:word` when `name` is a plain lowercase word and `word`
+/// starts with a letter or digit, so `std::vec` and `http://...` stay
+/// ordinary words
+fn qualifier(word: &str) -> Option<(&str, &str)> {
+    let (name, rest) = word.split_once(':')?;
+    let plain = !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+    let starts_word = rest.chars().next().is_some_and(char::is_alphanumeric);
+    (plain && starts_word).then_some((name, rest))
+}
+
+/// Why the analyzer leaves nothing of `word`
+fn unsearchable_reason(word: &str) -> &'static str {
+    match analyzer::dropped(word) {
+        Some(Dropped::Numeric) => "numbers are dropped (PAPER_ENGINE_DROP_NUMERIC_TOKENS)",
+        Some(Dropped::Symbol) => "symbols are dropped (PAPER_ENGINE_DROP_SYMBOL_TOKENS)",
+        None => "it has no indexable text",
+    }
+}
+
+/// When every word of `s` analyzes to nothing, each of them with why; `None`
+/// if any word (or `prefix*`, or `tag:name`) is left to search with. Queries
+/// like that would otherwise fail on their first word, hiding the rest.
+pub fn unsearchable_words(s: &str) -> Option<Vec<(String, &'static str)>> {
+    let mut dropped = vec![];
+    for (lexeme, _) in lex(s) {
+        let Lexeme::Word(raw) = lexeme else {
+            continue;
+        };
+        let word = match qualifier(&raw) {
+            Some(("tag", _)) => return None,
+            Some((_, word)) => word,
+            None => raw.as_str(),
+        };
+        let word = word
+            .rsplit_once('^')
+            .filter(|(_, boost)| boost.parse::<f64>().is_ok())
+            .map_or(word, |(word, _)| word);
+        if word.ends_with('*') || analyzer::tokenize(word).next().is_some() {
+            return None;
+        }
+        dropped.push((raw.clone(), unsearchable_reason(word)));
+    }
+    (!dropped.is_empty()).then_some(dropped)
+}
+
+fn is_default_boost(boost: &f64) -> bool {
+    *boost == 1.0
+}
+
+/// A single child needs no node of its own
+fn flatten(mut children: Vec<Query>, node: fn(Vec<Query>) -> Query) -> Query {
+    if children.len() == 1 {
+        children.pop().unwrap()
+    } else {
+        node(children)
+    }
+}
+
+pub fn parse(s: &str, default_op: Operator) -> Result<Query, ParseError> {
+    let mut parser = Parser {
+        lexemes: lex(s),
+        next: 0,
+        default_op,
+        end: s.chars().count(),
+    };
+    if parser.lexemes.is_empty() {
+        return parser.error("Query is empty");
+    }
+    let query = parser.or()?;
+    if parser.peek().is_some() {
+        return parser.error("Unexpected `)`");
+    }
+    Ok(query)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Validation {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ast: Option<Query>,
+    /// Stemmed leaf terms, as the index would look them up
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    terms: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ParseError>,
+}
+
+/// `GET /api/document/validate_query?s=..`: parses without touching the index
+pub async fn validate_query(
+    QueryParams(params): QueryParams<HashMap<String, String>>,
+) -> Result<Json<Validation>, ApiError> {
+    let s = params
+        .get("s")
+        .ok_or_else(|| ApiError::missing_param("Missing `s` parameter; give a query"))?;
+    let op = Operator::from_params(&params)?;
+    Ok(Json(match parse(s, op) {
+        Ok(query) => Validation {
+            valid: true,
+            terms: query
+                .terms()
+                .iter()
+                .map(|token| token.stem.to_string())
+                .collect(),
+            ast: Some(query),
+            error: None,
+        },
+        Err(error) => Validation {
+            valid: false,
+            ast: None,
+            terms: vec![],
+            error: Some(error),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::get_str;
+
+    #[test]
+    fn boost_suffix_parses() {
+        let query = parse("word^2", Operator::And).unwrap();
+        let Query::Term { token, boost, .. } = query else {
+            panic!("expected a term, got {query:?}");
+        };
+        assert_eq!(get_str(token.surface), "word");
+        assert_eq!(boost, 2.0);
+    }
+
+    #[test]
+    fn negative_boost_is_refused() {
+        let error = parse("word^-1", Operator::And).unwrap_err();
+        assert!(error.message.contains("Invalid boost"), "{}", error.message);
+    }
+
+    #[test]
+    fn boost_over_the_maximum_is_refused() {
+        assert!(parse("word^1000", Operator::And).is_ok());
+        let error = parse("word^1e300", Operator::And).unwrap_err();
+        assert!(error.message.contains("Invalid boost"), "{}", error.message);
+    }
+
+    #[test]
+    fn boost_that_is_not_a_number_is_refused() {
+        let error = parse("word^abc", Operator::And).unwrap_err();
+        assert!(error.message.contains("\"abc\""), "{}", error.message);
+    }
+}
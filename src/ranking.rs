@@ -0,0 +1,121 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::admin::require_admin;
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::wal;
+use crate::DocShared;
+
+/// Ranking weights that can be tuned while the server runs, through
+/// `/api/admin/ranking`. They live in the index and are saved with the cache;
+/// the matching `PAPER_ENGINE_*` variables are only the starting values.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RankingConfig {
+    /// Boost of body matches when a search doesn't give `fields`
+    pub body_boost: f64,
+    /// Boost of abstract matches when a search doesn't give `fields`
+    pub abstract_boost: f64,
+    /// Boost of annotation matches when a search doesn't give `fields`
+    pub annotation_boost: f64,
+    /// Age in days at which `recency=true` halves a score
+    pub recency_half_life_days: f64,
+}
+
+impl Default for RankingConfig {
+    fn default() -> Self {
+        Self {
+            body_boost: 1.0,
+            abstract_boost: CONFIG.abstract_boost,
+            annotation_boost: CONFIG.annotation_boost,
+            recency_half_life_days: CONFIG.recency_half_life.as_secs_f64() / 86400.0,
+        }
+    }
+}
+
+impl RankingConfig {
+    /// Every weight by name, the way the cache stores them
+    pub fn weights(&self) -> [(&'static str, f64); 4] {
+        [
+            ("body_boost", self.body_boost),
+            ("abstract_boost", self.abstract_boost),
+            ("annotation_boost", self.annotation_boost),
+            ("recency_half_life_days", self.recency_half_life_days),
+        ]
+    }
+
+    /// Sets the weight called `name`; unknown names (from a newer cache) are
+    /// ignored
+    pub fn set_weight(&mut self, name: &str, value: f64) {
+        match name {
+            "body_boost" => self.body_boost = value,
+            "abstract_boost" => self.abstract_boost = value,
+            "annotation_boost" => self.annotation_boost = value,
+            "recency_half_life_days" => self.recency_half_life_days = value,
+            _ => {}
+        }
+    }
+
+    fn validate(&self) -> Result<(), ApiError> {
+        for (name, value) in self.weights() {
+            if !value.is_finite() || value < 0.0 {
+                return Err(ApiError::invalid_param(format!(
+                    "Invalid `{name}` {value}; must be a finite, non-negative number"
+                )));
+            }
+        }
+        if self.recency_half_life_days == 0.0 {
+            return Err(ApiError::invalid_param("`recency_half_life_days` must be above zero"));
+        }
+        Ok(())
+    }
+}
+
+/// `GET /api/admin/ranking`
+pub async fn get_ranking(
+    headers: HeaderMap,
+    State(docs): State<DocShared>,
+) -> Result<Json<RankingConfig>, ApiError> {
+    require_admin(&headers)?;
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    Ok(Json(docs.ranking))
+}
+
+/// `PUT /api/admin/ranking` with every weight in the body; the next search
+/// uses them
+pub async fn put_ranking(
+    headers: HeaderMap,
+    State(docs): State<DocShared>,
+    Json(ranking): Json<RankingConfig>,
+) -> Result<Json<RankingConfig>, ApiError> {
+    require_admin(&headers)?;
+    ranking.validate()?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    docs.log_change(wal::Entry::Ranking(&ranking))?;
+    docs.ranking = ranking;
+    docs.changed();
+    tracing::info!("Ranking weights set to {ranking:?}");
+    Ok(Json(ranking))
+}
+
+/// `DELETE /api/admin/ranking`: back to the values from the environment
+pub async fn reset_ranking(
+    headers: HeaderMap,
+    State(docs): State<DocShared>,
+) -> Result<Json<RankingConfig>, ApiError> {
+    require_admin(&headers)?;
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    let ranking = RankingConfig::default();
+    docs.log_change(wal::Entry::Ranking(&ranking))?;
+    docs.ranking = ranking;
+    docs.changed();
+    tracing::info!("Ranking weights reset to {:?}", docs.ranking);
+    Ok(Json(docs.ranking))
+}
@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::error::ApiError;
+use crate::search::SortOrder;
+use crate::DocumentInfo;
+
+// Statistics on how a document reads, for sorting the library by how dense
+// its text is. They're counted from the extracted text as it's tokenized.
+// Syllables are counted with an English heuristic, so the reading ease means
+// little for documents in other languages.
+
+/// Fewest words a document needs to get a reading ease; with fewer, a
+/// sentence or two more or less swings it wildly
+const MIN_WORDS: u64 = 100;
+
+/// Running counts over a document's text, saved with it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextStats {
+    /// Runs of letters and digits with at least one letter
+    pub words: u64,
+    /// Letters and digits in those words
+    pub letters: u64,
+    pub syllables: u64,
+    /// `.`, `!` or `?` right after a word
+    pub sentences: u64,
+}
+
+impl TextStats {
+    pub fn add(&mut self, text: &str) {
+        let mut word = String::new();
+        // A trailing space ends the last word
+        for c in text.chars().chain([' ']) {
+            if c.is_alphanumeric() || (c == '\'' && !word.is_empty()) {
+                word.extend(c.to_lowercase());
+                continue;
+            }
+            if word.chars().any(char::is_alphabetic) {
+                self.words += 1;
+                self.letters += word.chars().filter(|c| c.is_alphanumeric()).count() as u64;
+                self.syllables += syllables(&word);
+                if matches!(c, '.' | '!' | '?') {
+                    self.sentences += 1;
+                }
+            }
+            word.clear();
+        }
+    }
+
+    pub fn average_word_length(&self) -> Option<f64> {
+        (self.words > 0).then(|| self.letters as f64 / self.words as f64)
+    }
+
+    /// Flesch reading ease: around 60 to 70 is plain English, below 30 reads
+    /// like an academic paper. `None` for too little text to tell, or text
+    /// without sentence ends (tables, lists of terms).
+    pub fn reading_ease(&self) -> Option<f64> {
+        if self.words < MIN_WORDS || self.sentences == 0 {
+            return None;
+        }
+        let words = self.words as f64;
+        Some(
+            206.835
+                - 1.015 * (words / self.sentences as f64)
+                - 84.6 * (self.syllables as f64 / words),
+        )
+    }
+}
+
+/// Groups of vowels, less a silent final `e`; at least one per word
+fn syllables(word: &str) -> u64 {
+    let is_vowel = |c: char| matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+    let mut groups = 0;
+    let mut previous = false;
+    for c in word.chars() {
+        let vowel = is_vowel(c);
+        if vowel && !previous {
+            groups += 1;
+        }
+        previous = vowel;
+    }
+    if groups > 1 && word.ends_with('e') && !word.ends_with("le") {
+        groups -= 1;
+    }
+    groups.max(1)
+}
+
+/// What `/list` orders documents by; `sort`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListSort {
+    Title,
+    WordCount,
+    UniqueTerms,
+    AverageWordLength,
+    ReadingEase,
+}
+
+impl ListSort {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        match params.get("sort").map(|v| v.as_str()) {
+            None | Some("title") => Ok(Self::Title),
+            Some("word_count") => Ok(Self::WordCount),
+            Some("unique_terms") => Ok(Self::UniqueTerms),
+            Some("average_word_length") => Ok(Self::AverageWordLength),
+            Some("reading_ease") => Ok(Self::ReadingEase),
+            Some(s) => Err(ApiError::invalid_param(format!(
+                "Unknown `sort` parameter {s:?}; use one of \"title\", \"word_count\", \
+                 \"unique_terms\", \"average_word_length\", \"reading_ease\""
+            ))),
+        }
+    }
+
+    /// Titles read naturally A-Z, statistics default to largest first
+    pub fn order(self, params: &HashMap<String, String>) -> Result<SortOrder, ApiError> {
+        match self {
+            Self::Title => SortOrder::from_params_or(params, SortOrder::Asc),
+            _ => SortOrder::from_params_or(params, SortOrder::Desc),
+        }
+    }
+
+    /// Sorts `list` by this key in `order`, ties by title. Documents without
+    /// the statistic (too little text, or indexed before it was kept) go last
+    /// either way.
+    pub fn sort(self, list: &mut [DocumentInfo], order: SortOrder) {
+        let key = |info: &DocumentInfo| -> Option<f64> {
+            let attributes = &info.attributes;
+            match self {
+                Self::Title => Some(0.0),
+                Self::WordCount => attributes.word_count.map(|n| n as f64),
+                Self::UniqueTerms => Some(attributes.unique_terms as f64),
+                Self::AverageWordLength => attributes.average_word_length,
+                Self::ReadingEase => attributes.reading_ease,
+            }
+        };
+        list.sort_by(|a, b| {
+            let ord = match (key(a), key(b)) {
+                (Some(x), Some(y)) => {
+                    let ord = x.total_cmp(&y);
+                    match order {
+                        SortOrder::Asc => ord,
+                        SortOrder::Desc => ord.reverse(),
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            let title = a.attributes.title.cmp(&b.attributes.title);
+            let title = match (self, order) {
+                (Self::Title, SortOrder::Desc) => title.reverse(),
+                _ => title,
+            };
+            ord.then(title)
+        });
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::config::CONFIG;
+use crate::wal;
+
+// What the logs may say about the library, for running on confidential
+// documents and shipping the logs elsewhere. Log lines that name a document's
+// file go through `path`, and those quoting a title, query, term or tag go
+// through `text`; each prints its value as before unless
+// `PAPER_ENGINE_LOG_REDACTION` says otherwise. Names of the engine's own
+// files (caches, logs, config) are never redacted.
+
+/// How much the logs leave out, each level including the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Redaction {
+    /// Log everything
+    None,
+    /// Document paths become a checksum, so lines about one file can still
+    /// be told apart; error messages, which can quote paths, are logged by
+    /// their code alone
+    Paths,
+    /// Titles, queries, terms and tags are left out too
+    All,
+}
+
+impl std::str::FromStr for Redaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "paths" => Ok(Self::Paths),
+            "all" => Ok(Self::All),
+            _ => Err(format!(
+                "expected \"none\", \"paths\" or \"all\", got {s:?}"
+            )),
+        }
+    }
+}
+
+pub fn at_least(level: Redaction) -> bool {
+    CONFIG.log_redaction >= level
+}
+
+/// A document path in a log line
+pub struct Path<'a>(&'a str);
+
+impl fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if at_least(Redaction::Paths) {
+            write!(f, "<path {:08x}>", wal::checksum(self.0.as_bytes()))
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
+pub fn path(path: &str) -> Path<'_> {
+    Path(path)
+}
+
+/// A title, query, term or tag in a log line
+pub struct Text<'a>(&'a str);
+
+impl fmt::Display for Text<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if at_least(Redaction::All) {
+            write!(f, "<redacted>")
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
+pub fn text(text: &str) -> Text<'_> {
+    Text(text)
+}
+
+/// A request path for the request log. With `all`, the user-chosen segment
+/// after `/term/` or `/tags/` is left out, since it's a word or tag name.
+pub fn route(route: &str) -> String {
+    if !at_least(Redaction::All) {
+        return route.to_owned();
+    }
+    let mut previous = "";
+    let mut segments = vec![];
+    for segment in route.split('/') {
+        segments.push(match previous {
+            "term" | "tags" if !segment.is_empty() => "_",
+            _ => segment,
+        });
+        previous = segment;
+    }
+    segments.join("/")
+}
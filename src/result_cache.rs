@@ -0,0 +1,155 @@
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::search::{SearchOutcome, SearchRequest};
+use crate::TfIdf;
+
+// Recent search outcomes of one index, so repeating a search (paging back,
+// a UI redrawing) skips ranking. Each entry remembers the `generation` it was
+// computed at and is only used while the index is still there, so a change
+// never serves stale results: the old entries just stop matching and are
+// dropped first when room is needed.
+//
+// At most `PAPER_ENGINE_RESULT_CACHE_SIZE` entries are kept; past that the
+// least recently used one goes.
+
+#[derive(Debug)]
+struct Entry {
+    generation: u64,
+    /// `Inner::clock` when last stored or hit
+    used: u64,
+    outcome: SearchOutcome,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Counts lookups and stores, to order entries by use
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+/// Shared by an index's snapshots; see the comment at the top of this file
+#[derive(Debug, Default)]
+pub struct ResultCache(Mutex<Inner>);
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    enabled: bool,
+    entries: usize,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+    /// `hits` over all lookups; 0 before the first
+    hit_rate: f64,
+    /// Entries dropped to make room while still current
+    evictions: u64,
+}
+
+fn enabled() -> bool {
+    CONFIG.result_cache && CONFIG.result_cache_size > 0
+}
+
+impl ResultCache {
+    fn get(&self, key: &str, generation: u64) -> Option<SearchOutcome> {
+        let Ok(mut inner) = self.0.lock() else {
+            return None;
+        };
+        inner.clock += 1;
+        let clock = inner.clock;
+        let outcome = match inner.entries.get_mut(key) {
+            Some(entry) if entry.generation == generation => {
+                entry.used = clock;
+                Some(entry.outcome.clone())
+            }
+            _ => None,
+        };
+        match outcome.is_some() {
+            true => inner.hits += 1,
+            false => inner.misses += 1,
+        }
+        outcome
+    }
+
+    fn insert(&self, key: String, generation: u64, outcome: &SearchOutcome) {
+        let too_large = CONFIG
+            .result_cache_max_results
+            .map_or(false, |max| outcome.results.len() > max);
+        if too_large {
+            return;
+        }
+        let Ok(mut inner) = self.0.lock() else {
+            return;
+        };
+        let full = |inner: &Inner| {
+            inner.entries.len() >= CONFIG.result_cache_size && !inner.entries.contains_key(&key)
+        };
+        if full(&inner) {
+            inner.entries.retain(|_, entry| entry.generation == generation);
+        }
+        while full(&inner) {
+            let Some(oldest) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+            inner.evictions += 1;
+        }
+        inner.clock += 1;
+        let entry = Entry {
+            generation,
+            used: inner.clock,
+            outcome: outcome.clone(),
+        };
+        inner.entries.insert(key, entry);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let lookups = inner.hits + inner.misses;
+        CacheStats {
+            enabled: enabled(),
+            entries: inner.entries.len(),
+            capacity: CONFIG.result_cache_size,
+            hits: inner.hits,
+            misses: inner.misses,
+            hit_rate: match lookups {
+                0 => 0.0,
+                n => inner.hits as f64 / n as f64,
+            },
+            evictions: inner.evictions,
+        }
+    }
+}
+
+impl TfIdf {
+    /// `search`, answered from the result cache when the same query and
+    /// request already ran since the index last changed
+    pub fn cached_search(
+        &self,
+        query: &str,
+        request: &SearchRequest,
+    ) -> Result<SearchOutcome, ApiError> {
+        // Scores under `recency=true` change with the clock
+        if !enabled() || request.options.recency {
+            return self.search(query, request);
+        }
+        // Every option is in the request, so its `Debug` form tells them apart
+        let key = format!("{query}\n{request:?}");
+        if let Some(outcome) = self.result_cache.get(&key, self.generation) {
+            return Ok(outcome);
+        }
+        let outcome = self.search(query, request)?;
+        self.result_cache.insert(key, self.generation, &outcome);
+        Ok(outcome)
+    }
+}
@@ -0,0 +1,1754 @@
+use crate::analyzer::{self, Token};
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::highlight;
+use crate::intern::{get_str, intern};
+use crate::query::{self, Operator, Query, QueryTerm};
+use crate::ranking::RankingConfig;
+use crate::redact::{self, Redaction};
+use crate::spellcheck::DidYouMean;
+use crate::{Document, DocumentFilter, Term, TfIdf};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+
+/// A separately indexed part of a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    /// Every indexed page
+    Body,
+    /// The first `abstract_pages` pages
+    Abstract,
+    /// PDF highlights and notes, for documents submitted with `annotations`
+    Annotations,
+}
+
+impl Field {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "body" => Some(Self::Body),
+            "abstract" => Some(Self::Abstract),
+            "annotations" => Some(Self::Annotations),
+            _ => None,
+        }
+    }
+}
+
+impl Document {
+    pub fn field_frequency(&self, field: Field) -> &HashMap<Term, f64> {
+        match field {
+            Field::Body => &self.term_frequency,
+            Field::Abstract => &self.abstract_frequency,
+            Field::Annotations => &self.annotation_frequency,
+        }
+    }
+
+    /// How much of a match `token` is in `field`, or the body for `None`:
+    /// with `exact` its `surface_share`, otherwise 1.0 if the stem is there
+    /// at all. Zero when it isn't.
+    pub fn match_share(&self, token: Token, field: Option<Field>, exact: bool) -> f64 {
+        let field = field.unwrap_or(Field::Body);
+        if !self.field_frequency(field).contains_key(&token.stem) {
+            0.0
+        } else if exact {
+            self.surface_share(token)
+        } else {
+            1.0
+        }
+    }
+
+    /// Share of `token.stem`'s occurrences that were spelled `token.surface`;
+    /// zero when that exact spelling never appeared
+    pub fn surface_share(&self, token: Token) -> f64 {
+        let Some(forms) = self.surface_forms.get(&token.stem) else {
+            return 0.0;
+        };
+        let total = forms.values().sum::<usize>();
+        match forms.get(&token.surface) {
+            Some(n) if total > 0 => *n as f64 / total as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Fields to score and how much each one counts
+#[derive(Debug, Clone)]
+pub struct FieldBoosts(Vec<(Field, f64)>);
+
+impl FieldBoosts {
+    /// The boost for `field`; 1.0 when it isn't listed, since a `field:`
+    /// qualifier in the query asks for it regardless
+    fn boost(&self, field: Field) -> f64 {
+        self.0
+            .iter()
+            .find(|(f, _)| *f == field)
+            .map_or(1.0, |(_, boost)| *boost)
+    }
+
+    /// Every field, at the live ranking weights
+    fn from_ranking(ranking: &RankingConfig) -> Self {
+        Self(vec![
+            (Field::Body, ranking.body_boost),
+            (Field::Abstract, ranking.abstract_boost),
+            (Field::Annotations, ranking.annotation_boost),
+        ])
+    }
+
+    /// Parses `fields=body:1,abstract:2`; a field left out is not scored at
+    /// all, and a field without `:boost` counts once. `None` without `fields`.
+    fn from_params(params: &HashMap<String, String>) -> Result<Option<Self>, ApiError> {
+        let Some(fields) = params.get("fields") else {
+            return Ok(None);
+        };
+        let mut boosts = vec![];
+        for spec in fields.split(',').filter(|s| !s.is_empty()) {
+            let (name, boost) = spec.split_once(':').unwrap_or((spec, "1"));
+            let field = Field::parse(name).ok_or_else(|| {
+                ApiError::invalid_param(format!(
+                    "Unknown field {name:?} in `fields`; use \"body\", \"abstract\" or \"annotations\""
+                ))
+            })?;
+            let boost = boost
+                .parse::<f64>()
+                .ok()
+                .filter(|b| b.is_finite() && *b >= 0.0)
+                .ok_or_else(|| {
+                    ApiError::invalid_param(format!(
+                        "Invalid boost {boost:?} for field {name:?}; must be a non-negative number"
+                    ))
+                })?;
+            boosts.push((field, boost));
+        }
+        if boosts.is_empty() {
+            return Err(ApiError::invalid_param("`fields` names no fields"));
+        }
+        Ok(Some(Self(boosts)))
+    }
+}
+
+/// How the per-term scores of one document become its final score
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Combine {
+    /// Add up every term's score, scaled for the query's length by
+    /// `QueryNorm`. The scaling doesn't change the ranking; it keeps scores
+    /// of queries of different lengths in the same range
+    #[default]
+    Sum,
+    /// Only the best-matching term counts, so a document matching one term
+    /// strongly beats one matching several weakly
+    Max,
+    /// Average over the terms the document actually matched, so missing
+    /// terms don't drag the score down
+    Mean,
+}
+
+impl Combine {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        match params.get("combine").map(|v| v.as_str()) {
+            None | Some("sum") => Ok(Self::Sum),
+            Some("max") => Ok(Self::Max),
+            Some("mean") => Ok(Self::Mean),
+            Some(s) => Err(ApiError::invalid_param(format!(
+                "Unknown `combine` parameter {s:?}; use one of \"sum\", \"max\", \"mean\""
+            ))),
+        }
+    }
+}
+
+/// How a `combine=sum` score is scaled for the number of query terms, so
+/// thresholds like `min_score` mean the same for short and long queries;
+/// `query_norm`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryNorm {
+    /// The plain sum, growing with every query term a document matches
+    None,
+    /// Divided by the number of query terms: the mean over the query
+    #[default]
+    Count,
+    /// Divided by the square root of the number of query terms, so matching
+    /// more of a long query still counts for something
+    Sqrt,
+}
+
+impl std::str::FromStr for QueryNorm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "count" => Ok(Self::Count),
+            "sqrt" => Ok(Self::Sqrt),
+            _ => Err(format!("expected \"none\", \"count\" or \"sqrt\", got {s:?}")),
+        }
+    }
+}
+
+impl QueryNorm {
+    /// The `query_norm` parameter, defaulting to `PAPER_ENGINE_QUERY_NORM`
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        match params.get("query_norm") {
+            None => Ok(CONFIG.query_norm),
+            Some(s) => s.parse().map_err(|e| {
+                ApiError::invalid_param(format!("Invalid `query_norm` parameter: {e}"))
+            }),
+        }
+    }
+
+    /// What the summed score of a query of `terms` terms is divided by
+    fn divisor(self, terms: usize) -> f64 {
+        // A document is only scored if some term matched, but never divide
+        // by zero regardless
+        let terms = terms.max(1) as f64;
+        match self {
+            Self::None => 1.0,
+            Self::Count => terms,
+            Self::Sqrt => terms.sqrt(),
+        }
+    }
+}
+
+/// Pseudo-relevance feedback: the query is run once, the top results' most
+/// telling terms are added to it, and it's run again; `expand=true`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expansion {
+    /// How many of the first results the terms are taken from
+    pub docs: usize,
+    /// How many terms are added
+    pub terms: usize,
+}
+
+impl Expansion {
+    /// `expand`, with `expand_docs` and `expand_terms` defaulting to
+    /// `PAPER_ENGINE_EXPAND_DOCS` and `PAPER_ENGINE_EXPAND_TERMS`
+    fn from_params(params: &HashMap<String, String>) -> Result<Option<Self>, ApiError> {
+        if !crate::bool_param(params, "expand")?.unwrap_or(false) {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            docs: crate::parse_param(params, "expand_docs")?.unwrap_or(CONFIG.expand_docs),
+            terms: crate::parse_param(params, "expand_terms")?.unwrap_or(CONFIG.expand_terms),
+        }))
+    }
+}
+
+/// A term `expand=true` added to the query
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpandedTerm {
+    /// The stem, as in `QueryEcho::terms`
+    pub term: String,
+    #[serde(serialize_with = "rounded")]
+    pub boost: f64,
+}
+
+/// How a document's score for a term is computed; `model`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Model {
+    /// Each field's count of the term over its distinct terms, times inverse
+    /// document frequency
+    #[default]
+    TfIdf,
+}
+
+impl Model {
+    /// Every model this build can rank with, by name
+    const AVAILABLE: &'static [(&'static str, Self)] = &[("tfidf", Self::TfIdf)];
+
+    /// As the search echoes it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::TfIdf => "tf-idf",
+        }
+    }
+
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        let Some(name) = params.get("model") else {
+            return Ok(Self::default());
+        };
+        let available = Self::AVAILABLE
+            .iter()
+            .map(|(name, _)| format!("{name:?}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match Self::AVAILABLE.iter().find(|(known, _)| known == name) {
+            Some((_, model)) => Ok(*model),
+            // Echoed as `tf-idf`, so accept it back
+            None if name == "tf-idf" => Ok(Self::TfIdf),
+            None if name == "bm25" || name == "bm25f" => Err(ApiError::invalid_param(format!(
+                "The {name:?} model isn't available in this build; use one of {available}"
+            ))),
+            None => Err(ApiError::invalid_param(format!(
+                "Unknown `model` parameter {name:?}; use one of {available}"
+            ))),
+        }
+    }
+}
+
+/// Which documents term rarity is measured against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdfScope {
+    /// The whole collection, whatever the filters
+    #[default]
+    Global,
+    /// Only the documents passing `favorite`/`read`, so rarity is relative to
+    /// what is actually searched. Costs a filter check per document per term.
+    Filtered,
+}
+
+impl IdfScope {
+    fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        match params.get("idf_scope").map(|v| v.as_str()) {
+            None | Some("global") => Ok(Self::Global),
+            Some("filtered") => Ok(Self::Filtered),
+            Some(s) => Err(ApiError::invalid_param(format!(
+                "Unknown `idf_scope` parameter {s:?}; use one of \"global\", \"filtered\""
+            ))),
+        }
+    }
+}
+
+/// Writes a fractional score to `PAPER_ENGINE_SCORE_DECIMALS` places, for
+/// `serialize_with`. Only the response is rounded; scores are compared and
+/// sorted at full precision.
+pub fn rounded<S: serde::Serializer>(score: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    let scale = 10f64.powi(CONFIG.score_decimals);
+    serializer.serialize_f64((score * scale).round() / scale)
+}
+
+/// `rounded` for an optional score
+pub fn rounded_option<S: serde::Serializer>(
+    score: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match score {
+        Some(score) => rounded(score, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// `rounded` for every score in a map
+pub fn rounded_values<K: serde::Serialize, S: serde::Serializer>(
+    scores: &BTreeMap<K, f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    let mut map = serializer.serialize_map(Some(scores.len()))?;
+    for (key, score) in scores {
+        map.serialize_entry(key, &Rounded(*score))?;
+    }
+    map.end()
+}
+
+/// A score that serializes `rounded`
+struct Rounded(f64);
+
+impl serde::Serialize for Rounded {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        rounded(&self.0, serializer)
+    }
+}
+
+/// Running per-document totals while scoring
+#[derive(Debug, Default)]
+struct Accumulator {
+    sum: u64,
+    max: u64,
+    /// Query terms the document contained
+    matched: u64,
+    /// The same, counting a term repeated in the query once
+    distinct: HashSet<Term>,
+    /// Fields any term matched in; only with `field_stats`
+    fields: BTreeSet<Field>,
+    explain: Option<Explain>,
+}
+
+/// Everything about how a query is scored, besides the terms themselves
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub model: Model,
+    /// From `fields`; `None` uses the live `RankingConfig`
+    pub boosts: Option<FieldBoosts>,
+    pub combine: Combine,
+    /// Scaling of `Combine::Sum` scores for the query's length
+    pub query_norm: QueryNorm,
+    /// Add terms from the top results and search again
+    pub expand: Option<Expansion>,
+    /// Attach an `Explain` to every result
+    pub explain: bool,
+    /// Match the words as written instead of their stems
+    pub exact: bool,
+    /// Decay scores by document age (see `RankingConfig::recency_half_life_days`)
+    pub recency: bool,
+    /// Rank by `match_count` first and only then by score
+    pub coverage_first: bool,
+    /// Add `title_highlighted` to every result on the page
+    pub highlight: bool,
+    /// Add `normalized` to every result
+    pub normalize: bool,
+    /// Favor terms that first appear early in the document
+    pub position_weight: bool,
+    /// Also return documents excluded from the corpus
+    pub include_excluded: bool,
+    /// Rank documents whose title has every query term above all others
+    pub known_item: bool,
+    pub idf_scope: IdfScope,
+    /// Count the results matching in each field
+    pub field_stats: bool,
+    /// Buckets to count the matches' scores in; only with `histogram=true`
+    pub histogram: Option<usize>,
+}
+
+impl SearchOptions {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        let buckets = crate::parse_param(params, "buckets")?.unwrap_or(CONFIG.histogram_buckets);
+        if !(1..=MAX_HISTOGRAM_BUCKETS).contains(&buckets) {
+            return Err(ApiError::invalid_param(format!(
+                "Invalid `buckets` {buckets}; must be from 1 to {MAX_HISTOGRAM_BUCKETS}"
+            )));
+        }
+        let histogram = crate::bool_param(params, "histogram")?.unwrap_or(false);
+        Ok(Self {
+            model: Model::from_params(params)?,
+            boosts: FieldBoosts::from_params(params)?,
+            combine: Combine::from_params(params)?,
+            query_norm: QueryNorm::from_params(params)?,
+            expand: Expansion::from_params(params)?,
+            explain: crate::bool_param(params, "explain")?.unwrap_or(false),
+            exact: crate::bool_param(params, "exact")?.unwrap_or(false),
+            recency: crate::bool_param(params, "recency")?.unwrap_or(false),
+            coverage_first: crate::bool_param(params, "coverage_first")?.unwrap_or(false),
+            highlight: crate::bool_param(params, "highlight")?.unwrap_or(false),
+            normalize: crate::bool_param(params, "normalize")?.unwrap_or(false),
+            position_weight: crate::bool_param(params, "position_weight")?.unwrap_or(false),
+            include_excluded: crate::bool_param(params, "include_excluded")?.unwrap_or(false),
+            known_item: crate::bool_param(params, "knownitem")?.unwrap_or(false),
+            idf_scope: IdfScope::from_params(params)?,
+            field_stats: crate::bool_param(params, "field_stats")?.unwrap_or(false),
+            histogram: histogram.then_some(buckets),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub score: u64,
+    /// `score` over the best score among all matches, so the best is 1.0;
+    /// only with `normalize=true`
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "rounded_option")]
+    pub normalized: Option<f64>,
+    pub path: String,
+    pub title: String,
+    /// `title` HTML-escaped, with the words matching the query in `<mark>`;
+    /// only with `highlight=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_highlighted: Option<String>,
+    /// How many distinct query terms the document contains
+    pub match_count: usize,
+    /// The title contains every query term; only set with `knownitem=true`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub title_match: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<Explain>,
+    /// Lower-scoring near-duplicates folded into this result by
+    /// `dedupe_similarity`, best first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplicates: Vec<Duplicate>,
+    /// Fields the query matched in; only kept with `field_stats=true`, for
+    /// `SearchOutcome::field_matches`
+    #[serde(skip)]
+    pub matched_fields: BTreeSet<Field>,
+}
+
+/// A result `dedupe_similarity` folded into a better one
+#[derive(Debug, Clone, Serialize)]
+pub struct Duplicate {
+    pub title: String,
+    pub path: String,
+    pub score: u64,
+    /// Cosine similarity of its tf-idf vector with the result it was folded
+    /// into, in [0, 1]
+    #[serde(serialize_with = "rounded")]
+    pub similarity: f64,
+}
+
+/// Quotes a CSV field if it contains a comma, quote or line break
+fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
+
+/// `score,title,path` rows with a header line, for `format=csv`
+pub fn results_csv(results: &[SearchResult]) -> String {
+    let mut csv = String::from("score,title,path\n");
+    for result in results {
+        csv += &format!(
+            "{},{},{}\n",
+            result.score,
+            csv_field(&result.title),
+            csv_field(&result.path)
+        );
+    }
+    csv
+}
+
+/// How a result's score was put together
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Explain {
+    pub combine: Combine,
+    /// How the sum was scaled for the query's length; only for
+    /// `combine=sum`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_norm: Option<QueryNormExplain>,
+    /// Fields that contained at least one query term
+    pub matched_fields: Vec<Field>,
+    /// Distinct query terms found, out of `query_terms`
+    pub match_count: usize,
+    pub query_terms: usize,
+    pub terms: Vec<TermExplain>,
+    /// Multiplier applied for the document's age, with `recency=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recency: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryNormExplain {
+    pub mode: QueryNorm,
+    /// What the summed score was divided by
+    #[serde(serialize_with = "rounded")]
+    pub divisor: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermExplain {
+    pub term: String,
+    #[serde(serialize_with = "rounded")]
+    pub idf: f64,
+    /// From the query's `^boost`, 1.0 without one
+    pub boost: f64,
+    /// Multiplier for how early the term first appears, with
+    /// `position_weight=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<f64>,
+    /// Score contributed by each field the term was found in, before
+    /// averaging over the query's terms
+    #[serde(serialize_with = "rounded_values")]
+    pub fields: BTreeMap<Field, f64>,
+}
+
+/// A query ready to match and score, from `TfIdf::prepare_query`
+#[derive(Debug)]
+pub struct PreparedQuery {
+    pub parsed: Query,
+    /// `parsed.positive_terms()`
+    pub terms: Vec<QueryTerm>,
+    pub truncated_wildcards: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+/// Everything a search asks for besides the query text; parsed once so a
+/// batch can reuse it for each of its queries
+#[derive(Debug, Clone)]
+pub struct SearchRequest {
+    pub options: SearchOptions,
+    pub filter: DocumentFilter,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    /// Keep only the best result per path
+    pub collapse: bool,
+    /// Fold results into better ones at least this similar to them
+    pub dedupe_similarity: Option<f64>,
+    /// Drop matches scoring below this
+    pub min_score: u64,
+    /// How terms written side by side are joined
+    pub op: Operator,
+    pub offset: usize,
+    /// Requested page size, clipped to `PAPER_ENGINE_MAX_RESULTS`
+    pub limit: usize,
+    /// Whether the requested `limit` (or lack of one) was over the cap
+    pub clipped: bool,
+    /// Reject `ids` naming documents that don't exist, rather than ignoring
+    /// them
+    pub strict_ids: bool,
+}
+
+impl SearchRequest {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        let sort = SortKey::from_params(params)?;
+        let collapse = match params.get("collapse").map(|v| v.as_str()) {
+            None => false,
+            Some("path") => true,
+            Some(s) => {
+                return Err(ApiError::invalid_param(format!(
+                    "Unknown `collapse` parameter {s:?}; only \"path\" is supported"
+                )))
+            }
+        };
+        let dedupe_similarity = crate::parse_param::<f64>(params, "dedupe_similarity")?;
+        if let Some(v) = dedupe_similarity.filter(|v| !(0.0..=1.0).contains(v) || *v == 0.0) {
+            return Err(ApiError::invalid_param(format!(
+                "Invalid `dedupe_similarity` {v}; must be above 0 and at most 1"
+            )));
+        }
+        let requested: Option<usize> = crate::parse_param(params, "limit")?;
+        let limit = requested.map_or(CONFIG.max_results, |n| n.min(CONFIG.max_results));
+        Ok(Self {
+            options: SearchOptions::from_params(params)?,
+            filter: DocumentFilter::from_params(params)?,
+            sort,
+            order: SortOrder::from_params(params, sort)?,
+            collapse,
+            dedupe_similarity,
+            min_score: crate::parse_param(params, "min_score")?.unwrap_or(CONFIG.min_score),
+            op: Operator::from_params(params)?,
+            offset: crate::parse_param(params, "offset")?.unwrap_or(0),
+            limit,
+            clipped: requested.map_or(true, |n| n > limit),
+            strict_ids: crate::bool_param(params, "strict_ids")?.unwrap_or(false),
+        })
+    }
+}
+
+/// The query as the engine understood it, echoed back with the results
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryEcho {
+    pub raw: String,
+    /// Stems that were looked up
+    pub terms: Vec<String>,
+    /// Words whose stem isn't in the index, so they can't match anything
+    pub dropped: Vec<String>,
+    pub model: &'static str,
+    /// What terms side by side meant
+    pub op: Operator,
+    pub combine: Combine,
+    pub idf_scope: IdfScope,
+    pub filter: DocumentFilter,
+    /// `prefix*` terms that matched more than `PAPER_ENGINE_MAX_WILDCARD_TERMS`
+    /// terms and were cut off there
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub truncated_wildcards: Vec<String>,
+    /// Words left out because they're on the blocklist
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocked: Vec<String>,
+    /// Terms `expand=true` added, most telling first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expanded: Vec<ExpandedTerm>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchOutcome<R = SearchResult> {
+    pub query: QueryEcho,
+    /// Matches before `offset` and `limit` were applied
+    pub total: usize,
+    /// The page size actually used
+    pub limit: usize,
+    /// `limit` is smaller than asked for because of the server-side cap; only
+    /// reported when it cut results off
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub clipped: bool,
+    /// Results dropped by `collapse=path`, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapsed: Option<usize>,
+    /// Results folded into others by `dedupe_similarity`, when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub near_duplicates: Option<usize>,
+    /// The score floor applied; 0 when there is none
+    pub min_score: u64,
+    /// Matches dropped for scoring under `min_score`. When that is all of
+    /// them, the query was too common to rank meaningfully.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub below_min_score: usize,
+    /// How many of the matches (not just the page) matched in each field,
+    /// with `field_stats=true`; a result can count towards several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_matches: Option<BTreeMap<Field, usize>>,
+    /// How the scores of every match are spread, with `histogram=true`; see
+    /// `score_histogram`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score_histogram: Option<Vec<ScoreBucket>>,
+    /// A corrected query likely to find more, when this one has misspelled
+    /// words and found little; see `TfIdf::did_you_mean`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_you_mean: Option<DidYouMean>,
+    pub results: Vec<R>,
+}
+
+impl SearchOutcome {
+    /// The same outcome with each result replaced by `f` of it
+    pub fn map_results<R>(self, f: impl FnMut(SearchResult) -> R) -> SearchOutcome<R> {
+        SearchOutcome {
+            query: self.query,
+            total: self.total,
+            limit: self.limit,
+            clipped: self.clipped,
+            collapsed: self.collapsed,
+            near_duplicates: self.near_duplicates,
+            min_score: self.min_score,
+            below_min_score: self.below_min_score,
+            field_matches: self.field_matches,
+            score_histogram: self.score_histogram,
+            did_you_mean: self.did_you_mean,
+            results: self.results.into_iter().map(f).collect(),
+        }
+    }
+}
+
+/// Most buckets `histogram=true` counts scores in
+pub const MAX_HISTOGRAM_BUCKETS: usize = 1000;
+
+/// Matches scoring from `min` to `max`, inclusive
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreBucket {
+    pub min: u64,
+    pub max: u64,
+    pub count: usize,
+}
+
+/// Counts the scores of `matches` in up to `buckets` equal ranges from 0 to
+/// the best score, lowest first. Scores are integers, so fewer buckets come
+/// back when there are fewer distinct scores to spread over. A bucket's `min`
+/// is the `min_score` that keeps it and everything above.
+fn score_histogram(matches: &[SearchResult], buckets: usize) -> Vec<ScoreBucket> {
+    let Some(top) = matches.iter().map(|result| result.score).max() else {
+        return vec![];
+    };
+    // In u128, so a best score of `u64::MAX` still has a range past it;
+    // `span` is at least 1, so `width` is too
+    let span = top as u128 + 1;
+    let width = span.div_ceil(buckets.max(1) as u128);
+    let mut histogram = (0..span.div_ceil(width))
+        .map(|i| ScoreBucket {
+            min: (i * width) as u64,
+            max: (i * width + width - 1).min(top as u128) as u64,
+            count: 0,
+        })
+        .collect::<Vec<_>>();
+    for result in matches {
+        histogram[(result.score as u128 / width) as usize].count += 1;
+    }
+    histogram
+}
+
+/// Names `fields[documents]` can pick, in the order they're written
+const RESULT_FIELDS: &[&str] = &[
+    "id",
+    "score",
+    "normalized",
+    "path",
+    "title",
+    "title_highlighted",
+    "match_count",
+    "title_match",
+    "explain",
+    "duplicates",
+    "tags",
+];
+
+/// The result fields a response includes, from `fields[documents]`: a
+/// JSON:API sparse fieldset, which plain JSON responses honor too. (`fields`
+/// alone already picks the fields scored.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResultFields(u16);
+
+impl ResultFields {
+    /// `None` when `fields[documents]` isn't given, so results are written
+    /// whole
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Option<Self>, ApiError> {
+        let Some(names) = params.get("fields[documents]") else {
+            return Ok(None);
+        };
+        let mut bits = 0;
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let Some(i) = RESULT_FIELDS.iter().position(|field| *field == name) else {
+                return Err(ApiError::invalid_param(format!(
+                    "Unknown field {name:?} in `fields[documents]`; use any of {}",
+                    RESULT_FIELDS.join(", ")
+                )));
+            };
+            bits |= 1 << i;
+        }
+        Ok(Some(Self(bits)))
+    }
+
+    fn has(self, name: &str) -> bool {
+        RESULT_FIELDS
+            .iter()
+            .position(|field| *field == name)
+            .is_some_and(|i| self.0 & (1 << i) != 0)
+    }
+
+    pub fn without(self, name: &str) -> Self {
+        match RESULT_FIELDS.iter().position(|field| *field == name) {
+            Some(i) => Self(self.0 & !(1 << i)),
+            None => self,
+        }
+    }
+}
+
+/// A result written with only the fields asked for. `id` and `tags` come
+/// from the document, since `SearchResult` doesn't carry them.
+#[derive(Debug, Clone)]
+pub struct SparseResult {
+    pub id: Option<u32>,
+    pub tags: Vec<String>,
+    pub result: SearchResult,
+    pub fields: ResultFields,
+}
+
+impl serde::Serialize for SparseResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let result = &self.result;
+        let has = |name| self.fields.has(name);
+        let mut map = serializer.serialize_map(None)?;
+        if has("id") {
+            map.serialize_entry("id", &self.id)?;
+        }
+        if has("score") {
+            map.serialize_entry("score", &result.score)?;
+        }
+        if let Some(normalized) = result.normalized.filter(|_| has("normalized")) {
+            map.serialize_entry("normalized", &Rounded(normalized))?;
+        }
+        if has("path") {
+            map.serialize_entry("path", &result.path)?;
+        }
+        if has("title") {
+            map.serialize_entry("title", &result.title)?;
+        }
+        let highlighted = result.title_highlighted.as_ref();
+        if let Some(title) = highlighted.filter(|_| has("title_highlighted")) {
+            map.serialize_entry("title_highlighted", title)?;
+        }
+        if has("match_count") {
+            map.serialize_entry("match_count", &result.match_count)?;
+        }
+        if has("title_match") {
+            map.serialize_entry("title_match", &result.title_match)?;
+        }
+        if let Some(explain) = result.explain.as_ref().filter(|_| has("explain")) {
+            map.serialize_entry("explain", explain)?;
+        }
+        if has("duplicates") {
+            map.serialize_entry("duplicates", &result.duplicates)?;
+        }
+        if has("tags") {
+            map.serialize_entry("tags", &self.tags)?;
+        }
+        map.end()
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Keeps only the best result for each path, so one file indexed under several
+/// titles shows up once. `doc_list` must still be in score order.
+///
+/// Returns how many results were dropped.
+fn collapse_by_path(doc_list: &mut Vec<SearchResult>) -> usize {
+    let before = doc_list.len();
+    let mut seen = std::collections::HashSet::new();
+    doc_list.retain(|result| seen.insert(result.path.clone()));
+    before - doc_list.len()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Score,
+    Title,
+    TokenCount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortKey {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, ApiError> {
+        match params.get("sort").map(|v| v.as_str()) {
+            None | Some("score") => Ok(Self::Score),
+            Some("title") => Ok(Self::Title),
+            Some("token_count") => Ok(Self::TokenCount),
+            Some(s) => Err(ApiError::invalid_param(format!(
+                "Unknown `sort` parameter {s:?}; use one of \"score\", \"title\", \"token_count\""
+            ))),
+        }
+    }
+}
+
+impl SortOrder {
+    /// Titles read naturally A-Z, everything else defaults to largest first
+    pub fn from_params(params: &HashMap<String, String>, key: SortKey) -> Result<Self, ApiError> {
+        let default = if key == SortKey::Title { Self::Asc } else { Self::Desc };
+        Self::from_params_or(params, default)
+    }
+
+    /// `order`, or `default` without one
+    pub fn from_params_or(
+        params: &HashMap<String, String>,
+        default: Self,
+    ) -> Result<Self, ApiError> {
+        match params.get("order").map(|v| v.as_str()) {
+            None => Ok(default),
+            Some("asc") => Ok(Self::Asc),
+            Some("desc") => Ok(Self::Desc),
+            Some(s) => Err(ApiError::invalid_param(format!(
+                "Unknown `order` parameter {s:?}; use one of \"asc\", \"desc\""
+            ))),
+        }
+    }
+}
+
+/// Orders by score, or with `coverage_first` by `match_count` and then score.
+/// Title matches (`knownitem=true`) come before either.
+fn score_cmp(a: &SearchResult, b: &SearchResult, coverage_first: bool) -> std::cmp::Ordering {
+    let tier = a.title_match.cmp(&b.title_match);
+    if coverage_first {
+        tier.then(a.match_count.cmp(&b.match_count)).then(a.score.cmp(&b.score))
+    } else {
+        tier.then(a.score.cmp(&b.score))
+    }
+}
+
+impl Document {
+    /// Halves every `half_life_days` since the document was indexed; 1.0
+    /// (no effect) for documents without a date
+    fn recency_decay(&self, now: u64, half_life_days: f64) -> f64 {
+        let Some(indexed_at) = self.indexed_at else {
+            return 1.0;
+        };
+        let age = now.saturating_sub(indexed_at) as f64;
+        0.5f64.powf(age / (half_life_days * 86400.0))
+    }
+}
+
+impl Document {
+    /// `1 + PAPER_ENGINE_POSITION_BONUS`, halving every
+    /// `PAPER_ENGINE_POSITION_HALF_LIFE_PAGES` pages into the document before
+    /// `term` first appears; 1.0 (no effect) when that isn't known
+    fn position_bonus(&self, term: Term) -> f64 {
+        let Some(page) = self.first_page.get(&term) else {
+            return 1.0;
+        };
+        1.0 + CONFIG.position_bonus * 0.5f64.powf(*page as f64 / CONFIG.position_half_life_pages)
+    }
+}
+
+impl TfIdf {
+    /// Parses `query` and expands it the way `search` does, refusing one
+    /// with nothing left to score
+    pub fn prepare_query(
+        &self,
+        query: &str,
+        request: &SearchRequest,
+    ) -> Result<PreparedQuery, ApiError> {
+        if query.trim().is_empty() {
+            return Err(ApiError::invalid_param(
+                "Query reduced to no searchable terms: it is empty",
+            ));
+        }
+        if let Some(words) = query::unsearchable_words(query) {
+            let words = words
+                .iter()
+                .map(|(word, reason)| format!("{word:?} ({reason})"))
+                .collect::<Vec<_>>();
+            return Err(ApiError::invalid_param(format!(
+                "Query reduced to no searchable terms: {}",
+                words.join(", ")
+            )));
+        }
+        let parsed = query::parse(query, request.op).map_err(|e| {
+            ApiError::invalid_param(format!(
+                "Invalid query at character {}: {}",
+                e.position, e.message
+            ))
+        })?;
+        let mut truncated_wildcards = vec![];
+        let parsed = self.expand_prefixes(parsed, request.options.exact, &mut truncated_wildcards);
+        let mut blocked = vec![];
+        let parsed = self.drop_blocked(parsed, &mut blocked).ok_or_else(|| {
+            ApiError::invalid_param(format!(
+                "Query reduced to no searchable terms: every word ({}) is blocklisted",
+                blocked.join(", ")
+            ))
+        })?;
+        let terms = parsed.positive_terms();
+        // Words that short match nearly everything, so ranking them is slow
+        // and tells nothing
+        let longest = terms
+            .iter()
+            .map(|term| get_str(term.token.surface).chars().count())
+            .max();
+        if longest.is_some_and(|longest| longest < CONFIG.min_query_chars) {
+            let words = terms
+                .iter()
+                .map(|term| format!("{:?}", get_str(term.token.surface)))
+                .collect::<Vec<_>>();
+            return Err(ApiError::invalid_param(format!(
+                "Query is too broad: every word ({}) is shorter than {} characters; \
+                 add a more specific one",
+                words.join(", "),
+                CONFIG.min_query_chars
+            )));
+        }
+        Ok(PreparedQuery {
+            parsed,
+            terms,
+            truncated_wildcards,
+            blocked,
+        })
+    }
+
+    /// Runs `query` through the analyzer, ranking, filters and ordering, and
+    /// returns the requested page of results
+    ///
+    /// `query` goes through the boolean parser; documents must satisfy it,
+    /// and are scored on its terms outside any `NOT`.
+    pub fn search(
+        &self,
+        query: &str,
+        request: &SearchRequest,
+    ) -> Result<SearchOutcome, ApiError> {
+        let unknown_ids = request.filter.unknown_ids(self);
+        if request.strict_ids && !unknown_ids.is_empty() {
+            return Err(ApiError::invalid_param(format!(
+                "No documents with ids {unknown_ids:?} in `ids`"
+            )));
+        }
+        if query.trim().is_empty() && request.filter.path.is_some() {
+            return Ok(self.path_search(request));
+        }
+        let PreparedQuery {
+            parsed,
+            terms,
+            truncated_wildcards,
+            blocked,
+        } = self.prepare_query(query, request)?;
+
+        let started = std::time::Instant::now();
+        let mut doc_list = self.sort_documents(&terms, &request.options, &request.filter);
+        let elapsed = started.elapsed();
+        let results = doc_list.len();
+        let logged = redact::text(query);
+        if elapsed < CONFIG.slow_query_threshold {
+            tracing::trace!(?elapsed, query = %logged, results, "Query");
+        } else if redact::at_least(Redaction::All) {
+            tracing::warn!(?elapsed, query = %logged, results, "Slow query");
+        } else {
+            tracing::warn!(?elapsed, query = %logged, ?terms, results, "Slow query");
+        }
+
+        doc_list.retain(|result| {
+            self.documents.get(&result.title).is_some_and(|doc| {
+                request.filter.matches(doc) && parsed.matches(doc, request.options.exact)
+            })
+        });
+        let expanded = match request.options.expand {
+            Some(expansion) => self.expansion_terms(&doc_list, &terms, expansion),
+            None => vec![],
+        };
+        if !expanded.is_empty() {
+            // Documents with only the added terms match too, as long as they
+            // get past the query's `tag:` and `NOT` clauses
+            let restrictions = parsed.restrictions();
+            let expanded_terms = terms.iter().chain(&expanded).copied().collect::<Vec<_>>();
+            doc_list = self.sort_documents(&expanded_terms, &request.options, &request.filter);
+            doc_list.retain(|result| {
+                self.documents.get(&result.title).is_some_and(|doc| {
+                    let exact = request.options.exact;
+                    request.filter.matches(doc)
+                        && (parsed.matches(doc, exact)
+                            || restrictions.iter().all(|clause| clause.matches(doc, exact)))
+                })
+            });
+        }
+        // Before the floor, so a UI can show what lowering it would bring in
+        let score_histogram = request
+            .options
+            .histogram
+            .map(|buckets| score_histogram(&doc_list, buckets));
+        let before_floor = doc_list.len();
+        doc_list.retain(|result| result.score >= request.min_score);
+        let below_min_score = before_floor - doc_list.len();
+        let collapsed = request.collapse.then(|| collapse_by_path(&mut doc_list));
+        let near_duplicates = request
+            .dedupe_similarity
+            .map(|threshold| self.collapse_near_duplicates(&mut doc_list, threshold));
+        self.order_documents(
+            &mut doc_list,
+            request.sort,
+            request.order,
+            request.options.coverage_first,
+        );
+
+        let (known, unknown): (Vec<_>, Vec<_>) = terms
+            .iter()
+            .map(|term| term.token)
+            .partition(|token| self.global_term_count.contains_key(&token.stem));
+        let echo = QueryEcho {
+            raw: query.to_owned(),
+            terms: known.iter().map(|token| get_str(token.stem).to_owned()).collect(),
+            dropped: unknown.iter().map(|token| get_str(token.surface).to_owned()).collect(),
+            model: request.options.model.name(),
+            op: request.op,
+            combine: request.options.combine,
+            idf_scope: request.options.idf_scope,
+            filter: request.filter.clone(),
+            truncated_wildcards,
+            blocked,
+            expanded: expanded
+                .iter()
+                .map(|term| ExpandedTerm {
+                    term: get_str(term.token.stem).to_owned(),
+                    boost: term.boost,
+                })
+                .collect(),
+        };
+
+        if request.options.normalize {
+            // Over every match rather than the page, so it doesn't change as
+            // you page through
+            let top = doc_list.iter().map(|result| result.score).max().unwrap_or(0);
+            for result in &mut doc_list {
+                result.normalized = Some(match top {
+                    // Nothing scored; everything ties for best
+                    0 => 1.0,
+                    top => result.score as f64 / top as f64,
+                });
+            }
+        }
+
+        let field_matches = request.options.field_stats.then(|| {
+            let mut counts = BTreeMap::new();
+            for field in doc_list.iter().flat_map(|result| &result.matched_fields) {
+                *counts.entry(*field).or_default() += 1;
+            }
+            counts
+        });
+
+        let total = doc_list.len();
+        let did_you_mean = self.did_you_mean(query, &terms, request.op, total);
+        let mut results = doc_list
+            .into_iter()
+            .skip(request.offset)
+            .take(request.limit)
+            .collect::<Vec<_>>();
+        // Only the page is highlighted, so the cost follows `limit` rather
+        // than the number of matches
+        if request.options.highlight {
+            for result in &mut results {
+                // Same matching as scoring: the stem, or with `exact` the spelling
+                result.title_highlighted = Some(highlight::highlight(&result.title, |word| {
+                    terms.iter().any(|term| {
+                        term.token.stem == word.stem
+                            && (!request.options.exact || term.token.surface == word.surface)
+                    })
+                }));
+            }
+        }
+        Ok(SearchOutcome {
+            query: echo,
+            total,
+            limit: request.limit,
+            clipped: request.clipped && total > request.offset.saturating_add(request.limit),
+            collapsed,
+            near_duplicates,
+            min_score: request.min_score,
+            below_min_score,
+            field_matches,
+            score_histogram,
+            did_you_mean,
+            results,
+        })
+    }
+
+    /// A search with a `path` and no query: every document passing the
+    /// filters, those with the `path` earliest in theirs first and then by
+    /// path. There is nothing to score, so every score is 0 and `min_score`
+    /// is ignored; a `sort` besides `score` still applies.
+    fn path_search(&self, request: &SearchRequest) -> SearchOutcome {
+        let mut matches = self
+            .documents
+            .values()
+            .filter(|doc| request.options.include_excluded || !doc.exclude_from_corpus)
+            .filter(|doc| request.filter.matches(doc))
+            .filter_map(|doc| Some((request.filter.path_position(doc)?, doc)))
+            .collect::<Vec<_>>();
+        matches.sort_by(|(a_at, a), (b_at, b)| {
+            a_at.cmp(b_at)
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.title.cmp(&b.title))
+        });
+        let mut doc_list = matches
+            .into_iter()
+            .map(|(_, doc)| SearchResult {
+                score: 0,
+                normalized: request.options.normalize.then_some(1.0),
+                path: doc.path.clone(),
+                title: doc.title.clone(),
+                title_highlighted: None,
+                match_count: 0,
+                title_match: false,
+                explain: None,
+                duplicates: vec![],
+                matched_fields: BTreeSet::new(),
+            })
+            .collect::<Vec<_>>();
+        let collapsed = request.collapse.then(|| collapse_by_path(&mut doc_list));
+        if request.sort != SortKey::Score {
+            self.order_documents(&mut doc_list, request.sort, request.order, false);
+        }
+
+        let score_histogram = request
+            .options
+            .histogram
+            .map(|buckets| score_histogram(&doc_list, buckets));
+        let total = doc_list.len();
+        let results = doc_list
+            .into_iter()
+            .skip(request.offset)
+            .take(request.limit)
+            .collect::<Vec<_>>();
+        SearchOutcome {
+            query: QueryEcho {
+                raw: String::new(),
+                terms: vec![],
+                dropped: vec![],
+                model: "path",
+                op: request.op,
+                combine: request.options.combine,
+                idf_scope: request.options.idf_scope,
+                filter: request.filter.clone(),
+                truncated_wildcards: vec![],
+                blocked: vec![],
+                expanded: vec![],
+            },
+            total,
+            limit: request.limit,
+            clipped: request.clipped && total > request.offset.saturating_add(request.limit),
+            collapsed,
+            near_duplicates: None,
+            min_score: 0,
+            below_min_score: 0,
+            field_matches: None,
+            score_histogram,
+            did_you_mean: None,
+            results,
+        }
+    }
+
+    /// Replaces every `prefix*` in `query` with an `Or` of the indexed terms
+    /// with a spelling starting with `prefix`: one per stem, or with `exact`
+    /// one per spelling. Past `PAPER_ENGINE_MAX_WILDCARD_TERMS` the rest are
+    /// left out and the prefix is added to `truncated`.
+    fn expand_prefixes(&self, query: Query, exact: bool, truncated: &mut Vec<String>) -> Query {
+        match query {
+            Query::Prefix {
+                prefix,
+                boost,
+                field,
+            } => {
+                let mut children = vec![];
+                let mut seen = HashSet::new();
+                let from = (Bound::Included(prefix.as_str()), Bound::Unbounded);
+                for (surface, stem) in self.surfaces.range::<str, _>(from) {
+                    if !surface.starts_with(prefix.as_str()) {
+                        break;
+                    }
+                    let surface = intern(surface);
+                    let key = if exact { surface } else { *stem };
+                    if !self.postings.contains_key(stem) || !seen.insert(key) {
+                        continue;
+                    }
+                    if children.len() == CONFIG.max_wildcard_terms {
+                        truncated.push(format!("{prefix}*"));
+                        break;
+                    }
+                    children.push(Query::Term {
+                        token: Token {
+                            surface,
+                            stem: *stem,
+                        },
+                        boost,
+                        field,
+                    });
+                }
+                Query::Or { children }
+            }
+            Query::And { children } => Query::And {
+                children: children
+                    .into_iter()
+                    .map(|child| self.expand_prefixes(child, exact, truncated))
+                    .collect(),
+            },
+            Query::Or { children } => Query::Or {
+                children: children
+                    .into_iter()
+                    .map(|child| self.expand_prefixes(child, exact, truncated))
+                    .collect(),
+            },
+            Query::Not { child } => Query::Not {
+                child: Box::new(self.expand_prefixes(*child, exact, truncated)),
+            },
+            leaf @ (Query::Term { .. } | Query::Tag { .. }) => leaf,
+        }
+    }
+
+    /// Scores every document containing any of `terms`, best first. Each
+    /// term's score is multiplied by its boost (the query's `^boost`).
+    ///
+    /// With `options.exact`, a document only matches a term if it contains
+    /// that exact spelling, and each field's frequency for the stem is scaled
+    /// by the share of occurrences spelled that way (see `Document::surface_share`).
+    ///
+    /// With `options.idf_scope` set to `Filtered`, only documents passing
+    /// `filter` are scored or counted towards IDF. Otherwise `filter` is left
+    /// to the caller, except that documents outside its `ids` are never
+    /// scored. Documents excluded from the corpus are never counted.
+    // TODO: Give higher weights to exact matches over stemmed matches
+    //
+    // Also normalize to not favor longer documents ("the")
+    pub fn sort_documents(
+        &self,
+        terms: &[QueryTerm],
+        options: &SearchOptions,
+        filter: &DocumentFilter,
+    ) -> Vec<SearchResult> {
+        let boosts = options
+            .boosts
+            .clone()
+            .unwrap_or_else(|| FieldBoosts::from_ranking(&self.ranking));
+        let in_scope =
+            |doc: &Document| options.idf_scope == IdfScope::Global || filter.matches(doc);
+        // Documents excluded from the corpus are only scored when asked for
+        let scored = |doc: &Document| {
+            in_scope(doc)
+                && filter.allows_id(doc.id)
+                && (options.include_excluded || !doc.exclude_from_corpus)
+        };
+        let corpus_size = self.idf_corpus_size(options, filter);
+        let mut documents: BTreeMap<&String, Accumulator> = BTreeMap::new();
+        for &QueryTerm {
+            token,
+            boost: term_boost,
+            field: only,
+        } in terms
+        {
+            let term = &token.stem;
+            // Without `exact`, any document containing the stem matches fully
+            let match_share = |doc: &Document| doc.match_share(token, only, options.exact);
+            // A `field:` qualifier scores that field alone
+            let restricted;
+            let field_boosts = match only {
+                Some(field) => {
+                    restricted = [(field, boosts.boost(field))];
+                    &restricted[..]
+                }
+                None => &boosts.0[..],
+            };
+
+            let idf = self.idf_within(corpus_size, token, only, options, filter);
+
+            for (_, doc) in &self.documents {
+                let share = match_share(doc);
+                if share == 0.0 || !scored(doc) {
+                    continue;
+                }
+                let position = options.position_weight.then(|| doc.position_bonus(*term));
+                let weight = term_boost * position.unwrap_or(1.0);
+                // Each field is scored on its own and the boosted scores summed
+                let mut freq = 0.0;
+                let mut fields = BTreeMap::new();
+                for &(field, boost) in field_boosts {
+                    if let Some(field_freq) = doc.field_frequency(field).get(term) {
+                        let field_freq = field_freq * share;
+                        freq += boost * field_freq;
+                        fields.insert(field, 100000.0 * idf * weight * boost * field_freq);
+                    }
+                }
+                if fields.is_empty() {
+                    continue;
+                }
+                tracing::trace!(
+                    freq,
+                    idf,
+                    title = %redact::text(&doc.title),
+                    term = %redact::text(get_str(*term)),
+                    ?fields,
+                    "Scored"
+                );
+
+                let score = (100000.0 * idf * weight * freq) as u64;
+                let entry = documents.entry(&doc.title).or_insert_with(|| Accumulator {
+                    explain: options.explain.then(|| Explain {
+                        combine: options.combine,
+                        ..Explain::default()
+                    }),
+                    ..Accumulator::default()
+                });
+                entry.sum = entry.sum.saturating_add(score);
+                entry.max = entry.max.max(score);
+                entry.matched += 1;
+                entry.distinct.insert(*term);
+                if options.field_stats {
+                    entry.fields.extend(fields.keys());
+                }
+                if let Some(explain) = &mut entry.explain {
+                    for field in fields.keys() {
+                        if !explain.matched_fields.contains(field) {
+                            explain.matched_fields.push(*field);
+                        }
+                    }
+                    let term = if options.exact { token.surface } else { token.stem };
+                    explain.terms.push(TermExplain {
+                        term: term.to_string(),
+                        idf,
+                        boost: term_boost,
+                        position,
+                        fields,
+                    });
+                }
+            }
+        }
+
+        let now = crate::unix_now();
+        let query_tokens = terms.iter().map(|term| term.token).collect::<HashSet<_>>();
+        let query_terms = query_tokens.iter().map(|token| token.stem).collect::<HashSet<_>>().len();
+        // Same matching as scoring: the stem, or with `exact` the spelling
+        let title_match = |title: &str| {
+            let words = analyzer::tokenize(title)
+                .map(|word| if options.exact { word.surface } else { word.stem })
+                .collect::<HashSet<_>>();
+            !query_tokens.is_empty()
+                && query_tokens.iter().all(|token| {
+                    words.contains(&if options.exact { token.surface } else { token.stem })
+                })
+        };
+        let mut doc_list = vec![];
+        for (title, mut acc) in documents {
+            let doc = self.documents.get(title).unwrap();
+            let path = doc.path.clone();
+            let match_count = acc.distinct.len();
+            let divisor = options.query_norm.divisor(terms.len());
+            if let Some(explain) = &mut acc.explain {
+                explain.matched_fields.sort();
+                explain.match_count = match_count;
+                explain.query_terms = query_terms;
+                explain.query_norm =
+                    (options.combine == Combine::Sum).then_some(QueryNormExplain {
+                        mode: options.query_norm,
+                        divisor,
+                    });
+            }
+            let mut score = match options.combine {
+                Combine::Sum => (acc.sum as f64 / divisor) as u64,
+                Combine::Max => acc.max,
+                Combine::Mean => acc.sum / acc.matched,
+            };
+            if options.recency {
+                let decay = doc.recency_decay(now, self.ranking.recency_half_life_days);
+                score = (score as f64 * decay) as u64;
+                if let Some(explain) = &mut acc.explain {
+                    explain.recency = Some(decay);
+                }
+            }
+            doc_list.push(SearchResult {
+                score,
+                normalized: None,
+                path,
+                title: title.to_owned(),
+                title_highlighted: None,
+                match_count,
+                title_match: options.known_item && title_match(title),
+                explain: acc.explain,
+                duplicates: vec![],
+                matched_fields: acc.fields,
+            });
+        }
+        // Highest score first; equal scores fall back to title so repeated
+        // queries (and pages of them) always come back in the same order.
+        // Titles are unique keys, so there is never a tie left for the id.
+        doc_list.sort_by(|a, b| {
+            score_cmp(b, a, options.coverage_first).then_with(|| a.title.cmp(&b.title))
+        });
+        doc_list
+    }
+
+    /// The terms `expand=true` adds: those with the highest TF-IDF summed over
+    /// the first `expansion.docs` of `top`, leaving out the query's own. Each
+    /// is boosted by `PAPER_ENGINE_EXPAND_WEIGHT` times its sum over the best
+    /// one's, so none counts for more than a typed word.
+    fn expansion_terms(
+        &self,
+        top: &[SearchResult],
+        terms: &[QueryTerm],
+        expansion: Expansion,
+    ) -> Vec<QueryTerm> {
+        let feedback = top
+            .iter()
+            .take(expansion.docs)
+            .filter_map(|result| self.documents.get(&result.title))
+            .collect::<Vec<_>>();
+        let typed = terms.iter().map(|term| term.token.stem).collect::<HashSet<_>>();
+        let corpus = self.corpus_size();
+        let mut weights = HashMap::<Term, f64>::new();
+        for doc in &feedback {
+            for (term, freq) in &doc.term_frequency {
+                if !typed.contains(term) {
+                    *weights.entry(*term).or_default() += freq;
+                }
+            }
+        }
+        let mut weights = weights
+            .into_iter()
+            .map(|(term, freq)| (term, freq * self.idf_in(term, corpus)))
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect::<Vec<_>>();
+        weights.sort_by(|(a, x), (b, y)| y.total_cmp(x).then_with(|| get_str(*a).cmp(get_str(*b))));
+        weights.truncate(expansion.terms);
+        let Some(&(_, best)) = weights.first() else {
+            return vec![];
+        };
+        weights
+            .into_iter()
+            .map(|(stem, weight)| {
+                // The spelling the feedback documents use most, so the term
+                // still matches with `exact`
+                let mut forms = HashMap::<Term, usize>::new();
+                for doc in &feedback {
+                    for (form, n) in doc.surface_forms.get(&stem).into_iter().flatten() {
+                        *forms.entry(*form).or_default() += n;
+                    }
+                }
+                let surface = forms
+                    .into_iter()
+                    .max_by(|(a, x), (b, y)| x.cmp(y).then_with(|| get_str(*b).cmp(get_str(*a))))
+                    .map_or(stem, |(form, _)| form);
+                QueryTerm {
+                    token: Token { surface, stem },
+                    boost: CONFIG.expand_weight * weight / best,
+                    field: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Documents that count towards IDF: never those excluded from the
+    /// corpus, and with `idf_scope=filtered` only those passing `filter`
+    fn counts_towards_idf(
+        doc: &Document,
+        options: &SearchOptions,
+        filter: &DocumentFilter,
+    ) -> bool {
+        !doc.exclude_from_corpus
+            && (options.idf_scope == IdfScope::Global || filter.matches(doc))
+    }
+
+    fn idf_corpus_size(&self, options: &SearchOptions, filter: &DocumentFilter) -> usize {
+        self.documents
+            .values()
+            .filter(|doc| Self::counts_towards_idf(doc, options, filter))
+            .count()
+    }
+
+    /// IDF of `term` as `sort_documents` scores it
+    pub fn term_idf(
+        &self,
+        term: &QueryTerm,
+        options: &SearchOptions,
+        filter: &DocumentFilter,
+    ) -> f64 {
+        let corpus_size = self.idf_corpus_size(options, filter);
+        self.idf_within(corpus_size, term.token, term.field, options, filter)
+    }
+
+    /// IDF of `token` among `corpus_size` counted documents
+    fn idf_within(
+        &self,
+        corpus_size: usize,
+        token: Token,
+        field: Option<Field>,
+        options: &SearchOptions,
+        filter: &DocumentFilter,
+    ) -> f64 {
+        let containing = self
+            .documents
+            .values()
+            .filter(|doc| {
+                Self::counts_towards_idf(doc, options, filter)
+                    && doc.match_share(token, field, options.exact) > 0.0
+            })
+            .count();
+        ((corpus_size as f64 + 1.0) / (containing as f64 + 1.0)).log10()
+    }
+
+    /// Reorders the output of `sort_documents`; does not change which documents matched.
+    ///
+    /// The sort is stable, so ties keep the order `sort_documents` gave them
+    /// (score descending, then title ascending).
+    pub fn order_documents(
+        &self,
+        doc_list: &mut [SearchResult],
+        key: SortKey,
+        order: SortOrder,
+        coverage_first: bool,
+    ) {
+        let token_count = |title: &str| self.documents.get(title).map(|doc| doc.token_count);
+        doc_list.sort_by(|a, b| {
+            let ord = match key {
+                SortKey::Score => score_cmp(a, b, coverage_first),
+                SortKey::Title => a.title.cmp(&b.title),
+                SortKey::TokenCount => token_count(&a.title).cmp(&token_count(&b.title)),
+            };
+            match order {
+                SortOrder::Asc => ord,
+                SortOrder::Desc => ord.reverse(),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::tests::{index, search_titles};
+
+    #[test]
+    fn tied_scores_come_back_in_the_same_order() {
+        let docs = index(&[
+            ("Gamma", "rust async runtime"),
+            ("Alpha", "rust async runtime"),
+            ("Beta", "rust async runtime"),
+            ("Other", "python data science"),
+        ]);
+        let first = search_titles(&docs, "rust");
+        assert_eq!(first, ["Alpha", "Beta", "Gamma"]);
+        assert_eq!(search_titles(&docs, "rust"), first);
+    }
+
+    #[test]
+    fn expansion_finds_the_abbreviation() {
+        // The tests' settings map NLP to "natural language processing"
+        let docs = index(&[
+            ("Tagging", "NLP pipelines for tagging"),
+            ("Compilers", "parsing source code"),
+        ]);
+        assert_eq!(search_titles(&docs, "natural language processing"), ["Tagging"]);
+        assert_eq!(search_titles(&docs, "nlp"), ["Tagging"]);
+    }
+
+    #[test]
+    fn stem_exception_keeps_words_apart() {
+        // The tests' settings keep "operations" from stemming to "oper"
+        let docs = index(&[
+            ("Logistics", "operations research"),
+            ("Algebra", "the operator norm"),
+        ]);
+        assert_eq!(search_titles(&docs, "operations"), ["Logistics"]);
+        assert_eq!(search_titles(&docs, "operator"), ["Algebra"]);
+    }
+
+    #[test]
+    fn query_of_only_dropped_words_is_refused() {
+        // The tests' settings drop numbers and symbols, as stopwords would be
+        let docs = index(&[("Counting", "from 42 to 1000 and beyond")]);
+        let request = SearchRequest::from_params(&HashMap::new()).unwrap();
+        let Err(err) = docs.read().unwrap().search("42 — 1000", &request) else {
+            panic!("a query of dropped words was searched");
+        };
+        assert_eq!(err.code, ErrorCode::InvalidParam);
+        assert!(err.message.starts_with("Query reduced to no searchable terms"), "{}", err.message);
+        assert!(err.message.contains("\"42\" (numbers are dropped"), "{}", err.message);
+        assert!(err.message.contains("\"—\" (symbols are dropped"), "{}", err.message);
+    }
+
+    /// Score of the best match for `query` with `query_norm={norm}`
+    fn top_score(docs: &crate::DocShared, query: &str, norm: &str) -> f64 {
+        let params = HashMap::from([("query_norm".to_owned(), norm.to_owned())]);
+        let request = SearchRequest::from_params(&params).unwrap();
+        let outcome = docs.read().unwrap().search(query, &request).unwrap();
+        outcome.results[0].score as f64
+    }
+
+    #[test]
+    fn query_norm_scales_with_query_length() {
+        // Each word is as rare and as frequent as the others, so each adds
+        // the same to the sum
+        let docs = index(&[
+            ("Target", "alpha beta gamma"),
+            ("Other", "delta epsilon zeta"),
+        ]);
+        let close = |a: f64, b: f64| (a - b).abs() <= 2.0;
+        let one = top_score(&docs, "alpha", "none");
+        assert!(one > 0.0);
+        let three = top_score(&docs, "alpha beta gamma", "none");
+        assert!(close(three, 3.0 * one), "{three} vs {one}");
+        let one = top_score(&docs, "alpha", "count");
+        let three = top_score(&docs, "alpha beta gamma", "count");
+        assert!(close(three, one), "{three} vs {one}");
+        let one = top_score(&docs, "alpha", "sqrt");
+        let three = top_score(&docs, "alpha beta gamma", "sqrt");
+        assert!(close(three, 3f64.sqrt() * one), "{three} vs {one}");
+    }
+
+    fn scored(scores: &[u64]) -> Vec<SearchResult> {
+        let docs = index(&[("Scored", "anything at all")]);
+        let request = SearchRequest::from_params(&HashMap::new()).unwrap();
+        let result = docs.read().unwrap().search("anything", &request).unwrap().results[0].clone();
+        scores
+            .iter()
+            .map(|score| SearchResult {
+                score: *score,
+                ..result.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn histogram_counts_every_score() {
+        let histogram = score_histogram(&scored(&[0, 5, 9, 10]), 2);
+        let ranges = histogram
+            .iter()
+            .map(|bucket| (bucket.min, bucket.max, bucket.count))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(0, 5, 2), (6, 10, 2)]);
+    }
+
+    #[test]
+    fn histogram_takes_the_largest_score() {
+        for buckets in [1, 3, MAX_HISTOGRAM_BUCKETS] {
+            let histogram = score_histogram(&scored(&[0, u64::MAX / 2, u64::MAX]), buckets);
+            assert_eq!(histogram.last().unwrap().max, u64::MAX);
+            assert_eq!(histogram.iter().map(|bucket| bucket.count).sum::<usize>(), 3);
+        }
+    }
+
+    #[test]
+    fn boosted_term_decides_the_order() {
+        let docs = index(&[
+            ("Orchard", "apple apple apple apple banana"),
+            ("Plantation", "banana banana banana banana apple"),
+            // So neither word is in every document, which would give it no IDF
+            ("Quarry", "granite and slate"),
+        ]);
+        assert_eq!(search_titles(&docs, "apple^5 banana"), ["Orchard", "Plantation"]);
+        assert_eq!(search_titles(&docs, "apple banana^5"), ["Plantation", "Orchard"]);
+    }
+
+    #[test]
+    fn no_terms_never_divide_by_zero() {
+        for norm in [QueryNorm::None, QueryNorm::Count, QueryNorm::Sqrt] {
+            assert_eq!(norm.divisor(0), 1.0);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use axum::{
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::signal;
+use tokio::sync::watch;
+
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+
+// On Ctrl+C or SIGTERM the server stops accepting connections and waits
+// `PAPER_ENGINE_SHUTDOWN_GRACE_SECS` for the requests already running. Any
+// still running then are cancelled with `SHUTTING_DOWN`, so the final cache
+// save starts within a bounded time however long a bulk ingest was going to
+// take. A cancelled request keeps what it committed before that; work it had
+// handed to a blocking thread may carry on past the save, and only the
+// write-ahead log (`PAPER_ENGINE_WAL`) keeps those changes.
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static::lazy_static! {
+    /// Set once the grace period is over
+    static ref CANCELLED: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// Counts a request as in flight for as long as it lives, cancelled or not
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Middleware counting requests in flight, and cancelling them once the
+/// shutdown grace period is over
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let _in_flight = InFlight::start();
+    let mut cancelled = CANCELLED.subscribe();
+    tokio::select! {
+        response = next.run(request) => response,
+        _ = cancelled.wait_for(|cancelled| *cancelled) => ApiError::new(
+            ErrorCode::ShuttingDown,
+            "The server shut down before the request finished",
+        )
+        .into_response(),
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM, for `with_graceful_shutdown`, and starts
+/// the grace period for the requests in flight
+pub async fn signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!(
+        "Shutting down with {} requests in flight; waiting up to {:?} for them",
+        IN_FLIGHT.load(Ordering::SeqCst),
+        CONFIG.shutdown_grace
+    );
+    tokio::spawn(async {
+        tokio::time::sleep(CONFIG.shutdown_grace).await;
+        let in_flight = IN_FLIGHT.load(Ordering::SeqCst);
+        if in_flight > 0 {
+            tracing::warn!("Cancelling {in_flight} requests still in flight");
+        }
+        CANCELLED.send_replace(true);
+    });
+}
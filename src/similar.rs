@@ -0,0 +1,334 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde_derive::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+use crate::intern::get_str;
+use crate::search::{rounded, Duplicate, SearchResult};
+use crate::{DocShared, Document, DocumentInfo, Term, TfIdf};
+
+#[derive(Debug, Serialize)]
+pub struct Similar {
+    /// Cosine similarity of the two documents' tf-idf vectors, in [0, 1]
+    #[serde(serialize_with = "rounded")]
+    score: f64,
+    document: DocumentInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedTerm {
+    term: Term,
+    /// This term's share of `score`; all shared terms sum to it
+    #[serde(serialize_with = "rounded")]
+    contribution: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Similarity {
+    /// Cosine similarity of the two documents' tf-idf vectors, in [0, 1]
+    #[serde(serialize_with = "rounded")]
+    score: f64,
+    a: DocumentInfo,
+    b: DocumentInfo,
+    /// The terms contributing most to `score`, largest first
+    shared_terms: Vec<SharedTerm>,
+}
+
+/// A heap entry ordered so the *weakest* candidate is the heap's maximum,
+/// which makes `BinaryHeap` a bounded min-heap of the best `k`
+#[derive(Debug)]
+struct Candidate {
+    score: f64,
+    id: u32,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Lower score is "greater"; on ties the higher id is, so results are
+        // stable
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl TfIdf {
+    /// Same smoothing as search, using the inverted index for the count.
+    /// Documents excluded from the corpus don't count.
+    fn idf(&self, term: Term) -> f64 {
+        self.idf_in(term, self.corpus_size())
+    }
+
+    pub(crate) fn corpus_size(&self) -> usize {
+        self.documents.values().filter(|doc| !doc.exclude_from_corpus).count()
+    }
+
+    /// `idf` with the corpus already counted, for callers asking about many
+    /// terms
+    pub(crate) fn idf_in(&self, term: Term, corpus: usize) -> f64 {
+        let counted = |id: &u32| {
+            self.document_by_id(*id)
+                .is_some_and(|doc| !doc.exclude_from_corpus)
+        };
+        let containing = self.postings(term).filter(counted).count();
+        ((corpus as f64 + 1.0) / (containing as f64 + 1.0)).log10()
+    }
+
+    fn weight(&self, doc: &Document, term: Term) -> f64 {
+        doc.term_frequency.get(&term).copied().unwrap_or(0.0) * self.idf(term)
+    }
+
+    fn norm(&self, doc: &Document) -> f64 {
+        doc.term_frequency
+            .keys()
+            .map(|term| self.weight(doc, *term).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    /// Cosine similarity of `a` and `b`, with the `k` shared terms that
+    /// contribute the most to it
+    pub fn similarity(&self, a: &Document, b: &Document, k: usize) -> Similarity {
+        let norms = self.norm(a) * self.norm(b);
+        let mut shared_terms = vec![];
+        if norms > 0.0 {
+            for term in a.term_frequency.keys() {
+                if b.term_frequency.contains_key(term) {
+                    let contribution = self.weight(a, *term) * self.weight(b, *term) / norms;
+                    if contribution > 0.0 {
+                        shared_terms.push(SharedTerm {
+                            term: *term,
+                            contribution,
+                        });
+                    }
+                }
+            }
+        }
+        // Sum before cutting to `k`, so the score covers every shared term
+        let score = shared_terms.iter().map(|shared| shared.contribution).sum::<f64>();
+        shared_terms.sort_by(|x, y| {
+            y.contribution
+                .total_cmp(&x.contribution)
+                .then_with(|| get_str(x.term).cmp(get_str(y.term)))
+        });
+        shared_terms.truncate(k);
+        Similarity {
+            // Rounding can land a hair outside [0, 1]
+            score: score.clamp(0.0, 1.0),
+            a: a.into(),
+            b: b.into(),
+            shared_terms,
+        }
+    }
+
+    /// The `k` documents most similar to `doc`, most similar first.
+    ///
+    /// Only documents sharing a term with `doc` are scored (found through the
+    /// inverted index), and only the best `k` are ever held, so this doesn't
+    /// sort the whole library.
+    pub fn similar_by_terms(&self, doc: &Document, k: usize) -> Vec<Similar> {
+        if k == 0 {
+            return vec![];
+        }
+        let norm = self.norm(doc);
+        if norm == 0.0 {
+            return vec![];
+        }
+
+        let mut dot = HashMap::<u32, f64>::new();
+        for term in doc.term_frequency.keys() {
+            let idf = self.idf(*term);
+            let weight = doc.term_frequency[term] * idf;
+            for id in self.postings(*term).filter(|id| *id != doc.id) {
+                let Some(other) = self.document_by_id(id) else {
+                    continue;
+                };
+                *dot.entry(id).or_insert(0.0) += weight * other.term_frequency[term] * idf;
+            }
+        }
+
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (id, dot) in dot {
+            let Some(other) = self.document_by_id(id) else {
+                continue;
+            };
+            let other_norm = self.norm(other);
+            if dot <= 0.0 || other_norm == 0.0 {
+                continue;
+            }
+            heap.push(Candidate {
+                // Rounding can land a hair outside [0, 1]
+                score: (dot / (norm * other_norm)).clamp(0.0, 1.0),
+                id,
+            });
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .filter_map(|Candidate { score, id }| {
+                Some(Similar {
+                    score,
+                    document: self.document_by_id(id)?.into(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A document's tf-idf vector scaled to length 1, so the cosine similarity
+/// of two is their dot product
+type UnitVector = HashMap<Term, f64>;
+
+fn cosine(a: &UnitVector, b: &UnitVector) -> f64 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let dot = small
+        .iter()
+        .filter_map(|(term, x)| Some(x * large.get(term)?))
+        .sum::<f64>();
+    // Rounding can land a hair outside [0, 1]
+    dot.clamp(0.0, 1.0)
+}
+
+impl TfIdf {
+    /// `doc`'s `UnitVector`, or `None` when all its weights are zero. `idf`
+    /// keeps each term's idf across calls.
+    fn unit_vector(
+        &self,
+        doc: &Document,
+        corpus: usize,
+        idf: &mut HashMap<Term, f64>,
+    ) -> Option<UnitVector> {
+        let mut vector = doc
+            .term_frequency
+            .iter()
+            .map(|(term, tf)| {
+                let idf = *idf.entry(*term).or_insert_with(|| self.idf_in(*term, corpus));
+                (*term, tf * idf)
+            })
+            .collect::<UnitVector>();
+        let norm = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+        if norm == 0.0 {
+            return None;
+        }
+        for weight in vector.values_mut() {
+            *weight /= norm;
+        }
+        Some(vector)
+    }
+
+    /// Folds each of the first `PAPER_ENGINE_MAX_RESULTS` results into the
+    /// best-scoring result before it whose document has a cosine similarity
+    /// of at least `threshold` with its own, listing it in that result's
+    /// `duplicates`. Results further down are left as they are. `results`
+    /// must be in score order.
+    ///
+    /// Returns how many results were folded away.
+    pub fn collapse_near_duplicates(
+        &self,
+        results: &mut Vec<SearchResult>,
+        threshold: f64,
+    ) -> usize {
+        let corpus = self.corpus_size();
+        let mut idf = HashMap::new();
+        // Index in `results` and vector of each result kept so far
+        let mut kept: Vec<(usize, UnitVector)> = vec![];
+        let mut folded = vec![];
+        let considered = results.len().min(CONFIG.max_results);
+        for (i, result) in results[..considered].iter().enumerate() {
+            let Some(vector) = self
+                .documents
+                .get(&result.title)
+                .and_then(|doc| self.unit_vector(doc, corpus, &mut idf))
+            else {
+                continue;
+            };
+            let best = kept
+                .iter()
+                .map(|(k, other)| (*k, cosine(&vector, other)))
+                .find(|(_, similarity)| *similarity >= threshold);
+            match best {
+                Some((k, similarity)) => folded.push((i, k, similarity)),
+                None => kept.push((i, vector)),
+            }
+        }
+
+        let n = folded.len();
+        // Back to front, so earlier indexes stay valid
+        for (i, k, similarity) in folded.into_iter().rev() {
+            let duplicate = results.remove(i);
+            results[k].duplicates.insert(
+                0,
+                Duplicate {
+                    title: duplicate.title,
+                    path: duplicate.path,
+                    score: duplicate.score,
+                    similarity,
+                },
+            );
+        }
+        n
+    }
+}
+
+/// `GET /api/document/{id}/similar_by_terms?k=10`
+pub async fn similar_by_terms(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Vec<Similar>>, ApiError> {
+    let k = crate::parse_param::<usize>(&params, "k")?
+        .unwrap_or(10)
+        .min(CONFIG.max_results);
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let doc = docs.document_by_id(document_id).ok_or_else(|| {
+        ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+    })?;
+    Ok(Json(docs.similar_by_terms(doc, k)))
+}
+
+/// `GET /api/document/similarity?a={id}&b={id}&k=10`
+pub async fn similarity(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Similarity>, ApiError> {
+    let id = |name: &str| {
+        crate::parse_param::<u32>(&params, name)?.ok_or_else(|| {
+            ApiError::missing_param(format!("Missing `{name}` parameter; give a document id"))
+        })
+    };
+    let (a, b) = (id("a")?, id("b")?);
+    let k = crate::parse_param::<usize>(&params, "k")?.unwrap_or(10);
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let document = |id: u32| {
+        docs.document_by_id(id)
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, format!("No document with id {id}")))
+    };
+    Ok(Json(docs.similarity(document(a)?, document(b)?, k)))
+}
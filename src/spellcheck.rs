@@ -0,0 +1,281 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::intern::get_str;
+use crate::query::{self, Operator, QueryTerm};
+use crate::search::rounded;
+use crate::{DocShared, Term, TfIdf};
+
+/// Most candidates `limit` can ask for per word
+const MAX_CANDIDATES: usize = 20;
+
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    /// The indexed spelling closest to the word
+    term: &'static str,
+    stem: Term,
+    /// Edits (insertions, deletions, substitutions, swaps of neighbors)
+    /// from the word
+    distance: usize,
+    /// Documents containing the stem
+    doc_frequency: usize,
+    /// How sure the correction is, in [0, 1]: this candidate's share of the
+    /// word's candidates, weighted by frequency and with each edit counting
+    /// tenfold against it, times how little of the word it changes
+    #[serde(serialize_with = "rounded")]
+    confidence: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TermCheck {
+    /// The word as written, lowercased
+    term: &'static str,
+    /// Whether its stem is in the index
+    known: bool,
+    /// Best first; empty for a known word
+    candidates: Vec<Candidate>,
+}
+
+/// A corrected query offered alongside a search's results
+#[derive(Debug, Clone, Serialize)]
+pub struct DidYouMean {
+    /// The query with each unindexed word replaced by its likeliest
+    /// correction, syntax and all
+    query: String,
+    /// Documents the corrected words are in, joined by the default operator;
+    /// filters and `NOT` aren't applied, so the search may find fewer
+    estimated_results: usize,
+}
+
+/// Edit distance between `a` and `b`, counting a swap of neighbors as one
+/// edit; `None` once it's past `max`
+fn edit_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // Rows of the usual dynamic programming table, two back for swaps
+    let mut before = vec![0; b.len() + 1];
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        if current.iter().min().is_some_and(|min| *min > max) {
+            return None;
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    Some(previous[b.len()]).filter(|distance| *distance <= max)
+}
+
+/// Edits allowed for a word of `len` characters: short words have too many
+/// neighbors for two edits to mean anything
+fn max_distance(len: usize) -> usize {
+    if len <= 4 {
+        1
+    } else {
+        2
+    }
+}
+
+impl TfIdf {
+    /// The `limit` indexed spellings nearest `word`, one per stem
+    fn spelling_candidates(&self, word: &str, limit: usize) -> Vec<Candidate> {
+        let chars = word.chars().collect::<Vec<_>>();
+        let max = max_distance(chars.len());
+        // Per stem, its closest spelling
+        let mut nearest: HashMap<Term, (usize, &'static str)> = HashMap::new();
+        for (&surface, &stem) in &self.surfaces {
+            let other = surface.chars().collect::<Vec<_>>();
+            let Some(distance) = edit_distance(&chars, &other, max) else {
+                continue;
+            };
+            let entry = nearest.entry(stem).or_insert((distance, surface));
+            if distance < entry.0 {
+                *entry = (distance, surface);
+            }
+        }
+
+        let weighted = nearest
+            .into_iter()
+            .map(|(stem, (distance, surface))| {
+                let doc_frequency = self.postings(stem).count();
+                let weight = doc_frequency as f64 / 10f64.powi(distance as i32);
+                (stem, surface, distance, doc_frequency, weight)
+            })
+            .collect::<Vec<_>>();
+        let total = weighted.iter().map(|(.., weight)| weight).sum::<f64>();
+        let mut candidates = weighted
+            .into_iter()
+            .filter(|(.., weight)| *weight > 0.0)
+            .map(
+                |(stem, surface, distance, doc_frequency, weight)| Candidate {
+                    term: surface,
+                    stem,
+                    distance,
+                    doc_frequency,
+                    confidence: weight / total
+                        * (1.0 - distance as f64 / chars.len().max(1) as f64).max(0.0),
+                },
+            )
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then_with(|| a.term.cmp(b.term))
+        });
+        candidates.truncate(limit);
+        candidates
+    }
+
+    /// Documents containing every one of `stems` with `Operator::And`, any
+    /// of them with `Operator::Or`
+    fn estimate_matches(&self, stems: &HashSet<Term>, op: Operator) -> usize {
+        let mut sets = stems
+            .iter()
+            .map(|stem| self.postings(*stem).collect::<HashSet<_>>());
+        let Some(first) = sets.next() else {
+            return 0;
+        };
+        sets.fold(first, |matched, set| match op {
+            Operator::And => &matched & &set,
+            Operator::Or => &matched | &set,
+        })
+        .len()
+    }
+
+    /// A correction of `query` to offer with its `results` results. Offered
+    /// only when all of these hold:
+    ///
+    /// - there were fewer than `PAPER_ENGINE_DID_YOU_MEAN_MAX_RESULTS`
+    ///   results;
+    /// - at least one of `terms` isn't indexed, and it has a spelling
+    ///   candidate (as `/document/spellcheck` finds them); under `op=and`,
+    ///   every unindexed word needs one;
+    /// - the corrected words are in at least
+    ///   `PAPER_ENGINE_DID_YOU_MEAN_MIN_GAIN` more documents than `results`.
+    pub fn did_you_mean(
+        &self,
+        query: &str,
+        terms: &[QueryTerm],
+        op: Operator,
+        results: usize,
+    ) -> Option<DidYouMean> {
+        if results >= CONFIG.did_you_mean_max_results {
+            return None;
+        }
+        let mut corrections = HashMap::new();
+        let mut stems = HashSet::new();
+        for term in terms {
+            let token = term.token;
+            if self.postings(token.stem).next().is_some() {
+                stems.insert(token.stem);
+                continue;
+            }
+            let word = get_str(token.surface);
+            match self.spelling_candidates(word, 1).into_iter().next() {
+                Some(best) => {
+                    corrections.insert(word, best.term);
+                    stems.insert(best.stem);
+                }
+                // Nothing could match all the words, corrected or not
+                None if op == Operator::And => return None,
+                None => {}
+            }
+        }
+        if corrections.is_empty() {
+            return None;
+        }
+        let estimated_results = self.estimate_matches(&stems, op);
+        if estimated_results < results + CONFIG.did_you_mean_min_gain {
+            return None;
+        }
+        let corrected = replace_words(query, &corrections);
+        // The words as the analyzer split them weren't found in the query
+        if corrected == query {
+            return None;
+        }
+        Some(DidYouMean {
+            query: corrected,
+            estimated_results,
+        })
+    }
+}
+
+/// `query` with each word (run of letters and digits) that, lowercased, is
+/// in `corrections` replaced by its correction
+fn replace_words(query: &str, corrections: &HashMap<&str, &str>) -> String {
+    let mut replaced = String::with_capacity(query.len());
+    let mut rest = query;
+    while let Some(start) = rest.find(char::is_alphanumeric) {
+        replaced.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        let lowercase = word.to_lowercase();
+        replaced.push_str(corrections.get(lowercase.as_str()).copied().unwrap_or(word));
+        rest = &rest[end..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// `GET /api/document/spellcheck?s=..&limit=5`: for each word of the query,
+/// whether the index knows it, and if not the `limit` (default 5, at most
+/// 20) likeliest corrections from the indexed spellings. Candidates within
+/// one edit are offered for words up to four letters long, and within two
+/// for longer ones.
+pub async fn spellcheck(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Vec<TermCheck>>, ApiError> {
+    let s = params
+        .get("s")
+        .ok_or_else(|| ApiError::missing_param("Missing `s` parameter; give a query"))?;
+    let limit = crate::parse_param::<usize>(&params, "limit")?
+        .unwrap_or(5)
+        .min(MAX_CANDIDATES);
+    let parsed = query::parse(s, Operator::from_params(&params)?).map_err(|e| {
+        ApiError::invalid_param(format!(
+            "Invalid query at character {}: {}",
+            e.position, e.message
+        ))
+    })?;
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let mut seen = HashSet::new();
+    let mut checks = vec![];
+    for token in parsed.terms() {
+        if !seen.insert(token.surface) {
+            continue;
+        }
+        let known = docs.postings(token.stem).next().is_some();
+        let word = get_str(token.surface);
+        checks.push(TermCheck {
+            term: word,
+            known,
+            candidates: match known {
+                true => vec![],
+                false => docs.spelling_candidates(word, limit),
+            },
+        });
+    }
+    Ok(Json(checks))
+}
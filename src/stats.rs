@@ -0,0 +1,178 @@
+use axum::{extract::State, routing::get, Json, Router};
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::Mutex;
+
+use crate::config::CONFIG;
+use crate::error::ApiError;
+use crate::intern::pool_stats;
+use crate::pdf_pool::{self, PoolStats};
+use crate::result_cache::CacheStats;
+use crate::{DocShared, Document, Term, TfIdf};
+
+pub fn routes(docs: DocShared) -> Router {
+    Router::new()
+        .route("/", get(stats))
+        .route("/queries", get(queries))
+        .with_state(docs)
+}
+
+/// Distinct queries/terms tracked before one-off entries are forgotten
+const MAX_TRACKED: usize = 10_000;
+
+/// How many entries `/api/stats/queries` lists as most popular
+const TOP_QUERIES: usize = 20;
+
+lazy_static::lazy_static! {
+    static ref QUERY_LOG: Mutex<QueryLog> = Mutex::new(QueryLog::default());
+}
+
+#[derive(Debug, Default)]
+struct QueryLog {
+    /// Most recent last; at most `Config::query_history_size` long
+    recent: VecDeque<String>,
+    queries: HashMap<String, usize>,
+    terms: HashMap<String, usize>,
+}
+
+/// Records a search for `/api/stats/queries`, unless history is turned off
+pub fn record_query(query: &str) {
+    if !CONFIG.query_history || CONFIG.query_history_size == 0 {
+        return;
+    }
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    if query.is_empty() {
+        return;
+    }
+    let Ok(mut log) = QUERY_LOG.lock() else {
+        return;
+    };
+
+    if log.recent.len() >= CONFIG.query_history_size {
+        log.recent.pop_front();
+    }
+    log.recent.push_back(query.clone());
+    for term in query.split(' ') {
+        increment(&mut log.terms, term.to_owned());
+    }
+    increment(&mut log.queries, query);
+}
+
+fn increment(counts: &mut HashMap<String, usize>, key: String) {
+    if counts.len() >= MAX_TRACKED && !counts.contains_key(&key) {
+        counts.retain(|_, n| *n > 1);
+    }
+    *counts.entry(key).or_insert(0) += 1;
+}
+
+fn most_popular(counts: &HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts = counts
+        .iter()
+        .map(|(k, n)| (k.clone(), *n))
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(TOP_QUERIES);
+    counts
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryStats {
+    enabled: bool,
+    /// Newest first
+    recent: Vec<String>,
+    popular_queries: Vec<(String, usize)>,
+    popular_terms: Vec<(String, usize)>,
+}
+
+async fn queries() -> Result<Json<QueryStats>, ApiError> {
+    let log = QUERY_LOG
+        .lock()
+        .map_err(|e| ApiError::internal(format!("Could not lock query log: {e}")))?;
+    Ok(Json(QueryStats {
+        enabled: CONFIG.query_history,
+        recent: log.recent.iter().rev().cloned().collect(),
+        popular_queries: most_popular(&log.queries),
+        popular_terms: most_popular(&log.terms),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    documents: usize,
+    /// Distinct terms in `global_term_count`
+    vocabulary: usize,
+    /// Entries across every document's term maps
+    term_entries: usize,
+    /// Interned strings, including ones no document uses anymore
+    pool_entries: usize,
+    pool_bytes: usize,
+    /// Rough heap size of `TfIdf` itself, excluding the pool
+    index_bytes: usize,
+    result_cache: CacheStats,
+    /// Shared by every collection
+    pdf_pool: PoolStats,
+}
+
+/// Approximate heap size of a `HashMap` with `len` entries: hashbrown keeps
+/// one control byte per slot and stays at most 7/8 full
+pub fn map_bytes<K, V>(len: usize) -> usize {
+    len * (size_of::<K>() + size_of::<V>() + 1) * 8 / 7
+}
+
+impl Document {
+    pub fn term_entries(&self) -> usize {
+        self.term_frequency.len()
+            + self.abstract_frequency.len()
+            + self.annotation_frequency.len()
+            + self.note_frequency.len()
+            + self.surface_forms.values().map(|forms| forms.len()).sum::<usize>()
+    }
+
+    pub fn approx_bytes(&self) -> usize {
+        self.title.len()
+            + self.path.len()
+            + self.text.as_ref().map_or(0, |text| text.len())
+            + self.notes.len()
+            + map_bytes::<Term, f64>(self.term_frequency.len())
+            + map_bytes::<Term, f64>(self.abstract_frequency.len())
+            + map_bytes::<Term, f64>(self.annotation_frequency.len())
+            + map_bytes::<Term, f64>(self.note_frequency.len())
+            + map_bytes::<Term, u32>(self.first_page.len())
+            + map_bytes::<Term, HashMap<Term, usize>>(self.surface_forms.len())
+            + self
+                .surface_forms
+                .values()
+                .map(|forms| map_bytes::<Term, usize>(forms.len()))
+                .sum::<usize>()
+    }
+}
+
+impl TfIdf {
+    pub fn approx_bytes(&self) -> usize {
+        map_bytes::<Term, usize>(self.global_term_count.len())
+            + map_bytes::<String, Document>(self.documents.len())
+            + self.documents.values().map(|doc| doc.approx_bytes()).sum::<usize>()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let (pool_entries, pool_bytes) = pool_stats();
+        Stats {
+            documents: self.documents.len(),
+            vocabulary: self.global_term_count.len(),
+            term_entries: self.documents.values().map(|doc| doc.term_entries()).sum(),
+            pool_entries,
+            pool_bytes,
+            index_bytes: self.approx_bytes(),
+            result_cache: self.result_cache.stats(),
+            pdf_pool: pdf_pool::stats(),
+        }
+    }
+}
+
+async fn stats(State(docs): State<DocShared>) -> Result<Json<Stats>, ApiError> {
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    Ok(Json(docs.stats()))
+}
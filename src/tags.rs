@@ -0,0 +1,429 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_derive::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::admin::require_admin;
+use crate::error::{ApiError, ErrorCode};
+use crate::redact;
+use crate::search::SearchRequest;
+use crate::wal;
+use crate::zip::ZipWriter;
+use crate::{DocShared, DocumentInfo, Term, TfIdf};
+
+pub fn routes(docs: DocShared) -> Router {
+    Router::new()
+        .route("/diff", get(diff))
+        .route("/:tag/download", get(download))
+        .with_state(docs)
+}
+
+/// Tags are case-insensitive and never empty
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    (!tag.is_empty()).then_some(tag)
+}
+
+/// Splits a comma-separated `tags` style parameter
+fn tag_list(params: &HashMap<String, String>, name: &str) -> Vec<String> {
+    params
+        .get(name)
+        .map(|v| v.split(',').filter_map(normalize_tag).collect())
+        .unwrap_or_default()
+}
+
+/// `POST /api/document/{id}/tags?add=a,b&remove=c`
+pub async fn set_tags(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<DocumentInfo>, ApiError> {
+    let add = tag_list(&params, "add");
+    let remove = tag_list(&params, "remove");
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    let not_found =
+        || ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"));
+    let mut tags = docs.document_by_id(document_id).ok_or_else(not_found)?.tags.clone();
+    for tag in remove {
+        tags.remove(&tag);
+    }
+    tags.extend(add);
+    docs.log_change(wal::Entry::Tags {
+        id: document_id,
+        tags: &tags,
+    })?;
+    let doc = docs.document_by_id_mut(document_id).ok_or_else(not_found)?;
+    doc.tags = tags;
+    Ok(Json((&*doc).into()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagByQuery {
+    tag: String,
+    /// Documents the search found, up to `limit`
+    matched: usize,
+    /// Matches that had the tag already
+    already_tagged: usize,
+    /// Matches given the tag, or that would be with `dry_run`
+    tagged: usize,
+    /// Ids of those, best match first
+    ids: Vec<u32>,
+    dry_run: bool,
+}
+
+/// `POST /api/document/tag_by_query?s=..&tag=..`: adds `tag` to every
+/// document matching the search `s`, or to the best `limit` of them; the
+/// `/search` filters and options decide what matches and in what order.
+/// `limit` isn't held to `PAPER_ENGINE_MAX_RESULTS` here. With
+/// `dry_run=true`, reports what would be tagged and changes nothing.
+///
+/// Admin only, being a bulk change. Each document is logged to the
+/// write-ahead log as it's tagged, so if the log fails part way through, the
+/// documents before it keep the tag.
+pub async fn tag_by_query(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<TagByQuery>, ApiError> {
+    require_admin(&headers)?;
+    let query = params
+        .get("s")
+        .ok_or_else(|| ApiError::missing_param("Missing `s` parameter; give search terms"))?;
+    let tag = params
+        .get("tag")
+        .and_then(|tag| normalize_tag(tag))
+        .ok_or_else(|| ApiError::missing_param("Missing `tag` parameter; give the tag to add"))?;
+    let dry_run = crate::bool_param(&params, "dry_run")?.unwrap_or(false);
+    let mut request = SearchRequest::from_params(&params)?;
+    request.offset = 0;
+    request.limit = crate::parse_param(&params, "limit")?.unwrap_or(usize::MAX);
+
+    let mut docs = docs
+        .write()
+        .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+    let outcome = docs.search(query, &request)?;
+    let matched = outcome
+        .results
+        .iter()
+        .filter_map(|result| docs.documents.get(&result.title))
+        .map(|doc| (doc.id, doc.tags.contains(&tag)))
+        .collect::<Vec<_>>();
+    let ids = matched
+        .iter()
+        .filter(|(_, tagged)| !tagged)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    if !dry_run {
+        for &id in &ids {
+            let Some(mut tags) = docs.document_by_id(id).map(|doc| doc.tags.clone()) else {
+                continue;
+            };
+            tags.insert(tag.clone());
+            docs.log_change(wal::Entry::Tags { id, tags: &tags })?;
+            if let Some(doc) = docs.document_by_id_mut(id) {
+                doc.tags = tags;
+            }
+        }
+    }
+    Ok(Json(TagByQuery {
+        tag,
+        matched: matched.len(),
+        already_tagged: matched.len() - ids.len(),
+        tagged: ids.len(),
+        ids,
+        dry_run,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Sibling {
+    shared_tags: Vec<String>,
+    document: DocumentInfo,
+}
+
+impl TfIdf {
+    /// Documents sharing the most tags with `tags`, most shared first and
+    /// then by title; documents sharing none are left out
+    pub fn tag_siblings(&self, id: u32, tags: &BTreeSet<String>, k: usize) -> Vec<Sibling> {
+        let mut siblings = self
+            .documents
+            .values()
+            .filter(|doc| doc.id != id)
+            .filter_map(|doc| {
+                let shared_tags = doc.tags.intersection(tags).cloned().collect::<Vec<_>>();
+                (!shared_tags.is_empty()).then(|| Sibling {
+                    shared_tags,
+                    document: doc.into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        siblings.sort_by(|a, b| {
+            b.shared_tags
+                .len()
+                .cmp(&a.shared_tags.len())
+                .then_with(|| a.document.attributes.title.cmp(&b.document.attributes.title))
+        });
+        siblings.truncate(k);
+        siblings
+    }
+}
+
+/// `GET /api/document/{id}/siblings?k=10`
+pub async fn siblings(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Vec<Sibling>>, ApiError> {
+    let k = crate::parse_param(&params, "k")?.unwrap_or(10);
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let doc = docs.document_by_id(document_id).ok_or_else(|| {
+        ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+    })?;
+    Ok(Json(docs.tag_siblings(doc.id, &doc.tags, k)))
+}
+
+/// Added to both sides of a frequency ratio so terms absent from one group
+/// don't divide by zero, and very rare terms don't dominate
+const DIFF_SMOOTHING: f64 = 1e-4;
+
+#[derive(Debug, Serialize)]
+pub struct DistinctTerm {
+    term: Term,
+    /// Mean term frequency in this group over the mean in the other
+    ratio: f64,
+    this_mean: f64,
+    other_mean: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagSide {
+    tag: String,
+    documents: usize,
+    /// Most overrepresented relative to the other tag first
+    terms: Vec<DistinctTerm>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagDiff {
+    a: TagSide,
+    b: TagSide,
+}
+
+fn tag_side(
+    tag: &str,
+    documents: usize,
+    this: &HashMap<Term, f64>,
+    other: &HashMap<Term, f64>,
+    k: usize,
+) -> TagSide {
+    let mut terms = this
+        .iter()
+        .map(|(term, this_mean)| {
+            let other_mean = other.get(term).copied().unwrap_or(0.0);
+            DistinctTerm {
+                term: *term,
+                ratio: (this_mean + DIFF_SMOOTHING) / (other_mean + DIFF_SMOOTHING),
+                this_mean: *this_mean,
+                other_mean,
+            }
+        })
+        .filter(|t| t.ratio > 1.0)
+        .collect::<Vec<_>>();
+    terms.sort_by(|x, y| {
+        y.ratio
+            .total_cmp(&x.ratio)
+            .then_with(|| x.term.to_string().cmp(&y.term.to_string()))
+    });
+    terms.truncate(k);
+    TagSide {
+        tag: tag.to_owned(),
+        documents,
+        terms,
+    }
+}
+
+impl TfIdf {
+    /// Mean `term_frequency` of each term over the documents tagged `tag`,
+    /// and how many documents that was
+    fn tag_term_means(&self, tag: &str) -> (HashMap<Term, f64>, usize) {
+        let mut sums: HashMap<Term, f64> = HashMap::new();
+        let mut n = 0;
+        for doc in self.documents.values().filter(|doc| doc.tags.contains(tag)) {
+            n += 1;
+            for (term, tf) in &doc.term_frequency {
+                *sums.entry(*term).or_insert(0.0) += tf;
+            }
+        }
+        sums.values_mut().for_each(|sum| *sum /= n.max(1) as f64);
+        (sums, n)
+    }
+
+    /// The `k` terms most overrepresented in documents tagged `a` compared to
+    /// those tagged `b`, and the other way round. A document with both tags
+    /// counts in both groups.
+    pub fn tag_diff(&self, a: &str, b: &str, k: usize) -> TagDiff {
+        let (a_means, a_docs) = self.tag_term_means(a);
+        let (b_means, b_docs) = self.tag_term_means(b);
+        TagDiff {
+            a: tag_side(a, a_docs, &a_means, &b_means, k),
+            b: tag_side(b, b_docs, &b_means, &a_means, k),
+        }
+    }
+}
+
+/// `GET /api/tags/diff?a=ml&b=systems&k=20`
+pub async fn diff(
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<TagDiff>, ApiError> {
+    let tag = |name: &str| {
+        params
+            .get(name)
+            .and_then(|tag| normalize_tag(tag))
+            .ok_or_else(|| {
+                ApiError::missing_param(format!("Missing `{name}` parameter; give a tag"))
+            })
+    };
+    let (a, b) = (tag("a")?, tag("b")?);
+    let k = crate::parse_param(&params, "k")?.unwrap_or(20);
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    Ok(Json(docs.tag_diff(&a, &b, k)))
+}
+
+/// A name for `path` inside an archive that no earlier entry has: its file
+/// name, numbered when another file had the same one
+fn entry_name(path: &str, taken: &mut HashSet<String>) -> String {
+    let path = std::path::Path::new(path);
+    let stem = path.file_stem().map_or("document".into(), |s| s.to_string_lossy());
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy()));
+    let extension = extension.unwrap_or_default();
+    let mut name = format!("{stem}{extension}");
+    let mut n = 1;
+    while !taken.insert(name.clone()) {
+        n += 1;
+        name = format!("{stem}-{n}{extension}");
+    }
+    name
+}
+
+/// `GET /api/tags/{tag}/download`: a zip of the files of every document
+/// tagged `tag`, read from their indexed paths, as `{tag}.zip`.
+///
+/// The archive is streamed a file at a time, so memory use follows the
+/// largest file rather than the whole set. Files missing from disk are left
+/// out and counted in `X-Skipped-Files`; the archive then ends with a
+/// `SKIPPED.txt` listing them, along with any that couldn't be read or didn't
+/// fit in the 4 GiB a plain zip can hold.
+pub async fn download(
+    Path(tag): Path<String>,
+    State(docs): State<DocShared>,
+) -> Result<Response, ApiError> {
+    let tag = normalize_tag(&tag).ok_or_else(|| ApiError::invalid_param("Tags can't be empty"))?;
+    let paths = {
+        let docs = docs
+            .read()
+            .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+        docs.documents
+            .values()
+            .filter(|doc| doc.tags.contains(&tag))
+            .map(|doc| doc.path.clone())
+            .collect::<BTreeSet<_>>()
+    };
+    if paths.is_empty() {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("No document is tagged {tag:?}"),
+        ));
+    }
+    let (present, missing): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .partition(|path| std::path::Path::new(path).is_file());
+    if !missing.is_empty() {
+        tracing::warn!(
+            "Leaving {} missing files out of {}.zip",
+            missing.len(),
+            redact::text(&tag)
+        );
+    }
+    let skipped_files = missing.len();
+
+    // One chunk at a time, so reading waits on the client
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Vec<u8>, std::io::Error>>(1);
+    tokio::task::spawn_blocking(move || {
+        let mut zip = ZipWriter::new();
+        let mut taken = HashSet::new();
+        let mut skipped = missing
+            .into_iter()
+            .map(|path| format!("{path}: not found"))
+            .collect::<Vec<_>>();
+        for path in present {
+            let data = match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    skipped.push(format!("{path}: {e}"));
+                    continue;
+                }
+            };
+            let name = entry_name(&path, &mut taken);
+            if !zip.fits(&name, data.len()) {
+                skipped.push(format!("{path}: over the archive's size limit"));
+                continue;
+            }
+            if tx.blocking_send(Ok(zip.entry(&name, &data))).is_err() {
+                // The client went away
+                return;
+            }
+        }
+        if !skipped.is_empty() {
+            let list = skipped.join("\n") + "\n";
+            let name = entry_name("SKIPPED.txt", &mut taken);
+            if zip.fits(&name, list.len()) {
+                let entry = zip.entry(&name, list.as_bytes());
+                if tx.blocking_send(Ok(entry)).is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = tx.blocking_send(Ok(zip.finish()));
+    });
+
+    // Keep the header value to safe characters
+    let file_name = tag
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}.zip\""),
+            ),
+            (
+                header::HeaderName::from_static("x-skipped-files"),
+                skipped_files.to_string(),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response())
+}
@@ -0,0 +1,171 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::IntoResponse,
+};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::CONFIG;
+use crate::error::{ApiError, ErrorCode};
+use crate::pdf::drop_page;
+use crate::{parse_param, pdf, pdf_pool, DocShared};
+
+/// Rendered pages, shared by every collection; file names carry a hash of
+/// the document's path and index time, so a reindexed file gets new ones
+const CACHE_DIR: &str = "paper-engine-thumbnails";
+
+/// Highest `dpi` a thumbnail can ask for
+pub const MAX_DPI: u32 = 300;
+
+/// Keeps concurrent renders of the same page from writing one temporary file
+static NEXT_RENDER: AtomicU64 = AtomicU64::new(0);
+
+/// Renders page `page` (1-based) of the PDF at `path` to `out`, through a
+/// temporary file so a half-written PNG is never served
+fn render(
+    path: &str,
+    page: usize,
+    dpi: u32,
+    password: Option<&str>,
+    out: &std::path::Path,
+) -> Result<(), ApiError> {
+    let tmp = format!(
+        "{}.{}.tmp",
+        out.display(),
+        NEXT_RENDER.fetch_add(1, Ordering::Relaxed)
+    );
+    pdf_pool::with_pdf(path, password, |pdf| {
+        let n_pages = pdf.get_n_pages();
+        let Some(pdf_page) = (page <= n_pages).then(|| pdf.get_page(page - 1)).flatten() else {
+            return Err(ApiError::invalid_param(format!(
+                "Invalid page {page}; {path:?} has pages 1..={n_pages}"
+            )));
+        };
+        let rendered =
+            pdf::render_png(&pdf_page, dpi as f64, CONFIG.thumbnail_max_px, tmp.as_ref());
+        drop_page(pdf_page);
+        rendered.map_err(|e| {
+            ApiError::internal(format!("Could not render page {page} of {path:?}: {e}"))
+        })
+    })??;
+    std::fs::rename(&tmp, out)
+        .map_err(|e| ApiError::internal(format!("Could not save thumbnail: {e}")))
+}
+
+/// Deletes the oldest thumbnails until the cache fits in
+/// `PAPER_ENGINE_THUMBNAIL_CACHE_MB`
+fn evict() -> std::io::Result<()> {
+    let mut files = vec![];
+    let mut total = 0;
+    for entry in std::fs::read_dir(CACHE_DIR)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            total += metadata.len();
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+    files.sort();
+    for (_, len, path) in files {
+        if total <= CONFIG.thumbnail_cache_bytes {
+            break;
+        }
+        std::fs::remove_file(&path)?;
+        total -= len;
+    }
+    Ok(())
+}
+
+/// `GET /api/document/{id}/thumbnail?page=N&dpi=D`: page `N` (1-based,
+/// default 1) of a PDF as a PNG, at `D` dots per inch (default
+/// `PAPER_ENGINE_THUMBNAIL_DPI`) but never more than
+/// `PAPER_ENGINE_THUMBNAIL_MAX_PX` on its longer side.
+///
+/// Renders are cached on disk. Encrypted documents need their `password`
+/// again and are never cached, since the image would show their text to
+/// anyone who asks.
+pub async fn thumbnail(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<impl IntoResponse, ApiError> {
+    let page = parse_param::<usize>(&params, "page")?.unwrap_or(1);
+    if page == 0 {
+        return Err(ApiError::invalid_param("Invalid `page` 0; pages start at 1"));
+    }
+    let dpi = parse_param::<u32>(&params, "dpi")?.unwrap_or(CONFIG.thumbnail_dpi);
+    if !(1..=MAX_DPI).contains(&dpi) {
+        return Err(ApiError::invalid_param(format!(
+            "Invalid `dpi` {dpi}; must be between 1 and {MAX_DPI}"
+        )));
+    }
+    let password = params.get("password").cloned();
+
+    let (path, encrypted, key) = {
+        let docs = docs.read().map_err(|e| {
+            ApiError::internal(format!("Could not get `DocShared` read lock: {e}"))
+        })?;
+        let doc = docs.document_by_id(document_id).ok_or_else(|| {
+            ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+        })?;
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (&doc.path, doc.indexed_at).hash(&mut hasher);
+        (doc.path.clone(), doc.encrypted, hasher.finish())
+    };
+    if path.starts_with("upload:") {
+        return Err(ApiError::new(
+            ErrorCode::NotFound,
+            format!("Document {document_id} was uploaded, and uploads aren't kept on disk"),
+        ));
+    }
+    let extension = std::path::Path::new(&path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    if matches!(extension.as_deref(), Some("txt" | "md" | "markdown" | "html" | "htm")) {
+        return Err(ApiError::invalid_param(format!(
+            "Document {document_id} isn't a PDF; only PDF pages have thumbnails"
+        )));
+    }
+    if encrypted && password.is_none() {
+        return Err(ApiError::new(
+            ErrorCode::Encrypted,
+            format!("Document {document_id} is encrypted; give its `password`"),
+        ));
+    }
+
+    let mut out = PathBuf::from(CACHE_DIR);
+    out.push(format!("{document_id}-{key:x}-{page}-{dpi}.png"));
+    if encrypted {
+        out.set_file_name(format!(
+            ".uncached-{}.png",
+            NEXT_RENDER.fetch_add(1, Ordering::Relaxed)
+        ));
+    }
+    let png = match tokio::fs::read(&out).await {
+        Ok(png) if !encrypted => png,
+        _ => {
+            let out = out.clone();
+            tokio::task::spawn_blocking(move || {
+                std::fs::create_dir_all(CACHE_DIR).map_err(|e| {
+                    ApiError::internal(format!("Could not create {CACHE_DIR:?}: {e}"))
+                })?;
+                render(&path, page, dpi, password.as_deref(), &out)?;
+                let png = std::fs::read(&out)
+                    .map_err(|e| ApiError::internal(format!("Could not read thumbnail: {e}")));
+                if encrypted {
+                    let _ = std::fs::remove_file(&out);
+                } else if let Err(e) = evict() {
+                    tracing::warn!("Could not trim the thumbnail cache: {e}");
+                }
+                png
+            })
+            .await
+            .map_err(|e| ApiError::internal(format!("Thumbnail task failed: {e}")))??
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
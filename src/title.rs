@@ -0,0 +1,198 @@
+use serde_derive::Serialize;
+
+use crate::config::CONFIG;
+
+// Where a document's title comes from. PDFs try the sources of
+// `PAPER_ENGINE_TITLE_CHAIN` in order and take the first plausible title, so
+// the many PDFs whose metadata title is empty or junk ("Untitled",
+// "Microsoft Word - draft3.docx") still get a real one. The file name is
+// always the last resort. Text files only have a `# Title` first line (a
+// heading) and web pages their `<title>` (metadata).
+
+/// Longest title taken from a document; more is a paragraph, not a title
+const MAX_TITLE_CHARS: usize = 250;
+
+/// Titles that name no document, compared lowercased
+const PLACEHOLDERS: &[&str] = &[
+    "untitled",
+    "untitled document",
+    "no title",
+    "title",
+    "document",
+    "abstract",
+    "contents",
+];
+
+/// What tools put in a title when it's really the file name
+const FILE_EXTENSIONS: &[&str] = &[
+    ".pdf", ".doc", ".docx", ".dvi", ".tex", ".ps", ".eps", ".rtf", ".odt", ".ppt", ".pptx",
+    ".indd", ".qxd",
+];
+
+/// Where a document's title came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleSource {
+    /// The PDF's title metadata, or an HTML `<title>`
+    Metadata,
+    /// The first heading on the first page, by font size, or a Markdown
+    /// `# Title` line
+    Heading,
+    /// The first run of the largest text on the first page
+    LargestText,
+    /// Made from the file name; see `extract::title_from_path`
+    Filename,
+}
+
+impl TitleSource {
+    /// Its byte in the 0x1E cache record
+    pub fn byte(self) -> u8 {
+        match self {
+            Self::Metadata => 0,
+            Self::Heading => 1,
+            Self::LargestText => 2,
+            Self::Filename => 3,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Metadata),
+            1 => Some(Self::Heading),
+            2 => Some(Self::LargestText),
+            3 => Some(Self::Filename),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for TitleSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "metadata" => Ok(Self::Metadata),
+            "heading" => Ok(Self::Heading),
+            "largest_text" => Ok(Self::LargestText),
+            "filename" => Ok(Self::Filename),
+            _ => Err(format!(
+                "expected \"metadata\", \"heading\", \"largest_text\" or \"filename\", got {s:?}"
+            )),
+        }
+    }
+}
+
+/// Whether `title` reads like a document's title rather than a placeholder,
+/// a file name or a stray letter
+pub fn plausible(title: &str) -> bool {
+    let title = title.trim();
+    let lower = title.to_lowercase();
+    let letters = title.chars().filter(|c| c.is_alphabetic()).count();
+    (3..=MAX_TITLE_CHARS).contains(&title.chars().count())
+        && letters >= 2
+        && !PLACEHOLDERS.contains(&lower.as_str())
+        && !lower.starts_with("microsoft ")
+        && !FILE_EXTENSIONS.iter().any(|extension| lower.ends_with(extension))
+}
+
+/// The first plausible title `find` gives for the sources of
+/// `PAPER_ENGINE_TITLE_CHAIN`, in order; `None` once the chain reaches
+/// `filename` or runs out, for the caller to fall back on the file name.
+/// Sources after a plausible title are never asked for.
+pub fn choose(find: impl FnMut(TitleSource) -> Option<String>) -> Option<(String, TitleSource)> {
+    choose_from(&CONFIG.title_chain, find)
+}
+
+/// [`choose`] with `chain` in place of `PAPER_ENGINE_TITLE_CHAIN`
+fn choose_from(
+    chain: &[TitleSource],
+    mut find: impl FnMut(TitleSource) -> Option<String>,
+) -> Option<(String, TitleSource)> {
+    for &source in chain {
+        if source == TitleSource::Filename {
+            return None;
+        }
+        if let Some(title) = find(source).filter(|title| plausible(title)) {
+            return Some((title, source));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TitleSource::*;
+
+    const CHAIN: &[TitleSource] = &[Metadata, Heading, LargestText, Filename];
+
+    /// Candidates for each source, recording which were asked for
+    fn stub<'a>(
+        candidates: &'a [(TitleSource, &'a str)],
+        asked: &'a mut Vec<TitleSource>,
+    ) -> impl FnMut(TitleSource) -> Option<String> + 'a {
+        move |source| {
+            asked.push(source);
+            candidates
+                .iter()
+                .find(|(candidate, _)| *candidate == source)
+                .map(|(_, title)| title.to_string())
+        }
+    }
+
+    #[test]
+    fn metadata_title_comes_first() {
+        let mut asked = vec![];
+        let candidates = [(Metadata, "Attention Is All You Need"), (Heading, "Abstract")];
+        let chosen = choose_from(CHAIN, stub(&candidates, &mut asked));
+        assert_eq!(chosen, Some(("Attention Is All You Need".to_owned(), Metadata)));
+        assert_eq!(asked, [Metadata]);
+    }
+
+    #[test]
+    fn junk_metadata_falls_back_to_the_heading() {
+        let mut asked = vec![];
+        let candidates = [
+            (Metadata, "Microsoft Word - draft3.docx"),
+            (Heading, "Sparse Graph Embeddings"),
+            (LargestText, "Proceedings"),
+        ];
+        let chosen = choose_from(CHAIN, stub(&candidates, &mut asked));
+        assert_eq!(chosen, Some(("Sparse Graph Embeddings".to_owned(), Heading)));
+        assert_eq!(asked, [Metadata, Heading]);
+    }
+
+    #[test]
+    fn no_heading_falls_back_to_the_largest_text() {
+        let mut asked = vec![];
+        let candidates = [(Metadata, "Untitled"), (LargestText, "Quantum Widgets")];
+        let chosen = choose_from(CHAIN, stub(&candidates, &mut asked));
+        assert_eq!(chosen, Some(("Quantum Widgets".to_owned(), LargestText)));
+        assert_eq!(asked, [Metadata, Heading, LargestText]);
+    }
+
+    #[test]
+    fn filename_ends_the_chain() {
+        let mut asked = vec![];
+        let candidates = [(Metadata, "untitled"), (Heading, "x")];
+        assert_eq!(choose_from(CHAIN, stub(&candidates, &mut asked)), None);
+        assert_eq!(asked, [Metadata, Heading, LargestText]);
+        // The caller then makes one from the file name
+        assert_eq!(crate::extract::title_from_path("sparse_graphs.pdf"), "Sparse Graphs");
+
+        // Sources after `filename` are never asked
+        let mut asked = vec![];
+        let candidates = [(Metadata, "A Fine Title")];
+        assert_eq!(choose_from(&[Filename, Metadata], stub(&candidates, &mut asked)), None);
+        assert!(asked.is_empty());
+    }
+
+    #[test]
+    fn chain_order_is_followed() {
+        let mut asked = vec![];
+        let candidates = [(Metadata, "Metadata Title"), (LargestText, "Large Title")];
+        let chosen = choose_from(&[LargestText, Metadata], stub(&candidates, &mut asked));
+        assert_eq!(chosen, Some(("Large Title".to_owned(), LargestText)));
+        assert_eq!(asked, [LargestText]);
+    }
+}
@@ -0,0 +1,409 @@
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+use serde_derive::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::admin::require_admin;
+use crate::collection;
+use crate::config::CONFIG;
+use crate::error::{self, ApiError, Error};
+use crate::wal;
+use crate::{DocShared, Document, Term, TfIdf};
+
+#[derive(Debug, Serialize)]
+pub struct PostingMismatch {
+    term: Term,
+    document: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CountMismatch {
+    term: Term,
+    maintained: usize,
+    /// Occurrences in the documents still indexed; the maintained count also
+    /// keeps removed documents, so it may be higher but never lower
+    in_documents: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenMismatch {
+    document: u32,
+    token_count: usize,
+    /// Sum of the document's surface form counts; pruning can lower it, so
+    /// only a sum above `token_count` is wrong
+    counted: usize,
+}
+
+/// Everything [`TfIdf::verify`] found out of step. Empty lists mean the
+/// maintained structures agree with the documents.
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    documents: usize,
+    /// Threads the documents were split between
+    threads: usize,
+    elapsed_ms: u128,
+    /// A document contains the term but isn't in its postings
+    missing_postings: Vec<PostingMismatch>,
+    /// The postings list a document that doesn't contain the term (or
+    /// doesn't exist)
+    stale_postings: Vec<PostingMismatch>,
+    /// Ids whose entry in the id => title map is missing or wrong
+    title_mismatches: Vec<u32>,
+    global_count_too_low: Vec<CountMismatch>,
+    token_count_too_low: Vec<TokenMismatch>,
+    /// Ids held by more than one document
+    duplicate_ids: Vec<u32>,
+    /// Ids at or past `next_id`, which a new document would be given again
+    ids_past_next_id: Vec<u32>,
+    /// Whether `repair=true` rebuilt the derived structures afterwards
+    repaired: bool,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing_postings.is_empty()
+            && self.stale_postings.is_empty()
+            && self.title_mismatches.is_empty()
+            && self.global_count_too_low.is_empty()
+            && self.token_count_too_low.is_empty()
+            && self.duplicate_ids.is_empty()
+            && self.ids_past_next_id.is_empty()
+    }
+
+    /// How many of each kind of disagreement were found, for a log line
+    fn summary(&self) -> String {
+        [
+            ("missing postings", self.missing_postings.len()),
+            ("stale postings", self.stale_postings.len()),
+            ("id map mismatches", self.title_mismatches.len()),
+            ("corpus counts too low", self.global_count_too_low.len()),
+            ("token counts too low", self.token_count_too_low.len()),
+            ("duplicate ids", self.duplicate_ids.len()),
+            ("ids past next_id", self.ids_past_next_id.len()),
+        ]
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|(what, n)| format!("{n} {what}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// What loading a cache does when what it maintains disagrees with its
+/// documents, as `/admin/verify` would find; `PAPER_ENGINE_LOAD_CHECK`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadCheck {
+    /// Refuse to load it
+    Strict,
+    /// Repair it as `repair=true` would, and save it
+    Repair,
+    /// Load it as written, without checking
+    Trust,
+}
+
+/// What a batch of documents adds up to; [`TfIdf::verify`] merges every
+/// batch's before comparing with the maintained structures
+#[derive(Debug, Default)]
+struct Tally {
+    postings: HashMap<Term, BTreeSet<u32>>,
+    in_documents: HashMap<Term, usize>,
+    ids: Vec<u32>,
+    title_mismatches: Vec<u32>,
+    token_count_too_low: Vec<TokenMismatch>,
+    ids_past_next_id: Vec<u32>,
+}
+
+impl Tally {
+    fn add(&mut self, tf_idf: &TfIdf, doc: &Document) {
+        for term in doc.term_frequency.keys() {
+            self.postings.entry(*term).or_default().insert(doc.id);
+        }
+        let mut counted = 0;
+        for (stem, forms) in &doc.surface_forms {
+            let n = forms.values().sum::<usize>();
+            *self.in_documents.entry(*stem).or_insert(0) += n;
+            counted += n;
+        }
+        if counted > doc.token_count {
+            self.token_count_too_low.push(TokenMismatch {
+                document: doc.id,
+                token_count: doc.token_count,
+                counted,
+            });
+        }
+        if tf_idf.titles.get(&doc.id) != Some(&doc.title) {
+            self.title_mismatches.push(doc.id);
+        }
+        self.ids.push(doc.id);
+        if doc.id >= tf_idf.next_id {
+            self.ids_past_next_id.push(doc.id);
+        }
+    }
+
+    fn merge(&mut self, other: Tally) {
+        for (term, ids) in other.postings {
+            self.postings.entry(term).or_default().extend(ids);
+        }
+        for (term, n) in other.in_documents {
+            *self.in_documents.entry(term).or_insert(0) += n;
+        }
+        self.ids.extend(other.ids);
+        self.title_mismatches.extend(other.title_mismatches);
+        self.token_count_too_low.extend(other.token_count_too_low);
+        self.ids_past_next_id.extend(other.ids_past_next_id);
+    }
+}
+
+impl std::str::FromStr for LoadCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "repair" => Ok(Self::Repair),
+            "trust" => Ok(Self::Trust),
+            _ => Err(format!("expected \"strict\", \"repair\" or \"trust\", got {s:?}")),
+        }
+    }
+}
+
+impl TfIdf {
+    /// Recomputes the inverted index, id map, corpus counts and token sums
+    /// from the documents and reports where the maintained ones disagree.
+    /// Doesn't change anything.
+    ///
+    /// The documents are tallied in batches of `PAPER_ENGINE_VERIFY_BATCH` on
+    /// up to `PAPER_ENGINE_VERIFY_THREADS` threads, then compared once.
+    pub fn verify(&self) -> VerifyReport {
+        let started = Instant::now();
+        let documents = self.documents.values().collect::<Vec<_>>();
+        let batches = documents.chunks(CONFIG.verify_batch).collect::<Vec<_>>();
+        let threads = CONFIG.verify_threads.min(batches.len()).max(1);
+        let next_batch = AtomicUsize::new(0);
+        let checked = AtomicUsize::new(0);
+        let tallies = std::thread::scope(|scope| {
+            let workers = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut tally = Tally::default();
+                        while let Some(batch) =
+                            batches.get(next_batch.fetch_add(1, Ordering::Relaxed))
+                        {
+                            for doc in *batch {
+                                tally.add(self, doc);
+                            }
+                            let checked =
+                                checked.fetch_add(batch.len(), Ordering::Relaxed) + batch.len();
+                            tracing::debug!(checked, total = documents.len(), "Verifying");
+                        }
+                        tally
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("verify thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        let mut tally = Tally::default();
+        for other in tallies {
+            tally.merge(other);
+        }
+        let Tally {
+            postings,
+            in_documents,
+            ids: all_ids,
+            title_mismatches,
+            token_count_too_low,
+            ids_past_next_id,
+        } = tally;
+
+        let mut report = VerifyReport {
+            documents: documents.len(),
+            threads,
+            title_mismatches,
+            token_count_too_low,
+            ids_past_next_id,
+            ..VerifyReport::default()
+        };
+        // Batches finish in any order; sort so the same index always gives
+        // the same report
+        report.title_mismatches.sort();
+        report.token_count_too_low.sort_by_key(|mismatch| mismatch.document);
+        report.ids_past_next_id.sort();
+        let mut ids = BTreeSet::new();
+        for id in all_ids {
+            if !ids.insert(id) {
+                report.duplicate_ids.push(id);
+            }
+        }
+        report.duplicate_ids.sort();
+        for id in self.titles.keys() {
+            if !ids.contains(id) {
+                report.title_mismatches.push(*id);
+            }
+        }
+
+        for (term, expected) in &postings {
+            let maintained = self.postings.get(term);
+            for id in expected {
+                if !maintained.is_some_and(|ids| ids.contains(id)) {
+                    report.missing_postings.push(PostingMismatch {
+                        term: *term,
+                        document: *id,
+                    });
+                }
+            }
+        }
+        for (term, maintained) in &self.postings {
+            let expected = postings.get(term);
+            for id in maintained {
+                if !expected.is_some_and(|ids| ids.contains(id)) {
+                    report.stale_postings.push(PostingMismatch {
+                        term: *term,
+                        document: *id,
+                    });
+                }
+            }
+        }
+
+        for (term, n) in in_documents {
+            let maintained = self.global_term_count.get(&term).copied().unwrap_or(0);
+            if maintained < n {
+                report.global_count_too_low.push(CountMismatch {
+                    term,
+                    maintained,
+                    in_documents: n,
+                });
+            }
+        }
+        report.elapsed_ms = started.elapsed().as_millis();
+        report
+    }
+
+    /// Checks a cache just loaded from `path`, its derived indexes built, as
+    /// `check` says. Returns whether it was repaired, and so wants saving.
+    pub fn check_on_load(&mut self, path: &str, check: LoadCheck) -> error::Result<bool> {
+        if check == LoadCheck::Trust {
+            return Ok(false);
+        }
+        let report = self.verify();
+        if report.is_ok() {
+            return Ok(false);
+        }
+        if check == LoadCheck::Strict {
+            return Err(Error::Format(format!(
+                "cache {path:?} disagrees with its own documents ({}). Set \
+                 PAPER_ENGINE_LOAD_CHECK=repair to fix it as it loads, or trust to load it as is",
+                report.summary()
+            )));
+        }
+        self.repair();
+        tracing::warn!("Repaired cache {path:?} as it loaded: {}", report.summary());
+        Ok(true)
+    }
+
+    /// Fixes what [`TfIdf::verify`] reports: new ids for clashing documents,
+    /// `next_id` moved past every id in use, corpus and token counts raised
+    /// to what the documents hold, and the inverted index and id map rebuilt
+    pub fn repair(&mut self) {
+        let mut next_id = self
+            .documents
+            .values()
+            .map(|doc| doc.id + 1)
+            .max()
+            .unwrap_or(0)
+            .max(self.next_id);
+        let mut ids = BTreeSet::new();
+        for doc in self.documents.values_mut() {
+            if !ids.insert(doc.id) {
+                doc.id = next_id;
+                next_id += 1;
+            }
+        }
+        self.next_id = next_id;
+
+        let mut in_documents: HashMap<Term, usize> = HashMap::new();
+        for doc in self.documents.values_mut() {
+            let mut counted = 0;
+            for (stem, forms) in &doc.surface_forms {
+                let n = forms.values().sum::<usize>();
+                *in_documents.entry(*stem).or_insert(0) += n;
+                counted += n;
+            }
+            doc.token_count = doc.token_count.max(counted);
+        }
+        for (term, n) in in_documents {
+            let count = self.global_term_count.entry(term).or_insert(0);
+            *count = (*count).max(n);
+        }
+
+        self.build_derived_indexes();
+        self.changed();
+    }
+}
+
+/// `POST /api/admin/verify?repair=true`
+pub async fn verify(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<VerifyReport>, ApiError> {
+    require_admin(&headers)?;
+    let repair = crate::bool_param(&params, "repair")?.unwrap_or(false);
+    let report = if repair {
+        let mut docs = docs
+            .write()
+            .map_err(|e| ApiError::internal(format!("Could not take `DocShared` lock: {e}")))?;
+        let mut report = docs.verify();
+        if !report.is_ok() {
+            docs.log_change(wal::Entry::Repair)?;
+            docs.repair();
+            report.repaired = true;
+        }
+        report
+    } else {
+        docs.read()
+            .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?
+            .verify()
+    };
+    if !report.is_ok() {
+        tracing::warn!("Index verification found discrepancies: {report:?}");
+    }
+    Ok(Json(report))
+}
+
+/// `paper-engine verify [--repair]`: checks every collection's cache without
+/// starting the server, and fails if any is out of step (unless repaired)
+pub fn cli(repair: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failed = vec![];
+    // Checked here instead, so nothing is repaired without `--repair`
+    for collection in collection::load_all(LoadCheck::Trust)? {
+        let mut docs = collection.docs.write().map_err(|e| e.to_string())?;
+        let mut report = docs.verify();
+        if report.is_ok() {
+            println!("{}: ok ({} documents)", collection.name, report.documents);
+            continue;
+        }
+        if repair {
+            docs.repair();
+            report.repaired = true;
+        }
+        println!("{}: {report:#?}", collection.name);
+        drop(docs);
+        if repair {
+            crate::save_cache(&collection)?;
+        } else {
+            failed.push(collection.name);
+        }
+    }
+    if !failed.is_empty() {
+        return Err(format!("Index out of step in {failed:?}; run `verify --repair`").into());
+    }
+    Ok(())
+}
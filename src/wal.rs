@@ -0,0 +1,376 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::error::{ApiError, Error, Result};
+use crate::intern::{get_str, intern};
+use crate::ranking::RankingConfig;
+use crate::{Document, Term, TfIdf};
+
+// The write-ahead log holds every change made since the last cache save, so
+// a crash between saves loses nothing. Changes are logged and synced to disk
+// before they're made, under the index's write lock, so the log is in the
+// order they happened.
+//
+// Each entry => {len}x4 {checksum}x4 {sequence}x8 {kind}x1 {body}
+//   `len` counts the bytes after the checksum, which is their FNV-1a hash,
+//   so an entry torn by a crash mid-write is recognized and dropped
+// 0x01 submit  => cache records (see `file_format.rs`): 0x0B pool entries,
+//                 0x0C counts to add to `global_term_count`, then the
+//                 document from its 0x02 title on
+// 0x02 flags   => {document id}x4 {flags}x1, bits as in the cache's 0x06
+// 0x03 tags    => {document id}x4, then {len}x2 {tag} for every tag it has
+// 0x04 ranking => cache records: 0x0B pool entries and a 0x12 per weight
+// 0x05 prune   => {min_count}x8
+// 0x06 repair  => no body
+// 0x07 block   => {len}x2 {stem} for every stem blocked
+// 0x08 unblock => {len}x2 {stem} for every stem unblocked
+// 0x09 notes   => {document id}x4 {notes}, the rest of the body
+//
+// Sequence numbers count up over the life of the index, and the cache
+// records the last one it includes (its 0x19 record). Recovery loads the
+// cache, then applies only the entries past that, in order; a save truncates
+// the log only once the new cache is in place. A crash at any point thus
+// either replays an entry or finds it already in the cache, never both.
+
+/// One change to an index
+pub enum Entry<'a> {
+    /// A document added or replaced, with the counts it adds to
+    /// `global_term_count`
+    Submit {
+        document: &'a Document,
+        term_count: &'a HashMap<Term, usize>,
+    },
+    /// A document's flags afterwards, see `Document::flags`
+    Flags { id: u32, flags: u8 },
+    /// Every tag a document has afterwards
+    Tags { id: u32, tags: &'a BTreeSet<String> },
+    Ranking(&'a RankingConfig),
+    Prune { min_count: usize },
+    Repair,
+    /// Stems added to the blocklist, see `blocklist.rs`
+    Block(&'a [Term]),
+    Unblock(&'a [Term]),
+    /// A document's notes afterwards, see `notes.rs`
+    Notes { id: u32, notes: &'a str },
+}
+
+impl Entry<'_> {
+    fn encode(&self, sequence: u64) -> Result<Vec<u8>> {
+        let mut payload = sequence.to_le_bytes().to_vec();
+        match self {
+            Entry::Submit {
+                document,
+                term_count,
+            } => {
+                payload.push(0x01);
+                TfIdf::serialize_submission(document, term_count, &mut payload)?;
+            }
+            Entry::Flags { id, flags } => {
+                payload.push(0x02);
+                payload.extend(id.to_le_bytes());
+                payload.push(*flags);
+            }
+            Entry::Tags { id, tags } => {
+                payload.push(0x03);
+                payload.extend(id.to_le_bytes());
+                for tag in tags.iter() {
+                    payload.extend((tag.len() as u16).to_le_bytes());
+                    payload.extend(tag.as_bytes());
+                }
+            }
+            Entry::Ranking(ranking) => {
+                payload.push(0x04);
+                TfIdf::serialize_ranking(ranking, &mut payload)?;
+            }
+            Entry::Prune { min_count } => {
+                payload.push(0x05);
+                payload.extend((*min_count as u64).to_le_bytes());
+            }
+            Entry::Repair => payload.push(0x06),
+            Entry::Block(stems) | Entry::Unblock(stems) => {
+                payload.push(match self {
+                    Entry::Block(_) => 0x07,
+                    _ => 0x08,
+                });
+                for stem in stems.iter() {
+                    let stem = get_str(*stem);
+                    payload.extend((stem.len() as u16).to_le_bytes());
+                    payload.extend(stem.as_bytes());
+                }
+            }
+            Entry::Notes { id, notes } => {
+                payload.push(0x09);
+                payload.extend(id.to_le_bytes());
+                payload.extend(notes.as_bytes());
+            }
+        }
+        let mut entry = (payload.len() as u32).to_le_bytes().to_vec();
+        entry.extend(checksum(&payload).to_le_bytes());
+        entry.extend(payload);
+        Ok(entry)
+    }
+}
+
+/// FNV-1a
+pub fn checksum(b: &[u8]) -> u32 {
+    b.iter()
+        .fold(0x811c9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193))
+}
+
+/// An entry as read back from the log
+struct Raw<'a> {
+    /// Byte offset of the entry in the log
+    start: usize,
+    sequence: u64,
+    kind: u8,
+    body: &'a [u8],
+}
+
+/// Every whole entry in `b`, and the offset where they end. Anything past
+/// that is an entry a crash cut short.
+fn entries(b: &[u8]) -> (Vec<Raw>, usize) {
+    let mut entries = vec![];
+    let mut i = 0;
+    while b.len() - i >= 8 {
+        let len = u32::from_le_bytes(b[i..][..4].try_into().unwrap()) as usize;
+        let sum = u32::from_le_bytes(b[i + 4..][..4].try_into().unwrap());
+        let Some(payload) = b.get(i + 8..i + 8 + len) else {
+            break;
+        };
+        if len < 9 || checksum(payload) != sum {
+            break;
+        }
+        entries.push(Raw {
+            start: i,
+            sequence: u64::from_le_bytes(payload[..8].try_into().unwrap()),
+            kind: payload[8],
+            body: &payload[9..],
+        });
+        i += 8 + len;
+    }
+    (entries, i)
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    path: String,
+    file: Mutex<File>,
+}
+
+impl Wal {
+    fn open(path: String) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends `entry` and waits for it to reach the disk. A failed write is
+    /// cut off again, so it can't hide the entries after it.
+    fn append(&self, entry: &[u8]) -> Result<()> {
+        let mut file = self.file.lock()?;
+        let len = file.metadata()?.len();
+        let written = file.write_all(entry).and_then(|()| file.sync_data());
+        if written.is_err() {
+            let _ = file.set_len(len);
+        }
+        Ok(written?)
+    }
+
+    /// Drops the entries up to `sequence`, which a saved cache now includes.
+    /// Entries logged while it was being written stay for the next save.
+    pub fn truncate_through(&self, sequence: u64) -> Result<()> {
+        let mut file = self.file.lock()?;
+        let b = std::fs::read(&self.path)?;
+        let (entries, end) = entries(&b);
+        let start = entries
+            .iter()
+            .find(|entry| entry.sequence > sequence)
+            .map_or(end, |entry| entry.start);
+        if start == 0 && end == b.len() {
+            return Ok(());
+        }
+        if start == end {
+            file.set_len(0)?;
+        } else {
+            // Like a cache save: a crash leaves either the old log or the new
+            let tmp_path = format!("{}.tmp", self.path);
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&b[start..end])?;
+            tmp.sync_all()?;
+            std::fs::rename(&tmp_path, &self.path)?;
+            *file = OpenOptions::new().append(true).open(&self.path)?;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// What `recover` found in the log
+#[derive(Debug, Default)]
+pub struct Recovery {
+    /// Entries applied on top of the cache
+    pub replayed: usize,
+    /// Bytes of a torn entry at the end
+    pub torn: usize,
+}
+
+impl Recovery {
+    /// Whether a save should fold the log into the cache right away
+    pub fn needs_save(&self) -> bool {
+        self.replayed > 0 || self.torn > 0
+    }
+}
+
+/// Applies the entries in the log at `path` that `tf_idf` (just loaded from
+/// its cache, derived indexes built) doesn't include yet, then attaches the
+/// log so later changes go to it
+pub fn recover(tf_idf: &mut TfIdf, path: String) -> Result<Recovery> {
+    let b = match std::fs::read(&path) {
+        Ok(b) => b,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e.into()),
+    };
+    let (entries, end) = entries(&b);
+    let mut recovery = Recovery {
+        replayed: 0,
+        torn: b.len() - end,
+    };
+    for entry in entries {
+        if entry.sequence <= tf_idf.wal_sequence {
+            continue;
+        }
+        tf_idf.replay(entry.kind, entry.body)?;
+        tf_idf.wal_sequence = entry.sequence;
+        recovery.replayed += 1;
+    }
+    if recovery.torn > 0 {
+        tracing::warn!(
+            "Dropping {} bytes of a write-ahead log entry in {path:?} cut short by a crash",
+            recovery.torn
+        );
+    }
+    if recovery.replayed > 0 {
+        tracing::info!("Replayed {} changes from {path:?}", recovery.replayed);
+    }
+    tf_idf.wal = Some(std::sync::Arc::new(Wal::open(path)?));
+    Ok(recovery)
+}
+
+fn short_entry(kind: u8) -> Error {
+    Error::Format(format!(
+        "Write-ahead log entry of kind {kind} is too short; potentially corrupted log"
+    ))
+}
+
+impl TfIdf {
+    /// Logs `entry` before the change it describes is made; without a log
+    /// this does nothing. On failure nothing is logged, and the caller must
+    /// not make the change either.
+    pub fn log_change(&mut self, entry: Entry) -> std::result::Result<(), ApiError> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let encoded = entry.encode(self.wal_sequence + 1)?;
+        wal.append(&encoded).map_err(|e| {
+            ApiError::internal(format!(
+                "Could not write to the write-ahead log, so nothing was changed: {e}"
+            ))
+        })?;
+        self.wal_sequence += 1;
+        Ok(())
+    }
+
+    /// Makes the change a logged entry describes, the same way the request
+    /// that logged it did
+    fn replay(&mut self, kind: u8, body: &[u8]) -> Result<()> {
+        let id = || {
+            body.get(..4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or_else(|| short_entry(kind))
+        };
+        match kind {
+            0x01 => {
+                let submission = TfIdf::deserialize_records(body)?;
+                for (term, n) in submission.global_term_count {
+                    *self.global_term_count.entry(term).or_insert(0) += n;
+                }
+                for (_, doc) in submission.documents {
+                    self.insert_document(doc);
+                }
+            }
+            0x02 => {
+                let id = id()?;
+                let flags = *body.get(4).ok_or_else(|| short_entry(kind))?;
+                match self.document_by_id_mut(id) {
+                    Some(doc) => doc.set_flags(flags),
+                    None => tracing::warn!("Skipping logged flags of missing document {id}"),
+                }
+            }
+            0x03 => {
+                let id = id()?;
+                let mut tags = BTreeSet::new();
+                let mut i = 4;
+                while i < body.len() {
+                    let len = body
+                        .get(i..i + 2)
+                        .map(|b| u16::from_le_bytes(b.try_into().unwrap()) as usize)
+                        .ok_or_else(|| short_entry(kind))?;
+                    let tag = body.get(i + 2..i + 2 + len).ok_or_else(|| short_entry(kind))?;
+                    tags.insert(String::from_utf8_lossy(tag).into_owned());
+                    i += 2 + len;
+                }
+                match self.document_by_id_mut(id) {
+                    Some(doc) => doc.tags = tags,
+                    None => tracing::warn!("Skipping logged tags of missing document {id}"),
+                }
+            }
+            0x04 => {
+                self.ranking = TfIdf::deserialize_records(body)?.ranking;
+                self.changed();
+            }
+            0x05 => {
+                let min_count = body
+                    .get(..8)
+                    .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                    .ok_or_else(|| short_entry(kind))?;
+                self.prune_terms(min_count as usize);
+            }
+            0x06 => self.repair(),
+            0x07 | 0x08 => {
+                let mut stems = vec![];
+                let mut i = 0;
+                while i < body.len() {
+                    let len = body
+                        .get(i..i + 2)
+                        .map(|b| u16::from_le_bytes(b.try_into().unwrap()) as usize)
+                        .ok_or_else(|| short_entry(kind))?;
+                    let stem = body.get(i + 2..i + 2 + len).ok_or_else(|| short_entry(kind))?;
+                    stems.push(intern(String::from_utf8_lossy(stem)));
+                    i += 2 + len;
+                }
+                if kind == 0x07 {
+                    self.block_terms(&stems);
+                } else {
+                    self.unblock_terms(&stems);
+                }
+            }
+            0x09 => {
+                let id = id()?;
+                let notes = String::from_utf8_lossy(&body[4..]).into_owned();
+                if !self.set_notes(id, notes) {
+                    tracing::warn!("Skipping logged notes of missing document {id}");
+                }
+            }
+            _ => {
+                return Err(Error::Format(format!(
+                    "Unknown write-ahead log entry kind {kind}; potentially corrupted log"
+                )))
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::{ApiError, ErrorCode};
+use crate::intern::get_str;
+use crate::search::{rounded, rounded_values, Combine, Field, QueryNormExplain, SearchRequest};
+use crate::DocShared;
+
+#[derive(Debug, Serialize)]
+pub struct WhyTerm {
+    /// As written in the query, lowercased
+    term: &'static str,
+    stem: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<Field>,
+    /// From the query's `^boost`, 1.0 without one
+    boost: f64,
+    /// The stem's frequency in the document's body; 0 when it's missing
+    frequency: f64,
+    #[serde(serialize_with = "rounded")]
+    idf: f64,
+    /// Multiplier for how early the term first appears, with
+    /// `position_weight=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<f64>,
+    /// Score contributed by each field the term was found in
+    #[serde(serialize_with = "rounded_values")]
+    fields: BTreeMap<Field, f64>,
+    /// The sum of `fields`, before `combine` folds the terms together
+    #[serde(serialize_with = "rounded")]
+    contribution: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Why {
+    id: u32,
+    title: String,
+    /// Whether the document satisfies the query and filters, so the same
+    /// search would return it
+    matched: bool,
+    /// 1-based place among the search's matches by score; `None` unless
+    /// `matched`
+    rank: Option<usize>,
+    /// As the search would report it; 0 for a document it wouldn't score
+    score: u64,
+    combine: Combine,
+    /// How the sum was scaled for the query's length, with `combine=sum`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query_norm: Option<QueryNormExplain>,
+    /// Multiplier applied for the document's age, with `recency=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recency: Option<f64>,
+    /// Every term the query scores, most contributing first
+    terms: Vec<WhyTerm>,
+    /// Words left out because they're on the blocklist
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    blocked: Vec<String>,
+}
+
+/// `GET /api/document/{id}/why?s=...`: how document `id` scores for a query,
+/// term by term. Takes the `/search` parameters that affect scoring and
+/// matching; the scores are the ones `sort_documents` gives, so they agree
+/// with `explain=true` on the same search.
+pub async fn why(
+    Path(document_id): Path<u32>,
+    Query(params): Query<HashMap<String, String>>,
+    State(docs): State<DocShared>,
+) -> Result<Json<Why>, ApiError> {
+    let query = params
+        .get("s")
+        .ok_or_else(|| ApiError::missing_param("Missing `s` parameter; give search terms"))?;
+    let mut request = SearchRequest::from_params(&params)?;
+    request.options.explain = true;
+    let exact = request.options.exact;
+
+    let docs = docs
+        .read()
+        .map_err(|e| ApiError::internal(format!("Could not get `DocShared` read lock: {e}")))?;
+    let doc = docs.document_by_id(document_id).ok_or_else(|| {
+        ApiError::new(ErrorCode::NotFound, format!("No document with id {document_id}"))
+    })?;
+    let prepared = docs.prepare_query(query, &request)?;
+    let mut results = docs.sort_documents(&prepared.terms, &request.options, &request.filter);
+    let result = results.iter().find(|result| result.title == doc.title).cloned();
+    results.retain(|result| {
+        docs.documents.get(&result.title).is_some_and(|other| {
+            request.filter.matches(other) && prepared.parsed.matches(other, exact)
+        })
+    });
+    let rank = results
+        .iter()
+        .position(|result| result.title == doc.title)
+        .map(|i| i + 1);
+
+    let explain = result.as_ref().and_then(|result| result.explain.as_ref());
+    // `explain` lists only the terms the document has, in query order
+    let mut explained = explain.map_or(&[][..], |explain| &explain.terms[..]).iter().peekable();
+    let mut terms = vec![];
+    for term in &prepared.terms {
+        let key = if exact { term.token.surface } else { term.token.stem };
+        let found = explained.next_if(|explained| explained.term == get_str(key));
+        let fields = found.map(|found| found.fields.clone()).unwrap_or_default();
+        terms.push(WhyTerm {
+            term: get_str(term.token.surface),
+            stem: get_str(term.token.stem),
+            field: term.field,
+            boost: term.boost,
+            frequency: doc.term_frequency.get(&term.token.stem).copied().unwrap_or(0.0),
+            idf: docs.term_idf(term, &request.options, &request.filter),
+            position: found.and_then(|found| found.position),
+            contribution: fields.values().sum(),
+            fields,
+        });
+    }
+    terms.sort_by(|a, b| b.contribution.total_cmp(&a.contribution));
+
+    Ok(Json(Why {
+        id: doc.id,
+        title: doc.title.clone(),
+        matched: rank.is_some(),
+        rank,
+        score: result.as_ref().map_or(0, |result| result.score),
+        combine: request.options.combine,
+        query_norm: explain.and_then(|explain| explain.query_norm.clone()),
+        recency: explain.and_then(|explain| explain.recency),
+        terms,
+        blocked: prepared.blocked,
+    }))
+}
@@ -0,0 +1,133 @@
+// Just enough of the zip format to hand files out as one download: entries
+// are stored uncompressed (PDFs barely compress anyway), so an archive can be
+// written a file at a time without a compression library. There's no zip64,
+// so an archive stops short of 4 GiB and 65535 entries.
+
+/// Most entries an archive can hold
+pub const MAX_ENTRIES: usize = u16::MAX as usize;
+
+/// 1980-01-01, the earliest date zip can express; entries aren't dated
+const DOS_DATE: u16 = (1 << 5) | 1;
+/// Names are UTF-8
+const UTF8_NAMES: u16 = 1 << 11;
+
+lazy_static::lazy_static! {
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    };
+}
+
+fn crc32(b: &[u8]) -> u32 {
+    !b.iter().fold(!0, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes an archive as a series of chunks: one per entry, then `finish`
+#[derive(Default)]
+pub struct ZipWriter {
+    /// Bytes written so far
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an entry of `name` and `len` bytes still fits, leaving room
+    /// for the central directory
+    pub fn fits(&self, name: &str, len: usize) -> bool {
+        let directory = self
+            .entries
+            .iter()
+            .map(|entry| 46 + entry.name.len() as u64)
+            .sum::<u64>()
+            + 46
+            + name.len() as u64
+            + 22;
+        self.entries.len() < MAX_ENTRIES
+            && self.offset + 30 + name.len() as u64 + len as u64 + directory <= u32::MAX as u64
+    }
+
+    /// The local header and data of an entry; check `fits` first
+    pub fn entry(&mut self, name: &str, data: &[u8]) -> Vec<u8> {
+        let crc = crc32(data);
+        let mut b = Vec::with_capacity(30 + name.len() + data.len());
+        b.extend(0x04034b50u32.to_le_bytes());
+        b.extend(20u16.to_le_bytes());
+        b.extend(UTF8_NAMES.to_le_bytes());
+        // Stored, at midnight
+        b.extend(0u16.to_le_bytes());
+        b.extend(0u16.to_le_bytes());
+        b.extend(DOS_DATE.to_le_bytes());
+        b.extend(crc.to_le_bytes());
+        b.extend((data.len() as u32).to_le_bytes());
+        b.extend((data.len() as u32).to_le_bytes());
+        b.extend((name.len() as u16).to_le_bytes());
+        b.extend(0u16.to_le_bytes());
+        b.extend(name.as_bytes());
+        b.extend(data);
+
+        self.entries.push(CentralEntry {
+            name: name.to_owned(),
+            crc,
+            size: data.len() as u32,
+            offset: self.offset as u32,
+        });
+        self.offset += b.len() as u64;
+        b
+    }
+
+    /// The central directory and end record that close the archive
+    pub fn finish(self) -> Vec<u8> {
+        let mut b = vec![];
+        for entry in &self.entries {
+            b.extend(0x02014b50u32.to_le_bytes());
+            b.extend(20u16.to_le_bytes());
+            b.extend(20u16.to_le_bytes());
+            b.extend(UTF8_NAMES.to_le_bytes());
+            b.extend(0u16.to_le_bytes());
+            b.extend(0u16.to_le_bytes());
+            b.extend(DOS_DATE.to_le_bytes());
+            b.extend(entry.crc.to_le_bytes());
+            b.extend(entry.size.to_le_bytes());
+            b.extend(entry.size.to_le_bytes());
+            b.extend((entry.name.len() as u16).to_le_bytes());
+            // Extra field, comment, disk, internal and external attributes
+            b.extend(0u16.to_le_bytes());
+            b.extend(0u16.to_le_bytes());
+            b.extend(0u16.to_le_bytes());
+            b.extend(0u16.to_le_bytes());
+            b.extend(0u32.to_le_bytes());
+            b.extend(entry.offset.to_le_bytes());
+            b.extend(entry.name.as_bytes());
+        }
+        let directory_len = b.len() as u32;
+        b.extend(0x06054b50u32.to_le_bytes());
+        b.extend(0u16.to_le_bytes());
+        b.extend(0u16.to_le_bytes());
+        b.extend((self.entries.len() as u16).to_le_bytes());
+        b.extend((self.entries.len() as u16).to_le_bytes());
+        b.extend(directory_len.to_le_bytes());
+        b.extend((self.offset as u32).to_le_bytes());
+        b.extend(0u16.to_le_bytes());
+        b
+    }
+}